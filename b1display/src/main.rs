@@ -48,8 +48,10 @@ use core::fmt::Write;
 use heapless::String;
 
 use fl16_inputmodules::control::*;
+use fl16_inputmodules::framing::FrameDecoder;
 use fl16_inputmodules::graphics::*;
 use fl16_inputmodules::serialnum::{device_release, get_serialnum};
+use fl16_inputmodules::settings::{self, SettingsStore};
 
 //                            FRA                - Framwork
 //                               KDE             - C1 LED Matrix
@@ -158,6 +160,8 @@ fn main() -> ! {
         },
         animation_period: 1_000_000, // 1000ms = 1Hz
     };
+    let mut session = Session::default();
+    let mut decoder = FrameDecoder::default();
 
     const INVERTED: bool = false;
     const AUTO_PWRDOWN: bool = true;
@@ -219,6 +223,16 @@ fn main() -> ! {
     }
     disp.flush().unwrap();
 
+    let mut settings = SettingsStore::new(settings::flash());
+    handle_command(
+        &Command::Settings(SettingsArg::Load),
+        &mut state,
+        logo_rect,
+        &mut disp,
+        &mut delay,
+        &mut settings,
+    );
+
     let sleep = pins.sleep.into_pull_down_input();
 
     let timer = Timer::new(pac.TIMER, &mut pac.RESETS);
@@ -296,6 +310,7 @@ fn main() -> ! {
 
         // Check for new data
         if usb_dev.poll(&mut [&mut serial]) {
+            session.update_dtr(serial.dtr());
             let mut buf = [0u8; 64];
             match serial.read(&mut buf) {
                 Err(_e) => {
@@ -305,37 +320,72 @@ fn main() -> ! {
                     // Do nothing
                 }
                 Ok(count) => {
-                    match (parse_command(count, &buf), &state.sleeping) {
-                        (Some(Command::Sleep(go_sleeping)), _) => {
-                            handle_sleep(go_sleeping, &mut state, &mut delay, &mut disp);
-                        }
-                        (Some(c @ Command::BootloaderReset), _)
-                        | (Some(c @ Command::IsSleeping), _) => {
-                            if let Some(response) =
-                                handle_command(&c, &mut state, logo_rect, &mut disp, &mut delay)
-                            {
-                                let _ = serial.write(&response);
-                            };
-                        }
-                        (Some(command), SimpleSleepState::Awake) => {
-                            // While sleeping no command is handled, except waking up
-                            if let Some(response) = handle_command(
-                                &command, &mut state, logo_rect, &mut disp, &mut delay,
-                            ) {
-                                let _ = serial.write(&response);
-                            };
-                            // Must write AFTER writing response, otherwise the
-                            // client interprets this debug message as the response
-                            //let mut text: String<64> = String::new();
-                            //write!(
-                            //    &mut text,
-                            //    "Handled command {}:{}:{}:{}\r\n",
-                            //    buf[0], buf[1], buf[2], buf[3]
-                            //)
-                            //.unwrap();
-                            //let _ = serial.write(text.as_bytes());
+                    decoder.push(&buf[..count]);
+                    while let Some(packet) = decoder.next_packet() {
+                        let command =
+                            packet.and_then(|packet| session.parse_command(packet.len(), &packet));
+                        match (command, &state.sleeping) {
+                            (Ok(Command::Sleep(go_sleeping)), _) => {
+                                handle_sleep(go_sleeping, &mut state, &mut delay, &mut disp);
+                                if let Some(ack) = session.ack(AckStatus::Ok) {
+                                    let _ = serial.write(&ack);
+                                }
+                            }
+                            (Ok(c @ Command::BootloaderReset), _)
+                            | (Ok(c @ Command::IsSleeping), _)
+                            | (Ok(c @ Command::GetCapabilities), _) => {
+                                if let Some(ack) = session.ack(AckStatus::Ok) {
+                                    let _ = serial.write(&ack);
+                                }
+                                if let Some(response) = handle_command(
+                                    &c,
+                                    &mut state,
+                                    logo_rect,
+                                    &mut disp,
+                                    &mut delay,
+                                    &mut settings,
+                                ) {
+                                    let _ = serial.write(&response);
+                                };
+                            }
+                            (Ok(command), SimpleSleepState::Awake) => {
+                                // While sleeping no command is handled, except waking up
+                                let response = handle_command(
+                                    &command,
+                                    &mut state,
+                                    logo_rect,
+                                    &mut disp,
+                                    &mut delay,
+                                    &mut settings,
+                                );
+                                if let Some(ack) = session.ack(AckStatus::Ok) {
+                                    let _ = serial.write(&ack);
+                                }
+                                if let Some(response) = response {
+                                    let _ = serial.write(&response);
+                                };
+                                // Must write AFTER writing response, otherwise the
+                                // client interprets this debug message as the response
+                                //let mut text: String<64> = String::new();
+                                //write!(
+                                //    &mut text,
+                                //    "Handled command {}:{}:{}:{}\r\n",
+                                //    buf[0], buf[1], buf[2], buf[3]
+                                //)
+                                //.unwrap();
+                                //let _ = serial.write(text.as_bytes());
+                            }
+                            (Err(err), _) => {
+                                if let Some(response) = session.reject(err, decoder.framed()) {
+                                    let _ = serial.write(&response);
+                                }
+                            }
+                            (Ok(_), SimpleSleepState::Sleeping) => {
+                                if let Some(ack) = session.ack(AckStatus::Sleeping) {
+                                    let _ = serial.write(&ack);
+                                }
+                            }
                         }
-                        _ => {}
                     }
                 }
             }
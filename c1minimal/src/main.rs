@@ -53,7 +53,9 @@ pub type Ws2812<'a> = ws2812_pio::Ws2812<
 >;
 
 use fl16_inputmodules::control::*;
+use fl16_inputmodules::framing::FrameDecoder;
 use fl16_inputmodules::serialnum::{device_release, get_serialnum};
+use fl16_inputmodules::settings::{self, SettingsStore};
 
 //                            FRA                - Framwork
 //                               000             - C1 Minimal Input Module (No assigned  value)
@@ -124,6 +126,8 @@ fn main() -> ! {
         color: colors::GREEN,
         brightness: 10,
     };
+    let mut session = Session::default();
+    let mut decoder = FrameDecoder::default();
 
     let timer = Timer::new(pac.TIMER, &mut pac.RESETS);
     let mut prev_timer = timer.get_counter().ticks();
@@ -137,6 +141,14 @@ fn main() -> ! {
         timer.count_down(),
     );
 
+    let mut settings = SettingsStore::new(settings::flash());
+    handle_command(
+        &Command::Settings(SettingsArg::Load),
+        &mut state,
+        &mut ws2812,
+        &mut settings,
+    );
+
     ws2812
         .write(smart_leds::brightness(
             [state.color].iter().cloned(),
@@ -157,6 +169,7 @@ fn main() -> ! {
 
         // Check for new data
         if usb_dev.poll(&mut [&mut serial]) {
+            session.update_dtr(serial.dtr());
             let mut buf = [0u8; 64];
             match serial.read(&mut buf) {
                 Err(_e) => {
@@ -166,16 +179,43 @@ fn main() -> ! {
                     // Do nothing
                 }
                 Ok(count) => {
-                    if let Some(command) = parse_command(count, &buf) {
-                        if let Command::Sleep(go_sleeping) = command {
-                            handle_sleep(go_sleeping, &mut state, &mut delay, &mut ws2812);
-                        } else if let SimpleSleepState::Awake = state.sleeping {
-                            // While sleeping no command is handled, except waking up
-                            if let Some(response) =
-                                handle_command(&command, &mut state, &mut ws2812)
-                            {
-                                let _ = serial.write(&response);
-                            };
+                    decoder.push(&buf[..count]);
+                    while let Some(packet) = decoder.next_packet() {
+                        let command =
+                            packet.and_then(|packet| session.parse_command(packet.len(), &packet));
+                        match command {
+                            Ok(Command::Sleep(go_sleeping)) => {
+                                handle_sleep(go_sleeping, &mut state, &mut delay, &mut ws2812);
+                                if let Some(ack) = session.ack(AckStatus::Ok) {
+                                    let _ = serial.write(&ack);
+                                }
+                            }
+                            Ok(command) => {
+                                // While sleeping no command is handled, except waking up.
+                                // Capabilities are needed by the host to talk to the module at all.
+                                let awake = matches!(state.sleeping, SimpleSleepState::Awake);
+                                if awake || command == Command::GetCapabilities {
+                                    let response = handle_command(
+                                        &command,
+                                        &mut state,
+                                        &mut ws2812,
+                                        &mut settings,
+                                    );
+                                    if let Some(ack) = session.ack(AckStatus::Ok) {
+                                        let _ = serial.write(&ack);
+                                    }
+                                    if let Some(response) = response {
+                                        let _ = serial.write(&response);
+                                    };
+                                } else if let Some(ack) = session.ack(AckStatus::Sleeping) {
+                                    let _ = serial.write(&ack);
+                                }
+                            }
+                            Err(err) => {
+                                if let Some(response) = session.reject(err, decoder.framed()) {
+                                    let _ = serial.write(&response);
+                                }
+                            }
                         }
                     }
                 }
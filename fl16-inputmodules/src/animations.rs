@@ -1,3 +1,6 @@
+use heapless::Vec;
+
+use crate::boot_animation::BootAnimationIterator;
 use crate::control::*;
 use crate::games::game_of_life::*;
 use crate::games::pong_animation::*;
@@ -19,6 +22,8 @@ pub enum Animation {
     Breathing(BreathingIterator),
     Snake(SnakeIterator),
     Pong(PongIterator),
+    Boot(BootAnimationIterator),
+    Sequence(SequenceIterator),
 }
 impl Iterator for Animation {
     type Item = Grid;
@@ -31,10 +36,40 @@ impl Iterator for Animation {
             Animation::Breathing(x) => x.next(),
             Animation::Snake(x) => x.next(),
             Animation::Pong(x) => x.next(),
+            Animation::Boot(x) => x.next(),
+            Animation::Sequence(x) => x.next(),
         }
     }
 }
 
+/// How many animation periods a frame is shown to last roughly its duration
+///
+/// Frames only change once every animation period (in microseconds), so each
+/// one is shown at least once.
+pub fn frame_repeats(duration_ms: u16, period: u64) -> u64 {
+    let period = period.max(1);
+    let duration = duration_ms as u64 * 1_000;
+    ((duration + period / 2) / period).max(1)
+}
+
+/// One of the built-in startup animations, picked by the random byte
+pub fn startup_animation(random: u8) -> Animation {
+    match random % 8 {
+        0 => Animation::Percentage(StartupPercentageIterator::default()),
+        1 => Animation::ZigZag(ZigZagIterator::default()),
+        2 => Animation::Gof(GameOfLifeIterator::new(GameOfLifeStartParam::Pattern1, 200)),
+        3 => Animation::Gof(GameOfLifeIterator::new(
+            GameOfLifeStartParam::BeaconToadBlinker,
+            128,
+        )),
+        4 => Animation::Gof(GameOfLifeIterator::new(GameOfLifeStartParam::Glider, 128)),
+        5 => Animation::Breathing(BreathingIterator::default()),
+        6 => Animation::Pong(PongIterator::default()),
+        7 => Animation::Snake(SnakeIterator::default()),
+        _ => unreachable!(),
+    }
+}
+
 pub struct ZigZagIterator {
     frames: usize,
     current_frame: usize,
@@ -106,7 +141,7 @@ impl GameOfLifeIterator {
     pub fn new(start_param: GameOfLifeStartParam, frames: usize) -> Self {
         Self {
             // Could start with a custom grid
-            state: GameOfLifeState::new(start_param, &Grid::default()),
+            state: GameOfLifeState::new(start_param.into(), &Grid::default()),
             frames_remaining: frames,
         }
     }
@@ -156,7 +191,7 @@ impl Iterator for BreathingIterator {
             let mut grid = Grid::default();
             let breath_step = 4;
             // TODO: Make it cycle up and down
-            self.current_brightness = (self.current_brightness + breath_step) % 255;
+            self.current_brightness = self.current_brightness.wrapping_add(breath_step) % 255;
             for y in 0..HEIGHT {
                 for x in 0..WIDTH {
                     grid.0[x][y] = self.current_brightness;
@@ -169,3 +204,98 @@ impl Iterator for BreathingIterator {
         }
     }
 }
+
+/// Frames uploaded by the host, each with how long it's shown in milliseconds
+pub type SequenceFrames = Vec<(Grid, u16), MAX_SEQUENCE_FRAMES>;
+
+/// Plays the uploaded frame sequence
+pub struct SequenceIterator {
+    frames: SequenceFrames,
+    /// How many more times the sequence is played, 0 means forever
+    loops: u8,
+    current_frame: usize,
+    /// How many more times the current frame is shown
+    repeats: u64,
+    /// Animation period in microseconds
+    period: u64,
+}
+
+impl SequenceIterator {
+    pub fn new(frames: SequenceFrames, loops: u8, period: u64) -> Self {
+        Self {
+            frames,
+            loops,
+            current_frame: 0,
+            repeats: 0,
+            period,
+        }
+    }
+}
+
+impl Iterator for SequenceIterator {
+    type Item = Grid;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.repeats == 0 {
+            if self.current_frame >= self.frames.len() {
+                if self.loops == 1 || self.frames.is_empty() {
+                    return None;
+                }
+                self.loops = self.loops.saturating_sub(1);
+                self.current_frame = 0;
+            }
+            self.repeats = frame_repeats(self.frames[self.current_frame].1, self.period);
+            self.current_frame += 1;
+        }
+        self.repeats -= 1;
+        Some(self.frames[self.current_frame - 1].0.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn startup_animations_end() {
+        // Frames of each one, for random % 8
+        let budgets = [34, 34, 200, 128, 128, 64, 136, 4 * 64];
+        for (random, budget) in budgets.into_iter().enumerate() {
+            let frames = startup_animation(random as u8).take(budget + 1).count();
+            assert!(frames <= budget, "{random}: {frames} frames");
+        }
+    }
+
+    /// Two frames, shown for 200ms and 100ms
+    fn sequence(loops: u8) -> SequenceIterator {
+        let mut frames = SequenceFrames::new();
+        frames.push((percentage(10), 200)).ok().unwrap();
+        frames.push((percentage(20), 100)).ok().unwrap();
+        SequenceIterator::new(frames, loops, 100_000)
+    }
+
+    #[test]
+    fn sequence_loops() {
+        let shown: std::vec::Vec<_> = sequence(2).map(|grid| grid.0).collect();
+        let (first, second) = (percentage(10).0, percentage(20).0);
+        assert_eq!(shown, [first, first, second, first, first, second]);
+    }
+
+    #[test]
+    fn sequence_loops_forever() {
+        let mut shown = sequence(0);
+        for _ in 0..1000 {
+            assert_eq!(shown.next().map(|grid| grid.0), Some(percentage(10).0));
+            shown.next();
+            assert_eq!(shown.next().map(|grid| grid.0), Some(percentage(20).0));
+        }
+    }
+
+    #[test]
+    fn empty_sequence_ends() {
+        for loops in [0, 1, 2] {
+            let mut shown = SequenceIterator::new(SequenceFrames::new(), loops, 100_000);
+            assert!(shown.next().is_none(), "{loops} loops");
+        }
+    }
+}
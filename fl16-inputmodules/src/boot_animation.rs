@@ -0,0 +1,231 @@
+//! Boot animation that the user uploaded to flash
+//!
+//! Stored in the "Boot Animation" region, see flash_layout.md.
+//!
+//! Region layout:
+//!
+//! ```text
+//! Byte 0-1: MAGIC
+//! Byte 2-3: Number of frames, little endian
+//! Byte 4-5: CRC-16 of all frames, little endian
+//! Byte 6-7: Unused
+//! Byte 8-:  Frames
+//! ```
+//!
+//! Frame layout:
+//!
+//! ```text
+//! Byte 0-1: How long the frame is shown in milliseconds, little endian
+//! Byte 2-:  Brightness of each LED, column by column
+//! ```
+//!
+//! The header is written after all frames, so an interrupted upload doesn't
+//! leave a broken animation behind.
+use inputmodule_protocol::MAX_BOOT_ANIMATION_FRAMES;
+
+use crate::animations::frame_repeats;
+use crate::flash::{Flash, RpFlash, SECTOR_SIZE};
+use crate::matrix::{Grid, HEIGHT, LEDS, WIDTH};
+
+/// Start of the Boot Animation region, relative to the start of flash
+const REGION_OFFSET: usize = 0x0E7000;
+const REGION_SIZE: usize = 20 * SECTOR_SIZE;
+
+const MAGIC: [u8; 2] = [0x32, 0xB0];
+const HEADER_SIZE: usize = 8;
+const FRAME_SIZE: usize = 2 + LEDS;
+
+const CRC: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_IBM_3740);
+
+fn flash() -> RpFlash {
+    RpFlash::new(REGION_OFFSET, REGION_SIZE)
+}
+
+fn frame_offset(index: usize) -> usize {
+    HEADER_SIZE + index * FRAME_SIZE
+}
+
+/// CRC of the first frames, as they are in flash
+fn checksum(flash: &RpFlash, frames: usize) -> u16 {
+    let mut digest = CRC.digest();
+    let mut buf = [0; FRAME_SIZE];
+    for i in 0..frames {
+        flash.read(frame_offset(i), &mut buf);
+        digest.update(&buf);
+    }
+    digest.finalize()
+}
+
+/// Erase the stored animation, the built-in ones are played again
+pub fn clear() {
+    flash().erase_sector(0);
+}
+
+/// Upload of a new boot animation that's in progress
+pub struct Upload {
+    flash: RpFlash,
+    frames: usize,
+    /// Number of bytes at the start of the region that have been erased
+    erased: usize,
+}
+
+impl Upload {
+    /// Erase the stored animation and start a new one
+    ///
+    /// Only the first sector is erased, the others right before they're needed.
+    pub fn begin() -> Self {
+        let mut flash = flash();
+        flash.erase_sector(0);
+        Self {
+            flash,
+            frames: 0,
+            erased: SECTOR_SIZE,
+        }
+    }
+
+    /// Append a frame. Returns false if it's dropped, because there are
+    /// already MAX_BOOT_ANIMATION_FRAMES.
+    pub fn add_frame(&mut self, grid: &Grid, duration_ms: u16) -> bool {
+        if self.frames >= MAX_BOOT_ANIMATION_FRAMES {
+            return false;
+        }
+        let offset = frame_offset(self.frames);
+        while self.erased < offset + FRAME_SIZE {
+            self.flash.erase_sector(self.erased);
+            self.erased += SECTOR_SIZE;
+        }
+
+        let mut buf = [0; FRAME_SIZE];
+        buf[..2].copy_from_slice(&duration_ms.to_le_bytes());
+        for (x, col) in grid.0.iter().enumerate() {
+            buf[2 + x * HEIGHT..][..HEIGHT].copy_from_slice(col);
+        }
+        self.flash.write(offset, &buf);
+        self.frames += 1;
+        true
+    }
+
+    /// Write the header, from now on the animation is played at boot
+    pub fn commit(mut self) {
+        // Nothing to play, keep showing the built-in animations
+        if self.frames == 0 {
+            return;
+        }
+        let crc = checksum(&self.flash, self.frames);
+        let mut header = [0xFF; HEADER_SIZE];
+        header[..2].copy_from_slice(&MAGIC);
+        header[2..4].copy_from_slice(&(self.frames as u16).to_le_bytes());
+        header[4..6].copy_from_slice(&crc.to_le_bytes());
+        self.flash.write(0, &header);
+    }
+}
+
+/// Plays the stored boot animation
+pub struct BootAnimationIterator {
+    flash: RpFlash,
+    frames: usize,
+    current_frame: usize,
+    /// How many more times the current frame is shown
+    repeats: u64,
+    /// Animation period in microseconds
+    period: u64,
+    grid: Grid,
+}
+
+impl BootAnimationIterator {
+    /// None if no animation was uploaded or it's corrupted
+    pub fn load(period: u64) -> Option<Self> {
+        let flash = flash();
+        let mut header = [0; HEADER_SIZE];
+        flash.read(0, &mut header);
+        let frames = u16::from_le_bytes([header[2], header[3]]) as usize;
+        let crc = u16::from_le_bytes([header[4], header[5]]);
+        if header[..2] != MAGIC
+            || frames == 0
+            || frames > MAX_BOOT_ANIMATION_FRAMES
+            || crc != checksum(&flash, frames)
+        {
+            return None;
+        }
+
+        Some(Self {
+            flash,
+            frames,
+            current_frame: 0,
+            repeats: 0,
+            period,
+            grid: Grid::default(),
+        })
+    }
+}
+
+impl Iterator for BootAnimationIterator {
+    type Item = Grid;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.repeats == 0 {
+            if self.current_frame >= self.frames {
+                return None;
+            }
+            let offset = frame_offset(self.current_frame);
+            let mut duration = [0; 2];
+            self.flash.read(offset, &mut duration);
+            for x in 0..WIDTH {
+                self.flash
+                    .read(offset + 2 + x * HEIGHT, &mut self.grid.0[x]);
+            }
+
+            self.repeats = frame_repeats(u16::from_le_bytes(duration), self.period);
+            self.current_frame += 1;
+        }
+        self.repeats -= 1;
+        Some(self.grid.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Only one test, they'd share the simulated flash
+    #[test]
+    fn uploaded_animation_ends() {
+        clear();
+        assert!(BootAnimationIterator::load(31_250).is_none());
+
+        let mut first = Grid::default();
+        first.0[0][0] = 0xFF;
+        let mut upload = Upload::begin();
+        assert!(upload.add_frame(&first, 100));
+        assert!(upload.add_frame(&Grid::default(), 0));
+        assert!(upload.add_frame(&Grid::default(), 1_000));
+        // Not played before it's committed
+        assert!(BootAnimationIterator::load(31_250).is_none());
+        upload.commit();
+
+        // Each frame is shown at least once, and roughly for its duration
+        let budget = 3 + 1 + 32;
+        let frames: std::vec::Vec<Grid> = BootAnimationIterator::load(31_250)
+            .unwrap()
+            .take(budget + 1)
+            .collect();
+        assert_eq!(frames.len(), budget);
+        assert_eq!(frames[0].0, first.0);
+        assert_eq!(frames[3].0, Grid::default().0);
+
+        // The most that fit, at the slowest animation period
+        let mut upload = Upload::begin();
+        for _ in 0..MAX_BOOT_ANIMATION_FRAMES {
+            assert!(upload.add_frame(&first, u16::MAX));
+        }
+        assert!(!upload.add_frame(&first, u16::MAX));
+        upload.commit();
+        let period = 1_000_000;
+        let budget = MAX_BOOT_ANIMATION_FRAMES * frame_repeats(u16::MAX, period) as usize;
+        let frames = BootAnimationIterator::load(period)
+            .unwrap()
+            .take(budget + 1)
+            .count();
+        assert_eq!(frames, budget);
+    }
+}
@@ -1,14 +1,20 @@
 //! Firmware API - Commands
-use num::FromPrimitive;
+#[cfg(not(feature = "rp2040"))]
+use crate::sim::reset_to_usb_boot;
+#[cfg(feature = "rp2040")]
 use rp2040_hal::rom_data::reset_to_usb_boot;
 
-use crate::serialnum::{device_release, is_pre_release};
+use crate::serialnum::{device_release, get_hw_revision, is_pre_release};
+#[cfg(any(feature = "ledmatrix", feature = "b1display", feature = "c1minimal"))]
+use crate::settings::{self, SettingsStore};
 
 #[cfg(feature = "b1display")]
 use crate::graphics::*;
+#[cfg(all(feature = "b1display", not(feature = "rp2040")))]
+use crate::sim::Delay;
 #[cfg(feature = "b1display")]
-use core::fmt::{Debug, Write};
-#[cfg(feature = "b1display")]
+use core::fmt::Debug;
+#[cfg(all(feature = "b1display", feature = "rp2040"))]
 use cortex_m::delay::Delay;
 #[cfg(feature = "b1display")]
 use embedded_graphics::Pixel;
@@ -23,15 +29,19 @@ use embedded_hal::blocking::spi;
 #[cfg(feature = "b1display")]
 use embedded_hal::digital::v2::OutputPin;
 #[cfg(feature = "b1display")]
-use heapless::String;
-#[cfg(feature = "b1display")]
 use st7306::{FpsConfig, PowerMode, ST7306};
 
+#[cfg(feature = "ledmatrix")]
+use crate::animations::{Animation, SequenceIterator};
+#[cfg(feature = "ledmatrix")]
+use crate::boot_animation;
 #[cfg(feature = "ledmatrix")]
 use crate::games::pong;
 #[cfg(feature = "ledmatrix")]
 use crate::games::snake;
 #[cfg(feature = "ledmatrix")]
+use crate::games::tetris;
+#[cfg(feature = "ledmatrix")]
 use crate::matrix::*;
 #[cfg(feature = "ledmatrix")]
 use crate::patterns::*;
@@ -41,177 +51,23 @@ use is31fl3741::PwmFreq;
 #[cfg(feature = "c1minimal")]
 use smart_leds::{SmartLedsWrite, RGB8};
 
-#[repr(u8)]
-#[derive(num_derive::FromPrimitive)]
-/// All available commands
-pub enum CommandVals {
-    Brightness = 0x00,
-    Pattern = 0x01,
-    BootloaderReset = 0x02,
-    Sleep = 0x03,
-    Animate = 0x04,
-    Panic = 0x05,
-    Draw = 0x06,
-    StageGreyCol = 0x07,
-    DrawGreyColBuffer = 0x08,
-    SetText = 0x09,
-    StartGame = 0x10,
-    GameControl = 0x11,
-    GameStatus = 0x12,
-    SetColor = 0x13,
-    DisplayOn = 0x14,
-    InvertScreen = 0x15,
-    SetPixelColumn = 0x16,
-    FlushFramebuffer = 0x17,
-    ClearRam = 0x18,
-    ScreenSaver = 0x19,
-    SetFps = 0x1A,
-    SetPowerMode = 0x1B,
-    AnimationPeriod = 0x1C,
-    PwmFreq = 0x1E,
-    DebugMode = 0x1F,
-    Version = 0x20,
-}
-
-#[derive(num_derive::FromPrimitive)]
-pub enum PatternVals {
-    Percentage = 0x00,
-    Gradient = 0x01,
-    DoubleGradient = 0x02,
-    DisplayLotus = 0x03,
-    ZigZag = 0x04,
-    FullBrightness = 0x05,
-    DisplayPanic = 0x06,
-    DisplayLotus2 = 0x07,
-}
-
-pub enum Game {
-    Snake,
-    Pong,
-    Tetris,
-    GameOfLife(GameOfLifeStartParam),
-}
-
-#[derive(Copy, Clone, num_derive::FromPrimitive)]
-pub enum GameVal {
-    Snake = 0,
-    Pong = 1,
-    Tetris = 2,
-    GameOfLife = 3,
-}
-
-#[derive(Copy, Clone, num_derive::FromPrimitive)]
-pub enum GameControlArg {
-    Up = 0,
-    Down = 1,
-    Left = 2,
-    Right = 3,
-    Exit = 4,
-    SecondLeft = 5,
-    SecondRight = 6,
-}
-
-#[derive(Copy, Clone, num_derive::FromPrimitive)]
-pub enum GameOfLifeStartParam {
-    CurrentMatrix = 0x00,
-    Pattern1 = 0x01,
-    Blinker = 0x02,
-    Toad = 0x03,
-    Beacon = 0x04,
-    Glider = 0x05,
-    BeaconToadBlinker = 0x06,
-}
-
-#[derive(Copy, Clone, num_derive::FromPrimitive)]
-pub enum DisplayMode {
-    /// Low Power Mode
-    Lpm = 0x00,
-    /// High Power Mode
-    Hpm = 0x01,
-}
+pub use inputmodule_protocol::response::{self, Ack, AckStatus, Capabilities, Response};
+pub use inputmodule_protocol::{
+    BootAnimationArg, Command, CommandVals, DisplayMode, Game, GameControlArg, GameOfLifeParam,
+    GameOfLifeStartParam, GameVal, LifeRule, ModuleType, ParseError, PatternVals, PongDifficulty,
+    PongMode, PongParam, PwmFreqArg, Rgb, SequenceArg, SettingsArg, MAX_SEQUENCE_FRAMES,
+};
 
 #[cfg(feature = "ledmatrix")]
-#[derive(Copy, Clone, num_derive::FromPrimitive)]
-pub enum PwmFreqArg {
-    /// 29kHz
-    P29k = 0x00,
-    /// 3.6kHz
-    P3k6 = 0x01,
-    /// 1.8kHz
-    P1k8 = 0x02,
-    /// 900Hz
-    P900 = 0x03,
-}
-#[cfg(feature = "ledmatrix")]
-impl From<PwmFreqArg> for PwmFreq {
-    fn from(val: PwmFreqArg) -> Self {
-        match val {
-            PwmFreqArg::P29k => PwmFreq::P29k,
-            PwmFreqArg::P3k6 => PwmFreq::P3k6,
-            PwmFreqArg::P1k8 => PwmFreq::P1k8,
-            PwmFreqArg::P900 => PwmFreq::P900,
-        }
+pub fn to_pwm_freq(val: PwmFreqArg) -> PwmFreq {
+    match val {
+        PwmFreqArg::P29k => PwmFreq::P29k,
+        PwmFreqArg::P3k6 => PwmFreq::P3k6,
+        PwmFreqArg::P1k8 => PwmFreq::P1k8,
+        PwmFreqArg::P900 => PwmFreq::P900,
     }
 }
 
-// TODO: Reduce size for modules that don't require other commands
-pub enum Command {
-    /// Get current brightness scaling
-    GetBrightness,
-    /// Set brightness scaling
-    SetBrightness(u8),
-    /// Display pre-programmed pattern
-    Pattern(PatternVals),
-    /// Reset into bootloader
-    BootloaderReset,
-    /// Light up a percentage of the screen
-    Percentage(u8),
-    /// Go to sleepe or wake up
-    Sleep(bool),
-    IsSleeping,
-    /// Start/stop animation (vertical scrolling)
-    SetAnimate(bool),
-    GetAnimate,
-    /// Panic. Just to test what happens
-    Panic,
-    /// Draw black/white on the grid
-    #[cfg(feature = "ledmatrix")]
-    Draw([u8; DRAW_BYTES]),
-    #[cfg(feature = "ledmatrix")]
-    StageGreyCol(u8, [u8; HEIGHT]),
-    DrawGreyColBuffer,
-    #[cfg(feature = "b1display")]
-    SetText(String<64>),
-    StartGame(Game),
-    GameControl(GameControlArg),
-    GameStatus,
-    Version,
-    GetColor,
-    #[cfg(feature = "c1minimal")]
-    SetColor(RGB8),
-    DisplayOn(bool),
-    GetDisplayOn,
-    InvertScreen(bool),
-    GetInvertScreen,
-    SetPixelColumn(usize, [u8; 50]),
-    FlushFramebuffer,
-    ClearRam,
-    ScreenSaver(bool),
-    GetScreenSaver,
-    SetFps(u8),
-    GetFps,
-    SetPowerMode(u8),
-    GetPowerMode,
-    SetAnimationPeriod(u16),
-    GetAnimationPeriod,
-    #[cfg(feature = "ledmatrix")]
-    SetPwmFreq(PwmFreqArg),
-    GetPwmFreq,
-    SetDebugMode(bool),
-    GetDebugMode,
-    _Unknown,
-}
-
 #[cfg(any(feature = "c1minimal", feature = "b1display"))]
 #[derive(Clone)]
 pub enum SimpleSleepState {
@@ -252,243 +108,241 @@ pub struct B1DIsplayState {
     pub animation_period: u64,
 }
 
-pub fn parse_command(count: usize, buf: &[u8]) -> Option<Command> {
-    if let Some(command) = parse_module_command(count, buf) {
-        return Some(command);
-    }
+pub fn parse_command(count: usize, buf: &[u8]) -> Result<Command, ParseError> {
+    check_supported(Command::parse(&buf[..count])?)
+}
 
-    // Parse the generic commands common to all modules
-    if count >= 3 && buf[0] == 0x32 && buf[1] == 0xAC {
-        let command = buf[2];
-        let arg = if count <= 3 { None } else { Some(buf[3]) };
-
-        //let mut text: String<64> = String::new();
-        //writeln!(&mut text, "Command: {command}, arg: {arg}").unwrap();
-        //let _ = serial.write(text.as_bytes());
-        match FromPrimitive::from_u8(command) {
-            Some(CommandVals::Sleep) => Some(if let Some(go_to_sleep) = arg {
-                Command::Sleep(go_to_sleep == 1)
-            } else {
-                Command::IsSleeping
-            }),
-            Some(CommandVals::BootloaderReset) => Some(Command::BootloaderReset),
-            Some(CommandVals::Panic) => Some(Command::Panic),
-            Some(CommandVals::Version) => Some(Command::Version),
-            _ => None, //Some(Command::Unknown),
-        }
+/// Commands that are common to all modules
+const GENERIC_COMMANDS: &[CommandVals] = &[
+    CommandVals::Sleep,
+    CommandVals::BootloaderReset,
+    CommandVals::Panic,
+    CommandVals::Version,
+    CommandVals::AckMode,
+    CommandVals::Capabilities,
+    CommandVals::HwRevision,
+    CommandVals::Settings,
+];
+
+fn check_supported(command: Command) -> Result<Command, ParseError> {
+    let generic = GENERIC_COMMANDS.contains(&command.command_val());
+    if generic || is_module_command(&command) {
+        Ok(command)
     } else {
-        None
+        Err(ParseError::Unsupported(command.command_val()))
     }
 }
 
-#[cfg(feature = "ledmatrix")]
-pub fn parse_module_command(count: usize, buf: &[u8]) -> Option<Command> {
-    if count >= 3 && buf[0] == 0x32 && buf[1] == 0xAC {
-        let command = buf[2];
-        let arg = if count <= 3 { None } else { Some(buf[3]) };
-
-        match FromPrimitive::from_u8(command) {
-            Some(CommandVals::Brightness) => Some(if let Some(brightness) = arg {
-                Command::SetBrightness(brightness)
-            } else {
-                Command::GetBrightness
-            }),
-            Some(CommandVals::Pattern) => match arg.and_then(FromPrimitive::from_u8) {
-                // TODO: Convert arg to PatternVals
-                Some(PatternVals::Percentage) => {
-                    if count >= 5 {
-                        Some(Command::Percentage(buf[4]))
-                    } else {
-                        None
-                    }
-                }
-                Some(PatternVals::Gradient) => Some(Command::Pattern(PatternVals::Gradient)),
-                Some(PatternVals::DoubleGradient) => {
-                    Some(Command::Pattern(PatternVals::DoubleGradient))
-                }
-                Some(PatternVals::DisplayLotus) => {
-                    Some(Command::Pattern(PatternVals::DisplayLotus))
-                }
-                Some(PatternVals::ZigZag) => Some(Command::Pattern(PatternVals::ZigZag)),
-                Some(PatternVals::FullBrightness) => {
-                    Some(Command::Pattern(PatternVals::FullBrightness))
-                }
-                Some(PatternVals::DisplayPanic) => {
-                    Some(Command::Pattern(PatternVals::DisplayPanic))
-                }
-                Some(PatternVals::DisplayLotus2) => {
-                    Some(Command::Pattern(PatternVals::DisplayLotus2))
-                }
-                None => None,
-            },
-            Some(CommandVals::Animate) => Some(if let Some(run_animation) = arg {
-                Command::SetAnimate(run_animation == 1)
-            } else {
-                Command::GetAnimate
-            }),
-            Some(CommandVals::Draw) => {
-                if count >= 3 + DRAW_BYTES {
-                    let mut bytes = [0; DRAW_BYTES];
-                    bytes.clone_from_slice(&buf[3..3 + DRAW_BYTES]);
-                    Some(Command::Draw(bytes))
-                } else {
-                    None
-                }
-            }
-            Some(CommandVals::StageGreyCol) => {
-                if count >= 3 + 1 + HEIGHT {
-                    let mut bytes = [0; HEIGHT];
-                    bytes.clone_from_slice(&buf[4..4 + HEIGHT]);
-                    Some(Command::StageGreyCol(buf[3], bytes))
-                } else {
-                    None
-                }
-            }
-            Some(CommandVals::DrawGreyColBuffer) => Some(Command::DrawGreyColBuffer),
-            Some(CommandVals::StartGame) => match arg.and_then(FromPrimitive::from_u8) {
-                Some(GameVal::Snake) => Some(Command::StartGame(Game::Snake)),
-                Some(GameVal::Pong) => Some(Command::StartGame(Game::Pong)),
-                Some(GameVal::Tetris) => None,
-                Some(GameVal::GameOfLife) => {
-                    if count >= 5 {
-                        FromPrimitive::from_u8(buf[4])
-                            .map(|x| Command::StartGame(Game::GameOfLife(x)))
-                    } else {
-                        None
-                    }
-                }
-                _ => None,
-            },
-            Some(CommandVals::GameControl) => match arg.and_then(FromPrimitive::from_u8) {
-                Some(GameControlArg::Up) => Some(Command::GameControl(GameControlArg::Up)),
-                Some(GameControlArg::Down) => Some(Command::GameControl(GameControlArg::Down)),
-                Some(GameControlArg::Left) => Some(Command::GameControl(GameControlArg::Left)),
-                Some(GameControlArg::Right) => Some(Command::GameControl(GameControlArg::Right)),
-                Some(GameControlArg::Exit) => Some(Command::GameControl(GameControlArg::Exit)),
-                Some(GameControlArg::SecondLeft) => {
-                    Some(Command::GameControl(GameControlArg::SecondLeft))
-                }
-                Some(GameControlArg::SecondRight) => {
-                    Some(Command::GameControl(GameControlArg::SecondRight))
-                }
-                _ => None,
-            },
-            Some(CommandVals::GameStatus) => Some(Command::GameStatus),
-            Some(CommandVals::AnimationPeriod) => {
-                if count == 3 + 2 {
-                    let period = u16::from_le_bytes([buf[3], buf[4]]);
-                    Some(Command::SetAnimationPeriod(period))
-                } else {
-                    Some(Command::GetAnimationPeriod)
-                }
-            }
-            Some(CommandVals::PwmFreq) => {
-                if let Some(freq) = arg {
-                    FromPrimitive::from_u8(freq).map(Command::SetPwmFreq)
-                } else {
-                    Some(Command::GetPwmFreq)
-                }
-            }
-            Some(CommandVals::DebugMode) => Some(if let Some(debug_mode) = arg {
-                Command::SetDebugMode(debug_mode == 1)
-            } else {
-                Command::GetDebugMode
-            }),
-            _ => None,
+/// Protocol state of the current connection to the host
+///
+/// Ack mode is reset when the host closes the serial port (DTR goes low).
+#[derive(Default)]
+pub struct Session {
+    ack_mode: bool,
+    dtr: bool,
+    /// Sequence number of the last command, if it needs to be acknowledged
+    pending_ack: Option<u8>,
+    /// Ack mode of the last command, takes effect if it's acknowledged with Ok
+    pending_ack_mode: Option<bool>,
+}
+
+impl Session {
+    pub fn ack_mode(&self) -> bool {
+        self.ack_mode
+    }
+
+    /// Must be called regularly with the DTR state of the serial port
+    pub fn update_dtr(&mut self, dtr: bool) {
+        if self.dtr && !dtr {
+            self.ack_mode = false;
         }
-    } else {
-        None
+        self.dtr = dtr;
     }
-}
 
-#[cfg(feature = "b1display")]
-pub fn parse_module_command(count: usize, buf: &[u8]) -> Option<Command> {
-    if count >= 3 && buf[0] == 0x32 && buf[1] == 0xAC {
-        let command = buf[2];
-        let arg = if count <= 3 { None } else { Some(buf[3]) };
-
-        match FromPrimitive::from_u8(command) {
-            Some(CommandVals::SetText) => {
-                if let Some(arg) = arg {
-                    let available_len = count - 4;
-                    let str_len = arg as usize;
-                    assert!(str_len <= available_len);
-
-                    assert!(str_len < 32);
-                    let mut bytes = [0; 32];
-                    bytes[..str_len].copy_from_slice(&buf[4..4 + str_len]);
-
-                    let text_str = core::str::from_utf8(&bytes[..str_len]).unwrap();
-                    let mut text: String<64> = String::new();
-                    writeln!(&mut text, "{}", text_str).unwrap();
-
-                    Some(Command::SetText(text))
-                } else {
-                    None
-                }
+    /// Parse a command, taking the sequence number into account in ack mode
+    ///
+    /// Every call must be followed by `ack` or `reject`.
+    pub fn parse_command(&mut self, count: usize, buf: &[u8]) -> Result<Command, ParseError> {
+        let command = if self.ack_mode {
+            let (seq, command) = Command::parse_sequenced(&buf[..count]);
+            self.pending_ack = Some(seq);
+            command
+        } else {
+            self.pending_ack = None;
+            Command::parse(&buf[..count])
+        };
+        let command = check_supported(command?)?;
+
+        self.pending_ack_mode = None;
+        if let Command::SetAckMode(enable) = command {
+            // Enabling is sent without sequence number, ack it with 0
+            if enable && !self.ack_mode {
+                self.pending_ack = Some(0);
             }
-            Some(CommandVals::DisplayOn) => Some(if let Some(on) = arg {
-                Command::DisplayOn(on == 1)
-            } else {
-                Command::GetDisplayOn
-            }),
-            Some(CommandVals::InvertScreen) => Some(if let Some(invert) = arg {
-                Command::InvertScreen(invert == 1)
-            } else {
-                Command::GetInvertScreen
-            }),
-            Some(CommandVals::SetPixelColumn) => {
-                //  3B for magic and command
-                //  2B for column (u16)
-                // 50B for 400 pixels (400/8=50)
-                if count == 3 + 2 + 50 {
-                    let column = u16::from_le_bytes([buf[3], buf[4]]);
-                    //panic!("SetPixelColumn. Col: {}", column);
-                    let mut pixels: [u8; 50] = [0; 50];
-                    pixels.clone_from_slice(&buf[5..55]);
-                    Some(Command::SetPixelColumn(column as usize, pixels))
-                } else {
-                    None
-                }
+            self.pending_ack_mode = Some(enable);
+        }
+        Ok(command)
+    }
+
+    /// Acknowledgement of the last command, if ack mode is enabled
+    ///
+    /// Must be sent before the command's regular response.
+    pub fn ack(&mut self, status: AckStatus) -> Option<Response> {
+        // Otherwise, like when sleeping, the host keeps using the mode it was in
+        if let Some(enable) = self.pending_ack_mode.take() {
+            if status == AckStatus::Ok {
+                self.ack_mode = enable;
             }
-            Some(CommandVals::FlushFramebuffer) => Some(Command::FlushFramebuffer),
-            Some(CommandVals::ClearRam) => Some(Command::ClearRam),
-            Some(CommandVals::ScreenSaver) => Some(if let Some(on) = arg {
-                Command::ScreenSaver(on == 1)
-            } else {
-                Command::GetScreenSaver
-            }),
-            Some(CommandVals::SetFps) => Some(if let Some(fps) = arg {
-                Command::SetFps(fps)
-            } else {
-                Command::GetFps
-            }),
-            Some(CommandVals::SetPowerMode) => Some(if let Some(mode) = arg {
-                Command::SetPowerMode(mode)
-            } else {
-                Command::GetPowerMode
-            }),
-            Some(CommandVals::AnimationPeriod) => {
-                if count == 3 + 2 {
-                    let period = u16::from_le_bytes([buf[3], buf[4]]);
-                    Some(Command::SetAnimationPeriod(period))
-                } else {
-                    Some(Command::GetAnimationPeriod)
-                }
+        }
+        self.pending_ack.take().map(|seq| {
+            Ack {
+                seq,
+                status,
+                error: None,
             }
-            _ => None,
+            .to_response()
+        })
+    }
+
+    /// Response to a command that couldn't be parsed
+    ///
+    /// Only sent in ack mode or if the command came in a frame. Legacy hosts
+    /// don't expect it and would take it for the response of their next getter.
+    pub fn reject(&mut self, err: ParseError, framed: bool) -> Option<Response> {
+        self.pending_ack_mode = None;
+        match self.pending_ack.take() {
+            Some(seq) => Some(Ack::from_error(seq, err).to_response()),
+            None if framed => Some(err.to_response()),
+            None => None,
         }
-    } else {
-        None
     }
 }
 
+/// Commands that only this module supports
+#[cfg(feature = "ledmatrix")]
+const MODULE_COMMANDS: &[CommandVals] = &[
+    CommandVals::Brightness,
+    CommandVals::Pattern,
+    CommandVals::Animate,
+    CommandVals::Draw,
+    CommandVals::StageGreyCol,
+    CommandVals::DrawGreyColBuffer,
+    CommandVals::StartGame,
+    CommandVals::GameControl,
+    CommandVals::GameStatus,
+    CommandVals::AnimationPeriod,
+    CommandVals::PwmFreq,
+    CommandVals::DebugMode,
+    CommandVals::BootAnimation,
+    CommandVals::Sequence,
+];
+
+/// Commands that only this module supports
+#[cfg(feature = "b1display")]
+const MODULE_COMMANDS: &[CommandVals] = &[
+    CommandVals::SetText,
+    CommandVals::DisplayOn,
+    CommandVals::InvertScreen,
+    CommandVals::SetPixelColumn,
+    CommandVals::FlushFramebuffer,
+    CommandVals::ClearRam,
+    CommandVals::ScreenSaver,
+    CommandVals::SetFps,
+    CommandVals::SetPowerMode,
+    CommandVals::AnimationPeriod,
+];
+
+/// Commands that only this module supports
+#[cfg(feature = "c1minimal")]
+const MODULE_COMMANDS: &[CommandVals] = &[CommandVals::Brightness, CommandVals::SetColor];
+
 #[cfg(not(any(feature = "ledmatrix", feature = "b1display", feature = "c1minimal")))]
-pub fn parse_module_command(_count: usize, _buf: &[u8]) -> Option<Command> {
-    None
+const MODULE_COMMANDS: &[CommandVals] = &[];
+
+/// Whether the command is supported by this module
+pub fn is_module_command(command: &Command) -> bool {
+    MODULE_COMMANDS.contains(&command.command_val())
+}
+
+/// Module type, display geometry in pixels and bits per pixel
+#[cfg(feature = "ledmatrix")]
+const DISPLAY: (ModuleType, u16, u16, u8) = (ModuleType::LedMatrix, WIDTH as u16, HEIGHT as u16, 8);
+#[cfg(feature = "b1display")]
+const DISPLAY: (ModuleType, u16, u16, u8) = (ModuleType::B1Display, 300, 400, 1);
+// Single RGB LED
+#[cfg(feature = "c1minimal")]
+const DISPLAY: (ModuleType, u16, u16, u8) = (ModuleType::C1Minimal, 1, 1, 24);
+
+/// Response to GetCapabilities
+#[cfg(any(feature = "ledmatrix", feature = "b1display", feature = "c1minimal"))]
+pub fn capabilities() -> Capabilities {
+    let (module, width, height, pixel_depth) = DISPLAY;
+    Capabilities {
+        module,
+        hw_revision: get_hw_revision().unwrap_or(0),
+        version: version(),
+        width,
+        height,
+        pixel_depth,
+        commands: Capabilities::command_bitmap(GENERIC_COMMANDS)
+            | Capabilities::command_bitmap(MODULE_COMMANDS),
+    }
 }
 
-pub fn handle_generic_command(command: &Command) -> Option<[u8; 32]> {
+/// Settings that are persisted with SettingsArg::Save
+#[cfg(feature = "ledmatrix")]
+const PERSISTENT_SETTINGS: [CommandVals; 4] = [
+    CommandVals::Brightness,
+    CommandVals::PwmFreq,
+    CommandVals::AnimationPeriod,
+    CommandVals::DebugMode,
+];
+#[cfg(feature = "b1display")]
+const PERSISTENT_SETTINGS: [CommandVals; 3] = [
+    CommandVals::SetFps,
+    CommandVals::SetPowerMode,
+    CommandVals::AnimationPeriod,
+];
+#[cfg(feature = "c1minimal")]
+const PERSISTENT_SETTINGS: [CommandVals; 2] = [CommandVals::Brightness, CommandVals::SetColor];
+
+/// Commands to restore the current state of PERSISTENT_SETTINGS
+#[cfg(feature = "ledmatrix")]
+fn current_settings(state: &LedmatrixState) -> [Command; 4] {
+    [
+        Command::SetBrightness(state.brightness),
+        Command::SetPwmFreq(state.pwm_freq),
+        Command::SetAnimationPeriod((state.animation_period / 1_000) as u16),
+        Command::SetDebugMode(state.debug_mode),
+    ]
+}
+#[cfg(feature = "b1display")]
+fn current_settings(state: &B1DIsplayState) -> [Command; 3] {
+    [
+        Command::SetFps(state.fps_config.as_u8()),
+        Command::SetPowerMode(match state.power_mode {
+            PowerMode::Lpm => 0,
+            PowerMode::Hpm => 1,
+        }),
+        Command::SetAnimationPeriod((state.animation_period / 1_000) as u16),
+    ]
+}
+#[cfg(feature = "c1minimal")]
+fn current_settings(state: &C1MinimalState) -> [Command; 2] {
+    [
+        Command::SetBrightness(state.brightness),
+        Command::SetColor(Rgb::new(state.color.r, state.color.g, state.color.b)),
+    ]
+}
+
+fn version() -> response::Version {
+    response::Version {
+        bcd_device: device_release(),
+        pre_release: is_pre_release(),
+    }
+}
+
+pub fn handle_generic_command(command: &Command) -> Option<Response> {
     match command {
         Command::BootloaderReset => {
             //let _ = serial.write("Bootloader Reset".as_bytes());
@@ -496,33 +350,50 @@ pub fn handle_generic_command(command: &Command) -> Option<[u8; 32]> {
             None
         }
         Command::Panic => panic!("Ahhh"),
-        Command::Version => {
-            let mut response: [u8; 32] = [0; 32];
-            let bcd_device = device_release().to_be_bytes();
-            response[0] = bcd_device[0];
-            response[1] = bcd_device[1];
-            response[2] = is_pre_release() as u8;
-            Some(response)
-        }
+        Command::Version => Some(version().to_response()),
+        #[cfg(any(feature = "ledmatrix", feature = "b1display", feature = "c1minimal"))]
+        Command::GetCapabilities => Some(capabilities().to_response()),
+        Command::GetHwRevision => Some(response::encode_u8(get_hw_revision().unwrap_or(0))),
         _ => None,
     }
 }
 
+/// Whether the command leaves a running animation alone
+///
+/// None of these change what's displayed. Some are sent by the host whenever
+/// it connects, which shouldn't stop a sequence that's playing.
+#[cfg(feature = "ledmatrix")]
+pub fn keeps_animation(command: &Command) -> bool {
+    matches!(
+        command,
+        Command::SetAckMode(_)
+            | Command::GetCapabilities
+            | Command::GetHwRevision
+            | Command::Version
+            | Command::IsSleeping
+            | Command::GetBrightness
+            | Command::SetBrightness(_)
+            | Command::GetAnimate
+            | Command::GetAnimationPeriod
+            | Command::GetPwmFreq
+            | Command::GetDebugMode
+    )
+}
+
+/// Fails with the status to acknowledge the command with, if it can't be done.
+/// Like adding a frame to a full sequence.
 #[cfg(feature = "ledmatrix")]
 pub fn handle_command(
     command: &Command,
     state: &mut LedmatrixState,
     matrix: &mut Foo,
     random: u8,
-) -> Option<[u8; 32]> {
+    store: &mut SettingsStore,
+) -> Result<Option<Response>, AckStatus> {
     use crate::games::game_of_life;
 
-    match command {
-        Command::GetBrightness => {
-            let mut response: [u8; 32] = [0; 32];
-            response[0] = state.brightness;
-            Some(response)
-        }
+    let response = match command {
+        Command::GetBrightness => Some(response::encode_u8(state.brightness)),
         Command::SetBrightness(br) => {
             //let _ = serial.write("Brightness".as_bytes());
             set_brightness(state, *br, matrix);
@@ -554,11 +425,7 @@ pub fn handle_command(
             state.animate = *a;
             None
         }
-        Command::GetAnimate => {
-            let mut response: [u8; 32] = [0; 32];
-            response[0] = state.animate as u8;
-            Some(response)
-        }
+        Command::GetAnimate => Some(response::encode_bool(state.animate)),
         Command::Draw(vals) => {
             state.grid = draw(vals);
             None
@@ -574,20 +441,66 @@ pub fn handle_command(
             state.col_buffer = percentage(0);
             None
         }
-        // TODO: Move to handle_generic_command
-        Command::IsSleeping => {
-            let mut response: [u8; 32] = [0; 32];
-            response[0] = match state.sleeping {
-                SleepState::Sleeping(_) => 1,
-                SleepState::Awake => 0,
+        Command::BootAnimation(BootAnimationArg::Begin) => {
+            state.boot_animation_upload = Some(boot_animation::Upload::begin());
+            None
+        }
+        Command::BootAnimation(BootAnimationArg::AddFrame(duration)) => {
+            // Frames are staged just like for DrawGreyColBuffer
+            let frame = core::mem::replace(&mut state.col_buffer, percentage(0));
+            let Some(upload) = &mut state.boot_animation_upload else {
+                return Err(AckStatus::BadArgs);
+            };
+            // Can hold at most MAX_BOOT_ANIMATION_FRAMES
+            if !upload.add_frame(&frame, *duration) {
+                return Err(AckStatus::BadArgs);
+            }
+            None
+        }
+        Command::BootAnimation(BootAnimationArg::Commit) => {
+            let Some(upload) = state.boot_animation_upload.take() else {
+                return Err(AckStatus::BadArgs);
             };
-            Some(response)
+            upload.commit();
+            None
+        }
+        Command::BootAnimation(BootAnimationArg::Clear) => {
+            state.boot_animation_upload = None;
+            boot_animation::clear();
+            None
+        }
+        Command::Sequence(SequenceArg::Begin) => {
+            state.sequence.clear();
+            None
+        }
+        Command::Sequence(SequenceArg::AddFrame(duration)) => {
+            let frame = core::mem::replace(&mut state.col_buffer, percentage(0));
+            // Can hold at most MAX_SEQUENCE_FRAMES
+            if state.sequence.push((frame, *duration)).is_err() {
+                return Err(AckStatus::BadArgs);
+            }
+            None
+        }
+        Command::Sequence(SequenceArg::Play(loops)) => {
+            // Keep the frames, so they can be played again
+            let frames = state.sequence.clone();
+            state.upcoming_frames = Some(Animation::Sequence(SequenceIterator::new(
+                frames,
+                *loops,
+                state.animation_period,
+            )));
+            None
         }
+        // TODO: Move to handle_generic_command
+        Command::IsSleeping => Some(response::encode_bool(matches!(
+            state.sleeping,
+            SleepState::Sleeping(_)
+        ))),
         Command::StartGame(game) => {
             match game {
                 Game::Snake => snake::start_game(state, random),
-                Game::Pong => pong::start_game(state, random),
-                Game::Tetris => {}
+                Game::Pong(param) => pong::start_game(state, random, *param),
+                Game::Tetris => tetris::start_game(state, random),
                 Game::GameOfLife(param) => game_of_life::start_game(state, random, *param),
             }
             None
@@ -596,44 +509,57 @@ pub fn handle_command(
             match state.game {
                 Some(GameState::Snake(_)) => snake::handle_control(state, arg),
                 Some(GameState::Pong(_)) => pong::handle_control(state, arg),
+                Some(GameState::Tetris(_)) => tetris::handle_control(state, arg),
                 Some(GameState::GameOfLife(_)) => game_of_life::handle_control(state, arg),
                 _ => {}
             }
             None
         }
-        Command::GameStatus => None,
+        Command::GameStatus => {
+            let status = state.game.as_ref().map(GameState::status);
+            Some(status.unwrap_or_default().to_response())
+        }
         Command::SetAnimationPeriod(period) => {
             state.animation_period = (*period as u64) * 1_000;
             None
         }
         Command::GetAnimationPeriod => {
             // TODO: Doesn't seem to work when the FPS is 16 or higher
-            let mut response: [u8; 32] = [0; 32];
             let period_ms = state.animation_period / 1_000;
-            response[0..2].copy_from_slice(&(period_ms as u16).to_le_bytes());
-            Some(response)
+            Some(response::encode_u16(period_ms as u16))
         }
         Command::SetPwmFreq(arg) => {
             state.pwm_freq = *arg;
-            matrix.device.set_pwm_freq(state.pwm_freq.into()).unwrap();
+            matrix
+                .device
+                .set_pwm_freq(to_pwm_freq(state.pwm_freq))
+                .unwrap();
             None
         }
-        Command::GetPwmFreq => {
-            let mut response: [u8; 32] = [0; 32];
-            response[0] = state.pwm_freq as u8;
-            Some(response)
-        }
+        Command::GetPwmFreq => Some(response::encode_u8(state.pwm_freq as u8)),
         Command::SetDebugMode(arg) => {
             state.debug_mode = *arg;
             None
         }
-        Command::GetDebugMode => {
-            let mut response: [u8; 32] = [0; 32];
-            response[0] = state.debug_mode as u8;
-            Some(response)
+        Command::GetDebugMode => Some(response::encode_bool(state.debug_mode)),
+        Command::Settings(SettingsArg::Save) => {
+            // Can't fail to fit, there are only a few small settings
+            let _ = settings::save(store, &current_settings(state));
+            None
+        }
+        Command::Settings(SettingsArg::Load) => {
+            for command in settings::load(store, &PERSISTENT_SETTINGS) {
+                let _ = handle_command(&command, state, matrix, random, store);
+            }
+            None
+        }
+        Command::Settings(SettingsArg::FactoryReset) => {
+            store.clear();
+            None
         }
         _ => handle_generic_command(command),
-    }
+    };
+    Ok(response)
 }
 
 #[cfg(feature = "b1display")]
@@ -643,7 +569,8 @@ pub fn handle_command<SPI, DC, CS, RST, const COLS: usize, const ROWS: usize>(
     logo_rect: Rectangle,
     disp: &mut ST7306<SPI, DC, CS, RST, COLS, ROWS>,
     delay: &mut Delay,
-) -> Option<[u8; 32]>
+    store: &mut SettingsStore,
+) -> Option<Response>
 where
     SPI: spi::Write<u8>,
     DC: OutputPin,
@@ -653,14 +580,10 @@ where
 {
     match command {
         // TODO: Move to handle_generic_command
-        Command::IsSleeping => {
-            let mut response: [u8; 32] = [0; 32];
-            response[0] = match state.sleeping {
-                SimpleSleepState::Sleeping => 1,
-                SimpleSleepState::Awake => 0,
-            };
-            Some(response)
-        }
+        Command::IsSleeping => Some(response::encode_bool(matches!(
+            state.sleeping,
+            SimpleSleepState::Sleeping
+        ))),
         Command::Panic => panic!("Ahhh"),
         Command::SetText(text) => {
             // Turn screensaver off, when drawing something
@@ -687,21 +610,13 @@ where
             disp.on_off(*on).unwrap();
             None
         }
-        Command::GetDisplayOn => {
-            let mut response: [u8; 32] = [0; 32];
-            response[0] = state.screen_on as u8;
-            Some(response)
-        }
+        Command::GetDisplayOn => Some(response::encode_bool(state.screen_on)),
         Command::InvertScreen(invert) => {
             state.screen_inverted = *invert;
             disp.invert_screen(state.screen_inverted).unwrap();
             None
         }
-        Command::GetInvertScreen => {
-            let mut response: [u8; 32] = [0; 32];
-            response[0] = state.screen_inverted as u8;
-            Some(response)
-        }
+        Command::GetInvertScreen => Some(response::encode_bool(state.screen_inverted)),
         Command::SetPixelColumn(column, pixel_bytes) => {
             // Turn screensaver off, when drawing something
             state.screensaver = None;
@@ -749,11 +664,7 @@ where
             };
             None
         }
-        Command::GetScreenSaver => {
-            let mut response: [u8; 32] = [0; 32];
-            response[0] = state.screensaver.is_some() as u8;
-            Some(response)
-        }
+        Command::GetScreenSaver => Some(response::encode_bool(state.screensaver.is_some())),
         Command::SetFps(fps) => {
             if let Some(fps_config) = FpsConfig::from_u8(*fps) {
                 state.fps_config = fps_config;
@@ -762,11 +673,7 @@ where
             }
             None
         }
-        Command::GetFps => {
-            let mut response: [u8; 32] = [0; 32];
-            response[0] = state.fps_config.as_u8();
-            Some(response)
-        }
+        Command::GetFps => Some(response::encode_u8(state.fps_config.as_u8())),
         Command::SetPowerMode(mode) => {
             match mode {
                 0 => {
@@ -781,24 +688,33 @@ where
             }
             None
         }
-        Command::GetPowerMode => {
-            let mut response: [u8; 32] = [0; 32];
-            response[0] = match state.power_mode {
-                PowerMode::Lpm => 0,
-                PowerMode::Hpm => 1,
-            };
-            Some(response)
-        }
+        Command::GetPowerMode => Some(response::encode_u8(match state.power_mode {
+            PowerMode::Lpm => 0,
+            PowerMode::Hpm => 1,
+        })),
         Command::SetAnimationPeriod(period) => {
             state.animation_period = (*period as u64) * 1_000;
             None
         }
         Command::GetAnimationPeriod => {
             // TODO: Doesn't seem to work when the FPS is 16 or higher
-            let mut response: [u8; 32] = [0; 32];
             let period_ms = state.animation_period / 1_000;
-            response[0..2].copy_from_slice(&(period_ms as u16).to_le_bytes());
-            Some(response)
+            Some(response::encode_u16(period_ms as u16))
+        }
+        Command::Settings(SettingsArg::Save) => {
+            // Can't fail to fit, there are only a few small settings
+            let _ = settings::save(store, &current_settings(state));
+            None
+        }
+        Command::Settings(SettingsArg::Load) => {
+            for command in settings::load(store, &PERSISTENT_SETTINGS) {
+                handle_command(&command, state, logo_rect, disp, delay, store);
+            }
+            None
+        }
+        Command::Settings(SettingsArg::FactoryReset) => {
+            store.clear();
+            None
         }
         _ => handle_generic_command(command),
     }
@@ -809,22 +725,15 @@ pub fn handle_command(
     command: &Command,
     state: &mut C1MinimalState,
     ws2812: &mut impl SmartLedsWrite<Color = RGB8, Error = ()>,
-) -> Option<[u8; 32]> {
+    store: &mut SettingsStore,
+) -> Option<Response> {
     match command {
         // TODO: Move to handle_generic_command
-        Command::IsSleeping => {
-            let mut response: [u8; 32] = [0; 32];
-            response[0] = match state.sleeping {
-                SimpleSleepState::Sleeping => 1,
-                SimpleSleepState::Awake => 0,
-            };
-            Some(response)
-        }
-        Command::GetBrightness => {
-            let mut response: [u8; 32] = [0; 32];
-            response[0] = state.brightness;
-            Some(response)
-        }
+        Command::IsSleeping => Some(response::encode_bool(matches!(
+            state.sleeping,
+            SimpleSleepState::Sleeping
+        ))),
+        Command::GetBrightness => Some(response::encode_u8(state.brightness)),
         Command::SetBrightness(br) => {
             //let _ = serial.write("Brightness".as_bytes());
             state.brightness = *br;
@@ -837,52 +746,118 @@ pub fn handle_command(
             None
         }
         Command::GetColor => {
-            let mut response: [u8; 32] = [0; 32];
-            response[0] = state.color.r;
-            response[1] = state.color.g;
-            response[2] = state.color.b;
-            Some(response)
+            Some(Rgb::new(state.color.r, state.color.g, state.color.b).to_response())
         }
         Command::SetColor(color) => {
-            state.color = *color;
+            state.color = RGB8::new(color.r, color.g, color.b);
             ws2812
                 .write(smart_leds::brightness(
-                    [*color].iter().cloned(),
+                    [state.color].iter().cloned(),
                     state.brightness,
                 ))
                 .unwrap();
             None
         }
+        Command::Settings(SettingsArg::Save) => {
+            // Can't fail to fit, there are only a few small settings
+            let _ = settings::save(store, &current_settings(state));
+            None
+        }
+        Command::Settings(SettingsArg::Load) => {
+            for command in settings::load(store, &PERSISTENT_SETTINGS) {
+                handle_command(&command, state, ws2812, store);
+            }
+            None
+        }
+        Command::Settings(SettingsArg::FactoryReset) => {
+            store.clear();
+            None
+        }
         // TODO: Make it return something
         _ => handle_generic_command(command),
     }
 }
 
-#[cfg(feature = "c1minimal")]
-pub fn parse_module_command(count: usize, buf: &[u8]) -> Option<Command> {
-    if count >= 3 && buf[0] == 0x32 && buf[1] == 0xAC {
-        let command = buf[2];
-        let arg = if count <= 3 { None } else { Some(buf[3]) };
-
-        match FromPrimitive::from_u8(command) {
-            Some(CommandVals::Brightness) => Some(if let Some(brightness) = arg {
-                Command::SetBrightness(brightness)
-            } else {
-                Command::GetBrightness
-            }),
-            Some(CommandVals::SetColor) => {
-                if count >= 6 {
-                    let (red, green, blue) = (buf[3], buf[4], buf[5]);
-                    Some(Command::SetColor(RGB8::new(red, green, blue)))
-                } else if arg.is_none() {
-                    Some(Command::GetColor)
-                } else {
-                    None
-                }
-            }
-            _ => None,
-        }
-    } else {
-        None
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use inputmodule_protocol::MAX_COMMAND_LEN;
+
+    /// Parse the command as it's sent without or with sequence number
+    fn parse(session: &mut Session, command: Command, seq: Option<u8>) -> Command {
+        let mut buf = [0; MAX_COMMAND_LEN + 1];
+        let len = match seq {
+            Some(seq) => command.encode_sequenced(seq, &mut buf),
+            None => command.encode(&mut buf),
+        };
+        session.parse_command(len, &buf).unwrap()
+    }
+
+    #[test]
+    fn enable_ack_mode_while_sleeping() {
+        let mut session = Session::default();
+        session.update_dtr(true);
+
+        // Module is sleeping and doesn't handle it
+        let enable = Command::SetAckMode(true);
+        assert_eq!(parse(&mut session, enable.clone(), None), enable);
+        let ack = Ack::from_response(&session.ack(AckStatus::Sleeping).unwrap()).unwrap();
+        assert_eq!((ack.seq, ack.status), (0, AckStatus::Sleeping));
+        assert!(!session.ack_mode());
+
+        // So the host wakes it up without sequence number
+        let wake = Command::Sleep(false);
+        assert_eq!(parse(&mut session, wake.clone(), None), wake);
+        assert_eq!(session.ack(AckStatus::Ok), None);
+
+        // Now it's handled
+        assert_eq!(parse(&mut session, enable.clone(), None), enable);
+        let ack = Ack::from_response(&session.ack(AckStatus::Ok).unwrap()).unwrap();
+        assert_eq!((ack.seq, ack.status), (0, AckStatus::Ok));
+        assert!(session.ack_mode());
+        assert_eq!(parse(&mut session, wake.clone(), Some(1)), wake);
+        let ack = Ack::from_response(&session.ack(AckStatus::Ok).unwrap()).unwrap();
+        assert_eq!(ack.seq, 1);
+    }
+
+    #[test]
+    fn disable_ack_mode_while_sleeping() {
+        let mut session = Session::default();
+        session.update_dtr(true);
+        parse(&mut session, Command::SetAckMode(true), None);
+        session.ack(AckStatus::Ok);
+
+        // Host still expects acks after it's rejected
+        let disable = Command::SetAckMode(false);
+        assert_eq!(parse(&mut session, disable.clone(), Some(1)), disable);
+        assert!(session.ack(AckStatus::Sleeping).is_some());
+        assert!(session.ack_mode());
+
+        assert_eq!(parse(&mut session, disable.clone(), Some(2)), disable);
+        assert!(session.ack(AckStatus::Ok).is_some());
+        assert!(!session.ack_mode());
+    }
+
+    #[test]
+    fn errors_only_in_ack_mode_or_frames() {
+        let mut session = Session::default();
+        session.update_dtr(true);
+        let unknown = [0x32, 0xAC, 0xF0];
+
+        // Legacy hosts would take it for the response of their next getter
+        let err = session.parse_command(unknown.len(), &unknown).unwrap_err();
+        assert_eq!(err, ParseError::UnknownCommand(0xF0));
+        assert_eq!(session.reject(err, false), None);
+        let err = session.parse_command(unknown.len(), &unknown).unwrap_err();
+        assert_eq!(session.reject(err, true), Some(err.to_response()));
+
+        parse(&mut session, Command::SetAckMode(true), None);
+        session.ack(AckStatus::Ok);
+        let sequenced = [0x32, 0xAC, 0xF0, 1];
+        let err = session
+            .parse_command(sequenced.len(), &sequenced)
+            .unwrap_err();
+        let ack = Ack::from_response(&session.reject(err, false).unwrap()).unwrap();
+        assert_eq!((ack.seq, ack.error), (1, Some(err)));
     }
 }
@@ -6,7 +6,7 @@ use embedded_hal::blocking::delay::DelayMs;
 use embedded_hal::blocking::i2c::Read;
 use embedded_hal::blocking::i2c::Write;
 #[allow(unused_imports)]
-use is31fl3741::{Error, IS31FL3741};
+use is31fl3741::{Error, SwSetting, IS31FL3741};
 
 pub const EVT_CALC_PIXEL: fn(x: u8, y: u8) -> (u8, u8) = |x: u8, y: u8| -> (u8, u8) {
     // Generated by led-matrix.py
@@ -643,6 +643,51 @@ pub const DVT2_CALC_PIXEL: fn(x: u8, y: u8) -> (u8, u8) = |x: u8, y: u8| -> (u8,
     }
 };
 
+/// Differences between hardware revisions of the LED matrix
+#[derive(Copy, Clone)]
+pub struct HwConfig {
+    /// EVT boards have a different LED mapping than DVT2
+    pub evt: bool,
+    /// Maximum brightness out of 255, depends on the current limiting resistor
+    pub max_brightness: u8,
+}
+
+impl HwConfig {
+    /// Configuration of a known hardware revision, see flash_layout.md
+    pub fn from_revision(revision: u8) -> Option<Self> {
+        match revision {
+            // 10k resistor, 94/255 is just below 500mA current draw
+            1 | 2 => Some(Self {
+                evt: true,
+                max_brightness: 94,
+            }),
+            // 27k resistor, 50/255 results in 160mA current draw
+            3 => Some(Self {
+                evt: true,
+                max_brightness: 50,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn calc_pixel(&self) -> fn(x: u8, y: u8) -> (u8, u8) {
+        if self.evt {
+            EVT_CALC_PIXEL
+        } else {
+            DVT2_CALC_PIXEL
+        }
+    }
+
+    /// EVT uses SW1-SW9, DVT only SW1-SW8
+    pub fn sw_setting(&self) -> SwSetting {
+        if self.evt {
+            SwSetting::Sw1Sw9
+        } else {
+            SwSetting::Sw1Sw8
+        }
+    }
+}
+
 pub struct LedMatrix<I2C> {
     pub device: IS31FL3741<I2C>,
 }
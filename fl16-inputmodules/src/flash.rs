@@ -0,0 +1,146 @@
+//! Regions of the RP2040's flash that the firmware writes to
+//!
+//! See flash_layout.md for where they are. On the host, flash is simulated in
+//! RAM and starts out erased.
+#[cfg(feature = "rp2040")]
+use rp2040_hal::rom_data;
+#[cfg(not(feature = "rp2040"))]
+use std::sync::{Mutex, MutexGuard};
+
+pub use inputmodule_settings::{Flash, SECTOR_SIZE};
+
+/// Flash is mapped into memory here
+#[cfg(feature = "rp2040")]
+const XIP_BASE: usize = 0x1000_0000;
+
+/// Flash can only be programmed in whole pages
+#[cfg(feature = "rp2040")]
+const PAGE_SIZE: usize = 256;
+/// Size of the second stage bootloader, at the start of flash
+#[cfg(feature = "rp2040")]
+const BOOT2_SIZE: usize = 256;
+
+/// Same size as the flash that the firmware uses
+#[cfg(not(feature = "rp2040"))]
+const SIM_FLASH_SIZE: usize = 0x100000;
+
+#[cfg(not(feature = "rp2040"))]
+static SIM_FLASH: Mutex<Vec<u8>> = Mutex::new(Vec::new());
+
+#[cfg(not(feature = "rp2040"))]
+fn sim_flash() -> MutexGuard<'static, Vec<u8>> {
+    let mut flash = SIM_FLASH.lock().unwrap();
+    if flash.is_empty() {
+        flash.resize(SIM_FLASH_SIZE, 0xFF);
+    }
+    flash
+}
+
+/// Region of the RP2040's flash
+pub struct RpFlash {
+    /// Start of the region, relative to the start of flash
+    offset: usize,
+    size: usize,
+}
+
+impl RpFlash {
+    /// Both must be multiples of SECTOR_SIZE
+    pub const fn new(offset: usize, size: usize) -> Self {
+        Self { offset, size }
+    }
+}
+
+#[cfg(feature = "rp2040")]
+impl Flash for RpFlash {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        let ptr = (XIP_BASE + self.offset + offset) as *const u8;
+        let data = unsafe { core::slice::from_raw_parts(ptr, buf.len()) };
+        buf.copy_from_slice(data);
+    }
+
+    fn erase_sector(&mut self, offset: usize) {
+        cortex_m::interrupt::free(|_| unsafe {
+            flash_op(self.offset + offset, None);
+        });
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) {
+        let mut addr = self.offset + offset;
+        let mut data = data;
+        while !data.is_empty() {
+            // Bytes programmed to 0xFF stay unchanged
+            let mut page = [0xFF; PAGE_SIZE];
+            let page_start = addr - addr % PAGE_SIZE;
+            let len = data.len().min(page_start + PAGE_SIZE - addr);
+            page[addr - page_start..][..len].copy_from_slice(&data[..len]);
+            cortex_m::interrupt::free(|_| unsafe {
+                flash_op(page_start, Some(&page));
+            });
+            addr += len;
+            data = &data[len..];
+        }
+    }
+}
+
+#[cfg(not(feature = "rp2040"))]
+impl Flash for RpFlash {
+    fn size(&self) -> usize {
+        self.size
+    }
+
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        buf.copy_from_slice(&sim_flash()[self.offset + offset..][..buf.len()]);
+    }
+
+    fn erase_sector(&mut self, offset: usize) {
+        sim_flash()[self.offset + offset..][..SECTOR_SIZE].fill(0xFF);
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) {
+        let mut flash = sim_flash();
+        let bytes = flash[self.offset + offset..][..data.len()].iter_mut();
+        for (byte, new) in bytes.zip(data) {
+            *byte &= new;
+        }
+    }
+}
+
+/// Erase the sector at addr, or program the page at addr if data is given
+///
+/// Runs from RAM, because flash can't be read while it's being written.
+/// All ROM functions are looked up beforehand, for the same reason.
+///
+/// # Safety
+///
+/// Interrupts must be disabled and the other core must not access flash.
+#[cfg(feature = "rp2040")]
+#[inline(never)]
+#[link_section = ".data.ram_func"]
+unsafe fn flash_op(addr: usize, data: Option<&[u8; PAGE_SIZE]>) {
+    let connect_internal_flash = rom_data::connect_internal_flash::ptr();
+    let flash_exit_xip = rom_data::flash_exit_xip::ptr();
+    let flash_range_erase = rom_data::flash_range_erase::ptr();
+    let flash_range_program = rom_data::flash_range_program::ptr();
+    let flash_flush_cache = rom_data::flash_flush_cache::ptr();
+
+    // Afterwards boot2 restores the fast XIP configuration.
+    // Must be copied to RAM, because flash isn't mapped while writing.
+    let mut boot2 = [0u32; BOOT2_SIZE / 4];
+    core::ptr::copy_nonoverlapping(XIP_BASE as *const u32, boot2.as_mut_ptr(), boot2.len());
+    // Thumb code, the lowest bit must be set
+    let boot2: extern "C" fn() = core::mem::transmute(boot2.as_ptr() as usize + 1);
+
+    connect_internal_flash();
+    flash_exit_xip();
+    match data {
+        // 0x20 is the command to erase a single 4K sector
+        None => flash_range_erase(addr as u32, SECTOR_SIZE, SECTOR_SIZE as u32, 0x20),
+        Some(data) => flash_range_program(addr as u32, data.as_ptr(), data.len()),
+    }
+    flash_flush_cache();
+    boot2();
+}
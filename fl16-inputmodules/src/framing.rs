@@ -0,0 +1,218 @@
+//! Reassembly of commands received over the serial port
+//!
+//! Framed commands (see `inputmodule_protocol::frame`) can be split across or
+//! coalesced into USB reads. The decoder buffers them until they're complete.
+//!
+//! Unframed commands are still accepted for compatibility. Like before, a read
+//! that starts with the command magic is taken as exactly one command.
+use heapless::Vec;
+
+use inputmodule_protocol::frame::{checksum, FRAME_MAGIC, FRAME_OVERHEAD, MAX_PAYLOAD_LEN};
+use inputmodule_protocol::{ParseError, MAGIC};
+
+/// A single command, including magic bytes
+pub type Packet = Vec<u8, MAX_PAYLOAD_LEN>;
+
+/// Big enough for an incomplete frame and a full USB packet
+const BUF_LEN: usize = 2 * (MAX_PAYLOAD_LEN + FRAME_OVERHEAD);
+
+#[derive(Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8, BUF_LEN>,
+    /// Unframed command, waiting to be picked up
+    raw: Option<Packet>,
+    /// Whether the last packet came in a frame
+    framed: bool,
+}
+
+impl FrameDecoder {
+    /// Add the bytes of a single serial read
+    ///
+    /// Must be followed by calling `next_packet` until it returns None.
+    pub fn push(&mut self, data: &[u8]) {
+        // Legacy unframed command, unless in the middle of a frame
+        if !self.buf.starts_with(&FRAME_MAGIC) && data.starts_with(&MAGIC) {
+            self.buf.clear();
+            let len = data.len().min(MAX_PAYLOAD_LEN);
+            self.raw = Vec::from_slice(&data[..len]).ok();
+            return;
+        }
+
+        for byte in data {
+            if self.buf.is_full() {
+                // Can't be part of a valid frame anymore, resync on the next magic
+                self.consume(1);
+            }
+            // Can't fail, just made room
+            let _ = self.buf.push(*byte);
+        }
+    }
+
+    /// Get the next complete command, or an error if a frame was corrupted
+    pub fn next_packet(&mut self) -> Option<Result<Packet, ParseError>> {
+        if let Some(raw) = self.raw.take() {
+            self.framed = false;
+            return Some(Ok(raw));
+        }
+
+        loop {
+            // Skip garbage until the start of a frame
+            let start = self
+                .buf
+                .windows(2)
+                .position(|window| window == FRAME_MAGIC)
+                .unwrap_or(self.buf.len().saturating_sub(1));
+            self.consume(start);
+
+            if self.buf.len() < 3 {
+                return None;
+            }
+            let len = self.buf[2] as usize;
+            if len > MAX_PAYLOAD_LEN {
+                // Not a real frame, just happened to contain the magic
+                self.consume(1);
+                continue;
+            }
+            if self.buf.len() < len + FRAME_OVERHEAD {
+                // Wait for the rest
+                return None;
+            }
+
+            let payload = &self.buf[3..3 + len];
+            let crc = u16::from_le_bytes([self.buf[3 + len], self.buf[4 + len]]);
+            let packet = if crc == checksum(payload) {
+                // Can't fail, len is at most MAX_PAYLOAD_LEN
+                Ok(Vec::from_slice(payload).unwrap_or_default())
+            } else {
+                Err(ParseError::Checksum)
+            };
+            self.consume(len + FRAME_OVERHEAD);
+            self.framed = true;
+            return Some(packet);
+        }
+    }
+
+    /// Whether the last packet from `next_packet` came in a frame
+    ///
+    /// Errors are only reported for those, legacy hosts don't expect them.
+    pub fn framed(&self) -> bool {
+        self.framed
+    }
+
+    fn consume(&mut self, count: usize) {
+        let remaining = self.buf.len() - count;
+        self.buf.copy_within(count.., 0);
+        self.buf.truncate(remaining);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use inputmodule_protocol::frame::{encode, MAX_FRAME_LEN};
+    use inputmodule_protocol::{Command, MAX_COMMAND_LEN};
+
+    fn command(command: Command) -> Packet {
+        let mut buf = [0; MAX_COMMAND_LEN];
+        let len = command.encode(&mut buf);
+        Vec::from_slice(&buf[..len]).unwrap()
+    }
+
+    fn frame(payload: &[u8]) -> Vec<u8, MAX_FRAME_LEN> {
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = encode(payload, &mut buf);
+        Vec::from_slice(&buf[..len]).unwrap()
+    }
+
+    #[test]
+    fn frame_split_across_reads() {
+        let brightness = command(Command::SetBrightness(0x50));
+        let frame = frame(&brightness);
+        let mut decoder = FrameDecoder::default();
+
+        for byte in &frame[..frame.len() - 1] {
+            decoder.push(&[*byte]);
+            assert_eq!(decoder.next_packet(), None);
+        }
+        decoder.push(&frame[frame.len() - 1..]);
+        assert_eq!(decoder.next_packet(), Some(Ok(brightness)));
+        assert_eq!(decoder.next_packet(), None);
+    }
+
+    #[test]
+    fn frames_in_one_read() {
+        let brightness = command(Command::SetBrightness(0x50));
+        let sleep = command(Command::Sleep(true));
+        let mut data: Vec<u8, BUF_LEN> = Vec::new();
+        data.extend_from_slice(&frame(&brightness)).unwrap();
+        data.extend_from_slice(&frame(&sleep)).unwrap();
+        // Beginning of a third one
+        data.extend_from_slice(&frame(&brightness)[..4]).unwrap();
+
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&data);
+        assert_eq!(decoder.next_packet(), Some(Ok(brightness.clone())));
+        assert_eq!(decoder.next_packet(), Some(Ok(sleep)));
+        assert_eq!(decoder.next_packet(), None);
+
+        decoder.push(&frame(&brightness)[4..]);
+        assert_eq!(decoder.next_packet(), Some(Ok(brightness)));
+        assert_eq!(decoder.next_packet(), None);
+    }
+
+    #[test]
+    fn resync_after_garbage() {
+        let brightness = command(Command::SetBrightness(0x50));
+        let mut decoder = FrameDecoder::default();
+
+        // Including a magic with a length that's too long for a frame
+        decoder.push(&[0x00, 0xFF, FRAME_MAGIC[0], FRAME_MAGIC[1], 0xFF, 0x12]);
+        assert_eq!(decoder.next_packet(), None);
+        decoder.push(&frame(&brightness));
+        assert_eq!(decoder.next_packet(), Some(Ok(brightness)));
+        assert_eq!(decoder.next_packet(), None);
+    }
+
+    #[test]
+    fn checksum_mismatch() {
+        let brightness = command(Command::SetBrightness(0x50));
+        let sleep = command(Command::Sleep(true));
+        let mut corrupted = frame(&brightness);
+        corrupted[6] ^= 0x01;
+
+        let mut decoder = FrameDecoder::default();
+        decoder.push(&corrupted);
+        decoder.push(&frame(&sleep));
+        assert_eq!(decoder.next_packet(), Some(Err(ParseError::Checksum)));
+        assert!(decoder.framed());
+        // The next frame is still received
+        assert_eq!(decoder.next_packet(), Some(Ok(sleep)));
+        assert_eq!(decoder.next_packet(), None);
+    }
+
+    #[test]
+    fn legacy_raw_command() {
+        let brightness = command(Command::SetBrightness(0x50));
+        let mut decoder = FrameDecoder::default();
+
+        decoder.push(&brightness);
+        assert_eq!(decoder.next_packet(), Some(Ok(brightness.clone())));
+        assert!(!decoder.framed());
+        assert_eq!(decoder.next_packet(), None);
+
+        // Garbage before it is dropped
+        decoder.push(&[0x00, 0xFF]);
+        decoder.push(&brightness);
+        assert_eq!(decoder.next_packet(), Some(Ok(brightness.clone())));
+        assert_eq!(decoder.next_packet(), None);
+
+        // In the middle of a frame it's the payload, not a raw command
+        let frame = frame(&brightness);
+        decoder.push(&frame[..3]);
+        assert!(frame[3..].starts_with(&MAGIC));
+        decoder.push(&frame[3..]);
+        assert_eq!(decoder.next_packet(), Some(Ok(brightness)));
+        assert!(decoder.framed());
+        assert_eq!(decoder.next_packet(), None);
+    }
+}
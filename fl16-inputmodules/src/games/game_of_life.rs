@@ -1,32 +1,60 @@
-use crate::control::{GameControlArg, GameOfLifeStartParam};
+use crate::control::{
+    response::GameStatus, GameControlArg, GameOfLifeParam, GameOfLifeStartParam, GameVal, LifeRule,
+};
+use crate::games::rng::Rng;
 use crate::matrix::{GameState, Grid, LedmatrixState, HEIGHT, WIDTH};
 
-#[derive(Clone, Copy, num_derive::FromPrimitive, PartialEq, Eq)]
+/// Generations to remember, cycles up to this long are noticed
+const HISTORY: usize = 16;
+
+#[derive(Clone, Copy, Debug, num_derive::FromPrimitive, PartialEq, Eq)]
 pub enum Cell {
     Dead = 0,
     Alive = 1,
 }
 
+type Cells = [[Cell; WIDTH]; HEIGHT];
+
 #[derive(Clone)]
 pub struct GameOfLifeState {
-    cells: [[Cell; WIDTH]; HEIGHT],
+    cells: Cells,
+    rule: LifeRule,
+    wrap: bool,
+    reseed: bool,
+    density: u8,
+    rng: Rng,
+    /// Hashes of the last generations, to notice when they repeat
+    history: [Option<u32>; HISTORY],
+    /// How often it started over with a new soup
+    reseeds: u8,
+    /// Generations since the start
+    pub ticks: u32,
 }
 
-impl GameOfLifeState {
-    pub fn combine(&self, other: &Self) -> Self {
-        let mut state = self.clone();
-        for x in 0..WIDTH {
-            for y in 0..HEIGHT {
-                if other.cells[y][x] == Cell::Alive {
-                    state.cells[y][x] = Cell::Alive;
-                }
+/// Cells that are alive in either
+fn combine(mut cells: Cells, other: &Cells) -> Cells {
+    for x in 0..WIDTH {
+        for y in 0..HEIGHT {
+            if other[y][x] == Cell::Alive {
+                cells[y][x] = Cell::Alive;
             }
         }
-        state
     }
+    cells
 }
 
-pub fn start_game(state: &mut LedmatrixState, _random: u8, param: GameOfLifeStartParam) {
+/// Random cells, density is the percentage that are alive
+fn soup(rng: &mut Rng, density: u8) -> Cells {
+    let mut cells = [[Cell::Dead; WIDTH]; HEIGHT];
+    for cell in cells.iter_mut().flatten() {
+        if rng.next_u32() % 100 < density as u32 {
+            *cell = Cell::Alive;
+        }
+    }
+    cells
+}
+
+pub fn start_game(state: &mut LedmatrixState, _random: u8, param: GameOfLifeParam) {
     let gol = GameOfLifeState::new(param, &state.grid);
     state.grid = gol.draw_matrix();
     state.game = Some(GameState::GameOfLife(gol));
@@ -49,8 +77,9 @@ pub fn game_step(state: &mut LedmatrixState, _random: u8) {
 
 impl GameOfLifeState {
     // TODO: Integrate Grid into GameOfLifeStartParam because it's only used in one of the enum variants
-    pub fn new(param: GameOfLifeStartParam, grid: &Grid) -> Self {
-        match param {
+    pub fn new(param: GameOfLifeParam, grid: &Grid) -> Self {
+        let mut rng = Rng::new(param.seed);
+        let cells = match param.start {
             GameOfLifeStartParam::Beacon => Self::beacon(),
             GameOfLifeStartParam::CurrentMatrix => {
                 let mut cells = [[Cell::Dead; WIDTH]; HEIGHT];
@@ -66,18 +95,32 @@ impl GameOfLifeState {
                 //cells: grid
                 //    .0
                 //    .map(|col| col.map(|val| if val == 0 { Cell::Dead } else { Cell::Alive })),
-                GameOfLifeState { cells }
+                cells
             }
             GameOfLifeStartParam::Pattern1 => Self::pattern1(),
             GameOfLifeStartParam::Blinker => Self::blinker(),
             GameOfLifeStartParam::Toad => Self::toad(),
             GameOfLifeStartParam::Glider => Self::glider(),
-            GameOfLifeStartParam::BeaconToadBlinker => Self::beacon()
-                .combine(&Self::toad())
-                .combine(&Self::blinker()),
-        }
+            GameOfLifeStartParam::BeaconToadBlinker => {
+                combine(combine(Self::beacon(), &Self::toad()), &Self::blinker())
+            }
+            GameOfLifeStartParam::Soup => soup(&mut rng, param.density),
+        };
+        let mut state = GameOfLifeState {
+            cells,
+            rule: param.rule,
+            wrap: param.wrap,
+            reseed: param.reseed,
+            density: param.density,
+            rng,
+            history: [None; HISTORY],
+            reseeds: 0,
+            ticks: 0,
+        };
+        state.history[0] = Some(state.hash());
+        state
     }
-    fn pattern1() -> Self {
+    fn pattern1() -> Cells {
         // Starts off with lots of alive cells, quickly reduced.
         // Eventually reaches a stable pattern without changes.
         let mut cells = [[Cell::Dead; WIDTH]; HEIGHT];
@@ -89,9 +132,9 @@ impl GameOfLifeState {
                 }
             }
         }
-        GameOfLifeState { cells }
+        cells
     }
-    fn blinker() -> Self {
+    fn blinker() -> Cells {
         // Oscillates between:
         //     XXX
         // and
@@ -105,9 +148,9 @@ impl GameOfLifeState {
         cells[8][5] = Cell::Alive;
         cells[8][6] = Cell::Alive;
         cells[8][7] = Cell::Alive;
-        GameOfLifeState { cells }
+        cells
     }
-    fn toad() -> Self {
+    fn toad() -> Cells {
         // Oscillates between
         //  XXX
         // XXX
@@ -123,9 +166,9 @@ impl GameOfLifeState {
         cells[18][5] = Cell::Alive;
         cells[18][6] = Cell::Alive;
         cells[18][7] = Cell::Alive;
-        GameOfLifeState { cells }
+        cells
     }
-    fn beacon() -> Self {
+    fn beacon() -> Cells {
         // Oscillates between
         //   XX
         //   XX
@@ -146,10 +189,10 @@ impl GameOfLifeState {
         cells[28][7] = Cell::Alive;
         cells[29][6] = Cell::Alive;
         cells[29][7] = Cell::Alive;
-        GameOfLifeState { cells }
+        cells
     }
 
-    fn glider() -> Self {
+    fn glider() -> Cells {
         //  X
         //   X
         // XXX
@@ -165,7 +208,7 @@ impl GameOfLifeState {
         cells[22][4] = Cell::Alive;
         cells[22][5] = Cell::Alive;
         cells[22][6] = Cell::Alive;
-        GameOfLifeState { cells }
+        cells
     }
 
     /// Count live neighbor cells
@@ -181,6 +224,11 @@ impl GameOfLifeState {
 
                 let neighbor_row = (row + delta_row) % HEIGHT;
                 let neighbor_col = (col + delta_col) % WIDTH;
+                // Went across the edge to the other side
+                let wrapped = neighbor_row.abs_diff(row) > 1 || neighbor_col.abs_diff(col) > 1;
+                if wrapped && !self.wrap {
+                    continue;
+                }
 
                 count += self.cells[neighbor_row][neighbor_col] as u8;
             }
@@ -195,17 +243,10 @@ impl GameOfLifeState {
                 let cell = self.cells[row][col];
                 let live_neighbors = self.live_neighbor_count(row, col);
 
-                let child_cell = match (cell, live_neighbors) {
-                    // Fewer than 2 neighbors causes it to die
-                    (Cell::Alive, x) if x < 2 => Cell::Dead,
-                    // 2 or three neighbors are good and it stays alive
-                    (Cell::Alive, 2) | (Cell::Alive, 3) => Cell::Alive,
-                    // More than 3 is too many and the cell dies
-                    (Cell::Alive, x) if x > 3 => Cell::Dead,
-                    // 3 neighbors when the cell is dead, revives it
-                    (Cell::Dead, 3) => Cell::Alive,
-                    // No change by default
-                    (c, _) => c,
+                let child_cell = match cell {
+                    Cell::Alive if self.rule.survives(live_neighbors) => Cell::Alive,
+                    Cell::Dead if self.rule.born(live_neighbors) => Cell::Alive,
+                    _ => Cell::Dead,
                 };
 
                 next_generation[row][col] = child_cell;
@@ -213,6 +254,45 @@ impl GameOfLifeState {
         }
 
         self.cells = next_generation;
+        self.ticks += 1;
+
+        let hash = self.hash();
+        if self.reseed && self.history.contains(&Some(hash)) {
+            // Stopped changing or repeats, start over
+            self.cells = soup(&mut self.rng, self.density);
+            self.history = [None; HISTORY];
+            self.history[self.ticks as usize % HISTORY] = Some(self.hash());
+            self.reseeds = self.reseeds.saturating_add(1);
+        } else {
+            self.history[self.ticks as usize % HISTORY] = Some(hash);
+        }
+    }
+
+    /// FNV-1a of the cells, to tell generations apart
+    fn hash(&self) -> u32 {
+        self.cells
+            .iter()
+            .flatten()
+            .fold(0x811C_9DC5, |hash, &cell| {
+                (hash ^ cell as u32).wrapping_mul(0x0100_0193)
+            })
+    }
+
+    pub fn status(&self) -> GameStatus {
+        let alive = self
+            .cells
+            .iter()
+            .flatten()
+            .filter(|&&cell| cell == Cell::Alive);
+        GameStatus {
+            game: Some(GameVal::GameOfLife),
+            game_over: false,
+            // Cells that are alive
+            score: alive.count() as u32,
+            second_score: 0,
+            level: self.reseeds,
+            ticks: self.ticks,
+        }
     }
 
     pub fn draw_matrix(&self) -> Grid {
@@ -227,3 +307,107 @@ impl GameOfLifeState {
         grid
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Start from the cells at (row, column)
+    fn from_cells(param: GameOfLifeParam, alive: &[(usize, usize)]) -> GameOfLifeState {
+        let mut grid = Grid::default();
+        for &(row, col) in alive {
+            grid.0[col][row] = 0xFF;
+        }
+        GameOfLifeState::new(param, &grid)
+    }
+
+    fn alive(gol: &GameOfLifeState) -> u32 {
+        gol.status().score
+    }
+
+    #[test]
+    fn block_across_the_corner() {
+        let corners = [
+            (0, 0),
+            (0, WIDTH - 1),
+            (HEIGHT - 1, 0),
+            (HEIGHT - 1, WIDTH - 1),
+        ];
+        let param: GameOfLifeParam = GameOfLifeStartParam::CurrentMatrix.into();
+
+        let mut wrapping = from_cells(param, &corners);
+        wrapping.tick();
+        assert_eq!(alive(&wrapping), 4);
+
+        let mut bounded = from_cells(
+            GameOfLifeParam {
+                wrap: false,
+                ..param
+            },
+            &corners,
+        );
+        bounded.tick();
+        assert_eq!(alive(&bounded), 0);
+    }
+
+    #[test]
+    fn other_rules() {
+        // Seeds, every cell dies and two next to each other give birth to four
+        let seeds = GameOfLifeParam {
+            rule: LifeRule::parse("B2/S").unwrap(),
+            ..GameOfLifeStartParam::CurrentMatrix.into()
+        };
+        let mut gol = from_cells(seeds, &[(10, 4), (10, 5)]);
+        gol.tick();
+        assert_eq!(alive(&gol), 4);
+        assert_eq!(gol.cells[10][4], Cell::Dead);
+        assert_eq!(gol.cells[9][4], Cell::Alive);
+    }
+
+    #[test]
+    fn soups_from_seed() {
+        let param = GameOfLifeParam {
+            seed: 1234,
+            density: 50,
+            ..GameOfLifeStartParam::Soup.into()
+        };
+        let first = GameOfLifeState::new(param, &Grid::default());
+        let second = GameOfLifeState::new(param, &Grid::default());
+        assert_eq!(first.draw_matrix().0, second.draw_matrix().0);
+
+        let other = GameOfLifeState::new(
+            GameOfLifeParam {
+                seed: 1235,
+                ..param
+            },
+            &Grid::default(),
+        );
+        assert_ne!(first.draw_matrix().0, other.draw_matrix().0);
+
+        // Roughly half of them
+        let cells = (WIDTH * HEIGHT) as u32;
+        assert!((cells * 4 / 10..cells * 6 / 10).contains(&alive(&first)));
+    }
+
+    #[test]
+    fn reseed_when_repeating() {
+        let blinker = GameOfLifeParam {
+            reseed: true,
+            ..GameOfLifeStartParam::Blinker.into()
+        };
+        let mut gol = GameOfLifeState::new(blinker, &Grid::default());
+        gol.tick();
+        assert_eq!(gol.status().level, 0);
+        // Back to how it started
+        gol.tick();
+        assert_eq!(gol.status().level, 1);
+        assert!(alive(&gol) > 6);
+
+        // Keeps blinking without
+        let mut gol = GameOfLifeState::new(GameOfLifeStartParam::Blinker.into(), &Grid::default());
+        for _ in 0..10 {
+            gol.tick();
+        }
+        assert_eq!((gol.status().level, alive(&gol)), (0, 6));
+    }
+}
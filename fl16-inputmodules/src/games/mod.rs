@@ -1,5 +1,8 @@
 pub mod game_of_life;
 pub mod pong;
 pub mod pong_animation;
+pub mod rng;
+pub mod score;
 pub mod snake;
 pub mod snake_animation;
+pub mod tetris;
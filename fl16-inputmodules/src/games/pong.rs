@@ -1,52 +1,147 @@
-use crate::control::GameControlArg;
+use core::cmp::Ordering;
+
+use crate::control::{
+    response::GameStatus, GameControlArg, GameVal, PongDifficulty, PongMode, PongParam,
+};
+use crate::games::rng::Rng;
+use crate::games::score::draw_scores;
 use crate::matrix::{GameState, Grid, LedmatrixState, HEIGHT, WIDTH};
 
 const PADDLE_WIDTH: usize = 5;
+/// The ball position is tracked in fractions of an LED, to move at any angle
+const SUBPIXELS: i16 = 16;
+/// Sideways speed of the ball, in subpixels per step
+const MAX_BALL_VX: i16 = 12;
+/// How much hitting the ball off center changes its sideways speed, per LED
+const ANGLE_STEP: i16 = 4;
+/// Steps get shorter with every other hit in a rally, up to this speed
+const MAX_SPEED: u64 = 10;
 
+/// Points of the players
 #[derive(Clone)]
 struct Score {
-    _upper: u8,
-    _lower: u8,
+    /// Player with the paddle at the top
+    upper: u8,
+    /// Player with the paddle at the bottom
+    lower: u8,
 }
 
-type Position = (usize, usize);
-type Velocity = (i8, i8);
-
+/// Position and velocity in subpixels
+///
+/// It moves by one row every step, only the sideways speed changes.
 #[derive(Clone)]
 struct Ball {
-    pos: Position,
-    // Not a position, more like a directional vector
-    direction: Velocity,
+    x: i16,
+    y: i16,
+    vx: i16,
+    vy: i16,
+}
+
+impl Ball {
+    fn column(&self) -> usize {
+        ((self.x + SUBPIXELS / 2) / SUBPIXELS) as usize
+    }
+
+    fn row(&self) -> usize {
+        (self.y / SUBPIXELS) as usize
+    }
 }
 
 #[derive(Clone)]
 pub struct PongState {
-    // TODO: Properly calculate score and display it
-    _score: Score,
+    param: PongParam,
+    score: Score,
     ball: Ball,
+    /// Leftmost column of the upper and lower paddle
     paddles: (usize, usize),
+    /// Hits in the current rally
+    hits: u8,
+    /// How many columns the computer misjudges the ball by, until it's hit
+    aim: i8,
     pub speed: u64,
+    pub game_over: bool,
+    /// Steps since the start, until the game is over
+    pub ticks: u32,
+    rng: Rng,
 }
 
-impl Default for PongState {
-    fn default() -> Self {
-        PongState {
-            _score: Score {
-                _upper: 0,
-                _lower: 0,
-            },
+impl PongState {
+    pub fn new(param: PongParam, seed: u32) -> Self {
+        let center = (WIDTH - PADDLE_WIDTH) / 2;
+        let mut state = PongState {
+            param,
+            score: Score { upper: 0, lower: 0 },
             ball: Ball {
-                pos: (4, 20),
-                direction: (0, 1),
+                x: 0,
+                y: 0,
+                vx: 0,
+                vy: 0,
             },
-            paddles: (PADDLE_WIDTH / 2, PADDLE_WIDTH / 2),
+            paddles: (center, center),
+            hits: 0,
+            aim: 0,
             speed: 0,
+            game_over: false,
+            ticks: 0,
+            rng: Rng::new(seed),
+        };
+        // Away from the upper paddle, to give a human player there time to get
+        // ready
+        state.serve(true);
+        state
+    }
+
+    /// Put the ball into the middle and send it towards the lower paddle, or
+    /// the upper one, at a random angle
+    fn serve(&mut self, downwards: bool) {
+        let range = (2 * MAX_BALL_VX / 3) as u32;
+        let vx = (self.rng.next_u32() % (2 * range + 1)) as i16 - range as i16;
+        self.ball = Ball {
+            x: (WIDTH / 2) as i16 * SUBPIXELS,
+            y: (HEIGHT / 2) as i16 * SUBPIXELS,
+            vx,
+            vy: if downwards { SUBPIXELS } else { -SUBPIXELS },
+        };
+        self.hits = 0;
+        self.speed = 0;
+        self.misjudge();
+    }
+
+    /// The easier, the further off the computer may aim at the ball
+    fn misjudge(&mut self) {
+        let spread = match self.param.difficulty {
+            PongDifficulty::Easy => 4,
+            PongDifficulty::Medium => 3,
+            PongDifficulty::Hard => 0,
+        };
+        self.aim = (self.rng.next_u32() % (2 * spread + 1)) as i8 - spread as i8;
+    }
+
+    fn is_ai(&self, upper: bool) -> bool {
+        match self.param.mode {
+            PongMode::PlayerVsAi => !upper,
+            PongMode::PlayerVsPlayer => false,
+            PongMode::AiVsAi => true,
         }
     }
-}
 
-impl PongState {
+    pub fn status(&self) -> GameStatus {
+        GameStatus {
+            game: Some(GameVal::Pong),
+            game_over: self.game_over,
+            score: self.score.upper as u32,
+            second_score: self.score.lower as u16,
+            level: self.speed as u8,
+            ticks: self.ticks,
+        }
+    }
+
+    /// Paddles and ball, or the score once the game is over
     pub fn draw_matrix(&self) -> Grid {
+        if self.game_over {
+            return draw_scores(self.score.upper as u32, self.score.lower as u32);
+        }
+
         let mut grid = Grid::default();
 
         for x in self.paddles.0..self.paddles.0 + PADDLE_WIDTH {
@@ -55,77 +150,152 @@ impl PongState {
         for x in self.paddles.1..self.paddles.1 + PADDLE_WIDTH {
             grid.0[x][HEIGHT - 1] = 0xFF;
         }
-        grid.0[self.ball.pos.0][self.ball.pos.1] = 0xFF;
+        grid.0[self.ball.column()][self.ball.row()] = 0xFF;
 
         grid
     }
 
     pub fn tick(&mut self) {
-        self.ball.pos = {
-            let (vx, vy) = self.ball.direction;
-            let (x, y) = add_velocity(self.ball.pos, self.ball.direction);
-            let x = if x > WIDTH - 1 { WIDTH - 1 } else { x };
-            if x == 0 || x == WIDTH - 1 {
-                // Hit wall, bounce back
-                self.ball.direction = (-vx, vy);
+        if self.game_over {
+            return;
+        }
+        self.ticks += 1;
+
+        for upper in [true, false] {
+            if self.is_ai(upper) {
+                self.move_ai(upper);
+            }
+        }
+
+        let ball = &mut self.ball;
+        ball.x += ball.vx;
+        ball.y += ball.vy;
+        let max_x = (WIDTH as i16 - 1) * SUBPIXELS;
+        if ball.x < 0 || ball.x > max_x {
+            // Hit wall, bounce back
+            ball.x = ball.x.clamp(0, max_x) * 2 - ball.x;
+            ball.vx = -ball.vx;
+        }
+
+        let row = self.ball.row();
+        let upwards = self.ball.vy < 0;
+        if upwards && row == 1 {
+            self.hit_paddle(self.paddles.0);
+        } else if !upwards && row == HEIGHT - 2 {
+            self.hit_paddle(self.paddles.1);
+        } else if row == 0 || row == HEIGHT - 1 {
+            // Missed the ball, the other player gets a point and it's their
+            // turn to return it
+            if row == 0 {
+                self.score.lower += 1;
+            } else {
+                self.score.upper += 1;
             }
+            let max_score = self.score.upper.max(self.score.lower);
+            self.game_over = max_score >= self.param.winning_score;
+            self.serve(row == 0);
+        }
+    }
+
+    /// Bounce the ball back if the paddle is there
+    ///
+    /// The further from the center of the paddle it hits, the more the angle
+    /// changes.
+    fn hit_paddle(&mut self, paddle: usize) {
+        let column = self.ball.column();
+        if column < paddle || column >= paddle + PADDLE_WIDTH {
+            return;
+        }
+        let offset = column as i16 - (paddle + PADDLE_WIDTH / 2) as i16;
+        let ball = &mut self.ball;
+        ball.vx = (ball.vx + offset * ANGLE_STEP).clamp(-MAX_BALL_VX, MAX_BALL_VX);
+        ball.vy = -ball.vy;
+        self.hits = self.hits.saturating_add(1);
+        self.speed = (self.hits as u64 / 2).min(MAX_SPEED);
+        self.misjudge();
+    }
 
-            let y = if y > HEIGHT - 1 { HEIGHT - 1 } else { y };
-            let (x, y) = if let Some(paddle_hit) = hit_paddle((x, y), self.paddles) {
-                // Hit paddle, bounce back
-                // TODO: Change vy direction slightly depending on where the paddle was hit
-                let (vx, vy) = self.ball.direction;
-                self.ball.direction = match paddle_hit {
-                    0 => (vx - 2, -vy),
-                    1 => (vx - 1, -vy),
-                    2 => (vx, -vy),
-                    3 => (vx + 1, -vy),
-                    4 => (vx + 2, -vy),
-                    // Shouldn't occur
-                    _ => (vx, -vy),
-                };
-                // TODO: Not sure if I want the speed to change. Speed by angle change is already high enough
-                //self.speed += 1;
-                (x, y)
-            } else if y == 0 || y == HEIGHT - 1 {
-                self.speed = 0;
-                self.ball.direction = (1, 1); //random_v(random);
-                (WIDTH / 2, HEIGHT / 2)
+    /// Column where the ball will arrive at the row in front of the paddle
+    fn predict_column(&self, upper: bool) -> usize {
+        let target_row = if upper { 1 } else { HEIGHT - 2 };
+        let steps = (self.ball.row() as i32 - target_row as i32).abs();
+        let x = self.ball.x as i32 + self.ball.vx as i32 * steps;
+        // Unfold the bounces off the walls
+        let max_x = (WIDTH as i32 - 1) * SUBPIXELS as i32;
+        let x = x.rem_euclid(2 * max_x);
+        let x = if x > max_x { 2 * max_x - x } else { x };
+        ((x + SUBPIXELS as i32 / 2) / SUBPIXELS as i32) as usize
+    }
+
+    /// Move the computer's paddle by up to one column
+    fn move_ai(&mut self, upper: bool) {
+        let towards = (self.ball.vy < 0) == upper;
+        let target = if !towards {
+            // Wait in the middle
+            WIDTH / 2
+        } else {
+            let column = if self.param.difficulty == PongDifficulty::Easy {
+                self.ball.column()
             } else {
-                (x, y)
+                self.predict_column(upper)
             };
-            (x, y)
+            (column as i8 + self.aim).clamp(0, WIDTH as i8 - 1) as usize
+        };
+
+        // The easier, the more often it hesitates
+        let hesitate = match self.param.difficulty {
+            PongDifficulty::Easy => self.rng.next_u32() & 1 == 0,
+            PongDifficulty::Medium => self.rng.next_u32() & 3 == 0,
+            PongDifficulty::Hard => false,
+        };
+        if hesitate {
+            return;
+        }
+
+        let goal = target
+            .saturating_sub(PADDLE_WIDTH / 2)
+            .min(WIDTH - PADDLE_WIDTH);
+        let paddle = if upper {
+            &mut self.paddles.0
+        } else {
+            &mut self.paddles.1
         };
+        match (*paddle).cmp(&goal) {
+            Ordering::Less => *paddle += 1,
+            Ordering::Greater => *paddle -= 1,
+            Ordering::Equal => {}
+        }
     }
+
     pub fn handle_control(&mut self, arg: &GameControlArg) {
-        match arg {
-            GameControlArg::Left => {
-                if self.paddles.0 + PADDLE_WIDTH < WIDTH {
-                    self.paddles.0 += 1;
-                }
-            }
-            GameControlArg::Right => {
-                if self.paddles.0 >= 1 {
-                    self.paddles.0 -= 1;
-                }
-            }
-            GameControlArg::SecondLeft => {
-                if self.paddles.1 + PADDLE_WIDTH < WIDTH {
-                    self.paddles.1 += 1;
-                }
-            }
-            GameControlArg::SecondRight => {
-                if self.paddles.1 >= 1 {
-                    self.paddles.1 -= 1;
-                }
-            }
-            _ => {}
+        let (upper, left) = match arg {
+            GameControlArg::Left => (true, true),
+            GameControlArg::Right => (true, false),
+            GameControlArg::SecondLeft => (false, true),
+            GameControlArg::SecondRight => (false, false),
+            _ => return,
+        };
+        if self.is_ai(upper) {
+            return;
+        }
+        let paddle = if upper {
+            &mut self.paddles.0
+        } else {
+            &mut self.paddles.1
+        };
+        // (0, 0) is at the top right corner
+        if left && *paddle + PADDLE_WIDTH < WIDTH {
+            *paddle += 1;
+        } else if !left && *paddle >= 1 {
+            *paddle -= 1;
         }
     }
 }
 
-pub fn start_game(state: &mut LedmatrixState, _random: u8) {
-    state.game = Some(GameState::Pong(PongState::default()))
+pub fn start_game(state: &mut LedmatrixState, random: u8, param: PongParam) {
+    let pong = PongState::new(param, random as u32);
+    state.grid = pong.draw_matrix();
+    state.game = Some(GameState::Pong(pong))
 }
 pub fn handle_control(state: &mut LedmatrixState, arg: &GameControlArg) {
     if let Some(GameState::Pong(ref mut pong_state)) = state.game {
@@ -136,34 +306,96 @@ pub fn handle_control(state: &mut LedmatrixState, arg: &GameControlArg) {
     }
 }
 
-// TODO: Randomize the velocity vector upon respawning
-fn _random_v(random: u8) -> Velocity {
-    // TODO: while food == head:
-    let x = ((random & 0xF0) >> 4) % WIDTH as u8;
-    let y = (random & 0x0F) % HEIGHT as u8;
-    (x as i8, y as i8)
+pub fn game_step(state: &mut LedmatrixState, _random: u8) {
+    if let Some(GameState::Pong(ref mut pong_state)) = state.game {
+        pong_state.tick();
+        state.grid = pong_state.draw_matrix();
+    }
 }
 
-fn add_velocity(pos: Position, v: Velocity) -> Position {
-    let (vx, vy) = v;
-    let (x, y) = pos;
-    (((x as i8) + vx) as usize, ((y as i8) + vy) as usize)
-}
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-fn hit_paddle(ball: Position, paddles: (usize, usize)) -> Option<usize> {
-    let (x, y) = ball;
-    if y == 1 && paddles.0 <= x && x <= paddles.0 + PADDLE_WIDTH {
-        Some(((paddles.0 as i32) - (x as i32)).unsigned_abs() as usize)
-    } else if y == HEIGHT - 2 && paddles.1 <= x && x <= paddles.1 + PADDLE_WIDTH {
-        Some(((paddles.1 as i32) - (x as i32)).unsigned_abs() as usize)
-    } else {
-        None
+    fn param(mode: PongMode, difficulty: PongDifficulty) -> PongParam {
+        PongParam {
+            mode,
+            difficulty,
+            winning_score: 5,
+        }
     }
-}
 
-pub fn game_step(state: &mut LedmatrixState, _random: u8) {
-    if let Some(GameState::Pong(ref mut pong_state)) = state.game {
-        pong_state.tick();
-        state.grid = pong_state.draw_matrix();
+    #[test]
+    fn same_seed_same_game() {
+        let param = param(PongMode::AiVsAi, PongDifficulty::Easy);
+        let mut first = PongState::new(param, 42);
+        let mut second = PongState::new(param, 42);
+        for _ in 0..2_000 {
+            first.tick();
+            second.tick();
+            assert_eq!(first.draw_matrix().0, second.draw_matrix().0);
+        }
+        assert_eq!(first.status(), second.status());
+    }
+
+    #[test]
+    fn first_to_winning_score() {
+        let mut pong = PongState::new(param(PongMode::AiVsAi, PongDifficulty::Easy), 1);
+        while !pong.game_over && pong.ticks < 100_000 {
+            pong.tick();
+        }
+        assert!(pong.game_over);
+        let status = pong.status();
+        assert_eq!(status.score.max(status.second_score as u32), 5);
+        assert!(status.score.min(status.second_score as u32) < 5);
+
+        // Stays over
+        pong.tick();
+        assert_eq!(pong.status(), status);
+    }
+
+    #[test]
+    fn hard_ai_returns_every_ball() {
+        let mut pong = PongState::new(param(PongMode::AiVsAi, PongDifficulty::Hard), 7);
+        for _ in 0..10_000 {
+            pong.tick();
+        }
+        assert_eq!((pong.score.upper, pong.score.lower), (0, 0));
+    }
+
+    #[test]
+    fn paddle_edge_changes_angle() {
+        let mut pong = PongState::new(param(PongMode::PlayerVsPlayer, PongDifficulty::Easy), 0);
+        pong.paddles.1 = 2;
+        // Straight down onto the paddle's last column
+        pong.ball = Ball {
+            x: 2 * SUBPIXELS,
+            y: (HEIGHT as i16 - 3) * SUBPIXELS,
+            vx: 0,
+            vy: SUBPIXELS,
+        };
+        pong.tick();
+        assert_eq!(pong.ball.vy, -SUBPIXELS);
+        assert_eq!(pong.ball.vx, -2 * ANGLE_STEP);
+
+        // The center sends it back at the same angle
+        pong.ball = Ball {
+            x: 4 * SUBPIXELS,
+            y: (HEIGHT as i16 - 3) * SUBPIXELS,
+            vx: 0,
+            vy: SUBPIXELS,
+        };
+        pong.tick();
+        assert_eq!((pong.ball.vx, pong.ball.vy), (0, -SUBPIXELS));
+    }
+
+    #[test]
+    fn players_only_move_their_paddles() {
+        let mut pong = PongState::new(param(PongMode::PlayerVsAi, PongDifficulty::Hard), 0);
+        let paddles = pong.paddles;
+        pong.handle_control(&GameControlArg::SecondLeft);
+        assert_eq!(pong.paddles, paddles);
+        pong.handle_control(&GameControlArg::Left);
+        assert_eq!(pong.paddles, (paddles.0 + 1, paddles.1));
     }
 }
@@ -1,19 +1,27 @@
-use crate::control::GameControlArg;
+use crate::control::{PongDifficulty, PongMode, PongParam};
 use crate::games::pong::PongState;
 use crate::matrix::Grid;
 
+/// Length of the animation
+const FRAMES: usize = 136;
+
+/// The computer playing against itself
 pub struct PongIterator {
     state: PongState,
-    commands: [Option<GameControlArg>; 136],
-    current_command: usize,
+    frames_remaining: usize,
 }
 
 impl Default for PongIterator {
     fn default() -> Self {
+        let param = PongParam {
+            mode: PongMode::AiVsAi,
+            difficulty: PongDifficulty::Medium,
+            winning_score: u8::MAX,
+        };
         PongIterator {
-            state: PongState::default(),
-            commands: SAMPLE_GAME,
-            current_command: 0,
+            // Always the same game
+            state: PongState::new(param, 0),
+            frames_remaining: FRAMES,
         }
     }
 }
@@ -22,155 +30,12 @@ impl Iterator for PongIterator {
     type Item = Grid;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.current_command >= self.commands.len() {
+        if self.frames_remaining == 0 {
             return None;
         }
-
-        if let Some(command) = self.commands[self.current_command] {
-            self.state.handle_control(&command);
-        }
-        self.current_command += 1;
+        self.frames_remaining -= 1;
 
         self.state.tick();
         Some(self.state.draw_matrix())
     }
 }
-
-const SAMPLE_GAME: [Option<GameControlArg>; 136] = [
-    Some(GameControlArg::Left), // Middle
-    None,
-    Some(GameControlArg::Left),
-    None,
-    None,
-    None,
-    Some(GameControlArg::SecondRight),
-    Some(GameControlArg::SecondRight),
-    None,
-    None,
-    None,
-    Some(GameControlArg::SecondLeft),
-    None, // hit and bounce back
-    None,
-    None,
-    None,
-    Some(GameControlArg::Right),
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    Some(GameControlArg::Right),
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    Some(GameControlArg::SecondLeft),
-    None,
-    Some(GameControlArg::SecondLeft),
-    None,
-    Some(GameControlArg::SecondRight),
-    None,
-    None,
-    Some(GameControlArg::SecondLeft),
-    None,
-    None,
-    Some(GameControlArg::Right),
-    None,
-    None,
-    Some(GameControlArg::Left),
-    None,
-    Some(GameControlArg::Right),
-    None,
-    None,
-    Some(GameControlArg::Left),
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    Some(GameControlArg::Right),
-    Some(GameControlArg::Right),
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-    None,
-];
@@ -0,0 +1,17 @@
+/// xorshift, so that the same seed always leads to the same game
+#[derive(Clone)]
+pub struct Rng(u32);
+
+impl Rng {
+    pub fn new(seed: u32) -> Self {
+        // Must not be 0
+        Rng(seed.wrapping_mul(0x9E37_79B9) | 1)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+}
@@ -0,0 +1,147 @@
+//! Score shown on the matrix once a game is over
+use crate::matrix::{Grid, HEIGHT, WIDTH};
+
+/// 3x5 digits, one row per byte, the highest of the 3 bits is on the left
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+/// Rows of a digit, including the gap to the next one
+const DIGIT_HEIGHT: usize = 6;
+/// Only this many digits fit below each other
+const MAX_DIGITS: usize = 5;
+
+/// Digits of the number, most significant first
+///
+/// Higher numbers than fit on the matrix show as 99999.
+fn digits(number: u32) -> ([u8; MAX_DIGITS], usize) {
+    let mut digits = [0; MAX_DIGITS];
+    let mut len = 0;
+    let mut rest = number.min(99_999);
+    loop {
+        digits[len] = (rest % 10) as u8;
+        len += 1;
+        rest /= 10;
+        if rest == 0 {
+            break;
+        }
+    }
+    digits[..len].reverse();
+    (digits, len)
+}
+
+/// Rows that a number takes
+fn number_height(number: u32) -> usize {
+    digits(number).1 * DIGIT_HEIGHT - 1
+}
+
+/// Draw the digits below each other, starting at the row `top`
+fn draw_number(grid: &mut Grid, number: u32, top: usize) {
+    let (digits, len) = digits(number);
+    for (i, digit) in digits[..len].iter().enumerate() {
+        for (row, bits) in DIGITS[*digit as usize].iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) != 0 {
+                    // (0, 0) is at the top right corner, use columns 3 to 5
+                    // from the left
+                    grid.0[WIDTH - 4 - col][top + i * DIGIT_HEIGHT + row] = 0xFF;
+                }
+            }
+        }
+    }
+}
+
+/// Score in the middle of the matrix
+pub fn draw_score(score: u32) -> Grid {
+    let mut grid = Grid::default();
+    draw_number(&mut grid, score, (HEIGHT - number_height(score)) / 2);
+    grid
+}
+
+/// Scores of two players, the upper one's at the top and the lower one's at
+/// the bottom
+pub fn draw_scores(upper: u32, lower: u32) -> Grid {
+    let mut grid = Grid::default();
+    draw_number(&mut grid, upper, 0);
+    draw_number(&mut grid, lower, HEIGHT - number_height(lower));
+    grid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rows of the three digit columns as bits, like in DIGITS
+    fn bit_rows(grid: &Grid) -> [u8; HEIGHT] {
+        let mut rows = [0; HEIGHT];
+        for (y, row) in rows.iter_mut().enumerate() {
+            for x in 0..WIDTH {
+                if grid.0[x][y] != 0 {
+                    // Only columns 3 to 5 from the left are used
+                    assert!((WIDTH - 6..=WIDTH - 4).contains(&x), "({x}, {y})");
+                    *row |= 0b100 >> (WIDTH - 4 - x);
+                }
+            }
+        }
+        rows
+    }
+
+    fn number_rows(digits: &[usize]) -> std::vec::Vec<u8> {
+        let mut rows = std::vec::Vec::new();
+        for digit in digits {
+            rows.extend_from_slice(&DIGITS[*digit]);
+            rows.push(0);
+        }
+        rows.pop();
+        rows
+    }
+
+    #[test]
+    fn digit_layout() {
+        let rows = bit_rows(&draw_scores(7, 0));
+        assert_eq!(rows[..5], DIGITS[7]);
+        assert_eq!(rows[5..HEIGHT - 5], [0; HEIGHT - 10]);
+        assert_eq!(rows[HEIGHT - 5..], DIGITS[0]);
+
+        // Left column of the 1 digit
+        let grid = draw_scores(1, 0);
+        assert_eq!(grid.0[WIDTH - 4][1], 0xFF);
+        assert_eq!(grid.0[WIDTH - 4][0], 0x00);
+    }
+
+    #[test]
+    fn two_digit_scores() {
+        let rows = bit_rows(&draw_scores(12, 34));
+        assert_eq!(rows[..11], number_rows(&[1, 2])[..]);
+        assert_eq!(rows[HEIGHT - 11..], number_rows(&[3, 4])[..]);
+        assert!(rows[11..HEIGHT - 11].iter().all(|row| *row == 0));
+
+        // In the middle
+        let rows = bit_rows(&draw_score(56));
+        let top = (HEIGHT - 11) / 2;
+        assert_eq!(rows[top..top + 11], number_rows(&[5, 6])[..]);
+        assert!(rows[..top].iter().all(|row| *row == 0));
+    }
+
+    #[test]
+    fn largest_scores() {
+        // Highest winning score of Pong, right on top of each other
+        let rows = bit_rows(&draw_scores(255, 255));
+        assert_eq!(rows[..17], number_rows(&[2, 5, 5])[..]);
+        assert_eq!(rows[17..], number_rows(&[2, 5, 5])[..]);
+
+        let rows = bit_rows(&draw_score(99_999));
+        assert_eq!(rows[2..31], number_rows(&[9; 5])[..]);
+        // Doesn't fit anymore, shows the highest that does
+        assert_eq!(draw_score(123_456).0, draw_score(99_999).0);
+        assert_eq!(draw_score(u32::MAX).0, draw_score(99_999).0);
+    }
+}
@@ -1,4 +1,5 @@
-use crate::control::GameControlArg;
+use crate::control::{response::GameStatus, GameControlArg, GameVal};
+use crate::games::score::draw_score;
 use crate::matrix::{GameState, Grid, LedmatrixState, HEIGHT, LEDS, WIDTH};
 
 use heapless::Vec;
@@ -24,6 +25,8 @@ pub struct SnakeState {
     pub body: Vec<Position, LEDS>,
     pub game_over: bool,
     food: Position,
+    /// Steps since the start, until the game is over
+    pub ticks: u32,
 }
 
 impl SnakeState {
@@ -34,12 +37,14 @@ impl SnakeState {
             body: Vec::new(),
             game_over: false,
             food: place_food(random),
+            ticks: 0,
         }
     }
     pub fn tick(&mut self, random: u8) {
         if self.game_over {
             return;
         }
+        self.ticks += 1;
 
         let (x, y) = self.head;
         let oldhead = self.head;
@@ -94,6 +99,18 @@ impl SnakeState {
             _ => {}
         }
     }
+    pub fn status(&self) -> GameStatus {
+        GameStatus {
+            game: Some(GameVal::Snake),
+            game_over: self.game_over,
+            // A point for every food eaten
+            score: self.body.len() as u32,
+            second_score: 0,
+            level: 0,
+            ticks: self.ticks,
+        }
+    }
+
     pub fn draw_matrix(&self) -> Grid {
         let (x, y) = self.head;
         let mut grid = Grid::default();
@@ -129,20 +146,14 @@ pub fn handle_control(state: &mut LedmatrixState, arg: &GameControlArg) {
     }
 }
 
-pub fn game_step(state: &mut LedmatrixState, random: u8) -> (HeadDirection, bool, usize, Position) {
+pub fn game_step(state: &mut LedmatrixState, random: u8) {
     if let Some(GameState::Snake(ref mut snake_state)) = state.game {
         snake_state.tick(random);
 
-        if !snake_state.game_over {
-            state.grid = snake_state.draw_matrix();
-        }
-        (
-            snake_state.direction,
-            snake_state.game_over,
-            snake_state.body.len(),
-            snake_state.head,
-        )
-    } else {
-        (HeadDirection::Down, true, 0, (0, 0))
+        state.grid = if snake_state.game_over {
+            draw_score(snake_state.body.len() as u32)
+        } else {
+            snake_state.draw_matrix()
+        };
     }
 }
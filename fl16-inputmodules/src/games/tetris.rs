@@ -0,0 +1,495 @@
+use crate::control::{response::GameStatus, GameControlArg, GameVal};
+use crate::games::score::draw_score;
+use crate::matrix::{GameState, Grid, LedmatrixState, HEIGHT, WIDTH};
+
+/// Points for clearing 1, 2, 3 or 4 lines at once, multiplied by the level + 1
+const LINE_POINTS: [u32; 5] = [0, 40, 100, 300, 1200];
+/// Lines to clear to get to the next level
+const LINES_PER_LEVEL: u16 = 10;
+
+/// Column and row of a block
+///
+/// Unlike on the grid, column 0 is on the left, the way the player sees it.
+/// (0, 0) of the grid is at the top right corner.
+type Position = (i8, i8);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Shape {
+    I,
+    O,
+    T,
+    S,
+    Z,
+    J,
+    L,
+}
+
+const SHAPES: [Shape; 7] = [
+    Shape::I,
+    Shape::O,
+    Shape::T,
+    Shape::S,
+    Shape::Z,
+    Shape::J,
+    Shape::L,
+];
+
+impl Shape {
+    fn from_random(random: u8) -> Self {
+        SHAPES[random as usize % SHAPES.len()]
+    }
+
+    /// Size of the square that the shape is rotated in
+    fn size(&self) -> i8 {
+        match self {
+            Shape::I => 4,
+            Shape::O => 2,
+            _ => 3,
+        }
+    }
+
+    /// Blocks of the unrotated shape, inside the square
+    fn blocks(&self) -> [Position; 4] {
+        match self {
+            Shape::I => [(0, 1), (1, 1), (2, 1), (3, 1)],
+            Shape::O => [(0, 0), (1, 0), (0, 1), (1, 1)],
+            Shape::T => [(1, 0), (0, 1), (1, 1), (2, 1)],
+            Shape::S => [(1, 0), (2, 0), (0, 1), (1, 1)],
+            Shape::Z => [(0, 0), (1, 0), (1, 1), (2, 1)],
+            Shape::J => [(0, 0), (0, 1), (1, 1), (2, 1)],
+            Shape::L => [(2, 0), (0, 1), (1, 1), (2, 1)],
+        }
+    }
+}
+
+/// The falling piece
+#[derive(Clone)]
+struct Piece {
+    shape: Shape,
+    /// Quarter turns clockwise
+    rotation: u8,
+    /// Top left corner of the square the shape is in
+    pos: Position,
+}
+
+impl Piece {
+    fn new(shape: Shape) -> Self {
+        Piece {
+            shape,
+            rotation: 0,
+            pos: ((WIDTH as i8 - shape.size()) / 2, 0),
+        }
+    }
+
+    fn blocks(&self) -> [Position; 4] {
+        let size = self.shape.size();
+        let mut blocks = self.shape.blocks();
+        for block in blocks.iter_mut() {
+            let (mut x, mut y) = *block;
+            for _ in 0..self.rotation {
+                (x, y) = (size - 1 - y, x);
+            }
+            *block = (self.pos.0 + x, self.pos.1 + y);
+        }
+        blocks
+    }
+
+    fn moved(&self, dx: i8, dy: i8) -> Self {
+        Piece {
+            pos: (self.pos.0 + dx, self.pos.1 + dy),
+            ..self.clone()
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct TetrisState {
+    /// Blocks of the pieces that have landed, by column and row
+    board: [[bool; HEIGHT]; WIDTH],
+    piece: Piece,
+    next: Shape,
+    pub score: u32,
+    pub lines: u16,
+    /// Goes up every LINES_PER_LEVEL lines, the pieces fall faster
+    pub level: u8,
+    pub game_over: bool,
+    /// Steps since the start, until the game is over
+    pub ticks: u32,
+}
+
+impl TetrisState {
+    pub fn new(random: u8) -> Self {
+        TetrisState {
+            board: [[false; HEIGHT]; WIDTH],
+            piece: Piece::new(Shape::from_random(random)),
+            // Only one random byte per step, use the other half
+            next: Shape::from_random(random >> 4),
+            score: 0,
+            lines: 0,
+            level: 0,
+            game_over: false,
+            ticks: 0,
+        }
+    }
+
+    /// Whether the piece is inside the playfield and doesn't overlap any block
+    fn fits(&self, piece: &Piece) -> bool {
+        piece.blocks().iter().all(|&(x, y)| {
+            (0..WIDTH as i8).contains(&x)
+                && (0..HEIGHT as i8).contains(&y)
+                && !self.board[x as usize][y as usize]
+        })
+    }
+
+    /// Move the piece, if there's space
+    fn try_move(&mut self, dx: i8, dy: i8) -> bool {
+        let moved = self.piece.moved(dx, dy);
+        if self.fits(&moved) {
+            self.piece = moved;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Rotate clockwise, pushing the piece away from walls and blocks if needed
+    fn rotate(&mut self) {
+        let mut rotated = self.piece.clone();
+        rotated.rotation = (rotated.rotation + 1) % 4;
+        for dx in [0, -1, 1, -2, 2] {
+            let kicked = rotated.moved(dx, 0);
+            if self.fits(&kicked) {
+                self.piece = kicked;
+                return;
+            }
+        }
+    }
+
+    /// Move the piece down, if it can't, land it and bring the next one
+    pub fn tick(&mut self, random: u8) {
+        if self.game_over {
+            return;
+        }
+        self.ticks += 1;
+        if self.try_move(0, 1) {
+            return;
+        }
+
+        for (x, y) in self.piece.blocks() {
+            self.board[x as usize][y as usize] = true;
+        }
+        self.clear_lines();
+
+        self.piece = Piece::new(self.next);
+        self.next = Shape::from_random(random);
+        if !self.fits(&self.piece) {
+            // Stacked up to the top
+            self.game_over = true;
+        }
+    }
+
+    fn clear_lines(&mut self) {
+        let mut cleared = 0;
+        let mut y = HEIGHT;
+        while y > 0 {
+            let row = y - 1;
+            if (0..WIDTH).all(|x| self.board[x][row]) {
+                // Move everything above down by one and check the same row again
+                for x in 0..WIDTH {
+                    self.board[x].copy_within(0..row, 1);
+                    self.board[x][0] = false;
+                }
+                cleared += 1;
+            } else {
+                y -= 1;
+            }
+        }
+
+        self.score += LINE_POINTS[cleared] * (self.level as u32 + 1);
+        self.lines += cleared as u16;
+        self.level = (self.lines / LINES_PER_LEVEL).min(u8::MAX as u16) as u8;
+    }
+
+    pub fn handle_control(&mut self, arg: &GameControlArg) {
+        if self.game_over {
+            return;
+        }
+        match arg {
+            GameControlArg::Up => self.rotate(),
+            GameControlArg::Down => {
+                // Soft drop, a point for every row
+                if self.try_move(0, 1) {
+                    self.score += 1;
+                }
+            }
+            GameControlArg::Left => {
+                self.try_move(-1, 0);
+            }
+            GameControlArg::Right => {
+                self.try_move(1, 0);
+            }
+            _ => {}
+        }
+    }
+
+    /// Microseconds between two steps, shorter on higher levels
+    pub fn step_period(&self) -> u64 {
+        500_000 - 40_000 * self.level.min(10) as u64
+    }
+
+    pub fn status(&self) -> GameStatus {
+        GameStatus {
+            game: Some(GameVal::Tetris),
+            game_over: self.game_over,
+            score: self.score,
+            second_score: 0,
+            level: self.level,
+            ticks: self.ticks,
+        }
+    }
+
+    /// Playfield with the falling piece, or the score once the game is over
+    pub fn draw_matrix(&self) -> Grid {
+        if self.game_over {
+            return draw_score(self.score);
+        }
+
+        let mut grid = Grid::default();
+        for x in 0..WIDTH {
+            for y in 0..HEIGHT {
+                if self.board[x][y] {
+                    grid.0[WIDTH - 1 - x][y] = 0xFF;
+                }
+            }
+        }
+        for (x, y) in self.piece.blocks() {
+            grid.0[WIDTH - 1 - x as usize][y as usize] = 0xFF;
+        }
+        grid
+    }
+}
+
+pub fn start_game(state: &mut LedmatrixState, random: u8) {
+    let tetris = TetrisState::new(random);
+    state.grid = tetris.draw_matrix();
+    state.game = Some(GameState::Tetris(tetris));
+}
+
+pub fn handle_control(state: &mut LedmatrixState, arg: &GameControlArg) {
+    if let Some(GameState::Tetris(ref mut tetris_state)) = state.game {
+        match arg {
+            GameControlArg::Exit => state.game = None,
+            _ => {
+                tetris_state.handle_control(arg);
+                // Don't wait for the next step to show the move
+                state.grid = tetris_state.draw_matrix();
+            }
+        }
+    }
+}
+
+pub fn game_step(state: &mut LedmatrixState, random: u8) {
+    if let Some(GameState::Tetris(ref mut tetris_state)) = state.game {
+        tetris_state.tick(random);
+        state.grid = tetris_state.draw_matrix();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn with_piece(shape: Shape, rotation: u8, pos: Position) -> TetrisState {
+        let mut tetris = TetrisState::new(0);
+        tetris.piece = Piece {
+            shape,
+            rotation,
+            pos,
+        };
+        tetris
+    }
+
+    fn fill_rows(tetris: &mut TetrisState, rows: core::ops::Range<usize>) {
+        for x in 0..WIDTH {
+            for y in rows.clone() {
+                tetris.board[x][y] = true;
+            }
+        }
+    }
+
+    #[test]
+    fn rotation() {
+        let mut tetris = with_piece(Shape::T, 0, (3, 5));
+        let start = tetris.piece.blocks();
+        tetris.handle_control(&GameControlArg::Up);
+        assert_eq!(tetris.piece.rotation, 1);
+        // Pointing right
+        assert_eq!(tetris.piece.blocks(), [(5, 6), (4, 5), (4, 6), (4, 7)]);
+        for _ in 0..3 {
+            tetris.handle_control(&GameControlArg::Up);
+        }
+        assert_eq!(tetris.piece.blocks(), start);
+
+        // The O doesn't move at all
+        let mut tetris = with_piece(Shape::O, 0, (3, 5));
+        let mut start = tetris.piece.blocks();
+        start.sort();
+        tetris.handle_control(&GameControlArg::Up);
+        let mut blocks = tetris.piece.blocks();
+        blocks.sort();
+        assert_eq!(blocks, start);
+    }
+
+    #[test]
+    fn wall_kicks() {
+        // Upright I in the leftmost column
+        let mut tetris = with_piece(Shape::I, 1, (-2, 5));
+        assert!(tetris.piece.blocks().iter().all(|&(x, _)| x == 0));
+        tetris.handle_control(&GameControlArg::Up);
+        assert_eq!(tetris.piece.rotation, 2);
+        assert_eq!(tetris.piece.pos, (0, 5));
+        assert!(tetris.fits(&tetris.piece));
+
+        // And in the rightmost one
+        let mut tetris = with_piece(Shape::I, 1, (WIDTH as i8 - 3, 5));
+        tetris.handle_control(&GameControlArg::Up);
+        assert_eq!(tetris.piece.rotation, 2);
+        assert!(tetris.fits(&tetris.piece));
+
+        // No room at all, stays as it is
+        let mut tetris = with_piece(Shape::I, 1, (2, 5));
+        for y in 0..HEIGHT {
+            for x in [0, 1, 2, 3, 5, 6, 7, 8] {
+                tetris.board[x][y] = true;
+            }
+        }
+        tetris.handle_control(&GameControlArg::Up);
+        assert_eq!((tetris.piece.rotation, tetris.piece.pos), (1, (2, 5)));
+    }
+
+    #[test]
+    fn moves_stay_inside() {
+        let mut tetris = with_piece(Shape::O, 0, (0, 0));
+        tetris.handle_control(&GameControlArg::Left);
+        assert_eq!(tetris.piece.pos, (0, 0));
+        for _ in 0..WIDTH {
+            tetris.handle_control(&GameControlArg::Right);
+        }
+        assert_eq!(tetris.piece.pos, (WIDTH as i8 - 2, 0));
+        tetris.handle_control(&GameControlArg::Down);
+        assert_eq!((tetris.piece.pos.1, tetris.score), (1, 1));
+    }
+
+    #[test]
+    fn clear_lines() {
+        let mut tetris = TetrisState::new(0);
+        fill_rows(&mut tetris, HEIGHT - 1..HEIGHT);
+        tetris.board[4][HEIGHT - 2] = true;
+        tetris.clear_lines();
+        assert_eq!((tetris.lines, tetris.score), (1, 40));
+        // Everything above moved down
+        assert!(tetris.board[4][HEIGHT - 1]);
+        assert_eq!(tetris.board.iter().flatten().filter(|x| **x).count(), 1);
+
+        // Four at once, with a partial row in between that stays
+        let mut tetris = TetrisState::new(0);
+        fill_rows(&mut tetris, HEIGHT - 5..HEIGHT);
+        tetris.board[0][HEIGHT - 3] = false;
+        tetris.clear_lines();
+        assert_eq!((tetris.lines, tetris.score), (4, 1200));
+        assert!(!tetris.board[0][HEIGHT - 1]);
+        assert!(tetris.board[1][HEIGHT - 1]);
+        assert!(!tetris.board[1][HEIGHT - 2]);
+    }
+
+    #[test]
+    fn landing_clears_lines() {
+        // O drops into the gap of two almost full rows
+        let mut tetris = with_piece(Shape::O, 0, (0, HEIGHT as i8 - 4));
+        fill_rows(&mut tetris, HEIGHT - 2..HEIGHT);
+        for y in HEIGHT - 2..HEIGHT {
+            tetris.board[0][y] = false;
+            tetris.board[1][y] = false;
+        }
+        for _ in 0..3 {
+            tetris.tick(0);
+        }
+        assert_eq!((tetris.lines, tetris.score), (2, 100));
+        assert!(tetris.board.iter().flatten().all(|x| !x));
+        assert!(!tetris.game_over);
+    }
+
+    #[test]
+    fn level_and_speed() {
+        let mut tetris = TetrisState::new(0);
+        assert_eq!(tetris.step_period(), 500_000);
+        tetris.lines = LINES_PER_LEVEL - 1;
+        fill_rows(&mut tetris, HEIGHT - 1..HEIGHT);
+        tetris.clear_lines();
+        assert_eq!(tetris.level, 1);
+        assert_eq!(tetris.score, 40);
+        assert_eq!(tetris.step_period(), 460_000);
+
+        // Points are multiplied by the level
+        fill_rows(&mut tetris, HEIGHT - 1..HEIGHT);
+        tetris.clear_lines();
+        assert_eq!(tetris.score, 40 + 2 * 40);
+
+        // Doesn't get any faster after level 10
+        tetris.lines = 20 * LINES_PER_LEVEL;
+        fill_rows(&mut tetris, HEIGHT - 1..HEIGHT);
+        tetris.clear_lines();
+        assert_eq!(tetris.level, 20);
+        assert_eq!(tetris.step_period(), 100_000);
+    }
+
+    #[test]
+    fn game_over_when_spawn_blocked() {
+        // Blocks where the next piece comes in, but no full rows
+        let mut tetris = with_piece(Shape::O, 0, (0, HEIGHT as i8 - 2));
+        for x in 2..WIDTH {
+            for y in 0..2 {
+                tetris.board[x][y] = true;
+            }
+        }
+        tetris.score = 12;
+        tetris.tick(0);
+        assert!(tetris.game_over);
+        assert!(tetris.status().game_over);
+        assert_eq!(tetris.draw_matrix().0, draw_score(12).0);
+
+        // Nothing changes anymore
+        let ticks = tetris.ticks;
+        tetris.tick(0);
+        tetris.handle_control(&GameControlArg::Down);
+        assert_eq!((tetris.ticks, tetris.score), (ticks, 12));
+    }
+
+    #[test]
+    fn same_seed_same_game() {
+        let mut first = TetrisState::new(42);
+        let mut second = TetrisState::new(42);
+        let controls = [
+            GameControlArg::Left,
+            GameControlArg::Up,
+            GameControlArg::Right,
+            GameControlArg::Down,
+        ];
+        let mut random: u8 = 42;
+        while !first.game_over && first.ticks < 10_000 {
+            // Same as the firmware would get from its random number generator
+            random = random.wrapping_mul(73).wrapping_add(11);
+            let control = &controls[random as usize % controls.len()];
+            first.handle_control(control);
+            second.handle_control(control);
+            first.tick(random);
+            second.tick(random);
+            assert_eq!(first.draw_matrix().0, second.draw_matrix().0);
+        }
+        assert!(first.game_over);
+        assert_eq!(first.status(), second.status());
+
+        let shapes: [Shape; 7] = core::array::from_fn(|i| TetrisState::new(i as u8).piece.shape);
+        assert_eq!(shapes, SHAPES);
+    }
+}
@@ -1,5 +1,5 @@
 #![allow(clippy::needless_range_loop)]
-#![no_std]
+#![cfg_attr(feature = "rp2040", no_std)]
 
 #[cfg(any(
     all(feature = "ledmatrix", feature = "b1display"),
@@ -8,11 +8,13 @@
 ))]
 compile_error!("Features \"ledmatrix\", \"b1display\", and \"c1minimal\" are mutually exclusive");
 
+#[cfg(feature = "ledmatrix")]
+pub mod boot_animation;
 #[cfg(feature = "ledmatrix")]
 pub mod fl16;
 #[cfg(feature = "ledmatrix")]
 pub mod games;
-#[cfg(feature = "ledmatrix")]
+#[cfg(all(feature = "ledmatrix", feature = "rp2040"))]
 pub mod led_hal;
 #[cfg(feature = "ledmatrix")]
 #[rustfmt::skip]
@@ -20,17 +22,26 @@ pub mod mapping;
 #[cfg(feature = "ledmatrix")]
 pub mod animations;
 #[cfg(feature = "ledmatrix")]
+pub mod main_loop;
+#[cfg(feature = "ledmatrix")]
 pub mod matrix;
 #[cfg(feature = "ledmatrix")]
 pub mod patterns;
 
 #[cfg(feature = "b1display")]
 pub mod graphics;
-#[cfg(feature = "b1display")]
+#[cfg(all(feature = "b1display", feature = "rp2040"))]
 pub mod lcd_hal;
 
-#[cfg(all(feature = "c1minimal", not(feature = "qtpy")))]
+#[cfg(all(feature = "c1minimal", not(feature = "qtpy"), feature = "rp2040"))]
 pub mod minimal_hal;
 
+/// Stand-ins for the hardware, to run the module logic on the host
+#[cfg(not(feature = "rp2040"))]
+pub mod sim;
+
 pub mod control;
+pub mod flash;
+pub mod framing;
 pub mod serialnum;
+pub mod settings;
@@ -0,0 +1,402 @@
+//! What the main loop of the LED Matrix does, apart from talking to the hardware
+//!
+//! Shared by the firmware and inputmodule-sim. They provide the time in
+//! microseconds, random bytes and the pin that enables the LED controller.
+use core::fmt::Debug;
+
+use embedded_hal::digital::v2::OutputPin;
+
+use crate::control::*;
+use crate::games::{game_of_life, pong, snake, tetris};
+use crate::matrix::*;
+use crate::patterns::*;
+use crate::settings::SettingsStore;
+
+#[derive(PartialEq, Eq)]
+#[allow(dead_code)]
+enum SleepMode {
+    /// Instantly go to sleep ant
+    Instant,
+    /// Fade brightness out and in slowly when sleeping/waking-up
+    Fading,
+    // Display "SLEEP" when sleeping, instead of turning LEDs off
+    Debug,
+}
+
+/// Static configuration whether sleep shohld instantly turn all LEDs on/off or
+/// slowly fade themm on/off
+const SLEEP_MODE: SleepMode = SleepMode::Fading;
+
+const STARTUP_ANIMATION: bool = true;
+
+/// Brightness changes by one step every 100ms, when fading in or out
+const FADE_PERIOD: u64 = 100_000;
+
+/// Timers and sleep state, that are kept across iterations of the main loop
+pub struct MainLoop {
+    sleep_reason: Option<SleepReason>,
+    /// Sleep reason that was handled last
+    last_sleep_reason: Option<SleepReason>,
+    animation_timer: u64,
+    game_timer: u64,
+    fade_timer: u64,
+}
+
+impl MainLoop {
+    /// Start the timers at the current time
+    pub fn new(now: u64) -> Self {
+        Self {
+            sleep_reason: None,
+            last_sleep_reason: None,
+            animation_timer: now,
+            game_timer: now,
+            fade_timer: now,
+        }
+    }
+
+    /// Why the module is going to sleep or sleeping, if it is
+    pub fn sleep_reason(&self) -> Option<SleepReason> {
+        self.sleep_reason
+    }
+
+    /// Go to sleep or wake up with a signal, like the SLEEP# pin
+    ///
+    /// Takes effect on the next `tick`.
+    pub fn sleep_signal(&mut self, need_sleep: bool, signal_changed: bool, reason: SleepReason) {
+        self.sleep_reason = assign_sleep_reason(
+            self.last_sleep_reason,
+            self.sleep_reason,
+            need_sleep,
+            signal_changed,
+            reason,
+        );
+    }
+
+    /// Handle a command from the host
+    ///
+    /// Every command, other than going to sleep, wakes the module up.
+    #[allow(clippy::too_many_arguments)]
+    pub fn handle_command<P: OutputPin>(
+        &mut self,
+        command: &Command,
+        state: &mut LedmatrixState,
+        matrix: &mut Foo,
+        led_enable: &mut P,
+        random: u8,
+        settings: &mut SettingsStore,
+    ) -> (AckStatus, Option<Response>)
+    where
+        P::Error: Debug,
+    {
+        if let Command::Sleep(go_sleeping) = command {
+            self.sleep_signal(*go_sleeping, true, SleepReason::Command);
+        } else {
+            // If already sleeping, wake up.
+            // This means every command will wake the device up.
+            // Much more convenient than having to send the wakeup commmand.
+            self.sleep_reason = None;
+        }
+        self.handle_sleep(state, matrix, led_enable);
+
+        // If there's a very early command, cancel the startup animation
+        if !keeps_animation(command) {
+            state.upcoming_frames = None;
+        }
+
+        let result = handle_command(command, state, matrix, random, settings);
+        fill_grid_pixels(state, matrix);
+        match result {
+            Ok(response) => (AckStatus::Ok, response),
+            Err(status) => (status, None),
+        }
+    }
+
+    /// Go to sleep or wake up, fade and advance animations and games
+    ///
+    /// Must be called on every iteration of the main loop.
+    pub fn tick<P: OutputPin>(
+        &mut self,
+        state: &mut LedmatrixState,
+        matrix: &mut Foo,
+        led_enable: &mut P,
+        now: u64,
+        random: impl FnOnce() -> u8,
+    ) where
+        P::Error: Debug,
+    {
+        self.handle_sleep(state, matrix, led_enable);
+
+        // Fade in or out step by step, so that commands are still handled meanwhile
+        if now > self.fade_timer + FADE_PERIOD {
+            if fade_step(state, matrix) && !debug_mode(state) {
+                // Turn LED controller off to save power
+                led_enable.set_low().unwrap();
+            }
+            self.fade_timer = now;
+        }
+
+        // Handle period display updates. Don't do it too often
+        let render_again = now > self.animation_timer + state.animation_period;
+        if matches!(state.sleeping, SleepState::Awake) && render_again {
+            if let Some(ref mut upcoming) = state.upcoming_frames {
+                if let Some(next_frame) = upcoming.next() {
+                    state.grid = next_frame;
+                } else {
+                    // Animation is over. Clear screen
+                    state.grid = Grid::default();
+                }
+            }
+
+            fill_grid_pixels(state, matrix);
+            if state.animate {
+                state.grid.rotate(1);
+            }
+            self.animation_timer = now;
+        }
+
+        // Handle game state
+        if now > self.game_timer + game_step_period(state) {
+            let random = random();
+            // Progress and score are available with GameStatus, don't write
+            // anything to the serial port that could be mistaken for a response
+            match state.game {
+                Some(GameState::GameOfLife(_)) => game_of_life::game_step(state, random),
+                Some(GameState::Pong(_)) => pong::game_step(state, random),
+                Some(GameState::Tetris(_)) => tetris::game_step(state, random),
+                Some(GameState::Snake(_)) => snake::game_step(state, random),
+                None => {}
+            }
+            self.game_timer = now;
+        }
+    }
+
+    fn handle_sleep<P: OutputPin>(
+        &mut self,
+        state: &mut LedmatrixState,
+        matrix: &mut Foo,
+        led_enable: &mut P,
+    ) where
+        P::Error: Debug,
+    {
+        handle_sleep(self.sleep_reason, state, matrix, led_enable);
+        self.last_sleep_reason = self.sleep_reason;
+    }
+}
+
+/// Time between two steps of the current game, in microseconds
+pub fn game_step_period(state: &LedmatrixState) -> u64 {
+    match state.game {
+        Some(GameState::Pong(ref pong_state)) => 100_000 - 5_000 * pong_state.speed,
+        Some(GameState::Snake(_)) => 500_000,
+        Some(GameState::Tetris(ref tetris_state)) => tetris_state.step_period(),
+        Some(GameState::GameOfLife(_)) => 500_000,
+        _ => 500_000,
+    }
+}
+
+fn dyn_sleep_mode(state: &LedmatrixState) -> SleepMode {
+    if state.debug_mode {
+        SleepMode::Debug
+    } else {
+        SLEEP_MODE
+    }
+}
+
+fn debug_mode(state: &LedmatrixState) -> bool {
+    dyn_sleep_mode(state) == SleepMode::Debug
+}
+
+pub fn show_startup_animation(state: &LedmatrixState) -> bool {
+    // Show startup animation
+    STARTUP_ANIMATION && !debug_mode(state)
+}
+
+fn assign_sleep_reason(
+    previous: Option<SleepReason>,
+    current: Option<SleepReason>,
+    need_sleep: bool,
+    // Whether the signal has actually changed in between firing
+    signal_changed: bool,
+    new: SleepReason,
+) -> Option<SleepReason> {
+    if !need_sleep {
+        None
+    } else if current.is_some() && (Some(new) == previous || !signal_changed) {
+        current
+    } else {
+        Some(new)
+    }
+}
+
+// Will do nothing if already in the right state
+fn handle_sleep<P: OutputPin>(
+    sleep_reason: Option<SleepReason>,
+    state: &mut LedmatrixState,
+    matrix: &mut Foo,
+    led_enable: &mut P,
+) where
+    P::Error: Debug,
+{
+    match (state.sleeping.clone(), sleep_reason) {
+        // Awake and staying awake
+        (SleepState::Awake, None) => (),
+        (SleepState::Awake, Some(sleep_reason)) => {
+            state.sleeping = SleepState::Sleeping((state.grid.clone(), state.brightness));
+            if dyn_sleep_mode(state) == SleepMode::Fading {
+                // Slowly decrease brightness, the LED controller is turned off afterwards
+                state.fade = Some(current_brightness(state));
+            } else if debug_mode(state) {
+                state.grid = display_sleep_reason(sleep_reason);
+                fill_grid_pixels(state, matrix);
+            } else {
+                // Turn LED controller off to save power
+                led_enable.set_low().unwrap();
+            }
+
+            // TODO: Set up SLEEP# pin as interrupt and wfi
+            //cortex_m::asm::wfi();
+        }
+        // Already sleeping and new sleep reason => just keep sleeping
+        (SleepState::Sleeping(_), Some(sleep_reason)) => {
+            // If debug mode is enabled, then make sure the latest sleep reason is displayed
+            if debug_mode(state) {
+                state.grid = display_sleep_reason(sleep_reason);
+                fill_grid_pixels(state, matrix);
+            }
+        }
+        // Sleeping and need to wake up
+        (SleepState::Sleeping((old_grid, _)), None) => {
+            // Restore back grid before sleeping
+            state.sleeping = SleepState::Awake;
+            state.grid = old_grid;
+
+            // Slowly increase brightness, from where fading out stopped
+            state.fade = if dyn_sleep_mode(state) == SleepMode::Fading {
+                Some(state.fade.unwrap_or(0))
+            } else {
+                None
+            };
+            fill_grid_pixels(state, matrix);
+
+            // Power LED controller back on
+            if !debug_mode(state) {
+                led_enable.set_high().unwrap();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fl16::{LedMatrix, EVT_CALC_PIXEL};
+    use crate::settings;
+    use crate::sim::{SimI2c, SimPin};
+
+    /// Everything that the firmware passes to the main loop
+    struct Module {
+        main_loop: MainLoop,
+        state: LedmatrixState,
+        matrix: Foo,
+        led_enable: SimPin,
+        settings: SettingsStore,
+        now: u64,
+    }
+
+    impl Module {
+        fn new(state: LedmatrixState) -> Self {
+            Self {
+                main_loop: MainLoop::new(0),
+                state,
+                matrix: LedMatrix::new(SimI2c, EVT_CALC_PIXEL),
+                led_enable: SimPin { high: true },
+                settings: SettingsStore::new(settings::flash()),
+                now: 0,
+            }
+        }
+
+        fn command(&mut self, command: Command) -> (AckStatus, Option<Response>) {
+            self.main_loop.handle_command(
+                &command,
+                &mut self.state,
+                &mut self.matrix,
+                &mut self.led_enable,
+                0,
+                &mut self.settings,
+            )
+        }
+
+        /// Wait until the fade takes the next step
+        fn fade_step(&mut self) {
+            self.now += FADE_PERIOD + 1;
+            self.main_loop.tick(
+                &mut self.state,
+                &mut self.matrix,
+                &mut self.led_enable,
+                self.now,
+                || 0,
+            );
+        }
+    }
+
+    #[test]
+    fn fade_without_blocking_commands() {
+        let mut module = Module::new(LedmatrixState {
+            grid: percentage(100),
+            brightness: 50,
+            ..Default::default()
+        });
+
+        // Handled right away, the LEDs go dark step by step
+        assert_eq!(module.command(Command::Sleep(true)), (AckStatus::Ok, None));
+        assert!(matches!(module.state.sleeping, SleepState::Sleeping(_)));
+        let mut steps = 0;
+        while current_brightness(&module.state) > 0 {
+            assert!(module.led_enable.high);
+            module.fade_step();
+            steps += 1;
+        }
+        assert_eq!(steps, 10);
+        assert!(!module.led_enable.high);
+
+        // Every command wakes it up, it gets brighter with every step
+        let brightness = Some(response::encode_u8(50));
+        assert_eq!(
+            module.command(Command::GetBrightness),
+            (AckStatus::Ok, brightness)
+        );
+        assert!(matches!(module.state.sleeping, SleepState::Awake));
+        assert!(module.led_enable.high);
+        assert_eq!(current_brightness(&module.state), 0);
+        // Only up to the brightness that's set meanwhile
+        module.command(Command::SetBrightness(12));
+        for brightness in [5, 10, 12, 12] {
+            module.fade_step();
+            assert_eq!(current_brightness(&module.state), brightness);
+        }
+        assert_eq!(module.state.fade, None);
+    }
+
+    #[test]
+    fn sequence_overflow_is_rejected() {
+        let mut module = Module::new(LedmatrixState::default());
+        module.command(Command::Sequence(SequenceArg::Begin));
+        for _ in 0..MAX_SEQUENCE_FRAMES {
+            let added = module.command(Command::Sequence(SequenceArg::AddFrame(100)));
+            assert_eq!(added, (AckStatus::Ok, None));
+        }
+        let added = module.command(Command::Sequence(SequenceArg::AddFrame(100)));
+        assert_eq!(added, (AckStatus::BadArgs, None));
+        assert_eq!(module.state.sequence.len(), MAX_SEQUENCE_FRAMES);
+    }
+
+    #[test]
+    fn boot_animation_needs_begin() {
+        // Doesn't touch the flash, that's only done after Begin
+        let mut module = Module::new(LedmatrixState::default());
+        let added = module.command(Command::BootAnimation(BootAnimationArg::AddFrame(100)));
+        assert_eq!(added, (AckStatus::BadArgs, None));
+        let committed = module.command(Command::BootAnimation(BootAnimationArg::Commit));
+        assert_eq!(committed, (AckStatus::BadArgs, None));
+    }
+}
@@ -1,11 +1,12 @@
 use crate::animations::*;
-use crate::control::PwmFreqArg;
+use crate::boot_animation;
+use crate::control::{response::GameStatus, PwmFreqArg};
 use crate::games::game_of_life::GameOfLifeState;
 use crate::games::pong::PongState;
 use crate::games::snake::SnakeState;
+use crate::games::tetris::TetrisState;
 
-pub const WIDTH: usize = 9;
-pub const HEIGHT: usize = 34;
+pub use inputmodule_protocol::{HEIGHT, WIDTH};
 pub const LEDS: usize = WIDTH * HEIGHT;
 
 #[derive(Clone)]
@@ -35,6 +36,8 @@ pub struct LedmatrixState {
     pub brightness: u8,
     /// Current sleep state
     pub sleeping: SleepState,
+    /// Brightness that the LEDs are driven with while fading in or out
+    pub fade: Option<u8>,
     /// State of the current game, if any
     pub game: Option<GameState>,
     pub animation_period: u64,
@@ -48,6 +51,30 @@ pub struct LedmatrixState {
     /// - No automatic sleeping
     pub debug_mode: bool,
     pub upcoming_frames: Option<Animation>,
+    /// Boot animation that's currently being uploaded
+    pub boot_animation_upload: Option<boot_animation::Upload>,
+    /// Frame sequence uploaded to RAM, played with SequenceArg::Play
+    pub sequence: SequenceFrames,
+}
+
+impl Default for LedmatrixState {
+    fn default() -> Self {
+        Self {
+            grid: Grid::default(),
+            col_buffer: Grid::default(),
+            animate: false,
+            brightness: 51, // Default to 51/255 = 20% brightness
+            sleeping: SleepState::Awake,
+            fade: None,
+            game: None,
+            animation_period: 31_250, // 31,250 us = 32 FPS
+            pwm_freq: PwmFreqArg::P29k,
+            debug_mode: false,
+            upcoming_frames: None,
+            boot_animation_upload: None,
+            sequence: SequenceFrames::new(),
+        }
+    }
 }
 
 #[allow(clippy::large_enum_variant)]
@@ -72,5 +99,18 @@ pub enum SleepReason {
 pub enum GameState {
     Snake(SnakeState),
     Pong(PongState),
+    Tetris(TetrisState),
     GameOfLife(GameOfLifeState),
 }
+
+impl GameState {
+    /// Response to the GameStatus command
+    pub fn status(&self) -> GameStatus {
+        match self {
+            GameState::Snake(snake) => snake.status(),
+            GameState::Pong(pong) => pong.status(),
+            GameState::Tetris(tetris) => tetris.status(),
+            GameState::GameOfLife(gol) => gol.status(),
+        }
+    }
+}
@@ -1,20 +1,21 @@
+#[cfg(feature = "rp2040")]
 use rp2040_hal::{
     gpio::bank0::{Gpio26, Gpio27},
     pac::I2C1,
 };
 
 use crate::fl16::LedMatrix;
+#[cfg(feature = "rp2040")]
 use crate::led_hal as bsp;
 use crate::mapping::*;
 use crate::matrix::*;
 
-/// Bytes needed to represent all LEDs with a single bit
-/// math.ceil(WIDTH * HEIGHT / 8)
-pub const DRAW_BYTES: usize = 39;
+pub use inputmodule_protocol::DRAW_BYTES;
 
 /// Maximum number of brightneses levels
 pub const BRIGHTNESS_LEVELS: u8 = 255;
 
+#[cfg(feature = "rp2040")]
 pub type Foo = LedMatrix<
     bsp::hal::I2C<
         I2C1,
@@ -24,6 +25,8 @@ pub type Foo = LedMatrix<
         ),
     >,
 >;
+#[cfg(not(feature = "rp2040"))]
+pub type Foo = LedMatrix<crate::sim::SimI2c>;
 
 pub fn draw(bytes: &[u8; DRAW_BYTES]) -> Grid {
     let mut grid = Grid::default();
@@ -320,6 +323,38 @@ pub fn set_brightness(state: &mut LedmatrixState, brightness: u8, matrix: &mut F
     fill_grid_pixels(state, matrix);
 }
 
+/// Brightness that the LEDs are currently driven with
+pub fn current_brightness(state: &LedmatrixState) -> u8 {
+    state.fade.unwrap_or(state.brightness)
+}
+
+/// Move the brightness of a fade one step closer to where it's going
+///
+/// When going to sleep it fades out, otherwise in to the set brightness.
+/// Returns true when it has just faded out completely.
+pub fn fade_step(state: &mut LedmatrixState, matrix: &mut Foo) -> bool {
+    let Some(current) = state.fade else {
+        return false;
+    };
+    let sleeping = matches!(state.sleeping, SleepState::Sleeping(_));
+    let target = if sleeping { 0 } else { state.brightness };
+    let next = if current < target {
+        current.saturating_add(5).min(target)
+    } else {
+        current.saturating_sub(5).max(target)
+    };
+    // Stays dark until waking up
+    state.fade = if sleeping || next != target {
+        Some(next)
+    } else {
+        None
+    };
+    if next != current {
+        fill_grid_pixels(state, matrix);
+    }
+    sleeping && current != 0 && next == 0
+}
+
 /// Just sends two I2C commands for the entire grid
 pub fn fill_grid_pixels(state: &LedmatrixState, matrix: &mut Foo) {
     // 0xB4 LEDs on the first page, 0xAB on the second page
@@ -328,7 +363,7 @@ pub fn fill_grid_pixels(state: &LedmatrixState, matrix: &mut Foo) {
         for x in 0..WIDTH {
             let (register, page) = (matrix.device.calc_pixel)(x as u8, y as u8);
             brightnesses[(page as usize) * 0xB4 + (register as usize)] =
-                ((state.grid.0[x][y] as u64) * (state.brightness as u64)
+                ((state.grid.0[x][y] as u64) * (current_brightness(state) as u64)
                     / (BRIGHTNESS_LEVELS as u64)) as u8;
         }
     }
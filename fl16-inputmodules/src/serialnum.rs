@@ -1,19 +1,27 @@
 // Get serial number from last 4K block of the first 1M
+#[cfg(feature = "rp2040")]
 const FLASH_OFFSET: usize = 0x10000000;
+#[cfg(feature = "rp2040")]
 const LAST_4K_BLOCK: usize = 0xff000;
+#[cfg(feature = "rp2040")]
 const SERIALNUM_LEN: usize = 18;
 
+#[cfg(feature = "rp2040")]
 #[repr(packed)]
 pub struct SerialnumStructRaw {
     sn_rev: u8,
     serialnum: [u8; SERIALNUM_LEN],
+    hw_rev: u8,
     crc32: [u8; 4],
 }
 
 pub struct SerialnumStruct {
     pub serialnum: &'static str,
+    /// Hardware revision, see flash_layout.md
+    pub hw_revision: u8,
 }
 
+#[cfg(feature = "rp2040")]
 pub fn get_serialnum() -> Option<SerialnumStruct> {
     // Flash is mapped into memory, just read it from there
     let ptr: *const u8 = (FLASH_OFFSET + LAST_4K_BLOCK) as *const u8;
@@ -39,9 +47,21 @@ pub fn get_serialnum() -> Option<SerialnumStruct> {
 
     Some(SerialnumStruct {
         serialnum: core::str::from_utf8(&sn_raw.serialnum).ok()?,
+        hw_revision: sn_raw.hw_rev,
     })
 }
 
+/// The simulator has no serial number block, like a module that was never programmed
+#[cfg(not(feature = "rp2040"))]
+pub fn get_serialnum() -> Option<SerialnumStruct> {
+    None
+}
+
+/// Hardware revision from the serial number block, if programmed
+pub fn get_hw_revision() -> Option<u8> {
+    Some(get_serialnum()?.hw_revision)
+}
+
 /// Get the firmware version in a format for USB Device Release
 /// The value is in binary coded decimal with a format of 0xJJMN where JJ is the major version number, M is the minor version number and N is the sub minor version number. e.g. USB 2.0 is reported as 0x0200, USB 1.1 as 0x0110 and USB 1.0 as 0x0100.
 pub fn device_release() -> u16 {
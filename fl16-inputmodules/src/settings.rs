@@ -0,0 +1,53 @@
+//! Settings that persist across power cycles
+//!
+//! Stored in the "Persistent Storage" region of the flash, see flash_layout.md.
+//! Each setting is stored as the parameters of the command that sets it, with
+//! the command ID as key. Loading them replays those commands.
+use heapless::Vec;
+
+pub use inputmodule_settings::{Store, StoreError, SECTOR_SIZE};
+
+use crate::control::{Command, CommandVals};
+use crate::flash::RpFlash;
+use inputmodule_protocol::{MAGIC, MAX_COMMAND_LEN};
+
+pub type SettingsStore = Store<RpFlash>;
+
+/// Start of the Persistent Storage region, relative to the start of flash
+const STORAGE_OFFSET: usize = 0x0FB000;
+const STORAGE_SIZE: usize = 4 * SECTOR_SIZE;
+
+/// Persistent Storage region of the flash
+pub fn flash() -> RpFlash {
+    RpFlash::new(STORAGE_OFFSET, STORAGE_SIZE)
+}
+
+/// Persist the parameters of the setter commands
+pub fn save(store: &mut SettingsStore, commands: &[Command]) -> Result<(), StoreError> {
+    for command in commands {
+        let mut buf = [0; MAX_COMMAND_LEN];
+        let len = command.encode(&mut buf);
+        store.set(command.command_val() as u8, &buf[3..len])?;
+    }
+    Ok(())
+}
+
+/// Setter commands that restore the persisted settings
+///
+/// Settings that were never saved are skipped, as are values that aren't valid
+/// anymore, for example after a firmware update.
+pub fn load(store: &SettingsStore, keys: &[CommandVals]) -> Vec<Command, 8> {
+    let mut commands = Vec::new();
+    for key in keys {
+        let mut buf = [0; MAX_COMMAND_LEN];
+        buf[..2].copy_from_slice(&MAGIC);
+        buf[2] = *key as u8;
+        let Some(len) = store.get(*key as u8, &mut buf[3..]) else {
+            continue;
+        };
+        if let Ok(command) = Command::parse(&buf[..3 + len]) {
+            let _ = commands.push(command);
+        }
+    }
+    commands
+}
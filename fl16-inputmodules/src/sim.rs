@@ -0,0 +1,137 @@
+//! Stand-ins for the hardware that the firmware talks to
+//!
+//! They accept everything and don't do anything, what's displayed is taken from
+//! the module state instead. Used by inputmodule-sim.
+use embedded_hal::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal::blocking::i2c;
+#[cfg(feature = "b1display")]
+use embedded_hal::blocking::spi;
+#[cfg(any(feature = "ledmatrix", feature = "b1display"))]
+use embedded_hal::digital::v2::OutputPin;
+#[cfg(feature = "c1minimal")]
+use smart_leds::{SmartLedsWrite, RGB8};
+
+/// The module disappears from the host when it resets, so does the simulator
+pub fn reset_to_usb_boot(_gpio_activity_pin_mask: u32, _disable_interface_mask: u32) {
+    std::process::exit(0);
+}
+
+/// I2C bus of the LED controller
+pub struct SimI2c;
+
+impl i2c::Write for SimI2c {
+    type Error = ();
+
+    fn write(&mut self, _address: u8, _bytes: &[u8]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl i2c::Read for SimI2c {
+    type Error = ();
+
+    fn read(&mut self, _address: u8, buffer: &mut [u8]) -> Result<(), Self::Error> {
+        buffer.fill(0);
+        Ok(())
+    }
+}
+
+/// SPI bus of the display controller
+#[cfg(feature = "b1display")]
+pub struct SimSpi;
+
+#[cfg(feature = "b1display")]
+impl spi::Write<u8> for SimSpi {
+    type Error = ();
+
+    fn write(&mut self, _words: &[u8]) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+/// GPIO that's used as output, like chip select or the LED controller enable
+///
+/// Remembers the level it was set to last.
+#[cfg(any(feature = "ledmatrix", feature = "b1display"))]
+#[derive(Default)]
+pub struct SimPin {
+    pub high: bool,
+}
+
+#[cfg(any(feature = "ledmatrix", feature = "b1display"))]
+impl OutputPin for SimPin {
+    type Error = ();
+
+    fn set_low(&mut self) -> Result<(), Self::Error> {
+        self.high = false;
+        Ok(())
+    }
+
+    fn set_high(&mut self) -> Result<(), Self::Error> {
+        self.high = true;
+        Ok(())
+    }
+}
+
+/// RGB LED, remembers the last color that was written
+#[cfg(feature = "c1minimal")]
+#[derive(Default)]
+pub struct SimLed {
+    pub color: RGB8,
+}
+
+#[cfg(feature = "c1minimal")]
+impl SmartLedsWrite for SimLed {
+    type Error = ();
+    type Color = RGB8;
+
+    fn write<T, I>(&mut self, iterator: T) -> Result<(), Self::Error>
+    where
+        T: IntoIterator<Item = I>,
+        I: Into<Self::Color>,
+    {
+        if let Some(color) = iterator.into_iter().next() {
+            self.color = color.into();
+        }
+        Ok(())
+    }
+}
+
+/// Blocks the thread, like the SysTick based delay of the firmware
+pub struct Delay;
+
+impl DelayMs<u32> for Delay {
+    fn delay_ms(&mut self, ms: u32) {
+        std::thread::sleep(std::time::Duration::from_millis(ms as u64));
+    }
+}
+
+impl DelayMs<u16> for Delay {
+    fn delay_ms(&mut self, ms: u16) {
+        self.delay_ms(ms as u32);
+    }
+}
+
+impl DelayMs<u8> for Delay {
+    fn delay_ms(&mut self, ms: u8) {
+        self.delay_ms(ms as u32);
+    }
+}
+
+impl DelayUs<u32> for Delay {
+    fn delay_us(&mut self, us: u32) {
+        std::thread::sleep(std::time::Duration::from_micros(us as u64));
+    }
+}
+
+impl DelayUs<u16> for Delay {
+    fn delay_us(&mut self, us: u16) {
+        self.delay_us(us as u32);
+    }
+}
+
+impl DelayUs<u8> for Delay {
+    fn delay_us(&mut self, us: u8) {
+        self.delay_us(us as u32);
+    }
+}
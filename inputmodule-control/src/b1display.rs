@@ -1,13 +1,18 @@
-use clap::Parser;
+use image::GrayImage;
+use inputmodule_protocol::response;
+use inputmodule_protocol::{heapless, Command, MAX_TEXT_LEN, PIXEL_COLUMN_BYTES};
 
-#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
-pub enum B1Pattern {
-    White,
-    Black,
-    //Checkerboard,
-}
+use crate::error::Error;
+use crate::module::{check_size, InputModule, ModulePort};
+
+const WIDTH: usize = 300;
+const HEIGHT: usize = 400;
+
+const HIGH_FPS_MASK: u8 = 0b00010000;
+const LOW_FPS_MASK: u8 = 0b00000111;
 
-#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
+/// Refresh rate of the display
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Fps {
     Quarter,
     Half,
@@ -19,72 +24,223 @@ pub enum Fps {
     ThirtyTwo,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
+impl Fps {
+    pub fn hz(&self) -> f32 {
+        match self {
+            Fps::Quarter => 0.25,
+            Fps::Half => 0.5,
+            Fps::One => 1.0,
+            Fps::Two => 2.0,
+            Fps::Four => 4.0,
+            Fps::Eight => 8.0,
+            Fps::Sixteen => 16.0,
+            Fps::ThirtyTwo => 32.0,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum PowerMode {
     Low,
     High,
 }
 
-/// B1 Display
-#[derive(Parser, Debug)]
-#[command(arg_required_else_help = true)]
-pub struct B1DisplaySubcommand {
-    /// Set sleep status or get, if no value provided
-    #[arg(long)]
-    pub sleeping: Option<Option<bool>>,
-
-    /// Jump to the bootloader
-    #[arg(long)]
-    pub bootloader: bool,
-
-    /// Crash the firmware (TESTING ONLY!)
-    #[arg(long)]
-    pub panic: bool,
-
-    /// Get the device version
-    #[arg(short, long)]
-    pub version: bool,
-
-    /// Turn display on/off
-    // TODO: Allow getting current state
-    #[arg(long)]
-    pub display_on: Option<Option<bool>>,
-
-    /// Display a simple pattern
-    #[arg(long)]
-    #[clap(value_enum)]
-    pub pattern: Option<B1Pattern>,
-
-    /// Invert screen on/off
-    #[arg(long)]
-    pub invert_screen: Option<Option<bool>>,
-
-    /// Screensaver on/off
-    #[arg(long)]
-    pub screen_saver: Option<Option<bool>>,
-
-    /// Set/get FPS
-    #[arg(long)]
-    #[clap(value_enum)]
-    pub fps: Option<Option<Fps>>,
-
-    /// Set/get power mode
-    #[arg(long)]
-    pub power_mode: Option<Option<PowerMode>>,
-
-    /// Set/get animation FPS
-    #[arg(long)]
-    pub animation_fps: Option<Option<u16>>,
-
-    /// Display a black&white image (300x400px)
-    #[arg(long)]
-    pub image: Option<String>,
-
-    /// Display an animated black&white GIF (300x400px)
-    #[arg(long)]
-    pub animated_gif: Option<String>,
+/// B1 Display, a 300x400px black and white LCD
+pub struct B1Display {
+    port: ModulePort,
+}
+
+impl InputModule for B1Display {
+    fn port(&mut self) -> &mut ModulePort {
+        &mut self.port
+    }
+}
+
+impl B1Display {
+    /// Size of the screen in pixels
+    pub const WIDTH: usize = WIDTH;
+    pub const HEIGHT: usize = HEIGHT;
+
+    pub fn open(serialdev: &str) -> Result<Self, Error> {
+        Ok(Self::from_port(ModulePort::open(serialdev)?))
+    }
+
+    /// Use a port that's already open, like after checking its capabilities
+    pub fn from_port(port: ModulePort) -> Self {
+        Self { port }
+    }
+
+    pub fn display_on(&mut self) -> Result<bool, Error> {
+        let response = self.port.query(Command::GetDisplayOn)?;
+        Ok(response::decode_bool(&response))
+    }
+
+    pub fn set_display_on(&mut self, on: bool) -> Result<(), Error> {
+        self.port.command(Command::DisplayOn(on))
+    }
+
+    pub fn invert_screen(&mut self) -> Result<bool, Error> {
+        let response = self.port.query(Command::GetInvertScreen)?;
+        Ok(response::decode_bool(&response))
+    }
+
+    pub fn set_invert_screen(&mut self, inverted: bool) -> Result<(), Error> {
+        self.port.command(Command::InvertScreen(inverted))
+    }
+
+    pub fn screensaver(&mut self) -> Result<bool, Error> {
+        let response = self.port.query(Command::GetScreenSaver)?;
+        Ok(response::decode_bool(&response))
+    }
+
+    pub fn set_screensaver(&mut self, on: bool) -> Result<(), Error> {
+        self.port.command(Command::ScreenSaver(on))
+    }
+
+    pub fn power_mode(&mut self) -> Result<PowerMode, Error> {
+        let response = self.port.query(Command::GetPowerMode)?;
+        if response::decode_bool(&response) {
+            Ok(PowerMode::High)
+        } else {
+            Ok(PowerMode::Low)
+        }
+    }
+
+    pub fn set_power_mode(&mut self, mode: PowerMode) -> Result<(), Error> {
+        match mode {
+            PowerMode::Low => self.port.command(Command::SetPowerMode(0)),
+            PowerMode::High => self.port.command(Command::SetPowerMode(1)),
+        }
+    }
+
+    /// Refresh rate in the current power mode
+    pub fn fps(&mut self) -> Result<Fps, Error> {
+        let response = self.port.query(Command::GetFps)?;
+        let current_fps = response::decode_u8(&response);
+
+        let fps = match self.power_mode()? {
+            PowerMode::High if current_fps & HIGH_FPS_MASK == 0 => Fps::Sixteen,
+            PowerMode::High => Fps::ThirtyTwo,
+            PowerMode::Low => match current_fps & LOW_FPS_MASK {
+                0 => Fps::Quarter,
+                1 => Fps::Half,
+                2 => Fps::One,
+                3 => Fps::Two,
+                4 => Fps::Four,
+                _ => Fps::Eight,
+            },
+        };
+        Ok(fps)
+    }
+
+    /// Set the refresh rate, switches to the power mode that supports it
+    pub fn set_fps(&mut self, fps: Fps) -> Result<(), Error> {
+        let response = self.port.query(Command::GetFps)?;
+        let current_fps = response::decode_u8(&response);
+
+        let power_mode = match fps {
+            Fps::Sixteen | Fps::ThirtyTwo => PowerMode::High,
+            _ => PowerMode::Low,
+        };
+        let fps_bits = match fps {
+            Fps::Quarter => current_fps & !LOW_FPS_MASK,
+            Fps::Half => (current_fps & !LOW_FPS_MASK) | 0b001,
+            Fps::One => (current_fps & !LOW_FPS_MASK) | 0b010,
+            Fps::Two => (current_fps & !LOW_FPS_MASK) | 0b011,
+            Fps::Four => (current_fps & !LOW_FPS_MASK) | 0b100,
+            Fps::Eight => (current_fps & !LOW_FPS_MASK) | 0b101,
+            Fps::Sixteen => current_fps & !HIGH_FPS_MASK,
+            Fps::ThirtyTwo => (current_fps & !HIGH_FPS_MASK) | 0b00010000,
+        };
+        self.set_power_mode(power_mode)?;
+        self.port.command(Command::SetFps(fps_bits))
+    }
+
+    /// Time between animation frames in ms
+    pub fn animation_period(&mut self) -> Result<u16, Error> {
+        let response = self.port.query(Command::GetAnimationPeriod)?;
+        Ok(response::decode_u16(&response))
+    }
+
+    pub fn set_animation_fps(&mut self, fps: u16) -> Result<(), Error> {
+        if fps == 0 {
+            return Err(Error::InvalidArgument("FPS must be at least 1".into()));
+        }
+        self.port.command(Command::SetAnimationPeriod(1000 / fps))
+    }
+
+    /// Show a line of text below the logo, at most MAX_TEXT_LEN bytes
+    pub fn set_text(&mut self, text: &str) -> Result<(), Error> {
+        if text.len() > MAX_TEXT_LEN {
+            return Err(Error::InvalidArgument(format!(
+                "Text must be at most {MAX_TEXT_LEN} bytes, not {}",
+                text.len()
+            )));
+        }
+        let mut string = heapless::String::new();
+        // Can't fail, the length is checked above
+        let _ = string.push_str(text);
+        self.port.command(Command::SetText(string))
+    }
 
     /// Clear display RAM
-    #[arg(long)]
-    pub clear_ram: bool,
+    pub fn clear_ram(&mut self) -> Result<(), Error> {
+        self.port.command(Command::ClearRam)
+    }
+
+    /// Display an image in black and white
+    /// Must be 300x400 in size.
+    /// Sends one 400px column in a single commands and a flush at the end
+    pub fn display_image(&mut self, img: &GrayImage) -> Result<(), Error> {
+        check_size(img, WIDTH, HEIGHT)?;
+
+        let (brightest, darkest) =
+            img.pixels()
+                .fold((0xFF, 0x00), |(brightest, darkest), pixel| {
+                    let br = pixel.0[0];
+                    let brightest = if br > brightest { br } else { brightest };
+                    let darkest = if br < darkest { br } else { darkest };
+                    (brightest, darkest)
+                });
+        let bright_diff = brightest - darkest;
+        // Anything brighter than 90% between darkest and brightest counts as white
+        // Just a heuristic. Don't use greyscale images! Use black and white instead
+        let threshold = darkest + (bright_diff / 10) * 9;
+
+        for x in 0..WIDTH {
+            let mut vals: [u8; PIXEL_COLUMN_BYTES] = [0; PIXEL_COLUMN_BYTES];
+
+            let mut byte: u8 = 0;
+            for y in 0..HEIGHT {
+                let pixel = img.get_pixel(x as u32, y as u32);
+                let brightness = pixel.0[0];
+                let black = brightness < threshold;
+
+                let bit = y % 8;
+                if bit == 0 {
+                    byte = 0;
+                }
+                if black {
+                    byte |= 1 << bit;
+                }
+                if bit == 7 {
+                    vals[y / 8] = byte;
+                }
+            }
+
+            self.port.command(Command::SetPixelColumn(x as u16, vals))?;
+        }
+
+        self.port.command(Command::FlushFramebuffer)
+    }
+
+    /// Make the whole display black or white
+    pub fn fill(&mut self, black: bool) -> Result<(), Error> {
+        for x in 0..WIDTH {
+            let byte = if black { 0xFF } else { 0x00 };
+            let vals: [u8; PIXEL_COLUMN_BYTES] = [byte; PIXEL_COLUMN_BYTES];
+            self.port.command(Command::SetPixelColumn(x as u16, vals))?;
+        }
+        self.port.command(Command::FlushFramebuffer)
+    }
 }
@@ -1,39 +1,30 @@
-use clap::Parser;
+use inputmodule_protocol::{Command, Rgb};
 
-#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
-pub enum Color {
-    White,
-    Black,
-    Red,
-    Green,
-    Blue,
-    Yellow,
-    Cyan,
-    Purple,
-}
+use crate::error::Error;
+use crate::module::{InputModule, ModulePort};
 
-#[derive(Parser, Debug)]
-#[command(arg_required_else_help = true)]
-pub struct C1MinimalSubcommand {
-    /// Set sleep status or get, if no value provided
-    #[arg(long)]
-    pub sleeping: Option<Option<bool>>,
+/// C1 Minimal, with a single RGB LED
+pub struct C1Minimal {
+    port: ModulePort,
+}
 
-    /// Jump to the bootloader
-    #[arg(long)]
-    pub bootloader: bool,
+impl InputModule for C1Minimal {
+    fn port(&mut self) -> &mut ModulePort {
+        &mut self.port
+    }
+}
 
-    /// Crash the firmware (TESTING ONLY!)
-    #[arg(long)]
-    pub panic: bool,
+impl C1Minimal {
+    pub fn open(serialdev: &str) -> Result<Self, Error> {
+        Ok(Self::from_port(ModulePort::open(serialdev)?))
+    }
 
-    /// Get the device version
-    #[arg(short, long)]
-    pub version: bool,
+    /// Use a port that's already open, like after checking its capabilities
+    pub fn from_port(port: ModulePort) -> Self {
+        Self { port }
+    }
 
-    /// Set color
-    // TODO: Allow getting current state
-    #[arg(long)]
-    #[clap(value_enum)]
-    pub set_color: Option<Color>,
+    pub fn set_color(&mut self, color: Rgb) -> Result<(), Error> {
+        self.port.command(Command::SetColor(color))
+    }
 }
@@ -0,0 +1,277 @@
+use image::{imageops, GrayImage, Luma};
+use inputmodule_protocol::{HEIGHT, WIDTH};
+
+use crate::error::Error;
+use crate::font::{convert_font, convert_symbol};
+use crate::ledmatrix::LedMatrix;
+use crate::module::check_size;
+
+/// Font items that fit on a single matrix, stacked from top to bottom
+const ITEMS_PER_MATRIX: usize = 5;
+
+/// Several LED matrices side by side, drawn on like a single one
+///
+/// Two matrices without gap make an 18x34 canvas. The gap is the number of
+/// columns between two matrices that aren't shown, so that shapes keep their
+/// proportions across the space in between.
+pub struct Canvas {
+    /// From left to right
+    matrices: Vec<LedMatrix>,
+    layout: Layout,
+}
+
+/// Where the matrices are on the canvas
+#[derive(Clone, Copy, Debug)]
+struct Layout {
+    matrices: usize,
+    gap: usize,
+}
+
+impl Canvas {
+    /// Takes the matrices from left to right
+    pub fn new(matrices: Vec<LedMatrix>, gap: usize) -> Result<Self, Error> {
+        if matrices.is_empty() {
+            return Err(Error::InvalidArgument(
+                "Canvas needs at least one LED matrix".into(),
+            ));
+        }
+        let layout = Layout {
+            matrices: matrices.len(),
+            gap,
+        };
+        Ok(Self { matrices, layout })
+    }
+
+    pub fn width(&self) -> usize {
+        self.layout.width()
+    }
+
+    pub fn height(&self) -> usize {
+        HEIGHT
+    }
+
+    /// For the commands that go to each matrix on its own, like brightness
+    pub fn matrices(&mut self) -> &mut [LedMatrix] {
+        &mut self.matrices
+    }
+
+    /// Display an image in greyscale
+    /// Must be the size of the canvas. First stages the columns on every
+    /// matrix and then commits all of them, so both sides change together.
+    pub fn display_gray_image(&mut self, img: &GrayImage) -> Result<(), Error> {
+        let parts = self.layout.split(img)?;
+        for (matrix, part) in self.matrices.iter_mut().zip(&parts) {
+            matrix.stage_gray_image(part)?;
+        }
+        for matrix in self.matrices.iter_mut() {
+            matrix.commit_cols()?;
+        }
+        Ok(())
+    }
+
+    /// Display an image in black and white
+    /// Must be the size of the canvas.
+    pub fn display_bw_image(&mut self, img: &GrayImage) -> Result<(), Error> {
+        self.display_gray_image(&black_and_white(img))
+    }
+
+    /// Display one equalizer bar per visible column, 9 per matrix
+    pub fn show_eq(&mut self, vals: &[u8]) -> Result<(), Error> {
+        let img = self.layout.eq_image(vals)?;
+        self.display_gray_image(&img)
+    }
+
+    /// Render a string, five letters on each matrix
+    pub fn show_string(&mut self, s: &str) -> Result<(), Error> {
+        let items: Vec<Vec<u8>> = s.chars().map(convert_font).collect();
+        self.show_font(&items)
+    }
+
+    /// Render a list of symbols, five on each matrix
+    /// Can use letters/numbers or symbol names, like 'sun', ':)'
+    pub fn show_symbols(&mut self, symbols: &[String]) -> Result<(), Error> {
+        let font_items: Vec<Vec<u8>> = symbols.iter().map(|x| convert_symbol(x)).collect();
+        self.show_font(&font_items)
+    }
+
+    /// Render 5x6 pixel font items, continuing on the next matrix when one is full
+    pub fn show_font(&mut self, font_items: &[Vec<u8>]) -> Result<(), Error> {
+        let img = self.layout.font_image(font_items);
+        self.display_gray_image(&img)
+    }
+}
+
+impl Layout {
+    fn width(&self) -> usize {
+        self.matrices * (WIDTH + self.gap) - self.gap
+    }
+
+    /// Column of the canvas where a matrix starts
+    fn offset(&self, matrix: usize) -> usize {
+        matrix * (WIDTH + self.gap)
+    }
+
+    /// Part of the image that each matrix shows, from left to right
+    fn split(&self, img: &GrayImage) -> Result<Vec<GrayImage>, Error> {
+        check_size(img, self.width(), HEIGHT)?;
+        Ok((0..self.matrices)
+            .map(|i| {
+                let x = self.offset(i) as u32;
+                imageops::crop_imm(img, x, 0, WIDTH as u32, HEIGHT as u32).to_image()
+            })
+            .collect())
+    }
+
+    fn eq_image(&self, vals: &[u8]) -> Result<GrayImage, Error> {
+        let columns = self.matrices * WIDTH;
+        if vals.len() > columns {
+            return Err(Error::InvalidArgument(format!(
+                "At most {columns} EQ values fit, not {}",
+                vals.len()
+            )));
+        }
+        let mut img = GrayImage::new(self.width() as u32, HEIGHT as u32);
+
+        for (col, val) in vals.iter().enumerate() {
+            let x = (self.offset(col / WIDTH) + col % WIDTH) as u32;
+            let row: usize = HEIGHT / 2;
+            let above: usize = (*val as usize).min(HEIGHT) / 2;
+            let below = (*val as usize).min(HEIGHT) - above;
+
+            for i in 0..above {
+                img.put_pixel(x, (row + i) as u32, Luma([0xFF]));
+            }
+            for i in 0..below {
+                img.put_pixel(x, (row - 1 - i) as u32, Luma([0xFF]));
+            }
+        }
+        Ok(img)
+    }
+
+    /// Items that don't fit anymore are left out
+    fn font_image(&self, font_items: &[Vec<u8>]) -> GrayImage {
+        let mut img = GrayImage::new(self.width() as u32, HEIGHT as u32);
+        let fit = self.matrices * ITEMS_PER_MATRIX;
+
+        for (item_i, item_pixels) in font_items.iter().take(fit).enumerate() {
+            let x_offset = self.offset(item_i / ITEMS_PER_MATRIX) + 2;
+            let y_offset = (item_i % ITEMS_PER_MATRIX) * 7;
+            for pixel_x in 0..5 {
+                for pixel_y in 0..6 {
+                    if item_pixels[pixel_x + pixel_y * 5] == 1 {
+                        let (x, y) = (x_offset + pixel_x, y_offset + pixel_y);
+                        img.put_pixel(x as u32, y as u32, Luma([0xFF]));
+                    }
+                }
+            }
+        }
+        img
+    }
+}
+
+fn black_and_white(img: &GrayImage) -> GrayImage {
+    GrayImage::from_fn(img.width(), img.height(), |x, y| {
+        if img.get_pixel(x, y).0[0] > 0xFF / 2 {
+            Luma([0xFF])
+        } else {
+            Luma([0x00])
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledmatrix::pixel_to_brightness;
+
+    const TWO_WITH_GAP: Layout = Layout {
+        matrices: 2,
+        gap: 3,
+    };
+
+    fn lit(img: &GrayImage, x: usize, y: usize) -> bool {
+        img.get_pixel(x as u32, y as u32).0[0] == 0xFF
+    }
+
+    #[test]
+    fn split_at_the_edges() {
+        let layout = Layout {
+            matrices: 2,
+            gap: 0,
+        };
+        assert_eq!(layout.width(), 2 * WIDTH);
+        assert_eq!(TWO_WITH_GAP.width(), 2 * WIDTH + 3);
+
+        // Last column of the left matrix, the gap and first of the right one
+        let img = GrayImage::from_fn(TWO_WITH_GAP.width() as u32, HEIGHT as u32, |x, _| {
+            Luma([x as u8])
+        });
+        let parts = TWO_WITH_GAP.split(&img).unwrap();
+        assert_eq!(parts.len(), 2);
+        for part in &parts {
+            assert_eq!(part.dimensions(), (WIDTH as u32, HEIGHT as u32));
+        }
+        assert_eq!(parts[0].get_pixel(WIDTH as u32 - 1, 0).0, [8]);
+        assert_eq!(parts[1].get_pixel(0, HEIGHT as u32 - 1).0, [12]);
+
+        // Must be exactly the size of the canvas
+        let too_small = GrayImage::new(2 * WIDTH as u32, HEIGHT as u32);
+        assert!(matches!(
+            TWO_WITH_GAP.split(&too_small),
+            Err(Error::ImageSize { .. })
+        ));
+    }
+
+    #[test]
+    fn eq_clipped_to_the_height() {
+        let img = TWO_WITH_GAP.eq_image(&[0, 1, 200]).unwrap();
+        assert!((0..HEIGHT).all(|y| !lit(&img, 0, y)));
+        assert_eq!((0..HEIGHT).filter(|&y| lit(&img, 1, y)).count(), 1);
+        assert!((0..HEIGHT).all(|y| lit(&img, 2, y)));
+
+        // The tenth bar is on the right matrix, after the gap
+        let mut vals = [0; 2 * WIDTH];
+        vals[WIDTH] = 2;
+        let img = TWO_WITH_GAP.eq_image(&vals).unwrap();
+        assert!(lit(&img, WIDTH + 3, HEIGHT / 2));
+        assert!(lit(&img, WIDTH + 3, HEIGHT / 2 - 1));
+        assert!((0..HEIGHT).all(|y| !lit(&img, WIDTH, y)));
+
+        assert!(matches!(
+            TWO_WITH_GAP.eq_image(&[0; 2 * WIDTH + 1]),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn font_continues_on_the_next_matrix() {
+        let item = vec![1; 30];
+        let img = TWO_WITH_GAP.font_image(&vec![item; 11]);
+        // Sixth item at the top of the right matrix
+        assert!(lit(&img, WIDTH + 3 + 2, 0));
+        assert!(!lit(&img, WIDTH + 3 + 1, 0));
+        // Eleventh doesn't fit anymore
+        let lit_pixels = img.pixels().filter(|pixel| pixel.0[0] == 0xFF).count();
+        assert_eq!(lit_pixels, 10 * 30);
+    }
+
+    #[test]
+    fn black_and_white_threshold() {
+        let img = GrayImage::from_fn(4, 1, |x, _| Luma([[0x00, 0x7F, 0x80, 0xFF][x as usize]]));
+        let bw = black_and_white(&img);
+        let vals: Vec<u8> = bw.pixels().map(|pixel| pixel.0[0]).collect();
+        assert_eq!(vals, [0x00, 0x00, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn grayscale_brightness() {
+        let brightness = |gray| pixel_to_brightness(&Luma([gray]));
+        assert_eq!(brightness(0x00), 0x00);
+        assert_eq!(brightness(0xFF), 0xFF);
+        // Dark ones are brightened, the middle ones dimmed
+        assert_eq!(brightness(20), 40);
+        assert_eq!(brightness(60), 60);
+        assert_eq!(brightness(120), 60);
+        assert_eq!(brightness(160), 200);
+    }
+}
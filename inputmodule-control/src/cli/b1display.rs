@@ -0,0 +1,180 @@
+use clap::Parser;
+use inputmodule_protocol::CommandVals;
+
+use crate::cli::SettingsAction;
+
+#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
+pub enum B1Pattern {
+    White,
+    Black,
+    //Checkerboard,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
+pub enum Fps {
+    Quarter,
+    Half,
+    One,
+    Two,
+    Four,
+    Eight,
+    Sixteen,
+    ThirtyTwo,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
+pub enum PowerMode {
+    Low,
+    High,
+}
+
+impl From<Fps> for inputmodule_control::Fps {
+    fn from(fps: Fps) -> Self {
+        use inputmodule_control::Fps as F;
+        match fps {
+            Fps::Quarter => F::Quarter,
+            Fps::Half => F::Half,
+            Fps::One => F::One,
+            Fps::Two => F::Two,
+            Fps::Four => F::Four,
+            Fps::Eight => F::Eight,
+            Fps::Sixteen => F::Sixteen,
+            Fps::ThirtyTwo => F::ThirtyTwo,
+        }
+    }
+}
+
+impl From<PowerMode> for inputmodule_control::PowerMode {
+    fn from(mode: PowerMode) -> Self {
+        match mode {
+            PowerMode::Low => inputmodule_control::PowerMode::Low,
+            PowerMode::High => inputmodule_control::PowerMode::High,
+        }
+    }
+}
+
+/// B1 Display
+#[derive(Parser, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct B1DisplaySubcommand {
+    /// Set sleep status or get, if no value provided
+    #[arg(long)]
+    pub sleeping: Option<Option<bool>>,
+
+    /// Jump to the bootloader
+    #[arg(long)]
+    pub bootloader: bool,
+
+    /// Crash the firmware (TESTING ONLY!)
+    #[arg(long)]
+    pub panic: bool,
+
+    /// Get the device version
+    #[arg(short, long)]
+    pub version: bool,
+
+    /// Turn display on/off
+    // TODO: Allow getting current state
+    #[arg(long)]
+    pub display_on: Option<Option<bool>>,
+
+    /// Show a line of text below the logo
+    #[arg(long)]
+    pub text: Option<String>,
+
+    /// Display a simple pattern
+    #[arg(long)]
+    #[clap(value_enum)]
+    pub pattern: Option<B1Pattern>,
+
+    /// Invert screen on/off
+    #[arg(long)]
+    pub invert_screen: Option<Option<bool>>,
+
+    /// Screensaver on/off
+    #[arg(long)]
+    pub screen_saver: Option<Option<bool>>,
+
+    /// Set/get FPS
+    #[arg(long)]
+    #[clap(value_enum)]
+    pub fps: Option<Option<Fps>>,
+
+    /// Set/get power mode
+    #[arg(long)]
+    pub power_mode: Option<Option<PowerMode>>,
+
+    /// Set/get animation FPS
+    #[arg(long)]
+    pub animation_fps: Option<Option<u16>>,
+
+    /// Display a black&white image (300x400px)
+    #[arg(long)]
+    pub image: Option<String>,
+
+    /// Display an animated black&white GIF (300x400px)
+    #[arg(long)]
+    pub animated_gif: Option<String>,
+
+    /// Clear display RAM
+    #[arg(long)]
+    pub clear_ram: bool,
+
+    /// Save the current settings to flash, load them or reset to defaults
+    #[arg(long)]
+    #[clap(value_enum)]
+    pub settings: Option<SettingsAction>,
+}
+
+impl B1DisplaySubcommand {
+    /// Module specific commands needed by the selected options
+    pub fn required_commands(&self) -> Vec<(&'static str, CommandVals)> {
+        let options = [
+            (
+                "--display-on",
+                self.display_on.is_some(),
+                CommandVals::DisplayOn,
+            ),
+            ("--text", self.text.is_some(), CommandVals::SetText),
+            (
+                "--pattern",
+                self.pattern.is_some(),
+                CommandVals::SetPixelColumn,
+            ),
+            (
+                "--invert-screen",
+                self.invert_screen.is_some(),
+                CommandVals::InvertScreen,
+            ),
+            (
+                "--screen-saver",
+                self.screen_saver.is_some(),
+                CommandVals::ScreenSaver,
+            ),
+            ("--fps", self.fps.is_some(), CommandVals::SetFps),
+            (
+                "--power-mode",
+                self.power_mode.is_some(),
+                CommandVals::SetPowerMode,
+            ),
+            (
+                "--animation-fps",
+                self.animation_fps.is_some(),
+                CommandVals::AnimationPeriod,
+            ),
+            ("--image", self.image.is_some(), CommandVals::SetPixelColumn),
+            (
+                "--animated-gif",
+                self.animated_gif.is_some(),
+                CommandVals::SetPixelColumn,
+            ),
+            ("--clear-ram", self.clear_ram, CommandVals::ClearRam),
+            ("--settings", self.settings.is_some(), CommandVals::Settings),
+        ];
+        options
+            .into_iter()
+            .filter(|(_, selected, _)| *selected)
+            .map(|(name, _, command)| (name, command))
+            .collect()
+    }
+}
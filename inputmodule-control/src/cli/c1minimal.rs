@@ -0,0 +1,81 @@
+use clap::Parser;
+use inputmodule_protocol::{CommandVals, Rgb};
+
+use crate::cli::SettingsAction;
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Color {
+    White,
+    Black,
+    Red,
+    Green,
+    Blue,
+    Yellow,
+    Cyan,
+    Purple,
+}
+
+impl From<Color> for Rgb {
+    fn from(color: Color) -> Self {
+        match color {
+            Color::White => Rgb::new(0xFF, 0xFF, 0xFF),
+            Color::Black => Rgb::new(0x00, 0x00, 0x00),
+            Color::Red => Rgb::new(0xFF, 0x00, 0x00),
+            Color::Green => Rgb::new(0x00, 0xFF, 0x00),
+            Color::Blue => Rgb::new(0x00, 0x00, 0xFF),
+            Color::Yellow => Rgb::new(0xFF, 0xFF, 0x00),
+            Color::Cyan => Rgb::new(0x00, 0xFF, 0xFF),
+            Color::Purple => Rgb::new(0xFF, 0x00, 0xFF),
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct C1MinimalSubcommand {
+    /// Set sleep status or get, if no value provided
+    #[arg(long)]
+    pub sleeping: Option<Option<bool>>,
+
+    /// Jump to the bootloader
+    #[arg(long)]
+    pub bootloader: bool,
+
+    /// Crash the firmware (TESTING ONLY!)
+    #[arg(long)]
+    pub panic: bool,
+
+    /// Get the device version
+    #[arg(short, long)]
+    pub version: bool,
+
+    /// Set color
+    // TODO: Allow getting current state
+    #[arg(long)]
+    #[clap(value_enum)]
+    pub set_color: Option<Color>,
+
+    /// Save the current settings to flash, load them or reset to defaults
+    #[arg(long)]
+    #[clap(value_enum)]
+    pub settings: Option<SettingsAction>,
+}
+
+impl C1MinimalSubcommand {
+    /// Module specific commands needed by the selected options
+    pub fn required_commands(&self) -> Vec<(&'static str, CommandVals)> {
+        let options = [
+            (
+                "--set-color",
+                self.set_color.is_some(),
+                CommandVals::SetColor,
+            ),
+            ("--settings", self.settings.is_some(), CommandVals::Settings),
+        ];
+        options
+            .into_iter()
+            .filter(|(_, selected, _)| *selected)
+            .map(|(name, _, command)| (name, command))
+            .collect()
+    }
+}
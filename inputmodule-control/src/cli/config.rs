@@ -0,0 +1,352 @@
+//! Config file with names for devices, so that they can be picked with `--device`
+//!
+//! ```toml
+//! [devices.left]
+//! location = "3-4.2"
+//!
+//! [devices.right]
+//! serial_number = "FRAKDEAM0020110001"
+//! module = "led-matrix"
+//!
+//! # Order of the LED matrices for --canvas, from left to right
+//! [canvas]
+//! devices = ["left", "right"]
+//! gap = 0
+//!
+//! # What notifications show, the first rule that matches applies
+//! [[notifications]]
+//! app = "Slack"
+//! symbol = ":)"
+//!
+//! [[notifications]]
+//! urgency = "critical"
+//! symbol = "!"
+//! flashes = 5
+//! ```
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use clap::ValueEnum;
+use inputmodule_control::protocol::ModuleType;
+use inputmodule_control::{DeviceInfo, Error, Selector};
+use serde::Deserialize;
+
+const CONFIG_FILE: &str = "inputmodule-control/config.toml";
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub devices: BTreeMap<String, DeviceAlias>,
+    #[serde(default)]
+    pub canvas: CanvasConfig,
+    #[serde(default)]
+    pub notifications: Vec<NotificationRule>,
+}
+
+/// Layout of the LED matrices when drawing across them
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CanvasConfig {
+    /// Names of the devices from left to right. Without them, the matrices
+    /// are used in the order they're found.
+    #[serde(default)]
+    pub devices: Vec<String>,
+    /// Columns between two matrices, that aren't shown
+    #[serde(default)]
+    pub gap: usize,
+}
+
+/// Criteria that select the device with this name
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceAlias {
+    pub serial_dev: Option<String>,
+    pub serial_number: Option<String>,
+    pub location: Option<String>,
+    /// Same names as the subcommands, like led-matrix
+    pub module: Option<String>,
+}
+
+/// How urgent a notification is, as in the freedesktop notification spec
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Urgency {
+    Low,
+    #[default]
+    Normal,
+    Critical,
+}
+
+/// Which notifications to show and how
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NotificationRule {
+    /// Name of the application that sent it, any if not set
+    pub app: Option<String>,
+    /// Any if not set
+    pub urgency: Option<Urgency>,
+    /// Flashed on the LED matrices, letters or symbol names like 'sun', ':)'
+    pub symbol: Option<String>,
+    /// How often the symbol blinks, before it stays
+    #[serde(default = "default_flashes")]
+    pub flashes: u8,
+    /// Show the summary on the B1 displays
+    #[serde(default = "default_true")]
+    pub summary: bool,
+}
+
+fn default_flashes() -> u8 {
+    3
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Default for NotificationRule {
+    /// Shows every notification, when no rules are configured
+    fn default() -> Self {
+        Self {
+            app: None,
+            urgency: None,
+            symbol: Some("!".into()),
+            flashes: default_flashes(),
+            summary: true,
+        }
+    }
+}
+
+impl NotificationRule {
+    pub fn matches(&self, app: &str, urgency: Urgency) -> bool {
+        let app_matches = match &self.app {
+            Some(rule_app) => rule_app == app,
+            None => true,
+        };
+        let urgency_matches = match self.urgency {
+            Some(rule_urgency) => rule_urgency == urgency,
+            None => true,
+        };
+        app_matches && urgency_matches
+    }
+}
+
+impl DeviceAlias {
+    pub fn selector(&self) -> Selector {
+        Selector {
+            serial_dev: self.serial_dev.clone(),
+            serial_number: self.serial_number.clone(),
+            location: self.location.clone(),
+        }
+    }
+
+    pub fn module(&self) -> Result<Option<ModuleType>, Error> {
+        let Some(module) = &self.module else {
+            return Ok(None);
+        };
+        let module = ModuleArg::from_str(module, true)
+            .map_err(|_| Error::InvalidArgument(format!("Unknown module type {module}")))?;
+        Ok(Some(module.into()))
+    }
+}
+
+impl Config {
+    /// Load the config file, if there is one
+    ///
+    /// Without an explicit path, it's looked for in the user's config
+    /// directory, like ~/.config on Linux.
+    pub fn load(path: Option<&Path>) -> Result<Self, Error> {
+        let path: PathBuf = match path {
+            Some(path) => path.to_path_buf(),
+            None => match dirs::config_dir() {
+                Some(dir) if dir.join(CONFIG_FILE).exists() => dir.join(CONFIG_FILE),
+                _ => return Ok(Config::default()),
+            },
+        };
+        let config = std::fs::read_to_string(&path).map_err(|err| {
+            Error::InvalidArgument(format!("Can't read config {}: {err}", path.display()))
+        })?;
+        toml::from_str(&config).map_err(|err| {
+            Error::InvalidArgument(format!("Invalid config {}: {err}", path.display()))
+        })
+    }
+
+    pub fn alias(&self, name: &str) -> Result<&DeviceAlias, Error> {
+        self.devices
+            .get(name)
+            .ok_or_else(|| Error::InvalidArgument(format!("No device named {name} in the config")))
+    }
+
+    /// Names of the aliases that select the device
+    ///
+    /// Doesn't check the module type, that would need to talk to the device.
+    pub fn aliases_of(&self, device: &DeviceInfo) -> Vec<&str> {
+        self.devices
+            .iter()
+            .filter(|(_, alias)| alias.selector().matches(device))
+            .map(|(name, _)| name.as_str())
+            .collect()
+    }
+}
+
+/// Type of module, named like the subcommands
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum ModuleArg {
+    LedMatrix,
+    B1Display,
+    C1Minimal,
+}
+
+impl From<ModuleArg> for ModuleType {
+    fn from(module: ModuleArg) -> Self {
+        match module {
+            ModuleArg::LedMatrix => ModuleType::LedMatrix,
+            ModuleArg::B1Display => ModuleType::B1Display,
+            ModuleArg::C1Minimal => ModuleType::C1Minimal,
+        }
+    }
+}
+
+impl From<ModuleType> for ModuleArg {
+    fn from(module: ModuleType) -> Self {
+        match module {
+            ModuleType::LedMatrix => ModuleArg::LedMatrix,
+            ModuleType::B1Display => ModuleArg::B1Display,
+            ModuleType::C1Minimal => ModuleArg::C1Minimal,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use inputmodule_control::{FRAMEWORK_VID, LED_MATRIX_PID};
+
+    const EXAMPLE: &str = r#"
+        [devices.left]
+        location = "3-4.2"
+
+        [devices.right]
+        serial_number = "FRAKDEAM0020110001"
+        module = "led-matrix"
+
+        [canvas]
+        devices = ["left", "right"]
+        gap = 2
+
+        [[notifications]]
+        app = "Slack"
+        symbol = ":)"
+    "#;
+
+    /// Write the config to a temporary file and load it
+    fn load(name: &str, config: &str) -> Result<Config, Error> {
+        let path = std::env::temp_dir().join(format!(
+            "inputmodule-control-{}-{name}.toml",
+            std::process::id()
+        ));
+        std::fs::write(&path, config).unwrap();
+        let result = Config::load(Some(&path));
+        std::fs::remove_file(&path).unwrap();
+        result
+    }
+
+    fn device(location: &str, serial_number: &str) -> DeviceInfo {
+        DeviceInfo {
+            port: "/dev/ttyACM0".into(),
+            vid: FRAMEWORK_VID,
+            pid: LED_MATRIX_PID,
+            serial_number: Some(serial_number.into()),
+            product: None,
+            location: Some(location.into()),
+        }
+    }
+
+    #[test]
+    fn parse_config() {
+        let config = load("parse", EXAMPLE).unwrap();
+        assert_eq!(config.devices.len(), 2);
+        assert_eq!(config.canvas.devices, ["left", "right"]);
+        assert_eq!(config.canvas.gap, 2);
+
+        let rule = &config.notifications[0];
+        assert_eq!(rule.app.as_deref(), Some("Slack"));
+        assert_eq!(rule.urgency, None);
+        // Defaults for what's not set
+        assert_eq!((rule.flashes, rule.summary), (3, true));
+    }
+
+    #[test]
+    fn empty_config() {
+        let config = load("empty", "").unwrap();
+        assert!(config.devices.is_empty());
+        assert!(config.canvas.devices.is_empty());
+        assert_eq!(config.canvas.gap, 0);
+        assert!(config.notifications.is_empty());
+    }
+
+    #[test]
+    fn missing_or_invalid_file() {
+        let missing = std::env::temp_dir().join("inputmodule-control-missing.toml");
+        assert!(matches!(
+            Config::load(Some(&missing)),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        assert!(matches!(
+            load("invalid", "[devices.left"),
+            Err(Error::InvalidArgument(_))
+        ));
+        // Typos aren't silently ignored
+        assert!(matches!(
+            load("unknown", "[devices.left]\nlocaton = \"3-4.2\""),
+            Err(Error::InvalidArgument(_))
+        ));
+        assert!(matches!(
+            load("urgency", "[[notifications]]\nurgency = \"urgent\""),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+
+    #[test]
+    fn resolve_devices() {
+        let config: Config = toml::from_str(EXAMPLE).unwrap();
+
+        let left = config.alias("left").unwrap();
+        assert_eq!(
+            left.selector(),
+            Selector {
+                location: Some("3-4.2".into()),
+                ..Default::default()
+            }
+        );
+        assert_eq!(left.module().unwrap(), None);
+        let right = config.alias("right").unwrap();
+        assert_eq!(right.module().unwrap(), Some(ModuleType::LedMatrix));
+        assert!(matches!(
+            config.alias("middle"),
+            Err(Error::InvalidArgument(_))
+        ));
+
+        assert_eq!(
+            config.aliases_of(&device("3-4.2", "FRAKDEAM0020110001")),
+            ["left", "right"]
+        );
+        assert_eq!(
+            config.aliases_of(&device("3-4.3", "FRAKDEAM0020110001")),
+            ["right"]
+        );
+        assert!(config
+            .aliases_of(&device("3-4.3", "FRAKDEAM0020110002"))
+            .is_empty());
+    }
+
+    #[test]
+    fn unknown_module_type() {
+        let config: Config = toml::from_str("[devices.left]\nmodule = \"keyboard\"").unwrap();
+        assert!(matches!(
+            config.alias("left").unwrap().module(),
+            Err(Error::InvalidArgument(_))
+        ));
+    }
+}
@@ -0,0 +1,608 @@
+//! Daemon that keeps all modules open and takes commands over a Unix socket
+//!
+//! Clients send one JSON-RPC 2.0 request per line and get one response per line:
+//!
+//! ```text
+//! > {"jsonrpc": "2.0", "id": 1, "method": "set_brightness", "params": {"brightness": 50}}
+//! < {"jsonrpc":"2.0","id":1,"result":["/dev/ttyACM0"]}
+//! ```
+//!
+//! Methods:
+//!
+//! - `list`: Connected modules
+//! - `set_brightness`: `brightness` of the LED matrices
+//! - `draw`: Greyscale frame, `pixels` row by row. 9x34 for LED matrices,
+//!   300x400 for B1 displays.
+//! - `show_string`: `text` on the LED matrices
+//! - `set_color`: `r`, `g` and `b` of the C1 minimal
+//! - `set_layer`: Add or replace the layer `name` on the LED matrices, with
+//!   9x34 `pixels`. Optional are `z` (higher is on top), `alpha`,
+//!   `brightness`, `ttl_ms` and a `region` with `x`, `y`, `width` and
+//!   `height` outside of which the layer is transparent. The region must be
+//!   within the 9x34 LEDs.
+//! - `remove_layer`: Layer `name`
+//! - `layers`: Current layers
+//!
+//! Layers let several clients share the LED matrices. `draw` and
+//! `show_string` replace everything until the layers change again.
+//!
+//! All but `list` and the layer methods take an optional `device`, the serial device or the name of
+//! a device in the config file. Without it they go to every module that
+//! supports them and return where they went to.
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use image::GrayImage;
+use inputmodule_control::protocol::{ModuleType, Rgb, HEIGHT, WIDTH};
+use inputmodule_control::{
+    usb_devices, B1Display, C1Minimal, Compositor, DeviceInfo, Error, InputModule, Layer,
+    LedMatrix, Region,
+};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::cli::config::{Config, ModuleArg};
+use crate::cli::output::Output;
+use crate::cli::{match_serialdevs, OpenModule, Selection};
+
+/// How often to look for modules that were plugged in or removed
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+const SOCKET_FILE: &str = "inputmodule-control.sock";
+
+// Error codes defined by JSON-RPC
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// Errors of the modules, with the exit code of the commandline tool as data
+const MODULE_ERROR: i64 = -32000;
+
+/// Keep the modules open and take commands from other programs
+#[derive(clap::Args, Debug)]
+pub struct DaemonSubcommand {
+    /// Path of the control socket, by default in the user's runtime directory
+    #[arg(long)]
+    pub socket: Option<PathBuf>,
+}
+
+enum Module {
+    LedMatrix(LedMatrix),
+    B1Display(B1Display),
+    C1Minimal(C1Minimal),
+}
+
+impl Module {
+    fn new(module: OpenModule, selected: Option<ModuleType>) -> Result<Self, Error> {
+        match module.module_type(selected) {
+            Some(ModuleType::LedMatrix) => Ok(Module::LedMatrix(LedMatrix::from_port(module.port))),
+            Some(ModuleType::B1Display) => Ok(Module::B1Display(B1Display::from_port(module.port))),
+            Some(ModuleType::C1Minimal) => Ok(Module::C1Minimal(C1Minimal::from_port(module.port))),
+            None => Err(Error::Unsupported(format!(
+                "Can't tell what type of module {} is",
+                module.serialdev
+            ))),
+        }
+    }
+
+    fn module_type(&self) -> ModuleType {
+        match self {
+            Module::LedMatrix(_) => ModuleType::LedMatrix,
+            Module::B1Display(_) => ModuleType::B1Display,
+            Module::C1Minimal(_) => ModuleType::C1Minimal,
+        }
+    }
+}
+
+struct Connected {
+    module: Module,
+    /// Not known for devices that aren't enumerated, like the simulator
+    info: Option<DeviceInfo>,
+}
+
+/// The modules that are currently connected, by serial device
+struct Devices {
+    modules: BTreeMap<String, Connected>,
+    config: Config,
+    /// Layers shown on all LED matrices
+    compositor: Compositor,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+    data: Value,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: Value::Null,
+        }
+    }
+}
+
+impl From<Error> for RpcError {
+    fn from(err: Error) -> Self {
+        Self {
+            code: MODULE_ERROR,
+            message: err.to_string(),
+            data: json!({ "exit_code": err.exit_code() }),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+#[derive(Deserialize)]
+struct BrightnessParams {
+    device: Option<String>,
+    brightness: u8,
+}
+
+#[derive(Deserialize)]
+struct DrawParams {
+    device: Option<String>,
+    pixels: Vec<u8>,
+}
+
+#[derive(Deserialize)]
+struct StringParams {
+    device: Option<String>,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct LayerParams {
+    name: String,
+    pixels: Vec<u8>,
+    #[serde(default)]
+    z: i32,
+    alpha: Option<u8>,
+    brightness: Option<u8>,
+    /// Removed after this many milliseconds
+    ttl_ms: Option<u64>,
+    region: Option<RegionParams>,
+}
+
+#[derive(Deserialize)]
+struct RegionParams {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Deserialize)]
+struct RemoveLayerParams {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct ColorParams {
+    device: Option<String>,
+    r: u8,
+    g: u8,
+    b: u8,
+}
+
+/// Serve the control socket until the process is killed
+pub fn run(
+    args: &crate::ClapCli,
+    daemon_args: &DaemonSubcommand,
+    output: &Output,
+) -> Result<(), Error> {
+    let config = Config::load(args.config.as_deref())?;
+    let selection = Selection::new(args, &config)?;
+
+    let path = daemon_args.socket.clone().unwrap_or_else(|| {
+        dirs::runtime_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join(SOCKET_FILE)
+    });
+    if UnixStream::connect(&path).is_ok() {
+        return Err(Error::InvalidArgument(format!(
+            "Another daemon is already listening on {}",
+            path.display()
+        )));
+    }
+    // Left behind by a daemon that didn't exit cleanly
+    let _ = fs::remove_file(&path);
+    let listener = UnixListener::bind(&path)?;
+    output.info(format!("Listening on {}", path.display()));
+
+    let devices = Mutex::new(Devices {
+        modules: BTreeMap::new(),
+        config,
+        compositor: Compositor::new(),
+    });
+    let devices = &devices;
+    thread::scope(|s| {
+        s.spawn(|| watch_devices(devices, &selection, output));
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    s.spawn(move || serve_client(stream, devices));
+                }
+                Err(err) => output.info(format!("Failed to accept client: {err}")),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// Open modules when they're plugged in and forget them when they're removed
+///
+/// Same polling as --wait-for-device, but it never stops.
+fn watch_devices(devices: &Mutex<Devices>, selection: &Selection, output: &Output) {
+    // Retried on every poll, but only reported once
+    let mut failed: Vec<String> = vec![];
+    // Not the selected type of module, not opened again until reconnected
+    let mut other: Vec<String> = vec![];
+    loop {
+        let found = match usb_devices() {
+            Ok(found) => found,
+            Err(err) => {
+                output.info(format!("Failed to list devices: {err}"));
+                vec![]
+            }
+        };
+        let serialdevs = match_serialdevs(&found, &selection.selector);
+        failed.retain(|serialdev| serialdevs.contains(serialdev));
+        other.retain(|serialdev| serialdevs.contains(serialdev));
+
+        let new: Vec<String> = {
+            let mut devices = devices.lock().unwrap();
+            devices.modules.retain(|serialdev, _| {
+                let present = serialdevs.contains(serialdev);
+                if !present {
+                    output.info(format!("Disconnected {serialdev}"));
+                }
+                present
+            });
+            serialdevs
+                .into_iter()
+                .filter(|serialdev| !devices.modules.contains_key(serialdev))
+                .filter(|serialdev| !other.contains(serialdev))
+                .collect()
+        };
+
+        // Opening takes a while, don't block the clients meanwhile
+        for serialdev in new {
+            let info = found.iter().find(|x| x.port == serialdev).cloned();
+            let pid = info.as_ref().map(|x| x.pid);
+            let module = match OpenModule::open(&serialdev, pid) {
+                Ok(module) if !module.is_selected(selection.module) => {
+                    other.push(serialdev);
+                    continue;
+                }
+                Ok(module) => Module::new(module, selection.module),
+                Err(err) => Err(err),
+            };
+            match module {
+                Ok(module) => {
+                    output.info(format!(
+                        "Connected {serialdev} ({:?})",
+                        module.module_type()
+                    ));
+                    failed.retain(|x| *x != serialdev);
+                    let mut connected = Connected { module, info };
+                    let mut devices = devices.lock().unwrap();
+                    if let (Module::LedMatrix(matrix), Some(frame)) =
+                        (&mut connected.module, devices.compositor.frame())
+                    {
+                        if let Err(err) = matrix.display_gray_values(frame) {
+                            output.info(format!("Failed to show layers on {serialdev}: {err}"));
+                        }
+                    }
+                    devices.modules.insert(serialdev, connected);
+                }
+                Err(err) => {
+                    if !failed.contains(&serialdev) {
+                        output.info(format!("Failed to open {serialdev}: {err}"));
+                        failed.push(serialdev);
+                    }
+                }
+            }
+        }
+
+        // Layers might have expired
+        if let Err(err) = devices.lock().unwrap().push_layers() {
+            output.info(format!("Failed to show layers: {err}"));
+        }
+        thread::sleep(POLL_INTERVAL);
+    }
+}
+
+fn serve_client(stream: UnixStream, devices: &Mutex<Devices>) {
+    let Ok(reader) = stream.try_clone() else {
+        return;
+    };
+    let mut writer = stream;
+    for line in BufReader::new(reader).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = handle_request(&line, devices);
+        if writeln!(writer, "{response}").is_err() {
+            break;
+        }
+    }
+}
+
+fn handle_request(line: &str, devices: &Mutex<Devices>) -> Value {
+    let (id, result) = match serde_json::from_str::<Request>(line) {
+        Ok(request) => {
+            let result = devices
+                .lock()
+                .unwrap()
+                .call(&request.method, request.params);
+            (request.id, result)
+        }
+        Err(err) => (
+            Value::Null,
+            Err(RpcError::new(PARSE_ERROR, err.to_string())),
+        ),
+    };
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(err) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": { "code": err.code, "message": err.message, "data": err.data },
+        }),
+    }
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    serde_json::from_value(params).map_err(|err| RpcError::new(INVALID_PARAMS, err.to_string()))
+}
+
+impl Devices {
+    fn call(&mut self, method: &str, params_value: Value) -> Result<Value, RpcError> {
+        match method {
+            "list" => Ok(self.list()),
+            "set_brightness" => {
+                let p: BrightnessParams = params(params_value)?;
+                self.for_each(method, p.device.as_deref(), |module| match module {
+                    Module::LedMatrix(matrix) => Some(matrix.set_brightness(p.brightness)),
+                    _ => None,
+                })
+            }
+            "draw" => {
+                let p: DrawParams = params(params_value)?;
+                let len = p.pixels.len();
+                if len != WIDTH * HEIGHT && len != B1Display::WIDTH * B1Display::HEIGHT {
+                    return Err(RpcError::new(
+                        INVALID_PARAMS,
+                        format!("{len} pixels fit neither the LED matrix nor the B1 display"),
+                    ));
+                }
+                self.for_each(method, p.device.as_deref(), |module| match module {
+                    Module::LedMatrix(matrix) if len == WIDTH * HEIGHT => {
+                        let img =
+                            GrayImage::from_raw(WIDTH as u32, HEIGHT as u32, p.pixels.clone());
+                        Some(matrix.display_gray_image(&img.unwrap()))
+                    }
+                    Module::B1Display(display) if len == B1Display::WIDTH * B1Display::HEIGHT => {
+                        let (width, height) = (B1Display::WIDTH as u32, B1Display::HEIGHT as u32);
+                        let img = GrayImage::from_raw(width, height, p.pixels.clone());
+                        Some(display.display_image(&img.unwrap()))
+                    }
+                    _ => None,
+                })
+            }
+            "show_string" => {
+                let p: StringParams = params(params_value)?;
+                self.for_each(method, p.device.as_deref(), |module| match module {
+                    Module::LedMatrix(matrix) => Some(matrix.show_string(&p.text)),
+                    _ => None,
+                })
+            }
+            "set_color" => {
+                let p: ColorParams = params(params_value)?;
+                let color = Rgb {
+                    r: p.r,
+                    g: p.g,
+                    b: p.b,
+                };
+                self.for_each(method, p.device.as_deref(), |module| match module {
+                    Module::C1Minimal(minimal) => Some(minimal.set_color(color)),
+                    _ => None,
+                })
+            }
+            "set_layer" => {
+                let p: LayerParams = params(params_value)?;
+                let frame = GrayImage::from_raw(WIDTH as u32, HEIGHT as u32, p.pixels).ok_or_else(
+                    || RpcError::new(INVALID_PARAMS, format!("Layer must be {WIDTH}x{HEIGHT}")),
+                )?;
+                let mut layer = Layer::new(frame)?;
+                layer.z = p.z;
+                layer.alpha = p.alpha.unwrap_or(layer.alpha);
+                layer.brightness = p.brightness.unwrap_or(layer.brightness);
+                layer.mask = p.region.map(|r| Region {
+                    x: r.x,
+                    y: r.y,
+                    width: r.width,
+                    height: r.height,
+                });
+                layer.expires = p
+                    .ttl_ms
+                    .map(|ttl| Instant::now() + Duration::from_millis(ttl));
+                self.compositor
+                    .set_layer(&p.name, layer)
+                    .map_err(|err| RpcError::new(INVALID_PARAMS, err.to_string()))?;
+                self.push_layers()?;
+                Ok(Value::Null)
+            }
+            "remove_layer" => {
+                let p: RemoveLayerParams = params(params_value)?;
+                if !self.compositor.remove_layer(&p.name) {
+                    return Err(RpcError::new(
+                        INVALID_PARAMS,
+                        format!("No layer {}", p.name),
+                    ));
+                }
+                self.push_layers()?;
+                Ok(Value::Null)
+            }
+            "layers" => {
+                let now = Instant::now();
+                let layers: Vec<Value> = self
+                    .compositor
+                    .layers()
+                    .iter()
+                    .map(|(name, layer)| {
+                        let ttl = layer
+                            .expires
+                            .map(|x| x.saturating_duration_since(now).as_millis() as u64);
+                        json!({
+                            "name": name,
+                            "z": layer.z,
+                            "alpha": layer.alpha,
+                            "brightness": layer.brightness,
+                            "ttl_ms": ttl,
+                        })
+                    })
+                    .collect();
+                Ok(Value::Array(layers))
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Unknown method {method}"),
+            )),
+        }
+    }
+
+    fn list(&mut self) -> Value {
+        let list: Vec<Value> = self
+            .modules
+            .iter_mut()
+            .map(|(serialdev, connected)| {
+                let module = ModuleArg::from(connected.module.module_type());
+                let version = match &mut connected.module {
+                    Module::LedMatrix(matrix) => matrix.version(),
+                    Module::B1Display(display) => display.version(),
+                    Module::C1Minimal(minimal) => minimal.version(),
+                };
+                let aliases = match &connected.info {
+                    Some(info) => self.config.aliases_of(info),
+                    None => vec![],
+                };
+                let version = version
+                    .ok()
+                    .map(|v| format!("{}.{}.{}", v.major(), v.minor(), v.patch()));
+                let info = connected.info.as_ref();
+                json!({
+                    "device": serialdev,
+                    "module": module.to_possible_value().map(|x| x.get_name().to_string()),
+                    "serial_number": info.and_then(|x| x.serial_number.clone()),
+                    "location": info.and_then(|x| x.location.clone()),
+                    "aliases": aliases,
+                    "version": version,
+                })
+            })
+            .collect();
+        Value::Array(list)
+    }
+
+    /// Show the layers on all LED matrices, if they changed
+    fn push_layers(&mut self) -> Result<bool, Error> {
+        // Don't clear what the matrices show before anyone used layers
+        if self.compositor.layers().is_empty() && self.compositor.frame().is_none() {
+            return Ok(false);
+        }
+        let matrices =
+            self.modules
+                .values_mut()
+                .filter_map(|connected| match &mut connected.module {
+                    Module::LedMatrix(matrix) => Some(matrix),
+                    _ => None,
+                });
+        self.compositor.push(matrices)
+    }
+
+    /// Whether the device was requested by serial device or config name
+    fn is_requested(&self, requested: &str, serialdev: &str, info: &Option<DeviceInfo>) -> bool {
+        if requested == serialdev {
+            return true;
+        }
+        let Some(alias) = self.config.devices.get(requested) else {
+            return false;
+        };
+        let selector = alias.selector();
+        match info {
+            Some(info) => selector.matches(info),
+            None => selector.serial_dev.as_deref() == Some(serialdev),
+        }
+    }
+
+    /// Run the command on the requested modules, or all if none was requested
+    ///
+    /// The command returns None for modules that don't support it. Modules
+    /// that stop responding are closed, they're opened again by the watcher.
+    fn for_each(
+        &mut self,
+        method: &str,
+        requested: Option<&str>,
+        mut command: impl FnMut(&mut Module) -> Option<Result<(), Error>>,
+    ) -> Result<Value, RpcError> {
+        let selected: Vec<String> = self
+            .modules
+            .iter()
+            .filter(|(serialdev, connected)| match requested {
+                Some(requested) => self.is_requested(requested, serialdev, &connected.info),
+                None => true,
+            })
+            .map(|(serialdev, _)| serialdev.clone())
+            .collect();
+        if selected.is_empty() {
+            let mut err = RpcError::from(Error::DeviceNotFound);
+            if let Some(requested) = requested {
+                err.message = format!("No module {requested} connected");
+            }
+            return Err(err);
+        }
+
+        let mut done = vec![];
+        for serialdev in selected {
+            let connected = self.modules.get_mut(&serialdev).unwrap();
+            match command(&mut connected.module) {
+                Some(Ok(())) => done.push(serialdev),
+                Some(Err(err)) => {
+                    if matches!(err, Error::Io(_) | Error::Timeout) {
+                        self.modules.remove(&serialdev);
+                    }
+                    return Err(err.into());
+                }
+                None => {}
+            }
+        }
+        if done.is_empty() {
+            return Err(Error::Unsupported(format!(
+                "None of the selected modules supports {method}"
+            ))
+            .into());
+        }
+        Ok(json!(done))
+    }
+}
@@ -0,0 +1,472 @@
+use clap::{Parser, Subcommand};
+use inputmodule_protocol::{CommandVals, LifeRule, PatternVals};
+
+use crate::cli::metrics::{Metric, MetricsStyle};
+use crate::cli::SettingsAction;
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+#[repr(u8)]
+pub enum Pattern {
+    Percentage = 0,
+    Gradient = 1,
+    DoubleGradient = 2,
+    LotusSideways = 3,
+    Zigzag = 4,
+    AllOn = 5,
+    Panic = 6,
+    LotusTopDown = 7,
+    //AllBrightnesses
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+#[repr(u8)]
+pub enum Game {
+    Snake = 0,
+    Pong = 1,
+    Tetris = 2,
+    GameOfLife = 3,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
+pub enum GameOfLifeStartParam {
+    CurrentMatrix = 0x00,
+    Pattern1 = 0x01,
+    Blinker = 0x02,
+    Toad = 0x03,
+    Beacon = 0x04,
+    Glider = 0x05,
+    BeaconToadBlinker = 0x06,
+    /// Random cells, see --life-seed and --life-density
+    Soup = 0x07,
+}
+
+/// Who controls the Pong paddles
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum PongMode {
+    /// Upper paddle against the computer
+    #[default]
+    PlayerVsAi,
+    /// Upper paddle with left/right, lower one with second-left/second-right
+    PlayerVsPlayer,
+    /// The computer plays both, as a demo
+    AiVsAi,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum PongDifficulty {
+    Easy,
+    #[default]
+    Medium,
+    Hard,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum ScrollMode {
+    /// Upright characters below each other, moving up
+    #[default]
+    Vertical,
+    /// Upright characters next to each other, moving left
+    Horizontal,
+    /// Characters turned by 90°, moving up
+    Rotated,
+}
+
+impl From<ScrollMode> for inputmodule_control::ScrollMode {
+    fn from(mode: ScrollMode) -> Self {
+        match mode {
+            ScrollMode::Vertical => Self::Vertical,
+            ScrollMode::Horizontal => Self::Horizontal,
+            ScrollMode::Rotated => Self::Rotated,
+        }
+    }
+}
+
+impl From<Pattern> for PatternVals {
+    fn from(pattern: Pattern) -> Self {
+        match pattern {
+            Pattern::Percentage => PatternVals::Percentage,
+            Pattern::Gradient => PatternVals::Gradient,
+            Pattern::DoubleGradient => PatternVals::DoubleGradient,
+            Pattern::LotusSideways => PatternVals::DisplayLotus,
+            Pattern::Zigzag => PatternVals::ZigZag,
+            Pattern::AllOn => PatternVals::FullBrightness,
+            Pattern::Panic => PatternVals::DisplayPanic,
+            Pattern::LotusTopDown => PatternVals::DisplayLotus2,
+        }
+    }
+}
+
+impl From<PongMode> for inputmodule_protocol::PongMode {
+    fn from(mode: PongMode) -> Self {
+        match mode {
+            PongMode::PlayerVsAi => Self::PlayerVsAi,
+            PongMode::PlayerVsPlayer => Self::PlayerVsPlayer,
+            PongMode::AiVsAi => Self::AiVsAi,
+        }
+    }
+}
+
+impl From<PongDifficulty> for inputmodule_protocol::PongDifficulty {
+    fn from(difficulty: PongDifficulty) -> Self {
+        match difficulty {
+            PongDifficulty::Easy => Self::Easy,
+            PongDifficulty::Medium => Self::Medium,
+            PongDifficulty::Hard => Self::Hard,
+        }
+    }
+}
+
+impl From<GameOfLifeStartParam> for inputmodule_protocol::GameOfLifeStartParam {
+    fn from(param: GameOfLifeStartParam) -> Self {
+        use inputmodule_protocol::GameOfLifeStartParam as P;
+        match param {
+            GameOfLifeStartParam::CurrentMatrix => P::CurrentMatrix,
+            GameOfLifeStartParam::Pattern1 => P::Pattern1,
+            GameOfLifeStartParam::Blinker => P::Blinker,
+            GameOfLifeStartParam::Toad => P::Toad,
+            GameOfLifeStartParam::Beacon => P::Beacon,
+            GameOfLifeStartParam::Glider => P::Glider,
+            GameOfLifeStartParam::BeaconToadBlinker => P::BeaconToadBlinker,
+            GameOfLifeStartParam::Soup => P::Soup,
+        }
+    }
+}
+
+/// How to start a game
+#[derive(clap::Args, Debug)]
+pub struct GameOptions {
+    /// Paramater for starting the game. Required for some games
+    #[arg(long)]
+    #[clap(value_enum)]
+    pub game_param: Option<GameOfLifeStartParam>,
+
+    /// Who plays Pong
+    #[arg(long, value_enum, default_value_t)]
+    pub pong_mode: PongMode,
+
+    /// How well the computer plays Pong
+    #[arg(long, value_enum, default_value_t)]
+    pub pong_difficulty: PongDifficulty,
+
+    /// Points to win a game of Pong
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u8).range(1..))]
+    pub pong_points: u8,
+
+    /// Rule of Game of Life in B/S notation, like B36/S23 for HighLife [default: B3/S23]
+    #[arg(long, value_parser = parse_life_rule)]
+    pub life_rule: Option<LifeRule>,
+
+    /// Cells at the edges of Game of Life don't wrap around to the other side
+    #[arg(long)]
+    pub life_bounded: bool,
+
+    /// Start Game of Life over with a new soup, once it stops changing or repeats
+    #[arg(long)]
+    pub life_reseed: bool,
+
+    /// Seed of the Game of Life soups [default: random]
+    #[arg(long)]
+    pub life_seed: Option<u32>,
+
+    /// Percentage of the cells that are alive in a Game of Life soup
+    #[arg(long, default_value_t = 35, value_parser = clap::value_parser!(u8).range(1..=100))]
+    pub life_density: u8,
+
+    /// Start Game of Life with a pattern from an RLE file, instead of --game-param
+    #[arg(long)]
+    pub life_rle: Option<String>,
+}
+
+fn parse_life_rule(rule: &str) -> Result<LifeRule, String> {
+    LifeRule::parse(rule).ok_or_else(|| "Expected B/S notation, like B3/S23".to_string())
+}
+
+#[derive(Subcommand, Debug)]
+pub enum LedMatrixCommand {
+    /// Start a game and control it with the keyboard
+    ///
+    /// Arrow keys or WASD steer, in Pong A and D move the second player's
+    /// paddle. Q or Esc stops the game.
+    Play(PlaySubcommand),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct PlaySubcommand {
+    #[arg(value_enum)]
+    pub game: Game,
+
+    #[command(flatten)]
+    pub game_options: GameOptions,
+}
+
+/// LED Matrix
+#[derive(Parser, Debug)]
+#[command(arg_required_else_help = true)]
+pub struct LedMatrixSubcommand {
+    #[command(subcommand)]
+    pub command: Option<LedMatrixCommand>,
+
+    /// Set LED max brightness percentage or get, if no value provided
+    #[arg(long)]
+    pub brightness: Option<Option<u8>>,
+
+    /// Set sleep status or get, if no value provided
+    #[arg(long)]
+    pub sleeping: Option<Option<bool>>,
+
+    /// Jump to the bootloader
+    #[arg(long)]
+    pub bootloader: bool,
+
+    /// Display a percentage (0-100)
+    #[arg(long)]
+    pub percentage: Option<u8>,
+
+    /// Start/stop animation
+    #[arg(long)]
+    pub animate: Option<Option<bool>>,
+
+    /// Display a pattern
+    #[arg(long)]
+    #[clap(value_enum)]
+    pub pattern: Option<Pattern>,
+
+    /// Show every brightness, one per pixel
+    #[arg(long)]
+    pub all_brightnesses: bool,
+
+    /// Blink the current pattern once a second
+    #[arg(long)]
+    pub blinking: bool,
+
+    /// Breathing brightness of the current pattern
+    #[arg(long)]
+    pub breathing: bool,
+
+    /// Display black&white image (9x34px)
+    #[arg(long)]
+    pub image_bw: Option<String>,
+
+    /// Display grayscale image
+    #[arg(long)]
+    pub image_gray: Option<String>,
+
+    /// Upload a grayscale GIF (9x34px) to play at boot instead of the built-in animations
+    #[arg(long)]
+    pub upload_boot_animation: Option<String>,
+
+    /// Go back to the built-in boot animations
+    #[arg(long)]
+    pub clear_boot_animation: bool,
+
+    /// Upload a grayscale GIF (9x34px) that the module plays on its own
+    #[arg(long)]
+    pub sequence: Option<String>,
+
+    /// How often to play the sequence, 0 means forever
+    #[arg(long, default_value_t = 0, requires = "sequence")]
+    pub sequence_loops: u8,
+
+    /// Random EQ
+    #[arg(long)]
+    pub random_eq: bool,
+
+    /// Display EQ of microphone input
+    #[cfg(feature = "audio-visualizations")]
+    #[arg(long)]
+    pub input_eq: bool,
+
+    /// EQ with custom values, 9 per matrix
+    #[arg(long, num_args(1..))]
+    pub eq: Option<Vec<u8>>,
+
+    /// Clock
+    #[arg(long)]
+    pub clock: bool,
+
+    /// Dashboard of system metrics, refreshed until stopped (Linux only)
+    #[arg(long, num_args(1..), value_enum)]
+    pub metrics: Option<Vec<Metric>>,
+
+    /// How to draw the metrics
+    #[arg(long, value_enum, default_value_t, requires = "metrics")]
+    pub metrics_style: MetricsStyle,
+
+    /// Time between updates of the metrics in ms
+    #[arg(long, default_value_t = 1000, requires = "metrics")]
+    pub metrics_interval: u64,
+
+    /// Display a string (max 5 chars)
+    #[arg(long)]
+    pub string: Option<String>,
+
+    /// Display a string (max 5 symbols)
+    #[arg(long, num_args(0..6))]
+    pub symbols: Option<Vec<String>>,
+
+    /// Draw images, text, EQ and clock across all matrices, as if they were one
+    #[arg(long)]
+    pub canvas: bool,
+
+    /// Columns between two matrices on the canvas, that aren't shown
+    #[arg(long, requires = "canvas")]
+    pub canvas_gap: Option<usize>,
+
+    /// Scroll text of any length through the matrix
+    #[arg(long)]
+    pub scroll: Option<String>,
+
+    /// Direction of the scrolling text
+    #[arg(long, value_enum, default_value_t, requires = "scroll")]
+    pub scroll_mode: ScrollMode,
+
+    /// Speed of the scrolling text in pixels per second
+    #[arg(long, default_value_t = 10, requires = "scroll")]
+    pub scroll_speed: u16,
+
+    /// How often to scroll the text, 0 means forever
+    #[arg(long, default_value_t = 1, requires = "scroll")]
+    pub scroll_loops: u8,
+
+    /// Upload the scrolling text, so that the module plays it on its own
+    #[arg(long, requires = "scroll")]
+    pub scroll_on_device: bool,
+
+    /// Start a game
+    #[arg(long)]
+    #[clap(value_enum)]
+    pub start_game: Option<Game>,
+
+    #[command(flatten)]
+    pub game_options: GameOptions,
+
+    /// Stop the currently running game
+    #[arg(long)]
+    #[clap(value_enum)]
+    pub stop_game: bool,
+
+    /// Get the score of the running game and whether it's over
+    #[arg(long)]
+    pub game_status: bool,
+
+    /// Set/get animation FPS
+    #[arg(long)]
+    pub animation_fps: Option<Option<u16>>,
+
+    /// Set/get PWM Frequency in Hz
+    #[arg(long)]
+    #[clap(value_enum)]
+    pub pwm_freq: Option<Option<u16>>,
+
+    /// Set debug mode or get current mode, if no value provided
+    #[arg(long)]
+    pub debug_mode: Option<Option<bool>>,
+
+    /// Save the current settings to flash, load them or reset to defaults
+    #[arg(long)]
+    #[clap(value_enum)]
+    pub settings: Option<SettingsAction>,
+
+    /// Crash the firmware (TESTING ONLY!)
+    #[arg(long)]
+    pub panic: bool,
+
+    /// Get the device version
+    #[arg(short, long)]
+    pub version: bool,
+}
+
+impl LedMatrixSubcommand {
+    /// Module specific commands needed by the selected options
+    pub fn required_commands(&self) -> Vec<(&'static str, CommandVals)> {
+        #[cfg(feature = "audio-visualizations")]
+        let input_eq = self.input_eq;
+        #[cfg(not(feature = "audio-visualizations"))]
+        let input_eq = false;
+        let scroll_command = if self.scroll_on_device {
+            CommandVals::Sequence
+        } else {
+            CommandVals::StageGreyCol
+        };
+        let play = matches!(self.command, Some(LedMatrixCommand::Play(_)));
+        let metrics_command = match self.metrics_style {
+            MetricsStyle::Percentage => CommandVals::Pattern,
+            _ => CommandVals::Draw,
+        };
+
+        let options = [
+            (
+                "--brightness",
+                self.brightness.is_some(),
+                CommandVals::Brightness,
+            ),
+            ("--blinking", self.blinking, CommandVals::Brightness),
+            ("--breathing", self.breathing, CommandVals::Brightness),
+            (
+                "--percentage",
+                self.percentage.is_some(),
+                CommandVals::Pattern,
+            ),
+            ("--pattern", self.pattern.is_some(), CommandVals::Pattern),
+            ("--animate", self.animate.is_some(), CommandVals::Animate),
+            ("--image-bw", self.image_bw.is_some(), CommandVals::Draw),
+            ("--random-eq", self.random_eq, CommandVals::Draw),
+            ("--input-eq", input_eq, CommandVals::Draw),
+            ("--eq", self.eq.is_some(), CommandVals::Draw),
+            ("--clock", self.clock, CommandVals::Draw),
+            ("--metrics", self.metrics.is_some(), metrics_command),
+            ("--string", self.string.is_some(), CommandVals::Draw),
+            ("--symbols", self.symbols.is_some(), CommandVals::Draw),
+            ("--scroll", self.scroll.is_some(), scroll_command),
+            ("--canvas", self.canvas, CommandVals::StageGreyCol),
+            (
+                "--all-brightnesses",
+                self.all_brightnesses,
+                CommandVals::StageGreyCol,
+            ),
+            (
+                "--image-gray",
+                self.image_gray.is_some(),
+                CommandVals::StageGreyCol,
+            ),
+            (
+                "--upload-boot-animation",
+                self.upload_boot_animation.is_some(),
+                CommandVals::BootAnimation,
+            ),
+            ("--sequence", self.sequence.is_some(), CommandVals::Sequence),
+            (
+                "--clear-boot-animation",
+                self.clear_boot_animation,
+                CommandVals::BootAnimation,
+            ),
+            (
+                "--start-game",
+                self.start_game.is_some(),
+                CommandVals::StartGame,
+            ),
+            ("--stop-game", self.stop_game, CommandVals::GameControl),
+            ("--game-status", self.game_status, CommandVals::GameStatus),
+            (
+                "--animation-fps",
+                self.animation_fps.is_some(),
+                CommandVals::AnimationPeriod,
+            ),
+            ("--pwm-freq", self.pwm_freq.is_some(), CommandVals::PwmFreq),
+            (
+                "--debug-mode",
+                self.debug_mode.is_some(),
+                CommandVals::DebugMode,
+            ),
+            ("--settings", self.settings.is_some(), CommandVals::Settings),
+            ("play", play, CommandVals::StartGame),
+            ("play", play, CommandVals::GameControl),
+            ("play", play, CommandVals::GameStatus),
+        ];
+        options
+            .into_iter()
+            .filter(|(_, selected, _)| *selected)
+            .map(|(name, _, command)| (name, command))
+            .collect()
+    }
+}
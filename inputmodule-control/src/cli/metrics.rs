@@ -0,0 +1,288 @@
+//! System metrics for the dashboards on the LED matrix
+//!
+//! Read from procfs and sysfs, so they're only available on Linux. All of them
+//! are percentages, the ones that are rates need two samples.
+use std::fs;
+use std::time::Instant;
+
+use inputmodule_control::Error;
+
+/// What to show
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Metric {
+    /// Time the CPUs are busy
+    Cpu,
+    /// Memory that's not available
+    Memory,
+    /// Time the busiest disk is doing I/O
+    Disk,
+    /// Bytes sent and received, relative to the highest rate so far
+    Network,
+    /// Charge of the batteries
+    Battery,
+}
+
+/// How the metrics are drawn
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum MetricsStyle {
+    /// A bar per metric, the columns are split between them
+    #[default]
+    Bars,
+    /// History of the first metric, one column per sample
+    Sparkline,
+    /// First metric as percentage, filling the matrix from the bottom
+    Percentage,
+}
+
+/// Counters of the previous sample, to calculate rates
+#[derive(Default)]
+pub struct Sampler {
+    /// Busy and total jiffies
+    cpu: Option<(u64, u64)>,
+    /// Milliseconds spent doing I/O, per disk
+    disk: Option<(Instant, Vec<u64>)>,
+    /// Bytes received and sent on all interfaces
+    network: Option<(Instant, u64)>,
+    /// Bytes per second
+    network_peak: f64,
+}
+
+impl Sampler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Percentage (0-100) of the metric
+    ///
+    /// Rates are 0 on the first sample.
+    pub fn sample(&mut self, metric: Metric) -> Result<u8, Error> {
+        let percent = match metric {
+            Metric::Cpu => self.cpu()?,
+            Metric::Memory => memory()?,
+            Metric::Disk => self.disk()?,
+            Metric::Network => self.network()?,
+            Metric::Battery => battery()?,
+        };
+        Ok(percent.clamp(0.0, 100.0) as u8)
+    }
+
+    fn cpu(&mut self) -> Result<f64, Error> {
+        let stat = fs::read_to_string("/proc/stat")?;
+        Ok(self.cpu_usage(&stat))
+    }
+
+    /// Busy time since the last sample, from the contents of /proc/stat
+    fn cpu_usage(&mut self, stat: &str) -> f64 {
+        let (busy, total) = cpu_jiffies(stat);
+        match self.cpu.replace((busy, total)) {
+            Some((prev_busy, prev_total)) if total > prev_total => {
+                busy.saturating_sub(prev_busy) as f64 * 100.0 / (total - prev_total) as f64
+            }
+            _ => 0.0,
+        }
+    }
+
+    fn disk(&mut self) -> Result<f64, Error> {
+        let io_ticks = disk_io_ticks(&fs::read_to_string("/proc/diskstats")?);
+        let now = Instant::now();
+
+        let percent = match self.disk.replace((now, io_ticks.clone())) {
+            Some((prev_time, prev_ticks)) if prev_ticks.len() == io_ticks.len() => {
+                let elapsed_ms = now.duration_since(prev_time).as_millis().max(1) as f64;
+                let busiest = io_ticks
+                    .iter()
+                    .zip(prev_ticks)
+                    .map(|(ticks, prev)| ticks.saturating_sub(prev))
+                    .max()
+                    .unwrap_or(0);
+                busiest as f64 * 100.0 / elapsed_ms
+            }
+            _ => 0.0,
+        };
+        Ok(percent)
+    }
+
+    fn network(&mut self) -> Result<f64, Error> {
+        let bytes = network_bytes(&fs::read_to_string("/proc/net/dev")?);
+        let now = Instant::now();
+
+        let percent = match self.network.replace((now, bytes)) {
+            Some((prev_time, prev_bytes)) => {
+                let elapsed = now.duration_since(prev_time).as_secs_f64().max(0.001);
+                let rate = bytes.saturating_sub(prev_bytes) as f64 / elapsed;
+                self.network_peak = self.network_peak.max(rate);
+                if self.network_peak > 0.0 {
+                    rate * 100.0 / self.network_peak
+                } else {
+                    0.0
+                }
+            }
+            None => 0.0,
+        };
+        Ok(percent)
+    }
+}
+
+/// Busy and total jiffies of all CPUs, from the contents of /proc/stat
+fn cpu_jiffies(stat: &str) -> (u64, u64) {
+    // cpu  user nice system idle iowait irq softirq steal ...
+    let fields: Vec<u64> = stat
+        .lines()
+        .next()
+        .unwrap_or_default()
+        .split_whitespace()
+        .skip(1)
+        .filter_map(|x| x.parse().ok())
+        .collect();
+    let total: u64 = fields.iter().sum();
+    let idle = fields.get(3).unwrap_or(&0) + fields.get(4).unwrap_or(&0);
+    (total - idle, total)
+}
+
+/// Milliseconds spent doing I/O per disk, from the contents of /proc/diskstats
+fn disk_io_ticks(diskstats: &str) -> Vec<u64> {
+    // major minor name reads ... io_ticks (the 10th counter) ...
+    diskstats
+        .lines()
+        .map(|line| line.split_whitespace().collect::<Vec<_>>())
+        .filter(|fields| fields.len() > 12)
+        .filter(|fields| !fields[2].starts_with("loop") && !fields[2].starts_with("ram"))
+        .filter_map(|fields| fields[12].parse().ok())
+        .collect()
+}
+
+/// Bytes received and sent on all interfaces but loopback, from the contents
+/// of /proc/net/dev
+fn network_bytes(dev: &str) -> u64 {
+    // Two header lines, then name: rx_bytes ... (8 counters) tx_bytes ...
+    dev.lines()
+        .skip(2)
+        .filter_map(|line| line.split_once(':'))
+        .filter(|(name, _)| name.trim() != "lo")
+        .map(|(_, counters)| {
+            let counters: Vec<u64> = counters
+                .split_whitespace()
+                .filter_map(|x| x.parse().ok())
+                .collect();
+            counters.first().unwrap_or(&0) + counters.get(8).unwrap_or(&0)
+        })
+        .sum()
+}
+
+fn memory() -> Result<f64, Error> {
+    memory_usage(&fs::read_to_string("/proc/meminfo")?)
+}
+
+/// Memory that's not available, from the contents of /proc/meminfo
+fn memory_usage(meminfo: &str) -> Result<f64, Error> {
+    let field = |name: &str| -> Option<f64> {
+        let line = meminfo.lines().find(|line| line.starts_with(name))?;
+        line.split_whitespace().nth(1)?.parse().ok()
+    };
+    match (field("MemTotal:"), field("MemAvailable:")) {
+        (Some(total), Some(available)) if total > 0.0 => Ok(100.0 - available * 100.0 / total),
+        _ => Err(Error::Unsupported("Can't read the memory usage".into())),
+    }
+}
+
+fn battery() -> Result<f64, Error> {
+    let mut capacities = vec![];
+    for entry in fs::read_dir("/sys/class/power_supply")?.flatten() {
+        let path = entry.path();
+        let is_battery = fs::read_to_string(path.join("type"))
+            .map(|x| x.trim() == "Battery")
+            .unwrap_or(false);
+        if !is_battery {
+            continue;
+        }
+        if let Ok(capacity) = fs::read_to_string(path.join("capacity")) {
+            if let Ok(capacity) = capacity.trim().parse::<f64>() {
+                capacities.push(capacity);
+            }
+        }
+    }
+    if capacities.is_empty() {
+        return Err(Error::Unsupported("No battery found".into()));
+    }
+    Ok(capacities.iter().sum::<f64>() / capacities.len() as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAT: &str = "\
+cpu  4705 356 584 3699 23 23 0 0 0 0
+cpu0 1393 280 283 1316 6 4 0 0 0 0
+cpu1 3312 76 301 2383 17 19 0 0 0 0
+intr 114930548 113199788 3 0 5 263 0 4 [... lots more numbers ...]
+ctxt 1990473
+btime 1062191376
+";
+
+    const DISKSTATS: &str = "\
+   7       0 loop0 52 0 2102 17 0 0 0 0 0 40 17 0 0 0 0
+ 259       0 nvme0n1 92553 29145 7354574 17937 87409 65834 4906098 108410 0 96980 132027 0 0 0 0
+ 259       1 nvme0n1p1 300 0 9840 52 2 0 2 0 0 96 52 0 0 0 0
+   8       1 sda1 412 12906 12 120
+";
+
+    const NET_DEV: &str = "\
+Inter-|   Receive                                                |  Transmit
+ face |bytes    packets errs drop fifo frame compressed multicast|bytes    packets errs drop fifo colls carrier compressed
+    lo: 1843296   15364    0    0    0     0          0         0  1843296   15364    0    0    0     0       0          0
+wlp1s0: 845207233  654732    0    0    0     0          0         0 41350117  284421    0    0    0     0       0          0
+enp2s0:    1000      10    0    0    0     0          0         0      234       3    0    0    0     0       0          0
+";
+
+    const MEMINFO: &str = "\
+MemTotal:       32000000 kB
+MemFree:         2000000 kB
+MemAvailable:    8000000 kB
+Buffers:          500000 kB
+Cached:          6000000 kB
+";
+
+    #[test]
+    fn cpu_from_stat() {
+        // Idle and iowait aren't busy
+        assert_eq!(cpu_jiffies(STAT), (4705 + 356 + 584 + 23, 9390));
+
+        let mut sampler = Sampler::new();
+        assert_eq!(sampler.cpu_usage(STAT), 0.0);
+        // 100 busy and 300 idle jiffies later
+        let later = STAT.replacen("cpu  4705 356 584 3699", "cpu  4805 356 584 3999", 1);
+        assert_eq!(sampler.cpu_usage(&later), 25.0);
+        // Counters didn't move
+        assert_eq!(sampler.cpu_usage(&later), 0.0);
+    }
+
+    #[test]
+    fn disk_from_diskstats() {
+        // Without loop devices and partitions of old kernels, with fewer counters
+        assert_eq!(disk_io_ticks(DISKSTATS), [96980, 96]);
+        assert!(disk_io_ticks("").is_empty());
+    }
+
+    #[test]
+    fn network_from_dev() {
+        // Loopback isn't counted
+        assert_eq!(network_bytes(NET_DEV), 845207233 + 41350117 + 1000 + 234);
+        assert_eq!(
+            network_bytes(&NET_DEV[..NET_DEV.find("    lo").unwrap()]),
+            0
+        );
+    }
+
+    #[test]
+    fn memory_from_meminfo() {
+        assert_eq!(memory_usage(MEMINFO).unwrap(), 75.0);
+        // Kernels before 3.14 don't have MemAvailable
+        let old = MEMINFO.replace("MemAvailable", "MemAvail");
+        assert!(matches!(memory_usage(&old), Err(Error::Unsupported(_))));
+        assert!(matches!(
+            memory_usage("MemTotal: 0 kB\nMemAvailable: 0 kB"),
+            Err(Error::Unsupported(_))
+        ));
+    }
+}
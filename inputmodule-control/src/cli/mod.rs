@@ -0,0 +1,1154 @@
+//! Commandline shell around the library
+pub mod b1display;
+pub mod c1minimal;
+pub mod config;
+#[cfg(unix)]
+pub mod daemon;
+pub mod ledmatrix;
+pub mod metrics;
+#[cfg(unix)]
+pub mod notifications;
+pub mod output;
+pub mod play;
+
+use std::fs;
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+
+use chrono::Local;
+use inputmodule_control::protocol::{
+    response::GameStatus, CommandVals, Game as GameArg, GameOfLifeParam, GameVal, ModuleType,
+    PongParam, PwmFreqArg, SettingsArg, MAX_BOOT_ANIMATION_FRAMES, MAX_SEQUENCE_FRAMES,
+};
+use inputmodule_control::protocol::{FromPrimitive, HEIGHT, WIDTH};
+use inputmodule_control::{
+    gif_gray_frames, load_gray_image, scroll_frames, usb_devices, B1Display, C1Minimal, Canvas,
+    DeviceInfo, Error, InputModule, LedMatrix, ModulePort, PowerMode, RlePattern, Selector,
+};
+use rand::prelude::*;
+use serde_json::json;
+
+use crate::cli::b1display::{B1DisplaySubcommand, B1Pattern};
+use crate::cli::c1minimal::C1MinimalSubcommand;
+use crate::cli::config::Config;
+use crate::cli::ledmatrix::{
+    Game, GameOfLifeStartParam, GameOptions, LedMatrixCommand, LedMatrixSubcommand,
+};
+use crate::cli::metrics::{Metric, MetricsStyle, Sampler};
+use crate::cli::output::{Output, Report};
+
+/// What to do with the settings persisted in the module's flash
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum SettingsAction {
+    /// Persist the current settings, they're restored at boot
+    Save,
+    /// Apply the persisted settings
+    Load,
+    /// Forget the persisted settings, defaults apply after the next boot
+    FactoryReset,
+}
+
+impl From<SettingsAction> for SettingsArg {
+    fn from(action: SettingsAction) -> Self {
+        match action {
+            SettingsAction::Save => SettingsArg::Save,
+            SettingsAction::Load => SettingsArg::Load,
+            SettingsAction::FactoryReset => SettingsArg::FactoryReset,
+        }
+    }
+}
+
+/// Which devices the commandline options and the subcommand select
+struct Selection {
+    selector: Selector,
+    module: Option<ModuleType>,
+}
+
+impl Selection {
+    fn new(args: &crate::ClapCli, config: &Config) -> Result<Self, Error> {
+        let mut selector = Selector {
+            serial_dev: args.serial_dev.clone(),
+            serial_number: args.serial_number.clone(),
+            location: args.location.clone(),
+        };
+        let mut module = args.module.map(ModuleType::from);
+        if let Some(name) = &args.device {
+            let alias = config.alias(name)?;
+            // Options on the commandline take precedence
+            selector = selector.or(alias.selector());
+            module = module.or(alias.module()?);
+        }
+        let module = match (module, args.command.as_ref().and_then(|x| x.module_type())) {
+            (Some(selected), Some(command)) if selected != command => {
+                return Err(Error::InvalidArgument(format!(
+                    "Selected a {selected:?} module, but the command is for {command:?}"
+                )))
+            }
+            (selected, command) => selected.or(command),
+        };
+        Ok(Self { selector, module })
+    }
+}
+
+/// Device that's open, with what type of module it is
+struct OpenModule {
+    serialdev: String,
+    port: ModulePort,
+    /// From its capabilities or else the USB PID. Not known for devices that
+    /// are neither, like the simulator with older firmware.
+    module: Option<ModuleType>,
+}
+
+impl OpenModule {
+    /// The capabilities are only queried here, the port keeps them
+    fn open(serialdev: &str, pid: Option<u16>) -> Result<Self, Error> {
+        let port = ModulePort::open(serialdev)?;
+        let module = match port.capabilities() {
+            Some(capabilities) => Some(capabilities.module),
+            None => pid.and_then(ModuleType::from_u16),
+        };
+        Ok(Self {
+            serialdev: serialdev.to_string(),
+            port,
+            module,
+        })
+    }
+
+    /// Type of the module, modules of unknown type are assumed to be the selected type
+    fn module_type(&self, selected: Option<ModuleType>) -> Option<ModuleType> {
+        self.module.or(selected)
+    }
+
+    /// Whether it's the selected type of module. By default accept any type.
+    fn is_selected(&self, selected: Option<ModuleType>) -> bool {
+        match (self.module, selected) {
+            (Some(module), Some(selected)) => module == selected,
+            _ => true,
+        }
+    }
+
+    /// Check that the firmware supports all commands needed by the selected options
+    ///
+    /// Firmware that can't report its capabilities is assumed to support all of them.
+    fn supports_commands(&self, required: &[(&str, CommandVals)], output: &Output) -> bool {
+        let Some(capabilities) = self.port.capabilities() else {
+            return true;
+        };
+        let mut supported = true;
+        for (option, command) in required {
+            if !capabilities.supports(*command) {
+                output.info(format!(
+                    "{option} is not supported by the firmware on {}, skipping device",
+                    self.serialdev
+                ));
+                supported = false;
+            }
+        }
+        supported
+    }
+}
+
+/// Serial devices that the selector matches, without opening them
+fn match_serialdevs(devices: &[DeviceInfo], selector: &Selector) -> Vec<String> {
+    if let Some(requested) = &selector.serial_dev {
+        if let Some(device) = devices.iter().find(|device| &device.port == requested) {
+            return if selector.matches(device) {
+                vec![device.port.clone()]
+            } else {
+                vec![]
+            };
+        }
+        // Not enumerated as serial port, like the pty of inputmodule-sim.
+        // Without a USB PID, only the capabilities tell what type it is.
+        let unknown = selector.serial_number.is_none() && selector.location.is_none();
+        if unknown && Path::new(requested).exists() {
+            return vec![requested.clone()];
+        }
+        vec![]
+    } else {
+        // Find all supported Framework devices
+        devices
+            .iter()
+            .filter(|device| device.is_input_module() && selector.matches(device))
+            .map(|device| device.port.clone())
+            .collect()
+    }
+}
+
+/// Open the devices that the selector matches and keep those of the selected type
+fn open_modules(devices: &[DeviceInfo], selection: &Selection) -> Result<Vec<OpenModule>, Error> {
+    let mut modules = vec![];
+    for serialdev in match_serialdevs(devices, &selection.selector) {
+        let pid = devices.iter().find(|x| x.port == serialdev).map(|x| x.pid);
+        let module = OpenModule::open(&serialdev, pid)?;
+        if module.is_selected(selection.module) {
+            modules.push(module);
+        }
+    }
+    Ok(modules)
+}
+
+/// Print the USB devices that the selector matches, whether they're modules or not
+fn list_ports(devices: &[DeviceInfo], selector: &Selector, config: &Config, output: &mut Output) {
+    let listed: Vec<&DeviceInfo> = devices
+        .iter()
+        .filter(|device| selector.matches(device))
+        .collect();
+    output.ports(&listed, config);
+}
+
+/// Only list the devices, without opening them
+pub fn list_devices(args: &crate::ClapCli, output: &mut Output) -> Result<(), Error> {
+    let config = Config::load(args.config.as_deref())?;
+    let selection = Selection::new(args, &config)?;
+    list_ports(&usb_devices()?, &selection.selector, &config, output);
+    Ok(())
+}
+
+/// Open the selected modules, waiting until there's one if asked to
+fn find_modules(
+    args: &crate::ClapCli,
+    wait_for_device: bool,
+    output: &mut Output,
+) -> Result<(Vec<OpenModule>, bool), Error> {
+    let config = Config::load(args.config.as_deref())?;
+    let selection = Selection::new(args, &config)?;
+    let mut modules: Vec<OpenModule>;
+    let mut waited = false;
+    loop {
+        let devices = usb_devices()?;
+        if args.list || args.verbose {
+            list_ports(&devices, &selection.selector, &config, output);
+        }
+        modules = open_modules(&devices, &selection)?;
+        if modules.is_empty() {
+            if wait_for_device {
+                // Waited at least once, that means the device was not present
+                // when the program started
+                waited = true;
+
+                // Try again after short wait
+                thread::sleep(Duration::from_millis(100));
+                continue;
+            } else {
+                return Ok((vec![], waited));
+            }
+        } else {
+            break;
+        }
+    }
+    Ok((modules, waited))
+}
+
+/// Commands that interact with serial devices
+pub fn serial_commands(args: &crate::ClapCli, output: &mut Output) -> Result<(), Error> {
+    let (modules, waited) = find_modules(args, args.wait_for_device, output)?;
+    if modules.is_empty() {
+        return Err(Error::DeviceNotFound);
+    } else if args.wait_for_device && !waited {
+        output.info("Device already present. No need to wait. Not executing command. Sleep 1s");
+        thread::sleep(Duration::from_millis(1000));
+        return Ok(());
+    }
+
+    let modules: Vec<OpenModule> = if let Some(command) = &args.command {
+        let required = command.required_commands();
+        modules
+            .into_iter()
+            .filter(|module| module.supports_commands(&required, output))
+            .collect()
+    } else {
+        modules
+    };
+    if modules.is_empty() {
+        return Err(Error::Unsupported(
+            "Selected options need newer firmware on all devices".into(),
+        ));
+    }
+
+    match &args.command {
+        Some(crate::Commands::LedMatrix(ledmatrix_args)) => {
+            ledmatrix_commands(args, ledmatrix_args, modules, output)
+        }
+        Some(crate::Commands::B1Display(b1display_args)) => {
+            b1display_commands(args, b1display_args, modules, output)
+        }
+        Some(crate::Commands::C1Minimal(c1minimal_args)) => {
+            c1minimal_commands(args, c1minimal_args, modules, output)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn ledmatrix_commands(
+    args: &crate::ClapCli,
+    ledmatrix_args: &LedMatrixSubcommand,
+    modules: Vec<OpenModule>,
+    output: &mut Output,
+) -> Result<(), Error> {
+    // Each module would run its own game
+    if ledmatrix_args.command.is_some() && modules.len() > 1 {
+        return Err(Error::InvalidArgument(
+            "Select a single LED matrix to play on".into(),
+        ));
+    }
+
+    let (serialdevs, mut matrices): (Vec<String>, Vec<LedMatrix>) = modules
+        .into_iter()
+        .map(|module| (module.serialdev, LedMatrix::from_port(module.port)))
+        .unzip();
+
+    // TODO: Handle generic commands without code deduplication
+    for (serialdev, matrix) in serialdevs.iter().zip(matrices.iter_mut()) {
+        if args.verbose {
+            output.info(format!("Selected serialdev: {:?}", serialdev));
+        }
+        let mut report = Report::new(output, serialdev);
+
+        if ledmatrix_args.bootloader {
+            matrix.bootloader()?;
+        }
+        if let Some(sleeping_arg) = ledmatrix_args.sleeping {
+            sleeping_cmd(matrix, sleeping_arg, &mut report)?;
+        }
+        if let Some(brightness_arg) = ledmatrix_args.brightness {
+            if let Some(brightness) = brightness_arg {
+                matrix.set_brightness(brightness)?;
+            } else {
+                let brightness = matrix.brightness()?;
+                report.field(
+                    "brightness",
+                    json!(brightness),
+                    format!("Current brightness: {brightness}"),
+                );
+            }
+        }
+        if let Some(percentage) = ledmatrix_args.percentage {
+            matrix.show_percentage(percentage)?;
+        }
+        if let Some(animate_arg) = ledmatrix_args.animate {
+            if let Some(animate) = animate_arg {
+                matrix.set_animate(animate)?;
+            } else {
+                let animating = matrix.animate()?;
+                report.field(
+                    "animate",
+                    json!(animating),
+                    format!("Currently animating: {animating}"),
+                );
+            }
+        }
+        if let Some(pattern) = ledmatrix_args.pattern {
+            matrix.show_pattern(pattern.into())?;
+        }
+        if ledmatrix_args.all_brightnesses {
+            matrix.show_all_brightnesses()?;
+        }
+        if ledmatrix_args.panic {
+            matrix.panic()?;
+        }
+        // Otherwise drawn across all matrices, after they're closed here
+        if !ledmatrix_args.canvas {
+            draw_cmd(matrix, ledmatrix_args, output)?;
+        }
+        if let Some(image_path) = &ledmatrix_args.upload_boot_animation {
+            let frames = matrix_gif_frames(image_path, MAX_BOOT_ANIMATION_FRAMES, output)?;
+            matrix.upload_boot_animation(&frames)?;
+        }
+        if let Some(image_path) = &ledmatrix_args.sequence {
+            let frames = matrix_gif_frames(image_path, MAX_SEQUENCE_FRAMES, output)?;
+            matrix.play_sequence(&frames, ledmatrix_args.sequence_loops)?;
+        }
+        if let Some(text) = &ledmatrix_args.scroll {
+            if ledmatrix_args.scroll_on_device {
+                matrix.upload_scroll_text(
+                    text,
+                    ledmatrix_args.scroll_mode.into(),
+                    scroll_period(ledmatrix_args.scroll_speed),
+                    ledmatrix_args.scroll_loops,
+                )?;
+            }
+        }
+        if ledmatrix_args.clear_boot_animation {
+            matrix.clear_boot_animation()?;
+        }
+
+        if let Some(game) = ledmatrix_args.start_game {
+            start_game_cmd(matrix, game, &ledmatrix_args.game_options)?;
+        }
+
+        if let Some(fps) = ledmatrix_args.animation_fps {
+            if let Some(fps) = fps {
+                matrix.set_animation_fps(fps)?;
+            } else {
+                print_animation_period(matrix.animation_period()?, &mut report);
+            }
+        }
+
+        if let Some(freq) = ledmatrix_args.pwm_freq {
+            if let Some(freq) = freq {
+                let freq = PwmFreqArg::from_hz(freq)
+                    .ok_or_else(|| Error::InvalidArgument(format!("Invalid frequency {freq}")))?;
+                matrix.set_pwm_freq(freq)?;
+            } else {
+                let freq = matrix.pwm_freq()?.hz();
+                report.field(
+                    "pwm_freq",
+                    json!(freq),
+                    format!("Animation Frequency: {freq}Hz"),
+                );
+            }
+        }
+        if let Some(debug_mode) = ledmatrix_args.debug_mode {
+            if let Some(enable_debug) = debug_mode {
+                matrix.set_debug_mode(enable_debug)?;
+            } else {
+                let debug_mode = matrix.debug_mode()?;
+                report.field(
+                    "debug_mode",
+                    json!(debug_mode),
+                    format!("Debug Mode enabled: {debug_mode}"),
+                );
+            }
+        }
+
+        if ledmatrix_args.stop_game {
+            matrix.stop_game()?;
+        }
+        if ledmatrix_args.game_status {
+            print_game_status(&matrix.game_status()?, &mut report);
+        }
+        if let Some(LedMatrixCommand::Play(play_args)) = &ledmatrix_args.command {
+            play::run(matrix, play_args)?;
+        }
+        if let Some(action) = ledmatrix_args.settings {
+            matrix.settings(action.into())?;
+        }
+        if ledmatrix_args.version {
+            print_version(matrix, &mut report)?;
+        }
+        output.module(report);
+    }
+
+    // With --canvas the matrices are drawn on as one
+    let mut canvas = None;
+    if ledmatrix_args.canvas {
+        let matrices = std::mem::take(&mut matrices);
+        let canvas = canvas.insert(open_canvas(args, ledmatrix_args, &serialdevs, matrices)?);
+        canvas_cmd(canvas, ledmatrix_args, output)?;
+    } else if ledmatrix_args.blinking
+        || ledmatrix_args.breathing
+        || ledmatrix_args.random_eq
+        || ledmatrix_args.clock
+        || ledmatrix_args.metrics.is_some()
+        || (ledmatrix_args.scroll.is_some() && !ledmatrix_args.scroll_on_device)
+    {
+        if ledmatrix_args.blinking {
+            blinking_cmd(&mut matrices)?;
+        }
+        if ledmatrix_args.breathing {
+            breathing_cmd(&mut matrices)?;
+        }
+
+        if ledmatrix_args.random_eq {
+            random_eq_cmd(&mut matrices)?;
+        }
+
+        if ledmatrix_args.clock {
+            clock_cmd(&mut matrices, output)?;
+        }
+
+        if let Some(metrics) = &ledmatrix_args.metrics {
+            metrics_cmd(&mut matrices, metrics, ledmatrix_args)?;
+        }
+
+        if let Some(text) = &ledmatrix_args.scroll {
+            if !ledmatrix_args.scroll_on_device {
+                scroll_cmd(&mut matrices, text, ledmatrix_args)?;
+            }
+        }
+    }
+
+    #[cfg(feature = "audio-visualizations")]
+    if ledmatrix_args.input_eq {
+        let matrices = match &mut canvas {
+            Some(canvas) => canvas.matrices(),
+            None => matrices.as_mut_slice(),
+        };
+        input_eq_cmd(matrices)?;
+    }
+
+    Ok(())
+}
+
+fn draw_cmd(
+    matrix: &mut LedMatrix,
+    ledmatrix_args: &LedMatrixSubcommand,
+    output: &Output,
+) -> Result<(), Error> {
+    if let Some(image_path) = &ledmatrix_args.image_bw {
+        matrix.display_bw_image(&load_gray_image(image_path)?)?;
+    }
+    if let Some(image_path) = &ledmatrix_args.image_gray {
+        matrix.display_gray_image(&load_gray_image(image_path)?)?;
+    }
+    if let Some(values) = &ledmatrix_args.eq {
+        matrix.show_eq(values)?;
+    }
+    if let Some(s) = &ledmatrix_args.string {
+        matrix.show_string(s)?;
+    }
+    if let Some(symbols) = &ledmatrix_args.symbols {
+        output.info(format!("Symbols: {symbols:?}"));
+        matrix.show_symbols(symbols)?;
+    }
+    Ok(())
+}
+
+/// Put the selected LED matrices on a canvas, ordered as in the config file
+fn open_canvas(
+    args: &crate::ClapCli,
+    ledmatrix_args: &LedMatrixSubcommand,
+    serialdevs: &[String],
+    matrices: Vec<LedMatrix>,
+) -> Result<Canvas, Error> {
+    let config = Config::load(args.config.as_deref())?;
+    let gap = ledmatrix_args.canvas_gap.unwrap_or(config.canvas.gap);
+
+    if config.canvas.devices.is_empty() {
+        return Canvas::new(matrices, gap);
+    }
+    let devices = usb_devices()?;
+    let mut matrices: Vec<Option<LedMatrix>> = matrices.into_iter().map(Some).collect();
+    let mut ordered = vec![];
+    for name in &config.canvas.devices {
+        let selector = config.alias(name)?.selector();
+        let index = serialdevs.iter().position(|serialdev| {
+            match devices.iter().find(|device| &device.port == serialdev) {
+                Some(device) => selector.matches(device),
+                // Not a USB device, like the pty of inputmodule-sim
+                None => selector.serial_dev.as_ref() == Some(serialdev),
+            }
+        });
+        let matrix = index.and_then(|index| matrices[index].take());
+        ordered.push(matrix.ok_or(Error::DeviceNotFound)?);
+    }
+    Canvas::new(ordered, gap)
+}
+
+/// Same as the drawing commands on a single matrix, but across the canvas
+fn canvas_cmd(
+    canvas: &mut Canvas,
+    ledmatrix_args: &LedMatrixSubcommand,
+    output: &Output,
+) -> Result<(), Error> {
+    if let Some(image_path) = &ledmatrix_args.image_bw {
+        canvas.display_bw_image(&load_gray_image(image_path)?)?;
+    }
+    if let Some(image_path) = &ledmatrix_args.image_gray {
+        canvas.display_gray_image(&load_gray_image(image_path)?)?;
+    }
+    if let Some(values) = &ledmatrix_args.eq {
+        canvas.show_eq(values)?;
+    }
+    if let Some(s) = &ledmatrix_args.string {
+        canvas.show_string(s)?;
+    }
+    if let Some(symbols) = &ledmatrix_args.symbols {
+        output.info(format!("Symbols: {symbols:?}"));
+        canvas.show_symbols(symbols)?;
+    }
+
+    // Commands that block and need manual looping
+    if ledmatrix_args.blinking {
+        blinking_cmd(canvas.matrices())?;
+    }
+    if ledmatrix_args.breathing {
+        breathing_cmd(canvas.matrices())?;
+    }
+    if ledmatrix_args.random_eq {
+        canvas_random_eq_cmd(canvas)?;
+    }
+    if ledmatrix_args.clock {
+        canvas_clock_cmd(canvas, output)?;
+    }
+    Ok(())
+}
+
+fn b1display_commands(
+    args: &crate::ClapCli,
+    b1display_args: &B1DisplaySubcommand,
+    modules: Vec<OpenModule>,
+    output: &mut Output,
+) -> Result<(), Error> {
+    for OpenModule {
+        serialdev, port, ..
+    } in modules
+    {
+        if args.verbose {
+            output.info(format!("Selected serialdev: {:?}", serialdev));
+        }
+        let mut report = Report::new(output, &serialdev);
+        let mut display = B1Display::from_port(port);
+
+        if b1display_args.bootloader {
+            display.bootloader()?;
+        }
+        if let Some(sleeping_arg) = b1display_args.sleeping {
+            sleeping_cmd(&mut display, sleeping_arg, &mut report)?;
+        }
+        if b1display_args.panic {
+            display.panic()?;
+        }
+        if b1display_args.version {
+            print_version(&mut display, &mut report)?;
+        }
+        if let Some(display_on) = b1display_args.display_on {
+            if let Some(display_on) = display_on {
+                display.set_display_on(display_on)?;
+            } else {
+                let on = display.display_on()?;
+                report.field("display_on", json!(on), format!("Currently on: {on}"));
+            }
+        }
+        if let Some(text) = &b1display_args.text {
+            display.set_text(text)?;
+        }
+        if let Some(invert_screen) = b1display_args.invert_screen {
+            if let Some(invert_on) = invert_screen {
+                display.set_invert_screen(invert_on)?;
+            } else {
+                let inverted = display.invert_screen()?;
+                report.field(
+                    "invert_screen",
+                    json!(inverted),
+                    format!("Currently inverted: {inverted}"),
+                );
+            }
+        }
+        if let Some(screensaver_on) = b1display_args.screen_saver {
+            if let Some(screensaver_on) = screensaver_on {
+                display.set_screensaver(screensaver_on)?;
+            } else {
+                let on = display.screensaver()?;
+                report.field("screen_saver", json!(on), format!("Currently on: {on}"));
+            }
+        }
+        if let Some(fps) = b1display_args.fps {
+            if let Some(fps) = fps {
+                display.set_fps(fps.into())?;
+            } else {
+                let fps = display.fps()?.hz();
+                report.field("fps", json!(fps), format!("Current FPS: {fps}"));
+            }
+        }
+        if let Some(power_mode) = b1display_args.power_mode {
+            if let Some(mode) = power_mode {
+                display.set_power_mode(mode.into())?;
+            } else {
+                let mode = match display.power_mode()? {
+                    PowerMode::High => "High",
+                    PowerMode::Low => "Low",
+                };
+                report.field(
+                    "power_mode",
+                    json!(mode.to_lowercase()),
+                    format!("Current Power Mode: {mode}"),
+                );
+            }
+        }
+        if let Some(fps) = b1display_args.animation_fps {
+            if let Some(fps) = fps {
+                display.set_animation_fps(fps)?;
+            } else {
+                print_animation_period(display.animation_period()?, &mut report);
+            }
+        }
+        if let Some(image_path) = &b1display_args.image {
+            display.display_image(&load_gray_image(image_path)?)?;
+        }
+        if let Some(image_path) = &b1display_args.animated_gif {
+            let frames = gif_gray_frames(image_path, 300, 400)?;
+            // Not delaying any further. Current transmission delay is big enough
+            loop {
+                for (frame_img, _delay) in &frames {
+                    display.display_image(frame_img)?;
+                }
+            }
+        }
+        if b1display_args.clear_ram {
+            display.clear_ram()?;
+        }
+        if let Some(pattern) = b1display_args.pattern {
+            match pattern {
+                B1Pattern::Black => display.fill(true)?,
+                B1Pattern::White => display.fill(false)?,
+            }
+        }
+        if let Some(action) = b1display_args.settings {
+            display.settings(action.into())?;
+        }
+        output.module(report);
+    }
+    Ok(())
+}
+
+fn c1minimal_commands(
+    args: &crate::ClapCli,
+    c1minimal_args: &C1MinimalSubcommand,
+    modules: Vec<OpenModule>,
+    output: &mut Output,
+) -> Result<(), Error> {
+    for OpenModule {
+        serialdev, port, ..
+    } in modules
+    {
+        if args.verbose {
+            output.info(format!("Selected serialdev: {:?}", serialdev));
+        }
+        let mut report = Report::new(output, &serialdev);
+        let mut minimal = C1Minimal::from_port(port);
+
+        if c1minimal_args.bootloader {
+            minimal.bootloader()?;
+        }
+        if let Some(sleeping_arg) = c1minimal_args.sleeping {
+            sleeping_cmd(&mut minimal, sleeping_arg, &mut report)?;
+        }
+        if c1minimal_args.panic {
+            minimal.panic()?;
+        }
+        if c1minimal_args.version {
+            print_version(&mut minimal, &mut report)?;
+        }
+        if let Some(color) = c1minimal_args.set_color {
+            minimal.set_color(color.into())?;
+        }
+        if let Some(action) = c1minimal_args.settings {
+            minimal.settings(action.into())?;
+        }
+        output.module(report);
+    }
+    Ok(())
+}
+
+fn print_version(module: &mut impl InputModule, report: &mut Report) -> Result<(), Error> {
+    let version = module.version()?;
+
+    let major = version.major();
+    let minor = version.minor();
+    let patch = version.patch();
+    let mut text = format!("Device Version: {major}.{minor}.{patch}");
+    if version.pre_release {
+        text.push_str(" (Pre-Release)");
+    }
+    let value = json!({
+        "major": major,
+        "minor": minor,
+        "patch": patch,
+        "pre_release": version.pre_release,
+    });
+    report.field("version", value, text);
+
+    let hw_revision = module.hw_revision();
+    let text = match hw_revision {
+        Some(hw_revision) => format!("Hardware Revision: {hw_revision}"),
+        None => "Hardware Revision: Unknown".to_string(),
+    };
+    report.field("hw_revision", json!(hw_revision), text);
+    Ok(())
+}
+
+fn game_name(game: GameVal) -> &'static str {
+    match game {
+        GameVal::Snake => "snake",
+        GameVal::Pong => "pong",
+        GameVal::Tetris => "tetris",
+        GameVal::GameOfLife => "game-of-life",
+    }
+}
+
+fn game_status_text(status: &GameStatus) -> String {
+    match status.game {
+        None => "No game running".to_string(),
+        Some(game) => {
+            let state = if status.game_over { "over" } else { "running" };
+            let score = match game {
+                GameVal::Pong => format!("{}:{}", status.score, status.second_score),
+                _ => status.score.to_string(),
+            };
+            format!(
+                "Game: {} ({state}), Score: {score}, Level: {}, Ticks: {}",
+                game_name(game),
+                status.level,
+                status.ticks
+            )
+        }
+    }
+}
+
+fn print_game_status(status: &GameStatus, report: &mut Report) {
+    let text = game_status_text(status);
+    let value = json!({
+        "game": status.game.map(game_name),
+        "game_over": status.game_over,
+        "score": status.score,
+        "second_score": status.second_score,
+        "level": status.level,
+        "ticks": status.ticks,
+    });
+    report.field("game_status", value, text);
+}
+
+fn print_animation_period(period: u16, report: &mut Report) {
+    report.field(
+        "animation_period_ms",
+        json!(period),
+        format!(
+            "Animation Frequency: {}ms / {}Hz",
+            period,
+            1_000 / period.max(1)
+        ),
+    );
+}
+
+fn sleeping_cmd(
+    module: &mut impl InputModule,
+    arg: Option<bool>,
+    report: &mut Report,
+) -> Result<(), Error> {
+    if let Some(goto_sleep) = arg {
+        module.set_sleeping(goto_sleep)
+    } else {
+        let sleeping = module.sleeping()?;
+        report.field(
+            "sleeping",
+            json!(sleeping),
+            format!("Currently sleeping: {sleeping}"),
+        );
+        Ok(())
+    }
+}
+
+fn start_game_cmd(matrix: &mut LedMatrix, game: Game, options: &GameOptions) -> Result<(), Error> {
+    match game {
+        Game::GameOfLife => start_game_of_life(matrix, options),
+        Game::Snake => matrix.start_game(GameArg::Snake),
+        Game::Pong => matrix.start_game(GameArg::Pong(PongParam {
+            mode: options.pong_mode.into(),
+            difficulty: options.pong_difficulty.into(),
+            winning_score: options.pong_points,
+        })),
+        Game::Tetris => matrix.start_game(GameArg::Tetris),
+    }
+}
+
+/// Start from --game-param, or draw the RLE pattern and start from the matrix
+fn start_game_of_life(matrix: &mut LedMatrix, options: &GameOptions) -> Result<(), Error> {
+    let mut rule = options.life_rule;
+    let start = match (&options.life_rle, options.game_param) {
+        (Some(path), _) => {
+            let rle = fs::read_to_string(path)
+                .map_err(|err| Error::InvalidArgument(format!("Can't read {path}: {err}")))?;
+            let pattern = RlePattern::parse(&rle)?;
+            matrix.draw_matrix(&pattern.to_matrix()?)?;
+            // Patterns are often made for a different rule
+            rule = rule.or(pattern.rule);
+            GameOfLifeStartParam::CurrentMatrix
+        }
+        (None, Some(param)) => param,
+        (None, None) => {
+            return Err(Error::InvalidArgument(
+                "To start Game of Life, provide a --game-param or --life-rle".into(),
+            ))
+        }
+    };
+    matrix.start_game(GameArg::GameOfLife(GameOfLifeParam {
+        start: start.into(),
+        rule: rule.unwrap_or_default(),
+        wrap: !options.life_bounded,
+        reseed: options.life_reseed,
+        seed: options.life_seed.unwrap_or_else(|| thread_rng().gen()),
+        density: options.life_density,
+    }))
+}
+
+/// Frames of a GIF, scaled to fit the LED matrix
+/// Only the first max_frames are returned.
+fn matrix_gif_frames(
+    image_path: &str,
+    max_frames: usize,
+    output: &Output,
+) -> Result<Vec<(image::GrayImage, u16)>, Error> {
+    let mut frames = gif_gray_frames(image_path, WIDTH as u32, HEIGHT as u32)?;
+    if frames.len() > max_frames {
+        output.info(format!(
+            "Only {} frames fit, dropping the other {}",
+            max_frames,
+            frames.len() - max_frames
+        ));
+        frames.truncate(max_frames);
+    }
+    Ok(frames)
+}
+
+fn set_brightness_multiple(matrices: &mut [LedMatrix], brightness: u8) -> Result<(), Error> {
+    for matrix in matrices {
+        matrix.set_brightness(brightness)?;
+    }
+    Ok(())
+}
+
+fn blinking_cmd(matrices: &mut [LedMatrix]) -> Result<(), Error> {
+    let duration = Duration::from_millis(500);
+    loop {
+        set_brightness_multiple(matrices, 0)?;
+        thread::sleep(duration);
+        set_brightness_multiple(matrices, 200)?;
+        thread::sleep(duration);
+    }
+}
+
+fn breathing_cmd(matrices: &mut [LedMatrix]) -> Result<(), Error> {
+    loop {
+        // Go quickly from 250 to 50
+        for i in 0..40 {
+            set_brightness_multiple(matrices, 250 - i * 5)?;
+            thread::sleep(Duration::from_millis(25));
+        }
+
+        // Go slowly from 50 to 0
+        for i in 0..50 {
+            set_brightness_multiple(matrices, 50 - i)?;
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // Go slowly from 0 to 50
+        for i in 0..50 {
+            set_brightness_multiple(matrices, i)?;
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        // Go quickly from 50 to 250
+        for i in 0..40 {
+            set_brightness_multiple(matrices, 50 + i * 5)?;
+            thread::sleep(Duration::from_millis(25));
+        }
+    }
+}
+
+/// Display an equlizer looking animation with random values.
+fn random_eq_cmd(matrices: &mut [LedMatrix]) -> Result<(), Error> {
+    loop {
+        // Lower values more likely, makes it look nicer
+        //weights = [i*i for i in range(33, 0, -1)]
+        let population: Vec<u8> = (1..34).collect();
+        let mut rng = thread_rng();
+        let vals = population
+            .choose_multiple_weighted(&mut rng, 9, |item| (34 - item) ^ 2)
+            .unwrap()
+            .copied()
+            .collect::<Vec<_>>();
+        for matrix in matrices.iter_mut() {
+            matrix.show_eq(vals.as_slice())?;
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+}
+
+/// Random EQ with a bar for every column of the canvas
+fn canvas_random_eq_cmd(canvas: &mut Canvas) -> Result<(), Error> {
+    let columns = canvas.matrices().len() * WIDTH;
+    loop {
+        let population: Vec<u8> = (1..34).collect();
+        let mut rng = thread_rng();
+        let vals = (0..columns)
+            .map(|_| {
+                *population
+                    .choose_weighted(&mut rng, |item| (34 - item) ^ 2)
+                    .unwrap()
+            })
+            .collect::<Vec<_>>();
+        canvas.show_eq(vals.as_slice())?;
+        thread::sleep(Duration::from_millis(200));
+    }
+}
+
+#[cfg(feature = "audio-visualizations")]
+/// The data-type for storing analyzer results
+#[derive(Debug, Clone)]
+pub struct AnalyzerResult {
+    spectrum: vis_core::analyzer::Spectrum<Vec<f32>>,
+    volume: f32,
+    beat: f32,
+}
+
+#[cfg(feature = "audio-visualizations")]
+// Equalizer-like animation that expands as volume goes up and retracts as it goes down
+fn input_eq_cmd(matrices: &mut [LedMatrix]) -> Result<(), Error> {
+    // Example from https://github.com/Rahix/visualizer2/blob/canon/README.md
+
+    // Initialize the logger.  Take a look at the sources if you want to customize
+    // the logger.
+    vis_core::default_log();
+
+    // Load the default config source.  More about config later on.  You can also
+    // do this manually if you have special requirements.
+    vis_core::default_config();
+
+    // Initialize some analyzer-tools.  These will be moved into the analyzer closure
+    // later on.
+    let mut analyzer = vis_core::analyzer::FourierBuilder::new()
+        .length(512)
+        .window(vis_core::analyzer::window::nuttall)
+        .plan();
+
+    let spectrum = vis_core::analyzer::Spectrum::new(vec![0.0; analyzer.buckets()], 0.0, 1.0);
+
+    let mut frames = vis_core::Visualizer::new(
+        AnalyzerResult {
+            spectrum,
+            volume: 0.0,
+            beat: 0.0,
+        },
+        // This closure is the "analyzer".  It will be executed in a loop to always
+        // have the latest data available.
+        move |info, samples| {
+            analyzer.analyze(samples);
+
+            info.spectrum.fill_from(&analyzer.average());
+            info.volume = samples.volume(0.3) * 400.0;
+            info.beat = info.spectrum.slice(50.0, 100.0).max() * 0.01;
+            info
+        },
+    )
+    // Build the frame iterator which is the base of your loop later on
+    .frames();
+
+    for frame in frames.iter() {
+        // This is just a primitive example, your vis core belongs here
+
+        let volumes_to_display = frame.info(|info| {
+            let sampled_volume = info.volume;
+            let limited_volume = sampled_volume.min(34.0);
+
+            let display_max_widths = [10.0, 14.0, 20.0, 28.0, 34.0, 28.0, 20.0, 14.0, 10.0];
+
+            display_max_widths
+                .iter()
+                .map(|x| {
+                    let computed_width = (limited_volume / 34.0) * x;
+                    let next_lowest_odd = computed_width - (computed_width % 2.0) - 1.0;
+                    next_lowest_odd as u8
+                })
+                .collect::<Vec<_>>()
+        });
+
+        for matrix in matrices.iter_mut() {
+            matrix.show_eq(volumes_to_display.as_slice())?;
+        }
+        thread::sleep(Duration::from_millis(30));
+    }
+    Ok(())
+}
+
+/// Draw the metrics at an interval.
+/// Loops forever
+fn metrics_cmd(
+    matrices: &mut [LedMatrix],
+    metrics: &[Metric],
+    ledmatrix_args: &LedMatrixSubcommand,
+) -> Result<(), Error> {
+    let mut sampler = Sampler::new();
+    // Oldest sample first, for the sparkline
+    let mut history: Vec<u8> = vec![0; WIDTH];
+    loop {
+        let values = metrics
+            .iter()
+            .map(|metric| sampler.sample(*metric))
+            .collect::<Result<Vec<_>, _>>()?;
+        history.remove(0);
+        history.push(values[0]);
+
+        for matrix in matrices.iter_mut() {
+            match ledmatrix_args.metrics_style {
+                MetricsStyle::Bars => {
+                    // Split the columns evenly, the ones that are left stay off
+                    let columns = (WIDTH / values.len()).max(1);
+                    let bars: Vec<u8> = values
+                        .iter()
+                        .flat_map(|value| vec![percent_to_height(*value); columns])
+                        .take(WIDTH)
+                        .collect();
+                    matrix.show_eq(&bars)?;
+                }
+                MetricsStyle::Sparkline => {
+                    let bars: Vec<u8> = history.iter().map(|x| percent_to_height(*x)).collect();
+                    matrix.show_eq(&bars)?;
+                }
+                MetricsStyle::Percentage => matrix.show_percentage(values[0])?,
+            }
+        }
+        thread::sleep(Duration::from_millis(ledmatrix_args.metrics_interval));
+    }
+}
+
+/// Height of an EQ bar for a percentage
+fn percent_to_height(percent: u8) -> u8 {
+    (percent as usize * HEIGHT / 100) as u8
+}
+
+/// Render the current time with seconds, continuing on the second matrix.
+/// Loops forever, updating every second
+fn canvas_clock_cmd(canvas: &mut Canvas, output: &Output) -> Result<(), Error> {
+    loop {
+        let date = Local::now();
+        let current_time = date.format("%H:%M:%S").to_string();
+        output.info(format!("Current Time = {current_time}"));
+
+        canvas.show_string(&current_time)?;
+        thread::sleep(Duration::from_millis(1000));
+    }
+}
+
+/// Scroll text through all matrices at the same time
+fn scroll_cmd(
+    matrices: &mut [LedMatrix],
+    text: &str,
+    ledmatrix_args: &LedMatrixSubcommand,
+) -> Result<(), Error> {
+    let frames = scroll_frames(text, ledmatrix_args.scroll_mode.into());
+    let period = scroll_period(ledmatrix_args.scroll_speed);
+    let mut loops = 0;
+    while ledmatrix_args.scroll_loops == 0 || loops < ledmatrix_args.scroll_loops {
+        for frame in &frames {
+            for matrix in matrices.iter_mut() {
+                matrix.display_gray_values(frame)?;
+            }
+            thread::sleep(period);
+        }
+        loops += 1;
+    }
+    Ok(())
+}
+
+/// Time to move by one pixel
+fn scroll_period(pixels_per_second: u16) -> Duration {
+    Duration::from_millis(1000 / pixels_per_second.max(1) as u64)
+}
+
+/// Render the current time and display.
+/// Loops forever, updating every second
+fn clock_cmd(matrices: &mut [LedMatrix], output: &Output) -> Result<(), Error> {
+    loop {
+        let date = Local::now();
+        let current_time = date.format("%H:%M").to_string();
+        output.info(format!("Current Time = {current_time}"));
+
+        for matrix in matrices.iter_mut() {
+            matrix.show_string(&current_time)?;
+        }
+        thread::sleep(Duration::from_millis(1000));
+    }
+}
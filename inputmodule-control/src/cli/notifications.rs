@@ -0,0 +1,335 @@
+//! Show desktop notifications on the LED matrices and B1 displays
+//!
+//! Listens to the calls to the freedesktop notification server on the D-Bus
+//! session bus, without replacing the server. Instead of D-Bus, a Unix socket
+//! can be used, for testing or other sources of notifications. It takes one
+//! JSON object per line:
+//!
+//! ```text
+//! {"app": "Slack", "summary": "New message", "urgency": "critical"}
+//! ```
+//!
+//! The rules in the config file decide what's shown, see config.rs.
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::thread;
+use std::time::Duration;
+
+use inputmodule_control::protocol::{ModuleType, MAX_TEXT_LEN};
+use inputmodule_control::{usb_devices, B1Display, Error, LedMatrix};
+use serde::Deserialize;
+
+use crate::cli::config::{Config, NotificationRule, Urgency};
+use crate::cli::output::Output;
+use crate::cli::{open_modules, Selection};
+
+/// How long the symbol is on and off when flashing
+const FLASH_PERIOD: Duration = Duration::from_millis(300);
+
+/// Show desktop notifications on the modules
+#[derive(clap::Args, Debug)]
+pub struct NotificationsSubcommand {
+    /// Take notifications from this Unix socket instead of D-Bus
+    #[arg(long)]
+    pub socket: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Notification {
+    #[serde(default)]
+    app: String,
+    #[serde(default)]
+    summary: String,
+    #[serde(default)]
+    urgency: Urgency,
+}
+
+/// Show notifications until the process is killed
+pub fn run(
+    args: &crate::ClapCli,
+    notifications_args: &NotificationsSubcommand,
+    output: &Output,
+) -> Result<(), Error> {
+    let config = Config::load(args.config.as_deref())?;
+    let selection = Selection::new(args, &config)?;
+    let rules = if config.notifications.is_empty() {
+        vec![NotificationRule::default()]
+    } else {
+        config.notifications
+    };
+
+    let (tx, rx) = mpsc::channel();
+    match &notifications_args.socket {
+        Some(path) => listen_socket(path, tx, output)?,
+        None => listen_dbus(tx)?,
+    }
+
+    for notification in rx {
+        if args.verbose {
+            output.info(format!("{notification:?}"));
+        }
+        let Some(rule) = find_rule(&rules, &notification) else {
+            continue;
+        };
+        if let Err(err) = show_notification(&selection, rule, &notification) {
+            output.info(format!("Failed to show notification: {err}"));
+        }
+    }
+    Ok(())
+}
+
+/// The first rule that matches the notification
+fn find_rule<'a>(
+    rules: &'a [NotificationRule],
+    notification: &Notification,
+) -> Option<&'a NotificationRule> {
+    rules
+        .iter()
+        .find(|rule| rule.matches(&notification.app, notification.urgency))
+}
+
+/// Show a notification on all selected modules
+///
+/// The modules are looked up for every notification, so that the ones that
+/// were plugged in meanwhile show it too. They're only open while showing it.
+fn show_notification(
+    selection: &Selection,
+    rule: &NotificationRule,
+    notification: &Notification,
+) -> Result<(), Error> {
+    let found = usb_devices()?;
+    let mut matrices = vec![];
+    for module in open_modules(&found, selection)? {
+        match module.module_type(selection.module) {
+            Some(ModuleType::LedMatrix) => matrices.push(LedMatrix::from_port(module.port)),
+            Some(ModuleType::B1Display) if rule.summary => {
+                let mut display = B1Display::from_port(module.port);
+                display.set_text(&truncate(&notification.summary))?;
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(symbol) = &rule.symbol {
+        let symbols = [symbol.clone()];
+        for _ in 0..rule.flashes {
+            for matrix in matrices.iter_mut() {
+                matrix.show_symbols(&symbols)?;
+            }
+            thread::sleep(FLASH_PERIOD);
+            for matrix in matrices.iter_mut() {
+                matrix.show_symbols(&[])?;
+            }
+            thread::sleep(FLASH_PERIOD);
+        }
+        for matrix in matrices.iter_mut() {
+            matrix.show_symbols(&symbols)?;
+        }
+    }
+    Ok(())
+}
+
+/// Cut the text to what fits into SetText, without splitting a character
+fn truncate(text: &str) -> String {
+    let mut end = text.len().min(MAX_TEXT_LEN);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].to_string()
+}
+
+fn listen_socket(path: &Path, tx: Sender<Notification>, output: &Output) -> Result<(), Error> {
+    // Left behind by a previous run
+    if UnixStream::connect(path).is_err() {
+        let _ = fs::remove_file(path);
+    }
+    let listener = UnixListener::bind(path)?;
+    output.info(format!("Listening on {}", path.display()));
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let tx = tx.clone();
+            thread::spawn(move || {
+                for line in BufReader::new(stream).lines() {
+                    let Ok(line) = line else {
+                        break;
+                    };
+                    // Skip what isn't a notification, there's nobody to tell
+                    if let Ok(notification) = serde_json::from_str(&line) {
+                        let _ = tx.send(notification);
+                    }
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Urgency from the hint of a D-Bus notification, normal if there's none
+#[cfg(any(feature = "notifications", test))]
+fn urgency_hint(hint: Option<u8>) -> Urgency {
+    match hint {
+        Some(0) => Urgency::Low,
+        Some(2) => Urgency::Critical,
+        _ => Urgency::Normal,
+    }
+}
+
+#[cfg(feature = "notifications")]
+fn listen_dbus(tx: Sender<Notification>) -> Result<(), Error> {
+    use std::collections::HashMap;
+    use zbus::blocking::{Connection, MessageIterator};
+    use zbus::zvariant::OwnedValue;
+
+    /// Arguments of org.freedesktop.Notifications.Notify
+    type NotifyArgs = (
+        String,
+        u32,
+        String,
+        String,
+        String,
+        Vec<String>,
+        HashMap<String, OwnedValue>,
+        i32,
+    );
+
+    let dbus_error = |err: zbus::Error| {
+        Error::Unsupported(format!("Can't listen to D-Bus notifications: {err}"))
+    };
+    let connection = Connection::session().map_err(dbus_error)?;
+    // Only watch the calls, the notification server still handles them
+    let rule = "type='method_call',interface='org.freedesktop.Notifications',member='Notify'";
+    connection
+        .call_method(
+            Some("org.freedesktop.DBus"),
+            "/org/freedesktop/DBus",
+            Some("org.freedesktop.DBus.Monitoring"),
+            "BecomeMonitor",
+            &(vec![rule], 0u32),
+        )
+        .map_err(dbus_error)?;
+
+    thread::spawn(move || {
+        for message in MessageIterator::from(&connection).flatten() {
+            let Ok((app, _, _, summary, _, _, hints, _)) = message.body::<NotifyArgs>() else {
+                continue;
+            };
+            let urgency = urgency_hint(
+                hints
+                    .get("urgency")
+                    .and_then(|x| x.downcast_ref::<u8>())
+                    .copied(),
+            );
+            let notification = Notification {
+                app,
+                summary,
+                urgency,
+            };
+            if tx.send(notification).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(())
+}
+
+#[cfg(not(feature = "notifications"))]
+fn listen_dbus(_tx: Sender<Notification>) -> Result<(), Error> {
+    Err(Error::Unsupported(
+        "Built without D-Bus support, enable the notifications feature or use --socket".into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn notification(app: &str, urgency: Urgency) -> Notification {
+        Notification {
+            app: app.into(),
+            summary: String::new(),
+            urgency,
+        }
+    }
+
+    fn rule(app: Option<&str>, urgency: Option<Urgency>, symbol: &str) -> NotificationRule {
+        NotificationRule {
+            app: app.map(Into::into),
+            urgency,
+            symbol: Some(symbol.into()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn rule_matches() {
+        let slack = rule(Some("Slack"), None, ":)");
+        assert!(slack.matches("Slack", Urgency::Low));
+        assert!(slack.matches("Slack", Urgency::Critical));
+        assert!(!slack.matches("slack", Urgency::Normal));
+
+        let critical = rule(None, Some(Urgency::Critical), "!");
+        assert!(critical.matches("Mail", Urgency::Critical));
+        assert!(!critical.matches("Mail", Urgency::Normal));
+
+        let both = rule(Some("Slack"), Some(Urgency::Critical), "!");
+        assert!(both.matches("Slack", Urgency::Critical));
+        assert!(!both.matches("Slack", Urgency::Normal));
+        assert!(!both.matches("Mail", Urgency::Critical));
+
+        // Without any rules configured, everything is shown
+        assert!(NotificationRule::default().matches("Mail", Urgency::Low));
+    }
+
+    #[test]
+    fn first_matching_rule() {
+        let rules = [
+            rule(Some("Slack"), Some(Urgency::Low), "a"),
+            rule(None, Some(Urgency::Critical), "b"),
+            rule(Some("Slack"), None, "c"),
+        ];
+        let symbol = |app, urgency| {
+            find_rule(&rules, &notification(app, urgency)).and_then(|rule| rule.symbol.as_deref())
+        };
+        assert_eq!(symbol("Slack", Urgency::Low), Some("a"));
+        assert_eq!(symbol("Slack", Urgency::Critical), Some("b"));
+        assert_eq!(symbol("Slack", Urgency::Normal), Some("c"));
+        assert_eq!(symbol("Mail", Urgency::Critical), Some("b"));
+        assert_eq!(symbol("Mail", Urgency::Normal), None);
+    }
+
+    #[test]
+    fn socket_notification() {
+        let parsed: Notification = serde_json::from_str(
+            r#"{"app": "Slack", "summary": "New message", "urgency": "critical"}"#,
+        )
+        .unwrap();
+        assert_eq!(parsed.app, "Slack");
+        assert_eq!(parsed.summary, "New message");
+        assert_eq!(parsed.urgency, Urgency::Critical);
+
+        let parsed: Notification = serde_json::from_str(r#"{"summary": "Hi"}"#).unwrap();
+        assert_eq!((parsed.app.as_str(), parsed.urgency), ("", Urgency::Normal));
+        assert!(serde_json::from_str::<Notification>(r#"{"urgency": "urgent"}"#).is_err());
+    }
+
+    #[test]
+    fn dbus_urgency() {
+        assert_eq!(urgency_hint(Some(0)), Urgency::Low);
+        assert_eq!(urgency_hint(Some(1)), Urgency::Normal);
+        assert_eq!(urgency_hint(Some(2)), Urgency::Critical);
+        assert_eq!(urgency_hint(None), Urgency::Normal);
+    }
+
+    #[test]
+    fn truncate_summary() {
+        assert_eq!(truncate("Hi"), "Hi");
+        let long = "ä".repeat(MAX_TEXT_LEN);
+        let truncated = truncate(&long);
+        assert!(truncated.len() <= MAX_TEXT_LEN);
+        assert!(long.starts_with(&truncated));
+        assert_eq!(truncated.chars().count(), MAX_TEXT_LEN / 2);
+    }
+}
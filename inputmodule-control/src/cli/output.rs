@@ -0,0 +1,131 @@
+use std::fmt::Display;
+
+use inputmodule_control::DeviceInfo;
+use serde_json::{json, Map, Value};
+
+use crate::cli::config::Config;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// Human readable
+    #[default]
+    Text,
+    /// A single JSON object, printed once all commands are done
+    Json,
+}
+
+/// Where the results of the commands go
+///
+/// Text is printed right away. JSON is collected and printed as one object by
+/// finish(), everything else that's printed goes to stderr then.
+pub struct Output {
+    format: OutputFormat,
+    json: Map<String, Value>,
+}
+
+impl Output {
+    pub fn new(format: OutputFormat) -> Self {
+        Self {
+            format,
+            json: Map::new(),
+        }
+    }
+
+    pub fn is_json(&self) -> bool {
+        self.format == OutputFormat::Json
+    }
+
+    /// Message for humans, not part of the results
+    pub fn info(&self, msg: impl Display) {
+        match self.format {
+            OutputFormat::Text => println!("{msg}"),
+            OutputFormat::Json => eprintln!("{msg}"),
+        }
+    }
+
+    /// List the USB serial ports
+    pub fn ports(&mut self, devices: &[&DeviceInfo], config: &Config) {
+        let mut list = vec![];
+        for device in devices {
+            let aliases = config.aliases_of(device);
+            if self.is_json() {
+                list.push(json!({
+                    "port": device.port,
+                    "vid": device.vid,
+                    "pid": device.pid,
+                    "serial_number": device.serial_number,
+                    "product": device.product,
+                    "location": device.location,
+                    "aliases": aliases,
+                }));
+                continue;
+            }
+            println!("{}", device.port);
+            println!("  VID      {:#06X}", device.vid);
+            println!("  PID      {:#06X}", device.pid);
+            if let Some(sn) = &device.serial_number {
+                println!("  SN       {}", sn);
+            }
+            if let Some(product) = &device.product {
+                // TODO: Seems to replace the spaces with underscore, not sure why
+                println!("  Product  {}", product);
+            }
+            if let Some(location) = &device.location {
+                println!("  Location {}", location);
+            }
+            if !aliases.is_empty() {
+                println!("  Aliases  {}", aliases.join(", "));
+            }
+        }
+        if self.is_json() {
+            self.json.insert("ports".into(), Value::Array(list));
+        }
+    }
+
+    /// Collect the results of a module
+    pub fn module(&mut self, report: Report) {
+        if let Some(fields) = report.fields {
+            let modules = self
+                .json
+                .entry("modules")
+                .or_insert_with(|| Value::Array(vec![]));
+            if let Value::Array(modules) = modules {
+                modules.push(Value::Object(fields));
+            }
+        }
+    }
+
+    /// Print the collected JSON, if any
+    pub fn finish(self) {
+        if self.is_json() {
+            println!("{}", Value::Object(self.json));
+        }
+    }
+}
+
+/// Results of the getters on a single module
+pub struct Report {
+    /// Only collected for JSON output
+    fields: Option<Map<String, Value>>,
+}
+
+impl Report {
+    pub fn new(output: &Output, serialdev: &str) -> Self {
+        let fields = output.is_json().then(|| {
+            let mut fields = Map::new();
+            fields.insert("device".into(), json!(serialdev));
+            fields
+        });
+        Self { fields }
+    }
+
+    /// Result of a getter, either as JSON value or as a line of text
+    pub fn field(&mut self, key: &str, value: Value, text: impl Display) {
+        match &mut self.fields {
+            Some(fields) => {
+                fields.insert(key.into(), value);
+            }
+            None => println!("{text}"),
+        }
+    }
+}
@@ -0,0 +1,198 @@
+//! Play the games on the LED matrix with the keyboard
+//!
+//! The terminal is in raw mode while playing, so that every key press is sent
+//! to the module right away, without waiting for Enter.
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal::{self, ClearType};
+use crossterm::{cursor, QueueableCommand};
+use inputmodule_control::protocol::GameControlArg;
+use inputmodule_control::{Error, LedMatrix};
+
+use crate::cli::ledmatrix::{Game, PlaySubcommand};
+use crate::cli::{game_status_text, start_game_cmd};
+
+/// How often the score is updated
+const STATUS_PERIOD: Duration = Duration::from_millis(250);
+
+/// Restores the terminal when playing ends, even with an error
+struct RawMode;
+
+impl RawMode {
+    fn enable() -> Result<Self, Error> {
+        terminal::enable_raw_mode()
+            .map_err(|err| Error::Unsupported(format!("Playing needs a terminal: {err}")))?;
+        Ok(RawMode)
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    Control(GameControlArg),
+    Quit,
+}
+
+/// What a key does in the game
+///
+/// Arrow keys and WASD steer, except in Pong, where A and D move the paddle
+/// of the second player.
+fn key_action(game: Game, key: KeyEvent) -> Option<Action> {
+    // Windows reports releasing the key too
+    if key.kind != KeyEventKind::Press {
+        return None;
+    }
+    let code = match key.code {
+        KeyCode::Char(c) => KeyCode::Char(c.to_ascii_lowercase()),
+        code => code,
+    };
+    let arg = match code {
+        // Doesn't send SIGINT in raw mode
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            return Some(Action::Quit)
+        }
+        KeyCode::Char('q') | KeyCode::Esc => return Some(Action::Quit),
+        KeyCode::Char('a') if game == Game::Pong => GameControlArg::SecondLeft,
+        KeyCode::Char('d') if game == Game::Pong => GameControlArg::SecondRight,
+        KeyCode::Up | KeyCode::Char('w') => GameControlArg::Up,
+        KeyCode::Down | KeyCode::Char('s') => GameControlArg::Down,
+        KeyCode::Left | KeyCode::Char('a') => GameControlArg::Left,
+        KeyCode::Right | KeyCode::Char('d') => GameControlArg::Right,
+        _ => return None,
+    };
+    Some(Action::Control(arg))
+}
+
+/// Play until the game is over or the player quits
+///
+/// Quitting stops the game, when it's over the score stays on the matrix.
+pub fn run(matrix: &mut LedMatrix, play_args: &PlaySubcommand) -> Result<(), Error> {
+    let mut stdout = io::stdout();
+    writeln!(stdout, "Arrow keys or WASD to play, Q to quit")?;
+
+    let raw_mode = RawMode::enable()?;
+    start_game_cmd(matrix, play_args.game, &play_args.game_options)?;
+
+    let mut last_status = None;
+    let mut next_status = Instant::now();
+    loop {
+        let timeout = next_status.saturating_duration_since(Instant::now());
+        if event::poll(timeout)? {
+            if let Event::Key(key) = event::read()? {
+                match key_action(play_args.game, key) {
+                    Some(Action::Control(arg)) => matrix.game_control(arg)?,
+                    Some(Action::Quit) => {
+                        matrix.stop_game()?;
+                        break;
+                    }
+                    None => {}
+                }
+            }
+        }
+        if Instant::now() < next_status {
+            continue;
+        }
+        next_status = Instant::now() + STATUS_PERIOD;
+
+        let status = matrix.game_status()?;
+        if last_status.as_ref() != Some(&status) {
+            // Overwrite the previous status
+            stdout
+                .queue(cursor::MoveToColumn(0))?
+                .queue(terminal::Clear(ClearType::CurrentLine))?;
+            write!(stdout, "{}", game_status_text(&status))?;
+            stdout.flush()?;
+        }
+        // Or stopped in some other way
+        if status.game_over || status.game.is_none() {
+            break;
+        }
+        last_status = Some(status);
+    }
+
+    drop(raw_mode);
+    writeln!(stdout)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(game: Game, code: KeyCode) -> Option<Action> {
+        key_action(game, KeyEvent::new(code, KeyModifiers::NONE))
+    }
+
+    #[test]
+    fn arrows_and_wasd() {
+        let keys = [
+            (KeyCode::Up, KeyCode::Char('w'), GameControlArg::Up),
+            (KeyCode::Down, KeyCode::Char('s'), GameControlArg::Down),
+            (KeyCode::Left, KeyCode::Char('a'), GameControlArg::Left),
+            (KeyCode::Right, KeyCode::Char('d'), GameControlArg::Right),
+        ];
+        for (arrow, letter, arg) in keys {
+            for game in [Game::Snake, Game::Tetris] {
+                assert_eq!(press(game, arrow), Some(Action::Control(arg)));
+                assert_eq!(press(game, letter), Some(Action::Control(arg)));
+            }
+        }
+        // Also with caps lock or shift
+        assert_eq!(
+            press(Game::Snake, KeyCode::Char('W')),
+            Some(Action::Control(GameControlArg::Up))
+        );
+        assert_eq!(press(Game::Snake, KeyCode::Char('x')), None);
+        assert_eq!(press(Game::Snake, KeyCode::Enter), None);
+    }
+
+    #[test]
+    fn pong_second_player() {
+        assert_eq!(
+            press(Game::Pong, KeyCode::Char('a')),
+            Some(Action::Control(GameControlArg::SecondLeft))
+        );
+        assert_eq!(
+            press(Game::Pong, KeyCode::Char('D')),
+            Some(Action::Control(GameControlArg::SecondRight))
+        );
+        // The arrows are still the first player's
+        assert_eq!(
+            press(Game::Pong, KeyCode::Left),
+            Some(Action::Control(GameControlArg::Left))
+        );
+        assert_eq!(
+            press(Game::Pong, KeyCode::Right),
+            Some(Action::Control(GameControlArg::Right))
+        );
+    }
+
+    #[test]
+    fn quit() {
+        assert_eq!(press(Game::Tetris, KeyCode::Char('q')), Some(Action::Quit));
+        assert_eq!(press(Game::Tetris, KeyCode::Esc), Some(Action::Quit));
+        let ctrl_c = KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL);
+        assert_eq!(key_action(Game::Pong, ctrl_c), Some(Action::Quit));
+        // Just C does nothing
+        assert_eq!(press(Game::Pong, KeyCode::Char('c')), None);
+    }
+
+    #[test]
+    fn only_key_presses() {
+        let release = KeyEvent::new_with_kind(
+            KeyCode::Char('q'),
+            KeyModifiers::NONE,
+            KeyEventKind::Release,
+        );
+        assert_eq!(key_action(Game::Snake, release), None);
+        let repeat = KeyEvent::new_with_kind(KeyCode::Up, KeyModifiers::NONE, KeyEventKind::Repeat);
+        assert_eq!(key_action(Game::Snake, repeat), None);
+    }
+}
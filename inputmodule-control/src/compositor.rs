@@ -0,0 +1,359 @@
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use image::{GrayImage, Luma};
+use inputmodule_protocol::{HEIGHT, WIDTH};
+
+use crate::error::Error;
+use crate::ledmatrix::LedMatrix;
+use crate::module::check_size;
+
+/// Rectangle on the LED matrix, in pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Region {
+    fn contains(&self, x: u32, y: u32) -> bool {
+        // Subtract, adding could overflow
+        x >= self.x && x - self.x < self.width && y >= self.y && y - self.y < self.height
+    }
+
+    /// Whether it's within the LED matrix
+    fn fits(&self) -> bool {
+        let fits = |start: u32, len: u32, max: usize| {
+            start
+                .checked_add(len)
+                .map_or(false, |end| end <= max as u32)
+        };
+        fits(self.x, self.width, WIDTH) && fits(self.y, self.height, HEIGHT)
+    }
+}
+
+/// Content of one tool, like a clock or a notification
+#[derive(Clone, Debug)]
+pub struct Layer {
+    /// 9x34 greyscale, the values are the LED brightness
+    pub frame: GrayImage,
+    /// Layers with a higher z are drawn on top
+    pub z: i32,
+    /// How much of the layers below is covered, 255 is opaque
+    pub alpha: u8,
+    /// Scales the values of the frame, 255 leaves them as they are
+    pub brightness: u8,
+    /// Only the pixels in this region are drawn, the rest is transparent
+    pub mask: Option<Region>,
+    /// Removed after this time
+    pub expires: Option<Instant>,
+}
+
+impl Layer {
+    /// Opaque layer at z 0 that stays until it's removed
+    pub fn new(frame: GrayImage) -> Result<Self, Error> {
+        check_size(&frame, WIDTH, HEIGHT)?;
+        Ok(Self {
+            frame,
+            z: 0,
+            alpha: 0xFF,
+            brightness: 0xFF,
+            mask: None,
+            expires: None,
+        })
+    }
+
+    fn is_expired(&self, now: Instant) -> bool {
+        match self.expires {
+            Some(expires) => expires <= now,
+            None => false,
+        }
+    }
+}
+
+/// Merges named layers into a single frame for the LED matrix
+///
+/// Several tools can share the matrix this way, instead of the last one that
+/// draws replacing everything.
+#[derive(Default)]
+pub struct Compositor {
+    layers: BTreeMap<String, Layer>,
+    /// Last frame that update() returned
+    frame: Option<GrayImage>,
+}
+
+impl Compositor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a layer or replace the one with the same name
+    ///
+    /// Fails if the mask doesn't fit on the LED matrix.
+    pub fn set_layer(&mut self, name: &str, layer: Layer) -> Result<(), Error> {
+        if let Some(mask) = layer.mask.filter(|mask| !mask.fits()) {
+            return Err(Error::InvalidArgument(format!(
+                "Region {mask:?} isn't within {WIDTH}x{HEIGHT}"
+            )));
+        }
+        self.layers.insert(name.to_string(), layer);
+        Ok(())
+    }
+
+    /// Whether there was a layer with that name
+    pub fn remove_layer(&mut self, name: &str) -> bool {
+        self.layers.remove(name).is_some()
+    }
+
+    pub fn layers(&self) -> &BTreeMap<String, Layer> {
+        &self.layers
+    }
+
+    /// Last frame that update() returned
+    pub fn frame(&self) -> Option<&GrayImage> {
+        self.frame.as_ref()
+    }
+
+    /// Merge all layers, from the lowest z to the highest
+    ///
+    /// Where no layer is drawn, the LEDs are off.
+    pub fn compose(&self) -> GrayImage {
+        let mut layers: Vec<&Layer> = self.layers.values().collect();
+        // Stable, so layers with the same z are in the order of their names
+        layers.sort_by_key(|layer| layer.z);
+
+        let mut frame = GrayImage::new(WIDTH as u32, HEIGHT as u32);
+        for layer in layers {
+            for (x, y, pixel) in frame.enumerate_pixels_mut() {
+                if let Some(mask) = &layer.mask {
+                    if !mask.contains(x, y) {
+                        continue;
+                    }
+                }
+                let src = layer.frame.get_pixel(x, y).0[0] as u32 * layer.brightness as u32 / 0xFF;
+                let dst = pixel.0[0] as u32;
+                let alpha = layer.alpha as u32;
+                *pixel = Luma([((src * alpha + dst * (0xFF - alpha)) / 0xFF) as u8]);
+            }
+        }
+        frame
+    }
+
+    /// Drop the expired layers and compose
+    ///
+    /// Returns the frame only if it's different from the last one.
+    pub fn update(&mut self, now: Instant) -> Option<GrayImage> {
+        self.layers.retain(|_, layer| !layer.is_expired(now));
+        let frame = self.compose();
+        if self.frame.as_ref() == Some(&frame) {
+            return None;
+        }
+        self.frame = Some(frame.clone());
+        Some(frame)
+    }
+
+    /// Update and display the frame on the matrices, if it changed
+    pub fn push<'a>(
+        &mut self,
+        matrices: impl IntoIterator<Item = &'a mut LedMatrix>,
+    ) -> Result<bool, Error> {
+        let Some(frame) = self.update(Instant::now()) else {
+            return Ok(false);
+        };
+        for matrix in matrices {
+            matrix.display_gray_values(&frame)?;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn filled(value: u8) -> Layer {
+        Layer::new(GrayImage::from_pixel(
+            WIDTH as u32,
+            HEIGHT as u32,
+            Luma([value]),
+        ))
+        .unwrap()
+    }
+
+    fn value(frame: &GrayImage, x: u32, y: u32) -> u8 {
+        frame.get_pixel(x, y).0[0]
+    }
+
+    #[test]
+    fn higher_z_on_top() {
+        let mut compositor = Compositor::new();
+        assert_eq!(value(&compositor.compose(), 0, 0), 0);
+
+        compositor
+            .set_layer("a", Layer { z: 1, ..filled(10) })
+            .unwrap();
+        compositor.set_layer("b", filled(20)).unwrap();
+        assert_eq!(value(&compositor.compose(), 0, 0), 10);
+
+        // Same z, in the order of their names
+        compositor
+            .set_layer("c", Layer { z: 1, ..filled(30) })
+            .unwrap();
+        assert_eq!(value(&compositor.compose(), 0, 0), 30);
+
+        // Replaced by name
+        compositor.set_layer("c", filled(30)).unwrap();
+        assert_eq!(value(&compositor.compose(), 0, 0), 10);
+        assert!(compositor.remove_layer("a"));
+        assert!(!compositor.remove_layer("a"));
+        assert_eq!(value(&compositor.compose(), 0, 0), 30);
+    }
+
+    #[test]
+    fn transparency() {
+        let mut compositor = Compositor::new();
+        compositor.set_layer("below", filled(200)).unwrap();
+        compositor
+            .set_layer(
+                "above",
+                Layer {
+                    z: 1,
+                    alpha: 0x80,
+                    ..filled(0)
+                },
+            )
+            .unwrap();
+        assert_eq!(value(&compositor.compose(), 0, 0), 99);
+
+        compositor
+            .set_layer(
+                "above",
+                Layer {
+                    z: 1,
+                    alpha: 0,
+                    ..filled(0)
+                },
+            )
+            .unwrap();
+        assert_eq!(value(&compositor.compose(), 0, 0), 200);
+
+        compositor
+            .set_layer(
+                "above",
+                Layer {
+                    z: 1,
+                    brightness: 0x80,
+                    ..filled(0xFF)
+                },
+            )
+            .unwrap();
+        assert_eq!(value(&compositor.compose(), 0, 0), 0x80);
+    }
+
+    #[test]
+    fn mask() {
+        let mut compositor = Compositor::new();
+        compositor.set_layer("below", filled(10)).unwrap();
+        let mask = Region {
+            x: 2,
+            y: 30,
+            width: 3,
+            height: 4,
+        };
+        compositor
+            .set_layer(
+                "above",
+                Layer {
+                    z: 1,
+                    mask: Some(mask),
+                    ..filled(20)
+                },
+            )
+            .unwrap();
+        let frame = compositor.compose();
+        assert_eq!(value(&frame, 2, 30), 20);
+        assert_eq!(value(&frame, 4, 33), 20);
+        assert_eq!(value(&frame, 1, 30), 10);
+        assert_eq!(value(&frame, 5, 33), 10);
+        assert_eq!(value(&frame, 2, 29), 10);
+    }
+
+    #[test]
+    fn mask_within_matrix() {
+        let mut compositor = Compositor::new();
+        let masked = |x, y, width, height| Layer {
+            mask: Some(Region {
+                x,
+                y,
+                width,
+                height,
+            }),
+            ..filled(20)
+        };
+        let whole = masked(0, 0, WIDTH as u32, HEIGHT as u32);
+        assert!(compositor.set_layer("a", whole).is_ok());
+        for outside in [
+            masked(1, 0, WIDTH as u32, 1),
+            masked(0, HEIGHT as u32, 1, 1),
+            masked(u32::MAX, 0, 2, 1),
+            masked(0, 1, 1, u32::MAX),
+        ] {
+            assert!(matches!(
+                compositor.set_layer("b", outside),
+                Err(Error::InvalidArgument(_))
+            ));
+        }
+        assert_eq!(compositor.layers().len(), 1);
+    }
+
+    #[test]
+    fn mask_at_the_end_of_u32() {
+        let region = Region {
+            x: u32::MAX - 1,
+            y: 0,
+            width: 2,
+            height: 1,
+        };
+        assert!(region.contains(u32::MAX, 0));
+        assert!(!region.contains(0, 0));
+    }
+
+    #[test]
+    fn expired_layers_removed() {
+        let now = Instant::now();
+        let mut compositor = Compositor::new();
+        compositor.set_layer("clock", filled(10)).unwrap();
+        compositor
+            .set_layer(
+                "notification",
+                Layer {
+                    z: 1,
+                    expires: Some(now + Duration::from_secs(5)),
+                    ..filled(20)
+                },
+            )
+            .unwrap();
+
+        let frame = compositor.update(now).unwrap();
+        assert_eq!(value(&frame, 0, 0), 20);
+        // Nothing changed
+        assert_eq!(compositor.update(now + Duration::from_secs(1)), None);
+        assert_eq!(compositor.frame(), Some(&frame));
+
+        let frame = compositor.update(now + Duration::from_secs(5)).unwrap();
+        assert_eq!(value(&frame, 0, 0), 10);
+        assert_eq!(compositor.layers().len(), 1);
+    }
+
+    #[test]
+    fn layer_size() {
+        assert!(matches!(
+            Layer::new(GrayImage::new(WIDTH as u32, HEIGHT as u32 - 1)),
+            Err(Error::ImageSize { .. })
+        ));
+    }
+}
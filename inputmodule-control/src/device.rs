@@ -0,0 +1,192 @@
+use serialport::{SerialPortInfo, SerialPortType};
+
+use crate::error::Error;
+use crate::module::{B1_LCD_PID, FRAMEWORK_VID, LED_MATRIX_PID};
+
+/// USB PIDs of all modules, including the C1 Minimal and the bootloader
+const PIDS: [u16; 4] = [LED_MATRIX_PID, B1_LCD_PID, 0x22, 0xFF];
+
+/// Serial port of a USB device
+#[derive(Clone, Debug, PartialEq)]
+pub struct DeviceInfo {
+    /// Path of the serial device, like /dev/ttyACM0 or COM3
+    pub port: String,
+    pub vid: u16,
+    pub pid: u16,
+    pub serial_number: Option<String>,
+    pub product: Option<String>,
+    /// Physical USB port, like 3-4.2. Only known on Linux.
+    pub location: Option<String>,
+}
+
+impl DeviceInfo {
+    fn from_port(port: &SerialPortInfo) -> Option<Self> {
+        let SerialPortType::UsbPort(usbinfo) = &port.port_type else {
+            return None;
+        };
+        Some(Self {
+            port: port.port_name.clone(),
+            vid: usbinfo.vid,
+            pid: usbinfo.pid,
+            serial_number: usbinfo.serial_number.clone(),
+            product: usbinfo.product.clone(),
+            location: usb_location(&port.port_name),
+        })
+    }
+
+    /// Whether it's one of the Framework input modules
+    pub fn is_input_module(&self) -> bool {
+        self.vid == FRAMEWORK_VID && PIDS.contains(&self.pid)
+    }
+}
+
+/// All serial ports of USB devices
+pub fn usb_devices() -> Result<Vec<DeviceInfo>, Error> {
+    let ports = serialport::available_ports().map_err(Error::Open)?;
+    Ok(ports.iter().filter_map(DeviceInfo::from_port).collect())
+}
+
+/// Which devices to pick, every criterion that's set must match
+///
+/// Unlike the path of the serial device, the serial number and the USB port
+/// stay the same across reboots.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Selector {
+    /// Path of the serial device, like /dev/ttyACM0 or COM3
+    pub serial_dev: Option<String>,
+    /// USB serial number, like FRAKDEAM0020110001
+    pub serial_number: Option<String>,
+    /// Physical USB port, like 3-4.2
+    pub location: Option<String>,
+}
+
+impl Selector {
+    pub fn matches(&self, device: &DeviceInfo) -> bool {
+        matches_criterion(&self.serial_dev, Some(&device.port))
+            && matches_criterion(&self.serial_number, device.serial_number.as_ref())
+            && matches_criterion(&self.location, device.location.as_ref())
+    }
+
+    /// Fill in the criteria that aren't set, from another selector
+    pub fn or(self, other: Selector) -> Selector {
+        Selector {
+            serial_dev: self.serial_dev.or(other.serial_dev),
+            serial_number: self.serial_number.or(other.serial_number),
+            location: self.location.or(other.location),
+        }
+    }
+}
+
+fn matches_criterion(wanted: &Option<String>, actual: Option<&String>) -> bool {
+    match wanted {
+        Some(wanted) => actual == Some(wanted),
+        None => true,
+    }
+}
+
+/// Physical USB port that the serial device is connected to
+///
+/// The tty is a child of the USB interface (like 3-4.2:1.0) in sysfs. Its
+/// parent is the USB device, named after the port.
+#[cfg(target_os = "linux")]
+fn usb_location(port_name: &str) -> Option<String> {
+    let tty = std::path::Path::new(port_name).file_name()?;
+    let sysfs = std::path::Path::new("/sys/class/tty")
+        .join(tty)
+        .join("device");
+    let interface = std::fs::canonicalize(sysfs).ok()?;
+    let device = interface.parent()?.file_name()?;
+    Some(device.to_str()?.to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn usb_location(_port_name: &str) -> Option<String> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn led_matrix() -> DeviceInfo {
+        DeviceInfo {
+            port: "/dev/ttyACM0".into(),
+            vid: FRAMEWORK_VID,
+            pid: LED_MATRIX_PID,
+            serial_number: Some("FRAKDEAM0020110001".into()),
+            product: Some("LED Matrix Input Module".into()),
+            location: Some("3-4.2".into()),
+        }
+    }
+
+    #[test]
+    fn input_modules() {
+        assert!(led_matrix().is_input_module());
+        let bootloader = DeviceInfo {
+            pid: 0xFF,
+            ..led_matrix()
+        };
+        assert!(bootloader.is_input_module());
+
+        let other_vid = DeviceInfo {
+            vid: 0x2E8A,
+            ..led_matrix()
+        };
+        assert!(!other_vid.is_input_module());
+        let other_pid = DeviceInfo {
+            pid: 0x0100,
+            ..led_matrix()
+        };
+        assert!(!other_pid.is_input_module());
+    }
+
+    #[test]
+    fn selector_matches_every_criterion() {
+        let device = led_matrix();
+        assert!(Selector::default().matches(&device));
+
+        let by_port = Selector {
+            location: Some("3-4.2".into()),
+            ..Default::default()
+        };
+        assert!(by_port.matches(&device));
+        let both = Selector {
+            serial_number: Some("FRAKDEAM0020110001".into()),
+            ..by_port.clone()
+        };
+        assert!(both.matches(&device));
+        let other_serial = Selector {
+            serial_number: Some("FRAKDEAM0020110002".into()),
+            ..by_port
+        };
+        assert!(!other_serial.matches(&device));
+
+        // Can't match when the device doesn't report it
+        let no_location = DeviceInfo {
+            location: None,
+            ..device
+        };
+        assert!(!both.matches(&no_location));
+    }
+
+    #[test]
+    fn selector_or() {
+        let commandline = Selector {
+            serial_dev: Some("/dev/ttyACM1".into()),
+            ..Default::default()
+        };
+        let alias = Selector {
+            serial_dev: Some("/dev/ttyACM0".into()),
+            location: Some("3-4.2".into()),
+            ..Default::default()
+        };
+        assert_eq!(
+            commandline.or(alias),
+            Selector {
+                serial_dev: Some("/dev/ttyACM1".into()),
+                serial_number: None,
+                location: Some("3-4.2".into()),
+            }
+        );
+    }
+}
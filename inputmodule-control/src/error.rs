@@ -0,0 +1,126 @@
+use std::fmt;
+use std::io;
+
+use inputmodule_protocol::response::{Ack, AckStatus, Response};
+use inputmodule_protocol::ParseError;
+
+/// Exit codes of the commandline tool, one per kind of error
+///
+/// 2 is what clap uses for invalid commandline arguments.
+pub mod exit_code {
+    pub const INVALID_ARGUMENT: i32 = 2;
+    pub const DEVICE_NOT_FOUND: i32 = 3;
+    pub const OPEN_FAILED: i32 = 4;
+    pub const TIMEOUT: i32 = 5;
+    pub const BAD_IMAGE: i32 = 6;
+    pub const UNSUPPORTED: i32 = 7;
+    pub const PROTOCOL: i32 = 8;
+    pub const IO: i32 = 9;
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// No module found, that matches the selection
+    DeviceNotFound,
+    /// Couldn't list or open the serial devices
+    Open(serialport::Error),
+    /// Module didn't respond in time
+    Timeout,
+    Io(io::Error),
+    /// Firmware is too old or the module doesn't support the command
+    Unsupported(String),
+    /// Firmware didn't accept the command
+    Rejected(Ack),
+    /// Firmware couldn't parse the command
+    ParseError(ParseError),
+    /// Got something that's not the expected ack or response
+    UnexpectedResponse(Response),
+    /// Couldn't read or decode an image
+    Image(image::ImageError),
+    /// Image doesn't have the size that the module displays
+    ImageSize {
+        expected: (u32, u32),
+        actual: (u32, u32),
+    },
+    /// Value can't be sent to the module
+    InvalidArgument(String),
+}
+
+impl Error {
+    /// Exit code of the commandline tool, scripts can use it to tell errors apart
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Error::DeviceNotFound => exit_code::DEVICE_NOT_FOUND,
+            Error::Open(_) => exit_code::OPEN_FAILED,
+            Error::Timeout => exit_code::TIMEOUT,
+            Error::Io(_) => exit_code::IO,
+            Error::Unsupported(_)
+            | Error::Rejected(Ack {
+                status: AckStatus::Unsupported,
+                ..
+            })
+            | Error::ParseError(ParseError::UnknownCommand(_) | ParseError::Unsupported(_)) => {
+                exit_code::UNSUPPORTED
+            }
+            Error::Rejected(_) | Error::ParseError(_) | Error::UnexpectedResponse(_) => {
+                exit_code::PROTOCOL
+            }
+            Error::Image(_) | Error::ImageSize { .. } => exit_code::BAD_IMAGE,
+            Error::InvalidArgument(_) => exit_code::INVALID_ARGUMENT,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::DeviceNotFound => write!(
+                f,
+                "Failed to find serial device. Please manually specify with --serial-dev"
+            ),
+            Error::Open(err) if err.kind == serialport::ErrorKind::Io(io::ErrorKind::PermissionDenied) => write!(f, "Permission denied, couldn't access inputmodule serialport. Ensure that you have permission, for example using a udev rule or sudo."),
+            Error::Open(err) => write!(f, "Couldn't open port: {err}"),
+            Error::Timeout => write!(f, "Device didn't respond in time"),
+            Error::Io(err) => write!(f, "Serial communication failed: {err}"),
+            Error::Unsupported(err) => write!(f, "Not supported: {err}"),
+            Error::Rejected(Ack {
+                status,
+                error: Some(err),
+                ..
+            }) => write!(f, "Device rejected command ({status:?}): {err}"),
+            Error::Rejected(Ack { status, .. }) => {
+                write!(f, "Device rejected command ({status:?})")
+            }
+            Error::ParseError(err) => write!(f, "Device rejected command: {err}"),
+            Error::UnexpectedResponse(response) => {
+                write!(f, "Unexpected response: {:X?}", &response[..8])
+            }
+            Error::Image(err) => write!(f, "Invalid image: {err}"),
+            Error::ImageSize {
+                expected: (width, height),
+                actual: (actual_width, actual_height),
+            } => write!(
+                f,
+                "Image must be {width}x{height}px, not {actual_width}x{actual_height}px"
+            ),
+            Error::InvalidArgument(err) => write!(f, "Invalid argument: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self {
+        match err.kind() {
+            io::ErrorKind::TimedOut => Error::Timeout,
+            _ => Error::Io(err),
+        }
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(err: image::ImageError) -> Self {
+        Error::Image(err)
+    }
+}
@@ -1,146 +1,361 @@
-use clap::Parser;
-
-#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
-#[repr(u8)]
-pub enum Pattern {
-    Percentage = 0,
-    Gradient = 1,
-    DoubleGradient = 2,
-    LotusSideways = 3,
-    Zigzag = 4,
-    AllOn = 5,
-    Panic = 6,
-    LotusTopDown = 7,
-    //AllBrightnesses
-}
+use std::time::Duration;
+
+use image::{GrayImage, Luma};
+use inputmodule_protocol::response::{self, GameStatus};
+use inputmodule_protocol::{
+    BootAnimationArg, Command, FromPrimitive, Game, GameControlArg, PatternVals, PwmFreqArg,
+    SequenceArg, DRAW_BYTES, HEIGHT, MAX_BOOT_ANIMATION_FRAMES, MAX_SEQUENCE_FRAMES, WIDTH,
+};
 
-#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
-#[repr(u8)]
-pub enum Game {
-    Snake = 0,
-    Pong = 1,
-    Tetris = 2,
-    GameOfLife = 3,
+use crate::error::Error;
+use crate::font::{convert_font, convert_symbol};
+use crate::module::{check_size, InputModule, ModulePort};
+use crate::scroll::{scroll_frames, thin_frames, ScrollMode};
+
+/// LED Matrix with 9x34 LEDs
+pub struct LedMatrix {
+    port: ModulePort,
 }
 
-#[derive(Copy, Clone, Debug, PartialEq, clap::ValueEnum)]
-pub enum GameOfLifeStartParam {
-    CurrentMatrix = 0x00,
-    Pattern1 = 0x01,
-    Blinker = 0x02,
-    Toad = 0x03,
-    Beacon = 0x04,
-    Glider = 0x05,
-    BeaconToadBlinker = 0x06,
+impl InputModule for LedMatrix {
+    fn port(&mut self) -> &mut ModulePort {
+        &mut self.port
+    }
 }
 
-/// LED Matrix
-#[derive(Parser, Debug)]
-#[command(arg_required_else_help = true)]
-pub struct LedMatrixSubcommand {
-    /// Set LED max brightness percentage or get, if no value provided
-    #[arg(long)]
-    pub brightness: Option<Option<u8>>,
-
-    /// Set sleep status or get, if no value provided
-    #[arg(long)]
-    pub sleeping: Option<Option<bool>>,
-
-    /// Jump to the bootloader
-    #[arg(long)]
-    pub bootloader: bool,
-
-    /// Display a percentage (0-100)
-    #[arg(long)]
-    pub percentage: Option<u8>,
-
-    /// Start/stop animation
-    #[arg(long)]
-    pub animate: Option<Option<bool>>,
-
-    /// Display a pattern
-    #[arg(long)]
-    #[clap(value_enum)]
-    pub pattern: Option<Pattern>,
-
-    /// Show every brightness, one per pixel
-    #[arg(long)]
-    pub all_brightnesses: bool,
-
-    /// Blink the current pattern once a second
-    #[arg(long)]
-    pub blinking: bool,
-
-    /// Breathing brightness of the current pattern
-    #[arg(long)]
-    pub breathing: bool,
-
-    /// Display black&white image (9x34px)
-    #[arg(long)]
-    pub image_bw: Option<String>,
-
-    /// Display grayscale image
-    #[arg(long)]
-    pub image_gray: Option<String>,
-
-    /// Random EQ
-    #[arg(long)]
-    pub random_eq: bool,
-
-    /// Display EQ of microphone input
-    #[cfg(feature = "audio-visualizations")]
-    #[arg(long)]
-    pub input_eq: bool,
-
-    /// EQ with custom values
-    #[arg(long, num_args(9))]
-    pub eq: Option<Vec<u8>>,
-
-    /// Clock
-    #[arg(long)]
-    pub clock: bool,
-
-    /// Display a string (max 5 chars)
-    #[arg(long)]
-    pub string: Option<String>,
-
-    /// Display a string (max 5 symbols)
-    #[arg(long, num_args(0..6))]
-    pub symbols: Option<Vec<String>>,
-
-    /// Start a game
-    #[arg(long)]
-    #[clap(value_enum)]
-    pub start_game: Option<Game>,
-
-    /// Paramater for starting the game. Required for some games
-    #[arg(long)]
-    #[clap(value_enum)]
-    pub game_param: Option<GameOfLifeStartParam>,
+impl LedMatrix {
+    pub fn open(serialdev: &str) -> Result<Self, Error> {
+        Ok(Self::from_port(ModulePort::open(serialdev)?))
+    }
+
+    /// Use a port that's already open, like after checking its capabilities
+    pub fn from_port(port: ModulePort) -> Self {
+        Self { port }
+    }
+
+    pub fn brightness(&mut self) -> Result<u8, Error> {
+        let response = self.port.query(Command::GetBrightness)?;
+        Ok(response::decode_u8(&response))
+    }
+
+    /// Set the maximum brightness of all LEDs
+    pub fn set_brightness(&mut self, brightness: u8) -> Result<(), Error> {
+        self.port.command(Command::SetBrightness(brightness))
+    }
+
+    /// Whether the current pattern scrolls
+    pub fn animate(&mut self) -> Result<bool, Error> {
+        let response = self.port.query(Command::GetAnimate)?;
+        Ok(response::decode_bool(&response))
+    }
+
+    pub fn set_animate(&mut self, animate: bool) -> Result<(), Error> {
+        self.port.command(Command::SetAnimate(animate))
+    }
+
+    pub fn debug_mode(&mut self) -> Result<bool, Error> {
+        let response = self.port.query(Command::GetDebugMode)?;
+        Ok(response::decode_bool(&response))
+    }
+
+    pub fn set_debug_mode(&mut self, debug_mode: bool) -> Result<(), Error> {
+        self.port.command(Command::SetDebugMode(debug_mode))
+    }
+
+    /// Time between animation frames in ms
+    pub fn animation_period(&mut self) -> Result<u16, Error> {
+        let response = self.port.query(Command::GetAnimationPeriod)?;
+        Ok(response::decode_u16(&response))
+    }
+
+    pub fn set_animation_fps(&mut self, fps: u16) -> Result<(), Error> {
+        if fps == 0 {
+            return Err(Error::InvalidArgument("FPS must be at least 1".into()));
+        }
+        self.port.command(Command::SetAnimationPeriod(1000 / fps))
+    }
+
+    pub fn pwm_freq(&mut self) -> Result<PwmFreqArg, Error> {
+        let response = self.port.query(Command::GetPwmFreq)?;
+        FromPrimitive::from_u8(response::decode_u8(&response))
+            .ok_or(Error::UnexpectedResponse(response))
+    }
+
+    pub fn set_pwm_freq(&mut self, freq: PwmFreqArg) -> Result<(), Error> {
+        self.port.command(Command::SetPwmFreq(freq))
+    }
+
+    /// Fill the matrix from the bottom, up to a percentage (0-100)
+    pub fn show_percentage(&mut self, percentage: u8) -> Result<(), Error> {
+        if percentage > 100 {
+            return Err(Error::InvalidArgument(format!(
+                "Percentage must be 0-100, not {percentage}"
+            )));
+        }
+        self.port.command(Command::Percentage(percentage))
+    }
+
+    pub fn show_pattern(&mut self, pattern: PatternVals) -> Result<(), Error> {
+        match pattern {
+            // Needs an argument, default to full
+            PatternVals::Percentage => self.show_percentage(100),
+            pattern => self.port.command(Command::Pattern(pattern)),
+        }
+    }
+
+    pub fn start_game(&mut self, game: Game) -> Result<(), Error> {
+        self.port.command(Command::StartGame(game))
+    }
 
     /// Stop the currently running game
-    #[arg(long)]
-    #[clap(value_enum)]
-    pub stop_game: bool,
-
-    /// Set/get animation FPS
-    #[arg(long)]
-    pub animation_fps: Option<Option<u16>>,
-
-    /// Set/get PWM Frequency in Hz
-    #[arg(long)]
-    #[clap(value_enum)]
-    pub pwm_freq: Option<Option<u16>>,
-
-    /// Set debug mode or get current mode, if no value provided
-    #[arg(long)]
-    pub debug_mode: Option<Option<bool>>,
-
-    /// Crash the firmware (TESTING ONLY!)
-    #[arg(long)]
-    pub panic: bool,
-
-    /// Get the device version
-    #[arg(short, long)]
-    pub version: bool,
+    pub fn stop_game(&mut self) -> Result<(), Error> {
+        self.game_control(GameControlArg::Exit)
+    }
+
+    /// Steer the running game, like with a key press
+    pub fn game_control(&mut self, arg: GameControlArg) -> Result<(), Error> {
+        self.port.command(Command::GameControl(arg))
+    }
+
+    /// Game that's running, with its score
+    pub fn game_status(&mut self) -> Result<GameStatus, Error> {
+        let response = self.port.query(Command::GameStatus)?;
+        Ok(GameStatus::from_response(&response))
+    }
+
+    /// Stage greyscale values for a single column. Must be committed with commit_cols()
+    pub fn send_col(&mut self, x: u8, vals: &[u8; HEIGHT]) -> Result<(), Error> {
+        self.port.command(Command::StageGreyCol(x, *vals))
+    }
+
+    /// Commit the changes from sending individual cols with send_col(), displaying the matrix.
+    /// This makes sure that the matrix isn't partially updated.
+    pub fn commit_cols(&mut self) -> Result<(), Error> {
+        self.port.command(Command::DrawGreyColBuffer)
+    }
+
+    ///Increase the brightness with each pixel.
+    ///Only 0-255 available, so it can't fill all 306 LEDs
+    pub fn show_all_brightnesses(&mut self) -> Result<(), Error> {
+        for x in 0..WIDTH {
+            let mut vals: [u8; HEIGHT] = [0; HEIGHT];
+
+            for y in 0..HEIGHT {
+                let brightness = x + WIDTH * y;
+                vals[y] = if brightness > 255 { 0 } else { brightness } as u8;
+            }
+
+            self.send_col(x as u8, &vals)?;
+        }
+        self.commit_cols()
+    }
+
+    /// Switch every LED fully on or off, one bit per LED
+    pub fn draw(&mut self, vals: [u8; DRAW_BYTES]) -> Result<(), Error> {
+        self.port.command(Command::Draw(vals))
+    }
+
+    /// Show a black/white matrix
+    /// Send everything in a single command
+    pub fn draw_matrix(&mut self, matrix: &[[bool; HEIGHT]; WIDTH]) -> Result<(), Error> {
+        // One bit for each LED, on or off
+        let mut vals: [u8; DRAW_BYTES] = [0x00; DRAW_BYTES];
+
+        for x in 0..WIDTH {
+            for y in 0..HEIGHT {
+                let i = x + WIDTH * y;
+                if matrix[x][y] {
+                    vals[i / 8] |= 1 << (i % 8);
+                }
+            }
+        }
+
+        self.draw(vals)
+    }
+
+    /// Display an image in black and white
+    /// Must be 9x34 in size.
+    /// Sends everything in a single command
+    pub fn display_bw_image(&mut self, img: &GrayImage) -> Result<(), Error> {
+        check_size(img, WIDTH, HEIGHT)?;
+        let mut vals: [u8; DRAW_BYTES] = [0; DRAW_BYTES];
+        for (x, y, pixel) in img.enumerate_pixels() {
+            let brightness = pixel.0[0];
+            if brightness > 0xFF / 2 {
+                let i = (x as usize) + (y as usize) * WIDTH;
+                vals[i / 8] |= 1 << (i % 8);
+            }
+        }
+
+        self.draw(vals)
+    }
+
+    /// Display an image in greyscale
+    /// Must be 9x34 in size.
+    /// Sends each 1x34 column and then commits => 10 commands
+    pub fn display_gray_image(&mut self, img: &GrayImage) -> Result<(), Error> {
+        self.stage_gray_image(img)?;
+        self.commit_cols()
+    }
+
+    /// Display greyscale values as LED brightness
+    /// Must be 9x34 in size. Unlike display_gray_image(), doesn't adjust the
+    /// values to make pictures look better.
+    pub fn display_gray_values(&mut self, img: &GrayImage) -> Result<(), Error> {
+        check_size(img, WIDTH, HEIGHT)?;
+        for x in 0..WIDTH {
+            let mut vals: [u8; HEIGHT] = [0; HEIGHT];
+            for y in 0..HEIGHT {
+                vals[y] = img.get_pixel(x as u32, y as u32).0[0];
+            }
+            self.send_col(x as u8, &vals)?;
+        }
+        self.commit_cols()
+    }
+
+    /// Send all columns of a 9x34 image, without committing them
+    pub(crate) fn stage_gray_image(&mut self, img: &GrayImage) -> Result<(), Error> {
+        check_size(img, WIDTH, HEIGHT)?;
+        for x in 0..WIDTH {
+            let mut vals: [u8; HEIGHT] = [0; HEIGHT];
+
+            for y in 0..HEIGHT {
+                let pixel = img.get_pixel(x as u32, y as u32);
+                vals[y] = pixel_to_brightness(pixel);
+            }
+
+            self.send_col(x as u8, &vals)?;
+        }
+        Ok(())
+    }
+
+    /// Upload 9x34 greyscale frames, with their delay in ms, as boot animation
+    /// Only the first MAX_BOOT_ANIMATION_FRAMES are uploaded.
+    pub fn upload_boot_animation(&mut self, frames: &[(GrayImage, u16)]) -> Result<(), Error> {
+        self.port
+            .command(Command::BootAnimation(BootAnimationArg::Begin))?;
+        for (frame_img, duration) in frames.iter().take(MAX_BOOT_ANIMATION_FRAMES) {
+            self.stage_gray_image(frame_img)?;
+            self.port
+                .command(Command::BootAnimation(BootAnimationArg::AddFrame(
+                    *duration,
+                )))?;
+        }
+        self.port
+            .command(Command::BootAnimation(BootAnimationArg::Commit))
+    }
+
+    /// Go back to the built-in boot animations
+    pub fn clear_boot_animation(&mut self) -> Result<(), Error> {
+        self.port
+            .command(Command::BootAnimation(BootAnimationArg::Clear))
+    }
+
+    /// Upload 9x34 greyscale frames, with their delay in ms, into the module's
+    /// RAM and let it play them `loops` times, 0 means forever
+    /// Only the first MAX_SEQUENCE_FRAMES are uploaded. Returns right away,
+    /// the module keeps playing on its own.
+    pub fn play_sequence(&mut self, frames: &[(GrayImage, u16)], loops: u8) -> Result<(), Error> {
+        self.port.command(Command::Sequence(SequenceArg::Begin))?;
+        for (frame_img, duration) in frames.iter().take(MAX_SEQUENCE_FRAMES) {
+            self.stage_gray_image(frame_img)?;
+            self.port
+                .command(Command::Sequence(SequenceArg::AddFrame(*duration)))?;
+        }
+        self.port
+            .command(Command::Sequence(SequenceArg::Play(loops)))
+    }
+
+    /// Upload text scrolling through the matrix and let the module play it
+    /// `loops` times, 0 means forever
+    /// The text moves a pixel every `period`. Longer text moves several pixels
+    /// per frame instead, to fit into the MAX_SEQUENCE_FRAMES that the module
+    /// can keep.
+    pub fn upload_scroll_text(
+        &mut self,
+        text: &str,
+        mode: ScrollMode,
+        period: Duration,
+        loops: u8,
+    ) -> Result<(), Error> {
+        let frames = thin_frames(scroll_frames(text, mode), period, MAX_SEQUENCE_FRAMES);
+        self.play_sequence(&frames, loops)
+    }
+
+    /// Display up to 9 values in equalizer diagram starting from the middle, going up and down
+    pub fn show_eq(&mut self, vals: &[u8]) -> Result<(), Error> {
+        if vals.len() > WIDTH {
+            return Err(Error::InvalidArgument(format!(
+                "At most {WIDTH} EQ values fit, not {}",
+                vals.len()
+            )));
+        }
+        let mut matrix = [[false; HEIGHT]; WIDTH];
+
+        for (col, val) in vals.iter().enumerate() {
+            let row: usize = HEIGHT / 2;
+            let above: usize = (*val as usize).min(HEIGHT) / 2;
+            let below = (*val as usize).min(HEIGHT) - above;
+
+            for i in 0..above {
+                matrix[col][row + i] = true;
+            }
+            for i in 0..below {
+                matrix[col][row - 1 - i] = true;
+            }
+        }
+
+        self.draw_matrix(&matrix)
+    }
+
+    /// Render a string with up to five letters
+    pub fn show_string(&mut self, s: &str) -> Result<(), Error> {
+        let items: Vec<Vec<u8>> = s.chars().take(5).map(convert_font).collect();
+        self.show_font(&items)
+    }
+
+    /// Render a list of up to five symbols
+    /// Can use letters/numbers or symbol names, like 'sun', ':)'
+    pub fn show_symbols(&mut self, symbols: &[String]) -> Result<(), Error> {
+        let font_items: Vec<Vec<u8>> = symbols.iter().take(5).map(|x| convert_symbol(x)).collect();
+        self.show_font(&font_items)
+    }
+
+    /// Render up to five 5x6 pixel font items
+    pub fn show_font(&mut self, font_items: &[Vec<u8>]) -> Result<(), Error> {
+        let mut vals: [u8; DRAW_BYTES] = [0x00; DRAW_BYTES];
+
+        for (digit_i, digit_pixels) in font_items.iter().take(5).enumerate() {
+            let offset = digit_i * 7;
+            for pixel_x in 0..5 {
+                for pixel_y in 0..6 {
+                    let pixel_value = digit_pixels[pixel_x + pixel_y * 5];
+                    let i = (2 + pixel_x) + (WIDTH * (pixel_y + offset));
+                    if pixel_value == 1 {
+                        vals[i / 8] |= 1 << (i % 8);
+                    }
+                }
+            }
+        }
+
+        self.draw(vals)
+    }
+}
+
+// Calculate pixel brightness from an RGB triple
+pub(crate) fn pixel_to_brightness(pixel: &Luma<u8>) -> u8 {
+    let brightness = pixel.0[0];
+    // Poor man's scaling to make the greyscale pop better.
+    // Should find a good function.
+    if brightness > 200 {
+        brightness
+    } else if brightness > 150 {
+        ((brightness as u32) * 10 / 8) as u8
+    } else if brightness > 100 {
+        brightness / 2
+    } else if brightness > 50 {
+        brightness
+    } else {
+        brightness * 2
+    }
 }
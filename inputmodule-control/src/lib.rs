@@ -0,0 +1,42 @@
+//! Control the Framework 16 input modules over their serial port
+//!
+//! Every type of module has a handle, that's opened with the path of its
+//! serial device, like `/dev/ttyACM0` or `COM3`:
+//!
+//! ```no_run
+//! use inputmodule_control::{InputModule, LedMatrix};
+//!
+//! let mut matrix = LedMatrix::open("/dev/ttyACM0")?;
+//! matrix.set_brightness(100)?;
+//! let version = matrix.version()?;
+//! println!("Firmware {}.{}.{}", version.major(), version.minor(), version.patch());
+//! # Ok::<(), inputmodule_control::Error>(())
+//! ```
+#![allow(clippy::needless_range_loop)]
+mod b1display;
+mod c1minimal;
+mod canvas;
+mod compositor;
+mod device;
+mod error;
+pub mod font;
+mod ledmatrix;
+mod module;
+mod rle;
+mod scroll;
+
+pub use b1display::{B1Display, Fps, PowerMode};
+pub use c1minimal::C1Minimal;
+pub use canvas::Canvas;
+pub use compositor::{Compositor, Layer, Region};
+pub use device::{usb_devices, DeviceInfo, Selector};
+pub use error::{exit_code, Error};
+pub use ledmatrix::LedMatrix;
+pub use module::{
+    gif_gray_frames, load_gray_image, InputModule, ModulePort, B1_LCD_PID, FRAMEWORK_VID,
+    LED_MATRIX_PID,
+};
+pub use rle::RlePattern;
+pub use scroll::{scroll_frames, ScrollMode};
+
+pub use inputmodule_protocol as protocol;
@@ -1,32 +1,54 @@
-#![allow(clippy::needless_range_loop)]
 #![allow(clippy::single_match)]
-mod b1display;
-mod c1minimal;
-mod font;
-mod inputmodule;
-mod ledmatrix;
+mod cli;
+
+use std::path::PathBuf;
+use std::process;
 
 use clap::{Parser, Subcommand};
-use inputmodule::find_serialdevs;
+use inputmodule_protocol::{CommandVals, ModuleType};
 
-use crate::b1display::B1DisplaySubcommand;
-use crate::c1minimal::C1MinimalSubcommand;
-use crate::inputmodule::{serial_commands, B1_LCD_PID, LED_MATRIX_PID};
-use crate::ledmatrix::LedMatrixSubcommand;
+use crate::cli::b1display::B1DisplaySubcommand;
+use crate::cli::c1minimal::C1MinimalSubcommand;
+use crate::cli::config::ModuleArg;
+#[cfg(unix)]
+use crate::cli::daemon::DaemonSubcommand;
+use crate::cli::ledmatrix::LedMatrixSubcommand;
+#[cfg(unix)]
+use crate::cli::notifications::NotificationsSubcommand;
+use crate::cli::output::{Output, OutputFormat};
+use crate::cli::{list_devices, serial_commands};
 
 #[derive(Subcommand, Debug)]
 enum Commands {
-    LedMatrix(LedMatrixSubcommand),
+    LedMatrix(Box<LedMatrixSubcommand>),
     B1Display(B1DisplaySubcommand),
     C1Minimal(C1MinimalSubcommand),
+    #[cfg(unix)]
+    Daemon(DaemonSubcommand),
+    #[cfg(unix)]
+    Notifications(NotificationsSubcommand),
 }
 
 impl Commands {
-    pub fn to_pid(&self) -> u16 {
+    /// None for commands that work with any type of module
+    pub fn module_type(&self) -> Option<ModuleType> {
+        match self {
+            Self::LedMatrix(_) => Some(ModuleType::LedMatrix),
+            Self::B1Display(_) => Some(ModuleType::B1Display),
+            Self::C1Minimal(_) => Some(ModuleType::C1Minimal),
+            #[cfg(unix)]
+            Self::Daemon(_) | Self::Notifications(_) => None,
+        }
+    }
+
+    /// Module specific commands needed by the selected options
+    pub fn required_commands(&self) -> Vec<(&'static str, CommandVals)> {
         match self {
-            Self::LedMatrix(_) => LED_MATRIX_PID,
-            Self::B1Display(_) => B1_LCD_PID,
-            Self::C1Minimal(_) => 0x22,
+            Self::LedMatrix(args) => args.required_commands(),
+            Self::B1Display(args) => args.required_commands(),
+            Self::C1Minimal(args) => args.required_commands(),
+            #[cfg(unix)]
+            Self::Daemon(_) | Self::Notifications(_) => vec![],
         }
     }
 }
@@ -50,21 +72,60 @@ pub struct ClapCli {
     #[arg(long)]
     pub serial_dev: Option<String>,
 
+    /// Select the device with this USB serial number
+    #[arg(long)]
+    serial_number: Option<String>,
+
+    /// Select the device on this physical USB port, like 3-4.2 (Linux only)
+    #[arg(long)]
+    location: Option<String>,
+
+    /// Select devices of this module type
+    #[arg(long, value_enum)]
+    module: Option<ModuleArg>,
+
+    /// Select the device with this name in the config file
+    #[arg(long)]
+    device: Option<String>,
+
+    /// Config file with device names, instead of the one in the user's config directory
+    #[arg(long)]
+    config: Option<PathBuf>,
+
     /// Retry connecting to the device until it works
     #[arg(long)]
     wait_for_device: bool,
+
+    /// Format of the device list and getter results
+    #[arg(long, value_enum, default_value_t)]
+    output: OutputFormat,
 }
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
     let args = ClapCli::parse_from(args);
 
-    match args.command {
-        Some(_) => serial_commands(&args),
+    let mut output = Output::new(args.output);
+    let result = match &args.command {
+        #[cfg(unix)]
+        Some(Commands::Daemon(daemon_args)) => cli::daemon::run(&args, daemon_args, &output),
+        #[cfg(unix)]
+        Some(Commands::Notifications(notifications_args)) => {
+            cli::notifications::run(&args, notifications_args, &output)
+        }
+        Some(_) => serial_commands(&args, &mut output),
         None => {
             if args.list {
-                find_serialdevs(&args, false);
+                list_devices(&args, &mut output)
+            } else {
+                Ok(())
             }
         }
+    };
+    // Even if a command failed, the results of the previous ones are valid
+    output.finish();
+    if let Err(err) = result {
+        eprintln!("{err}");
+        process::exit(err.exit_code());
     }
 }
@@ -0,0 +1,336 @@
+use std::time::Duration;
+
+use image::codecs::gif::GifDecoder;
+use image::{io::Reader as ImageReader, AnimationDecoder, DynamicImage, GrayImage, ImageError};
+use inputmodule_protocol::response::{
+    self, Ack, AckStatus, Capabilities, Response, Version, RESPONSE_SIZE,
+};
+use inputmodule_protocol::{frame, Command, SettingsArg};
+use serialport::{ClearBuffer, SerialPort};
+
+use crate::error::Error;
+
+pub const FRAMEWORK_VID: u16 = 0x32AC;
+pub const LED_MATRIX_PID: u16 = 0x0020;
+pub const B1_LCD_PID: u16 = 0x0021;
+
+const SERIAL_TIMEOUT: Duration = Duration::from_millis(20);
+const ACK_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Serial connection to a module
+pub struct ModulePort {
+    port: Box<dyn SerialPort>,
+    /// Sequence number of the next command, if the firmware supports framing
+    /// and acknowledges commands
+    next_seq: Option<u8>,
+    /// What the module reported about itself when it was opened
+    capabilities: Option<Capabilities>,
+}
+
+impl ModulePort {
+    pub fn open(serialdev: &str) -> Result<Self, Error> {
+        let port = serialport::new(serialdev, 115_200)
+            .timeout(SERIAL_TIMEOUT)
+            .open()
+            .map_err(Error::Open)?;
+        let mut port = ModulePort {
+            port,
+            next_seq: None,
+            capabilities: None,
+        };
+
+        // Ask the firmware to acknowledge every command until the port is closed.
+        // Sent as a frame, older firmware doesn't understand either of them and
+        // doesn't respond or responds with an error.
+        let _ = port.port.clear(ClearBuffer::Input);
+        let enable_ack = frame::to_vec(&Command::SetAckMode(true).to_vec());
+        if port.port.write_all(&enable_ack).is_ok() {
+            let mut response: Response = [0; RESPONSE_SIZE];
+            if port.port.read_exact(&mut response).is_ok() {
+                if let Some(Ack {
+                    status: AckStatus::Ok,
+                    ..
+                }) = Ack::from_response(&response)
+                {
+                    port.next_seq = Some(1);
+                    // Firmware only acks after handling the command, which can take a while
+                    let _ = port.port.set_timeout(ACK_TIMEOUT);
+                }
+            }
+        }
+
+        // Asked only once, they don't change while the port is open
+        let response = port.query(Command::GetCapabilities);
+        port.capabilities = response.ok().and_then(|x| Capabilities::from_response(&x));
+        Ok(port)
+    }
+
+    /// What the module is and which commands it supports
+    ///
+    /// Returns None if the firmware is too old to tell.
+    pub fn capabilities(&self) -> Option<Capabilities> {
+        self.capabilities
+    }
+
+    /// Send a command and wait until the firmware acknowledges it, if it can
+    pub fn command(&mut self, command: Command) -> Result<(), Error> {
+        let Some(seq) = self.next_seq else {
+            self.port.write_all(&command.to_vec())?;
+            return Ok(());
+        };
+        self.next_seq = Some(seq.wrapping_add(1));
+        self.port
+            .write_all(&frame::to_vec(&command.to_vec_sequenced(seq)))?;
+
+        // Device resets before it can respond
+        if matches!(command, Command::BootloaderReset | Command::Panic) {
+            return Ok(());
+        }
+
+        let mut response: Response = [0; RESPONSE_SIZE];
+        self.port.read_exact(&mut response)?;
+        match Ack::from_response(&response) {
+            Some(ack) if ack.seq != seq => Err(Error::UnexpectedResponse(response)),
+            Some(Ack {
+                status: AckStatus::Ok,
+                ..
+            }) => Ok(()),
+            Some(ack) => Err(Error::Rejected(ack)),
+            None => Err(Error::UnexpectedResponse(response)),
+        }
+    }
+
+    /// Send a getter command and read its response
+    ///
+    /// The response isn't checked for an error, it can start the same way.
+    /// In ack mode errors come with the ack and older firmware doesn't send any.
+    pub fn query(&mut self, command: Command) -> Result<Response, Error> {
+        self.command(command)?;
+        let mut response: Response = [0; RESPONSE_SIZE];
+        self.port.read_exact(&mut response)?;
+        Ok(response)
+    }
+}
+
+/// Commands that every module understands
+pub trait InputModule {
+    fn port(&mut self) -> &mut ModulePort;
+
+    /// What the module is and which commands it supports
+    ///
+    /// Returns None if the firmware is too old to tell.
+    fn capabilities(&mut self) -> Option<Capabilities> {
+        // The one of the port, the method of this trait takes precedence
+        ModulePort::capabilities(self.port())
+    }
+
+    fn version(&mut self) -> Result<Version, Error> {
+        let response = self.port().query(Command::Version)?;
+        Ok(Version::from_response(&response))
+    }
+
+    /// Revision of the module's hardware
+    ///
+    /// Returns None if it's unknown, because the firmware is too old or the
+    /// revision wasn't programmed at the factory.
+    fn hw_revision(&mut self) -> Option<u8> {
+        let response = self.port().query(Command::GetHwRevision).ok()?;
+        match response::decode_u8(&response) {
+            0 => None,
+            hw_revision => Some(hw_revision),
+        }
+    }
+
+    fn sleeping(&mut self) -> Result<bool, Error> {
+        let response = self.port().query(Command::IsSleeping)?;
+        Ok(response::decode_bool(&response))
+    }
+
+    fn set_sleeping(&mut self, sleeping: bool) -> Result<(), Error> {
+        self.port().command(Command::Sleep(sleeping))
+    }
+
+    /// Jump to the bootloader, to flash new firmware
+    fn bootloader(&mut self) -> Result<(), Error> {
+        self.port().command(Command::BootloaderReset)
+    }
+
+    /// Crash the firmware (TESTING ONLY!)
+    fn panic(&mut self) -> Result<(), Error> {
+        self.port().command(Command::Panic)
+    }
+
+    /// Save, load or reset the settings persisted in the module's flash
+    fn settings(&mut self, action: SettingsArg) -> Result<(), Error> {
+        self.port().command(Command::Settings(action))
+    }
+}
+
+/// Any type of module
+impl InputModule for ModulePort {
+    fn port(&mut self) -> &mut ModulePort {
+        self
+    }
+}
+
+/// Open an image and convert it to greyscale
+pub fn load_gray_image(image_path: &str) -> Result<GrayImage, Error> {
+    let reader = ImageReader::open(image_path).map_err(ImageError::IoError)?;
+    Ok(reader.decode()?.to_luma8())
+}
+
+/// Frames of a GIF in greyscale, scaled to width x height, with their delay in ms
+pub fn gif_gray_frames(
+    image_path: &str,
+    width: u32,
+    height: u32,
+) -> Result<Vec<(GrayImage, u16)>, Error> {
+    let img = std::fs::File::open(image_path).map_err(ImageError::IoError)?;
+    let gif = GifDecoder::new(img)?;
+    let frames = gif.into_frames().collect_frames()?;
+
+    Ok(frames
+        .into_iter()
+        .map(|frame| {
+            let (numer, denom) = frame.delay().numer_denom_ms();
+            let duration = (numer / denom.max(1)).min(u16::MAX as u32) as u16;
+
+            let frame_img = DynamicImage::from(frame.into_buffer());
+            let frame_img =
+                frame_img.resize_exact(width, height, image::imageops::FilterType::Gaussian);
+            (frame_img.into_luma8(), duration)
+        })
+        .collect())
+}
+
+/// Check that an image has the size, that the module displays
+pub(crate) fn check_size(img: &GrayImage, width: usize, height: usize) -> Result<(), Error> {
+    let expected = (width as u32, height as u32);
+    if img.dimensions() != expected {
+        return Err(Error::ImageSize {
+            expected,
+            actual: img.dimensions(),
+        });
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::io::{Read, Write};
+    use std::thread::{self, JoinHandle};
+
+    use inputmodule_protocol::{CommandVals, ModuleType, ParseError, Rgb};
+    use serialport::TTYPort;
+
+    use super::*;
+
+    /// Pretend to be the firmware, answering each command with the next responses
+    fn fake_module(responses: Vec<Vec<Response>>) -> (String, JoinHandle<()>) {
+        let responses = responses.into_iter().map(|r| (Duration::ZERO, r));
+        slow_fake_module(responses.collect())
+    }
+
+    /// Like `fake_module`, but takes a while to handle each command
+    fn slow_fake_module(responses: Vec<(Duration, Vec<Response>)>) -> (String, JoinHandle<()>) {
+        let (mut module, mut host) = TTYPort::pair().unwrap();
+        host.set_exclusive(false).unwrap();
+        let path = host.name().unwrap();
+        let handle = thread::spawn(move || {
+            // Needed until the host opened the port, or reading fails
+            let mut host = Some(host);
+            module.set_timeout(Duration::from_secs(5)).unwrap();
+            for (delay, responses) in responses {
+                let mut command = [0; 64];
+                let _ = module.read(&mut command).unwrap();
+                host.take();
+                thread::sleep(delay);
+                for response in responses {
+                    // Fails if the host already gave up waiting and closed the port
+                    let _ = module.write_all(&response);
+                }
+            }
+            // Until the host closed the port, the responses it didn't read yet are gone after
+            let _ = module.read(&mut [0; 1]);
+        });
+        (path, handle)
+    }
+
+    /// Ack of firmware that doesn't know the Capabilities command
+    fn unsupported(seq: u8) -> Vec<Response> {
+        let err = ParseError::UnknownCommand(CommandVals::Capabilities as u8);
+        vec![Ack::from_error(seq, err).to_response()]
+    }
+
+    #[test]
+    fn capabilities_queried_once() {
+        let capabilities = Capabilities {
+            module: ModuleType::C1Minimal,
+            hw_revision: 0,
+            version: Version {
+                bcd_device: 0x0100,
+                pre_release: false,
+            },
+            width: 1,
+            height: 1,
+            pixel_depth: 24,
+            commands: Capabilities::command_bitmap(&[CommandVals::SetColor]),
+        };
+        let (path, module) = fake_module(vec![
+            vec![Ack::ok(0).to_response()],
+            vec![Ack::ok(1).to_response(), capabilities.to_response()],
+        ]);
+
+        let mut port = ModulePort::open(&path).unwrap();
+        // The fake module doesn't respond again
+        assert_eq!(port.capabilities(), Some(capabilities));
+        assert_eq!(InputModule::capabilities(&mut port), Some(capabilities));
+        drop(port);
+        module.join().unwrap();
+    }
+
+    #[test]
+    fn getter_response_like_error_or_ack() {
+        // Start like an error and an ack response
+        let colors = [Rgb::new(0x32, 0xAC, 0xFF), Rgb::new(0x32, 0xAC, 0xFE)];
+        // Older firmware, that doesn't report its capabilities
+        let mut responses = vec![vec![Ack::ok(0).to_response()], unsupported(1)];
+        for (seq, color) in (2..).zip(colors) {
+            responses.push(vec![Ack::ok(seq).to_response(), color.to_response()]);
+        }
+        // Even exactly like one
+        let like_error = ParseError::TooShort.to_response();
+        responses.push(vec![Ack::ok(4).to_response(), like_error]);
+        let (path, module) = fake_module(responses);
+
+        let mut port = ModulePort::open(&path).unwrap();
+        for color in colors {
+            let response = port.query(Command::GetColor).unwrap();
+            assert_eq!(Rgb::from_response(&response), color);
+        }
+        assert_eq!(port.query(Command::Version).unwrap(), like_error);
+        drop(port);
+        module.join().unwrap();
+    }
+
+    #[test]
+    fn ack_after_handling_command() {
+        let ok = |seq| vec![Ack::ok(seq).to_response()];
+        let (path, module) = slow_fake_module(vec![
+            (Duration::ZERO, ok(0)),
+            (Duration::ZERO, unsupported(1)),
+            // Like sleeping or waking up, the fade mustn't hold up the ack
+            (ACK_TIMEOUT / 2, ok(2)),
+            (ACK_TIMEOUT * 2, ok(3)),
+        ]);
+
+        let mut port = ModulePort::open(&path).unwrap();
+        port.command(Command::Sleep(true)).unwrap();
+        assert!(matches!(
+            port.command(Command::Sleep(false)),
+            Err(Error::Timeout)
+        ));
+        drop(port);
+        module.join().unwrap();
+    }
+}
@@ -0,0 +1,264 @@
+//! Patterns in the RLE format that Life programs like Golly use
+//!
+//! ```text
+//! #N Glider
+//! x = 3, y = 3, rule = B3/S23
+//! bob$2bo$3o!
+//! ```
+//!
+//! `b` is a dead cell, `o` an alive one, `$` ends a row and `!` the pattern.
+//! Any other letter is taken as alive, for patterns with more than two states.
+use inputmodule_protocol::{LifeRule, HEIGHT, WIDTH};
+
+use crate::Error;
+
+/// Far more than fits on the matrix, but a bogus header can't use up all memory
+const MAX_CELLS: usize = 1 << 20;
+
+/// Alive cells of a pattern, by row and column
+#[derive(Clone, Debug, PartialEq)]
+pub struct RlePattern {
+    pub width: usize,
+    pub height: usize,
+    /// From the header, if it has one
+    pub rule: Option<LifeRule>,
+    pub cells: Vec<Vec<bool>>,
+}
+
+impl RlePattern {
+    pub fn parse(rle: &str) -> Result<Self, Error> {
+        let invalid = |msg: String| Error::InvalidArgument(format!("Invalid RLE pattern: {msg}"));
+
+        let mut lines = rle
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        let header = lines.next().ok_or_else(|| invalid("It's empty".into()))?;
+
+        let (mut width, mut height, mut rule) = (None, None, None);
+        for field in header.split(',') {
+            let (key, value) = field.split_once('=').ok_or_else(|| {
+                invalid(format!("Expected key = value in the header, not {field}"))
+            })?;
+            let value = value.trim();
+            match key.trim() {
+                "x" => width = value.parse::<usize>().ok(),
+                "y" => height = value.parse::<usize>().ok(),
+                "rule" => {
+                    rule = Some(
+                        LifeRule::parse(value)
+                            .ok_or_else(|| invalid(format!("Unsupported rule {value}")))?,
+                    )
+                }
+                _ => {}
+            }
+        }
+        let (Some(width), Some(height)) = (width, height) else {
+            return Err(invalid("The header needs x and y".into()));
+        };
+        if width.saturating_mul(height) > MAX_CELLS {
+            return Err(invalid(format!("{width}x{height} is too large")));
+        }
+
+        let mut cells = vec![vec![false; width]; height];
+        let (mut row, mut col): (usize, usize) = (0, 0);
+        let mut count: Option<usize> = None;
+        'lines: for line in lines {
+            for c in line.chars() {
+                if let Some(digit) = c.to_digit(10) {
+                    count = count
+                        .unwrap_or(0)
+                        .checked_mul(10)
+                        .and_then(|count| count.checked_add(digit as usize));
+                    if count.is_none() {
+                        return Err(invalid("Run count is too large".into()));
+                    }
+                    continue;
+                }
+                let run = count.take().unwrap_or(1);
+                match c {
+                    '!' => break 'lines,
+                    '$' => {
+                        row = row.saturating_add(run);
+                        col = 0;
+                    }
+                    'b' | '.' => col = col.saturating_add(run),
+                    c if c.is_ascii_alphabetic() => {
+                        if row >= height || col.saturating_add(run) > width {
+                            return Err(invalid(format!(
+                                "Cells outside of the {width}x{height} in the header"
+                            )));
+                        }
+                        cells[row][col..col + run].fill(true);
+                        col += run;
+                    }
+                    c if c.is_whitespace() => {}
+                    c => return Err(invalid(format!("Unexpected {c}"))),
+                }
+            }
+        }
+
+        Ok(Self {
+            width,
+            height,
+            rule,
+            cells,
+        })
+    }
+
+    /// Pattern in the middle of the matrix, for LedMatrix::draw_matrix
+    ///
+    /// Patterns that are wider than high are turned by 90°, if that makes them fit.
+    pub fn to_matrix(&self) -> Result<[[bool; HEIGHT]; WIDTH], Error> {
+        let rotate = self.width > WIDTH && self.width <= HEIGHT && self.height <= WIDTH;
+        let (width, height) = if rotate {
+            (self.height, self.width)
+        } else {
+            (self.width, self.height)
+        };
+        if width > WIDTH || height > HEIGHT {
+            return Err(Error::InvalidArgument(format!(
+                "RLE pattern is {}x{}, only up to {WIDTH}x{HEIGHT} fits",
+                self.width, self.height
+            )));
+        }
+
+        let (left, top) = ((WIDTH - width) / 2, (HEIGHT - height) / 2);
+        let mut matrix = [[false; HEIGHT]; WIDTH];
+        for (row, cells) in self.cells.iter().enumerate() {
+            for (col, _) in cells.iter().enumerate().filter(|(_, alive)| **alive) {
+                // Clockwise, the first row ends up on the right
+                let (x, y) = if rotate {
+                    (self.height - 1 - row, col)
+                } else {
+                    (col, row)
+                };
+                matrix[left + x][top + y] = true;
+            }
+        }
+        Ok(matrix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GLIDER: &str = "#N Glider
+#O Richard K. Guy
+#C The smallest, most common, and first discovered spaceship.
+x = 3, y = 3, rule = B3/S23
+bob$2bo$3o!
+";
+
+    /// Rows of the pattern, like they're written with b and o
+    fn rows(pattern: &RlePattern) -> Vec<String> {
+        pattern
+            .cells
+            .iter()
+            .map(|row| row.iter().map(|&x| if x { 'o' } else { 'b' }).collect())
+            .collect()
+    }
+
+    fn invalid(rle: &str) -> bool {
+        matches!(RlePattern::parse(rle), Err(Error::InvalidArgument(_)))
+    }
+
+    #[test]
+    fn header_and_rule() {
+        let glider = RlePattern::parse(GLIDER).unwrap();
+        assert_eq!((glider.width, glider.height), (3, 3));
+        assert_eq!(glider.rule, Some(LifeRule::CONWAY));
+
+        let pattern = RlePattern::parse("x=2,y=1\n2o!").unwrap();
+        assert_eq!((pattern.width, pattern.height, pattern.rule), (2, 1, None));
+
+        let high_life = RlePattern::parse("x = 1, y = 1, rule = B36/S23\no!").unwrap();
+        assert!(high_life.rule.unwrap().born(6));
+    }
+
+    #[test]
+    fn runs() {
+        let glider = RlePattern::parse(GLIDER).unwrap();
+        assert_eq!(rows(&glider), ["bob", "bbo", "ooo"]);
+
+        // Counts before $ skip rows, the rest of a row is dead
+        let pattern = RlePattern::parse("x = 12, y = 4\n2b10o$o2$11bo!").unwrap();
+        assert_eq!(
+            rows(&pattern),
+            [
+                "bboooooooooo",
+                "obbbbbbbbbbb",
+                "bbbbbbbbbbbb",
+                "bbbbbbbbbbbo"
+            ]
+        );
+
+        // Split across lines and ends at !
+        let pattern = RlePattern::parse("x = 3, y = 2\nb\n2o$\n3o!\nooo$ooo!").unwrap();
+        assert_eq!(rows(&pattern), ["boo", "ooo"]);
+
+        // Other states are alive too
+        let pattern = RlePattern::parse("x = 3, y = 1\n.AB!").unwrap();
+        assert_eq!(rows(&pattern), ["boo"]);
+    }
+
+    #[test]
+    fn comment_lines() {
+        let rle = "#C Before the header\n\n  x = 2, y = 2\n#C Between rows\no$\n  #R Indented\nbo!";
+        let pattern = RlePattern::parse(rle).unwrap();
+        assert_eq!(rows(&pattern), ["ob", "bo"]);
+    }
+
+    #[test]
+    fn on_the_matrix() {
+        let matrix = RlePattern::parse(GLIDER).unwrap().to_matrix().unwrap();
+        // In the middle
+        let (left, top) = ((WIDTH - 3) / 2, (HEIGHT - 3) / 2);
+        assert!(matrix[left + 1][top] && matrix[left + 2][top + 1]);
+        assert!((0..3).all(|x| matrix[left + x][top + 2]));
+        assert_eq!(matrix.iter().flatten().filter(|x| **x).count(), 5);
+    }
+
+    #[test]
+    fn larger_than_the_matrix() {
+        // Wider than high, turned to fit
+        let wide = RlePattern::parse(&format!(
+            "x = {HEIGHT}, y = 2\no{}bo${HEIGHT}b!",
+            HEIGHT - 2
+        ))
+        .unwrap();
+        let matrix = wide.to_matrix().unwrap();
+        let left = (WIDTH - 2) / 2;
+        // First row on the right
+        assert!(matrix[left + 1][0] && matrix[left + 1][HEIGHT - 1]);
+        assert_eq!(matrix.iter().flatten().filter(|x| **x).count(), 2);
+
+        for (width, height) in [(WIDTH + 1, HEIGHT), (WIDTH, HEIGHT + 1), (HEIGHT + 1, 2)] {
+            let pattern = RlePattern::parse(&format!("x = {width}, y = {height}\no!")).unwrap();
+            assert!(
+                matches!(pattern.to_matrix(), Err(Error::InvalidArgument(_))),
+                "{width}x{height}"
+            );
+        }
+    }
+
+    #[test]
+    fn malformed() {
+        assert!(invalid(""));
+        assert!(invalid("#N Only a comment"));
+        assert!(invalid("bob$2bo$3o!"));
+        assert!(invalid("x = 3\nbob!"));
+        assert!(invalid("x = three, y = 3\nbob!"));
+        assert!(invalid("x = 3, y = 3, rule = B9/S23\nbob!"));
+        assert!(invalid("x = 3, y = 3\nb?o!"));
+        // Cells outside of the size in the header
+        assert!(invalid("x = 3, y = 3\n4o!"));
+        assert!(invalid("x = 3, y = 1\no$o!"));
+        // Too large
+        assert!(invalid("x = 3, y = 3\n99999999999999999999999o!"));
+        assert!(invalid(&format!("x = {}, y = 2\no!", usize::MAX)));
+        assert!(invalid(&format!("x = 1, y = 1\n{}$o!", usize::MAX)));
+        assert!(invalid("x = 100000, y = 100000\no!"));
+    }
+}
@@ -0,0 +1,217 @@
+use std::time::Duration;
+
+use image::{imageops, GrayImage, Luma};
+use inputmodule_protocol::{HEIGHT, WIDTH};
+
+use crate::font::convert_font;
+
+/// Size of a character of the font
+const FONT_WIDTH: u32 = 5;
+const FONT_HEIGHT: u32 = 6;
+/// Space to the next character
+const SPACING: u32 = 1;
+
+/// How text moves through the matrix
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScrollMode {
+    /// Upright characters below each other, moving up
+    Vertical,
+    /// Upright characters next to each other, moving left
+    Horizontal,
+    /// Characters turned by 90°, to read with the head tilted right, moving up
+    Rotated,
+}
+
+/// Frames of text moving through the matrix, one pixel each
+///
+/// The text comes in from the bottom or right edge and the last frame is the
+/// one after it left the matrix. The font only has upper case letters, lower
+/// case is converted and other characters are shown as '?'.
+pub fn scroll_frames(text: &str, mode: ScrollMode) -> Vec<GrayImage> {
+    let chars: Vec<char> = text.chars().flat_map(char::to_uppercase).collect();
+    let (width, height) = (WIDTH as u32, HEIGHT as u32);
+    match mode {
+        ScrollMode::Vertical => {
+            // Two empty columns on the left, like show_string()
+            let column = render_column(&chars);
+            let mut strip = GrayImage::new(width, column.height());
+            imageops::replace(&mut strip, &column, 2, 0);
+            scroll_up(&strip)
+        }
+        ScrollMode::Horizontal => {
+            let line = render_line(&chars);
+            let mut strip = GrayImage::new(line.width(), height);
+            imageops::replace(&mut strip, &line, 0, ((height - FONT_HEIGHT) / 2) as i64);
+            // Pad, so that the text comes in and goes out completely
+            let mut padded = GrayImage::new(strip.width() + 2 * width, height);
+            imageops::replace(&mut padded, &strip, width as i64, 0);
+            (0..=padded.width() - width)
+                .map(|x| imageops::crop_imm(&padded, x, 0, width, height).to_image())
+                .collect()
+        }
+        ScrollMode::Rotated => {
+            let line = imageops::rotate90(&render_line(&chars));
+            let mut strip = GrayImage::new(width, line.height());
+            let x = (width - line.width()) / 2;
+            imageops::replace(&mut strip, &line, x as i64, 0);
+            scroll_up(&strip)
+        }
+    }
+}
+
+/// Every few frames, so that at most `max` are left, with their duration in ms
+///
+/// Each frame is shown for `period`, or as long as it takes to move the
+/// pixels of the ones that were skipped.
+pub(crate) fn thin_frames(
+    frames: Vec<GrayImage>,
+    period: Duration,
+    max: usize,
+) -> Vec<(GrayImage, u16)> {
+    let step = frames.len().div_ceil(max).max(1);
+    let delay = (period.as_millis() * step as u128).min(u16::MAX as u128) as u16;
+    frames
+        .into_iter()
+        .step_by(step)
+        .map(|frame| (frame, delay))
+        .collect()
+}
+
+/// Characters next to each other, only as high as the font
+fn render_line(chars: &[char]) -> GrayImage {
+    let step = FONT_WIDTH + SPACING;
+    let mut line = GrayImage::new((chars.len() as u32 * step).max(1), FONT_HEIGHT);
+    for (i, c) in chars.iter().enumerate() {
+        draw_char(&mut line, *c, i as u32 * step, 0);
+    }
+    line
+}
+
+/// Characters below each other, only as wide as the font
+fn render_column(chars: &[char]) -> GrayImage {
+    let step = FONT_HEIGHT + SPACING;
+    let mut column = GrayImage::new(FONT_WIDTH, (chars.len() as u32 * step).max(1));
+    for (i, c) in chars.iter().enumerate() {
+        draw_char(&mut column, *c, 0, i as u32 * step);
+    }
+    column
+}
+
+fn draw_char(img: &mut GrayImage, c: char, x: u32, y: u32) {
+    let pixels = convert_font(c);
+    for pixel_y in 0..FONT_HEIGHT {
+        for pixel_x in 0..FONT_WIDTH {
+            if pixels[(pixel_x + pixel_y * FONT_WIDTH) as usize] == 1 {
+                img.put_pixel(x + pixel_x, y + pixel_y, Luma([0xFF]));
+            }
+        }
+    }
+}
+
+/// Move a strip as wide as the matrix up through it, starting below
+fn scroll_up(strip: &GrayImage) -> Vec<GrayImage> {
+    let (width, height) = (WIDTH as u32, HEIGHT as u32);
+    let mut padded = GrayImage::new(width, strip.height() + 2 * height);
+    imageops::replace(&mut padded, strip, 0, height as i64);
+    (0..=padded.height() - height)
+        .map(|y| imageops::crop_imm(&padded, 0, y, width, height).to_image())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn blank(frame: &GrayImage) -> bool {
+        frame.pixels().all(|pixel| pixel.0[0] == 0)
+    }
+
+    fn column(frame: &GrayImage, x: u32) -> Vec<u8> {
+        (0..frame.height())
+            .map(|y| frame.get_pixel(x, y).0[0])
+            .collect()
+    }
+
+    fn row(frame: &GrayImage, y: u32) -> Vec<u8> {
+        (0..frame.width())
+            .map(|x| frame.get_pixel(x, y).0[0])
+            .collect()
+    }
+
+    #[test]
+    fn horizontal_steps_one_pixel() {
+        let frames = scroll_frames("HI", ScrollMode::Horizontal);
+        // Text width plus coming in and going out
+        assert_eq!(frames.len(), 2 * 6 + WIDTH + 1);
+        for (frame, next) in frames.iter().zip(&frames[1..]) {
+            assert_eq!(frame.dimensions(), (WIDTH as u32, HEIGHT as u32));
+            for x in 1..WIDTH as u32 {
+                assert_eq!(column(next, x - 1), column(frame, x));
+            }
+        }
+        // Comes in at the right edge
+        assert!(!blank(&frames[1]));
+        assert!((0..WIDTH as u32 - 1).all(|x| column(&frames[1], x).iter().all(|&v| v == 0)));
+    }
+
+    #[test]
+    fn vertical_steps_one_pixel() {
+        for mode in [ScrollMode::Vertical, ScrollMode::Rotated] {
+            let frames = scroll_frames("HELLO WORLD", mode);
+            for (frame, next) in frames.iter().zip(&frames[1..]) {
+                for y in 1..HEIGHT as u32 {
+                    assert_eq!(row(next, y - 1), row(frame, y));
+                }
+            }
+            // Comes in at the bottom edge
+            assert!(!blank(&frames[1]));
+            assert!(row(&frames[1], HEIGHT as u32 - 1).contains(&0xFF));
+            assert!((0..HEIGHT as u32 - 1).all(|y| !row(&frames[1], y).contains(&0xFF)));
+        }
+    }
+
+    #[test]
+    fn ends_after_the_text() {
+        for mode in [
+            ScrollMode::Vertical,
+            ScrollMode::Horizontal,
+            ScrollMode::Rotated,
+        ] {
+            let frames = scroll_frames("Hello", mode);
+            // So that it wraps around without a jump when looping
+            assert!(blank(&frames[0]), "{mode:?}");
+            assert!(blank(frames.last().unwrap()), "{mode:?}");
+            // Gone after the spacing of the last character
+            let last_shown = frames.iter().rposition(|frame| !blank(frame)).unwrap();
+            assert_eq!(last_shown, frames.len() - 2 - SPACING as usize, "{mode:?}");
+        }
+
+        let frames = scroll_frames("", ScrollMode::Horizontal);
+        assert!(frames.iter().all(blank));
+    }
+
+    #[test]
+    fn lower_case_like_upper_case() {
+        assert_eq!(
+            scroll_frames("hello", ScrollMode::Vertical),
+            scroll_frames("HELLO", ScrollMode::Vertical)
+        );
+    }
+
+    #[test]
+    fn thin_long_text() {
+        let period = Duration::from_millis(50);
+        let frames = scroll_frames("HI", ScrollMode::Horizontal);
+        let count = frames.len();
+        let thinned = thin_frames(frames.clone(), period, 100);
+        assert_eq!(thinned.len(), count);
+        assert!(thinned.iter().all(|(_, delay)| *delay == 50));
+
+        // Every third frame, each shown three times as long
+        let max = count.div_ceil(3);
+        let thinned = thin_frames(frames.clone(), period, max);
+        assert!(thinned.len() <= max);
+        assert_eq!(thinned[1].0, frames[3]);
+        assert!(thinned.iter().all(|(_, delay)| *delay == 150));
+    }
+}
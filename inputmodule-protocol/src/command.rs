@@ -0,0 +1,866 @@
+use heapless::String;
+use num_traits::FromPrimitive;
+
+use crate::*;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// Get current brightness scaling
+    GetBrightness,
+    /// Set brightness scaling
+    SetBrightness(u8),
+    /// Display pre-programmed pattern
+    Pattern(PatternVals),
+    /// Reset into bootloader
+    BootloaderReset,
+    /// Light up a percentage of the screen
+    Percentage(u8),
+    /// Go to sleepe or wake up
+    Sleep(bool),
+    IsSleeping,
+    /// Start/stop animation (vertical scrolling)
+    SetAnimate(bool),
+    GetAnimate,
+    /// Panic. Just to test what happens
+    Panic,
+    /// Draw black/white on the grid
+    Draw([u8; DRAW_BYTES]),
+    StageGreyCol(u8, [u8; HEIGHT]),
+    DrawGreyColBuffer,
+    /// At most MAX_TEXT_LEN bytes, so that it always fits into the command
+    SetText(String<MAX_TEXT_LEN>),
+    StartGame(Game),
+    GameControl(GameControlArg),
+    GameStatus,
+    Version,
+    GetColor,
+    SetColor(Rgb),
+    DisplayOn(bool),
+    GetDisplayOn,
+    InvertScreen(bool),
+    GetInvertScreen,
+    SetPixelColumn(u16, [u8; PIXEL_COLUMN_BYTES]),
+    FlushFramebuffer,
+    ClearRam,
+    ScreenSaver(bool),
+    GetScreenSaver,
+    SetFps(u8),
+    GetFps,
+    SetPowerMode(u8),
+    GetPowerMode,
+    SetAnimationPeriod(u16),
+    GetAnimationPeriod,
+    SetPwmFreq(PwmFreqArg),
+    GetPwmFreq,
+    SetDebugMode(bool),
+    GetDebugMode,
+    /// Acknowledge every command, see response::Ack
+    SetAckMode(bool),
+    /// Describe the module, see response::Capabilities
+    GetCapabilities,
+    /// Hardware revision from the serial number block, 0 if unknown
+    GetHwRevision,
+    /// Save, load or erase the settings persisted in flash
+    Settings(SettingsArg),
+    /// Upload a boot animation to flash, step by step
+    BootAnimation(BootAnimationArg),
+    /// Upload frames to RAM and play them without the host
+    Sequence(SequenceArg),
+}
+
+impl Command {
+    /// ID that's sent on the wire for this command
+    pub fn command_val(&self) -> CommandVals {
+        match self {
+            Command::GetBrightness | Command::SetBrightness(_) => CommandVals::Brightness,
+            Command::Pattern(_) | Command::Percentage(_) => CommandVals::Pattern,
+            Command::BootloaderReset => CommandVals::BootloaderReset,
+            Command::Sleep(_) | Command::IsSleeping => CommandVals::Sleep,
+            Command::SetAnimate(_) | Command::GetAnimate => CommandVals::Animate,
+            Command::Panic => CommandVals::Panic,
+            Command::Draw(_) => CommandVals::Draw,
+            Command::StageGreyCol(_, _) => CommandVals::StageGreyCol,
+            Command::DrawGreyColBuffer => CommandVals::DrawGreyColBuffer,
+            Command::SetText(_) => CommandVals::SetText,
+            Command::StartGame(_) => CommandVals::StartGame,
+            Command::GameControl(_) => CommandVals::GameControl,
+            Command::GameStatus => CommandVals::GameStatus,
+            Command::Version => CommandVals::Version,
+            Command::GetColor | Command::SetColor(_) => CommandVals::SetColor,
+            Command::DisplayOn(_) | Command::GetDisplayOn => CommandVals::DisplayOn,
+            Command::InvertScreen(_) | Command::GetInvertScreen => CommandVals::InvertScreen,
+            Command::SetPixelColumn(_, _) => CommandVals::SetPixelColumn,
+            Command::FlushFramebuffer => CommandVals::FlushFramebuffer,
+            Command::ClearRam => CommandVals::ClearRam,
+            Command::ScreenSaver(_) | Command::GetScreenSaver => CommandVals::ScreenSaver,
+            Command::SetFps(_) | Command::GetFps => CommandVals::SetFps,
+            Command::SetPowerMode(_) | Command::GetPowerMode => CommandVals::SetPowerMode,
+            Command::SetAnimationPeriod(_) | Command::GetAnimationPeriod => {
+                CommandVals::AnimationPeriod
+            }
+            Command::SetPwmFreq(_) | Command::GetPwmFreq => CommandVals::PwmFreq,
+            Command::SetDebugMode(_) | Command::GetDebugMode => CommandVals::DebugMode,
+            Command::SetAckMode(_) => CommandVals::AckMode,
+            Command::GetCapabilities => CommandVals::Capabilities,
+            Command::GetHwRevision => CommandVals::HwRevision,
+            Command::Settings(_) => CommandVals::Settings,
+            Command::BootAnimation(_) => CommandVals::BootAnimation,
+            Command::Sequence(_) => CommandVals::Sequence,
+        }
+    }
+
+    /// Write magic, command ID and parameters into the buffer
+    ///
+    /// Returns the number of bytes written.
+    /// The buffer must be at least MAX_COMMAND_LEN bytes long.
+    pub fn encode(&self, buf: &mut [u8]) -> usize {
+        buf[..2].copy_from_slice(&MAGIC);
+        buf[2] = self.command_val() as u8;
+        let args = &mut buf[3..];
+        let args_len = match self {
+            Command::SetBrightness(val) | Command::SetFps(val) | Command::SetPowerMode(val) => {
+                args[0] = *val;
+                1
+            }
+            Command::Percentage(val) => {
+                args[0] = PatternVals::Percentage as u8;
+                args[1] = *val;
+                2
+            }
+            Command::Sleep(val)
+            | Command::SetAnimate(val)
+            | Command::DisplayOn(val)
+            | Command::InvertScreen(val)
+            | Command::ScreenSaver(val)
+            | Command::SetDebugMode(val)
+            | Command::SetAckMode(val) => {
+                args[0] = *val as u8;
+                1
+            }
+            Command::Pattern(pattern) => {
+                args[0] = *pattern as u8;
+                1
+            }
+            Command::Draw(vals) => {
+                args[..DRAW_BYTES].copy_from_slice(vals);
+                DRAW_BYTES
+            }
+            Command::StageGreyCol(col, vals) => {
+                args[0] = *col;
+                args[1..1 + HEIGHT].copy_from_slice(vals);
+                1 + HEIGHT
+            }
+            Command::SetText(text) => {
+                let text = text.as_bytes();
+                args[0] = text.len() as u8;
+                args[1..1 + text.len()].copy_from_slice(text);
+                1 + text.len()
+            }
+            Command::StartGame(game) => {
+                args[0] = game.game_val() as u8;
+                match game {
+                    Game::Pong(param) => {
+                        args[1] = param.mode as u8;
+                        args[2] = param.difficulty as u8;
+                        args[3] = param.winning_score;
+                        4
+                    }
+                    Game::GameOfLife(param) => {
+                        args[1] = param.start as u8;
+                        args[2..4].copy_from_slice(&param.rule.birth.to_le_bytes());
+                        args[4..6].copy_from_slice(&param.rule.survival.to_le_bytes());
+                        args[6] = param.wrap as u8 | (param.reseed as u8) << 1;
+                        args[7..11].copy_from_slice(&param.seed.to_le_bytes());
+                        args[11] = param.density;
+                        12
+                    }
+                    _ => 1,
+                }
+            }
+            Command::GameControl(arg) => {
+                args[0] = *arg as u8;
+                1
+            }
+            Command::Settings(arg) => {
+                args[0] = *arg as u8;
+                1
+            }
+            Command::BootAnimation(arg) => {
+                args[0] = arg.boot_animation_val() as u8;
+                if let BootAnimationArg::AddFrame(duration) = arg {
+                    args[1..3].copy_from_slice(&duration.to_le_bytes());
+                    3
+                } else {
+                    1
+                }
+            }
+            Command::Sequence(arg) => {
+                args[0] = arg.sequence_val() as u8;
+                match arg {
+                    SequenceArg::Begin => 1,
+                    SequenceArg::AddFrame(duration) => {
+                        args[1..3].copy_from_slice(&duration.to_le_bytes());
+                        3
+                    }
+                    SequenceArg::Play(loops) => {
+                        args[1] = *loops;
+                        2
+                    }
+                }
+            }
+            Command::SetColor(color) => {
+                args[..3].copy_from_slice(&[color.r, color.g, color.b]);
+                3
+            }
+            Command::SetPixelColumn(column, pixels) => {
+                args[..2].copy_from_slice(&column.to_le_bytes());
+                args[2..2 + PIXEL_COLUMN_BYTES].copy_from_slice(pixels);
+                2 + PIXEL_COLUMN_BYTES
+            }
+            Command::SetAnimationPeriod(period) => {
+                args[..2].copy_from_slice(&period.to_le_bytes());
+                2
+            }
+            Command::SetPwmFreq(freq) => {
+                args[0] = *freq as u8;
+                1
+            }
+            // Getters and commands without parameters
+            _ => 0,
+        };
+        3 + args_len
+    }
+
+    /// Like encode, but with a sequence number after the command ID
+    ///
+    /// That's the format expected when ack mode is enabled.
+    pub fn encode_sequenced(&self, seq: u8, buf: &mut [u8]) -> usize {
+        let mut plain = [0; MAX_COMMAND_LEN];
+        let len = self.encode(&mut plain);
+        buf[..3].copy_from_slice(&plain[..3]);
+        buf[3] = seq;
+        buf[4..len + 1].copy_from_slice(&plain[3..len]);
+        len + 1
+    }
+
+    #[cfg(feature = "std")]
+    pub fn to_vec(&self) -> std::vec::Vec<u8> {
+        let mut buf = [0; MAX_COMMAND_LEN];
+        let len = self.encode(&mut buf);
+        buf[..len].to_vec()
+    }
+
+    #[cfg(feature = "std")]
+    pub fn to_vec_sequenced(&self, seq: u8) -> std::vec::Vec<u8> {
+        let mut buf = [0; MAX_COMMAND_LEN + 1];
+        let len = self.encode_sequenced(seq, &mut buf);
+        buf[..len].to_vec()
+    }
+
+    /// Parse a command sent in ack mode, with a sequence number after the command ID
+    ///
+    /// Returns the sequence number, or 0 if the command was too short to contain one.
+    pub fn parse_sequenced(buf: &[u8]) -> (u8, Result<Command, ParseError>) {
+        if buf.len() < 4 {
+            return (0, Err(ParseError::TooShort));
+        }
+        // Anything longer than the longest command is ignored anyways
+        let len = buf.len().min(MAX_COMMAND_LEN + 1);
+        let seq = buf[3];
+        let mut plain = [0; MAX_COMMAND_LEN];
+        plain[..3].copy_from_slice(&buf[..3]);
+        plain[3..len - 1].copy_from_slice(&buf[4..len]);
+        (seq, Command::parse(&plain[..len - 1]))
+    }
+
+    /// Parse a command from the bytes received in a single transfer
+    ///
+    /// Commands without parameters ignore any trailing bytes. Getters are
+    /// only chosen if no parameter bytes were sent at all.
+    pub fn parse(buf: &[u8]) -> Result<Command, ParseError> {
+        if buf.len() < 3 {
+            return Err(ParseError::TooShort);
+        }
+        if buf[..2] != MAGIC {
+            return Err(ParseError::BadMagic);
+        }
+        let command: CommandVals =
+            FromPrimitive::from_u8(buf[2]).ok_or(ParseError::UnknownCommand(buf[2]))?;
+        let payload = &buf[3..];
+
+        // Make sure the payload has at least `len` bytes
+        let need = |len: usize| {
+            if payload.len() >= len {
+                Ok(())
+            } else {
+                Err(ParseError::PayloadLength {
+                    command,
+                    expected: len as u8,
+                    actual: payload.len() as u8,
+                })
+            }
+        };
+        let invalid = |value: u8| ParseError::InvalidValue { command, value };
+        let bool_arg = |value: u8| match value {
+            0 => Ok(false),
+            1 => Ok(true),
+            _ => Err(invalid(value)),
+        };
+        let arg = payload.first().copied();
+
+        Ok(match command {
+            CommandVals::Brightness => match arg {
+                Some(brightness) => Command::SetBrightness(brightness),
+                None => Command::GetBrightness,
+            },
+            CommandVals::Pattern => {
+                need(1)?;
+                match FromPrimitive::from_u8(payload[0]).ok_or(invalid(payload[0]))? {
+                    PatternVals::Percentage => {
+                        need(2)?;
+                        if payload[1] > 100 {
+                            return Err(invalid(payload[1]));
+                        }
+                        Command::Percentage(payload[1])
+                    }
+                    pattern => Command::Pattern(pattern),
+                }
+            }
+            CommandVals::BootloaderReset => Command::BootloaderReset,
+            CommandVals::Sleep => match arg {
+                Some(go_to_sleep) => Command::Sleep(bool_arg(go_to_sleep)?),
+                None => Command::IsSleeping,
+            },
+            CommandVals::Animate => match arg {
+                Some(run_animation) => Command::SetAnimate(bool_arg(run_animation)?),
+                None => Command::GetAnimate,
+            },
+            CommandVals::Panic => Command::Panic,
+            CommandVals::Draw => {
+                need(DRAW_BYTES)?;
+                let mut bytes = [0; DRAW_BYTES];
+                bytes.clone_from_slice(&payload[..DRAW_BYTES]);
+                Command::Draw(bytes)
+            }
+            CommandVals::StageGreyCol => {
+                need(1 + HEIGHT)?;
+                if payload[0] as usize >= WIDTH {
+                    return Err(invalid(payload[0]));
+                }
+                let mut bytes = [0; HEIGHT];
+                bytes.clone_from_slice(&payload[1..1 + HEIGHT]);
+                Command::StageGreyCol(payload[0], bytes)
+            }
+            CommandVals::DrawGreyColBuffer => Command::DrawGreyColBuffer,
+            CommandVals::SetText => {
+                need(1)?;
+                let str_len = payload[0] as usize;
+                if str_len > MAX_TEXT_LEN {
+                    return Err(invalid(payload[0]));
+                }
+                need(1 + str_len)?;
+                let text_str = core::str::from_utf8(&payload[1..1 + str_len])
+                    .map_err(|_| ParseError::InvalidText)?;
+                let mut text: String<MAX_TEXT_LEN> = String::new();
+                // Can't fail, the length is checked above
+                let _ = text.push_str(text_str);
+                Command::SetText(text)
+            }
+            CommandVals::StartGame => {
+                need(1)?;
+                match FromPrimitive::from_u8(payload[0]).ok_or(invalid(payload[0]))? {
+                    GameVal::Snake => Command::StartGame(Game::Snake),
+                    // Without parameters, like older hosts send it
+                    GameVal::Pong if payload.len() == 1 => {
+                        Command::StartGame(Game::Pong(PongParam::default()))
+                    }
+                    GameVal::Pong => {
+                        need(4)?;
+                        if payload[3] == 0 {
+                            return Err(invalid(payload[3]));
+                        }
+                        Command::StartGame(Game::Pong(PongParam {
+                            mode: FromPrimitive::from_u8(payload[1]).ok_or(invalid(payload[1]))?,
+                            difficulty: FromPrimitive::from_u8(payload[2])
+                                .ok_or(invalid(payload[2]))?,
+                            winning_score: payload[3],
+                        }))
+                    }
+                    GameVal::Tetris => Command::StartGame(Game::Tetris),
+                    GameVal::GameOfLife => {
+                        need(2)?;
+                        let start: GameOfLifeStartParam =
+                            FromPrimitive::from_u8(payload[1]).ok_or(invalid(payload[1]))?;
+                        // Only the start pattern, like older hosts send it
+                        if payload.len() == 2 {
+                            return Ok(Command::StartGame(Game::GameOfLife(start.into())));
+                        }
+                        need(12)?;
+                        let mask = |i: usize| {
+                            let mask = u16::from_le_bytes([payload[i], payload[i + 1]]);
+                            if mask & !LifeRule::MASK == 0 {
+                                Ok(mask)
+                            } else {
+                                Err(invalid(payload[i + 1]))
+                            }
+                        };
+                        if payload[6] > 0b11 {
+                            return Err(invalid(payload[6]));
+                        }
+                        if payload[11] > 100 {
+                            return Err(invalid(payload[11]));
+                        }
+                        Command::StartGame(Game::GameOfLife(GameOfLifeParam {
+                            start,
+                            rule: LifeRule {
+                                birth: mask(2)?,
+                                survival: mask(4)?,
+                            },
+                            wrap: payload[6] & 1 != 0,
+                            reseed: payload[6] & 2 != 0,
+                            seed: u32::from_le_bytes([
+                                payload[7],
+                                payload[8],
+                                payload[9],
+                                payload[10],
+                            ]),
+                            density: payload[11],
+                        }))
+                    }
+                }
+            }
+            CommandVals::GameControl => {
+                need(1)?;
+                Command::GameControl(FromPrimitive::from_u8(payload[0]).ok_or(invalid(payload[0]))?)
+            }
+            CommandVals::GameStatus => Command::GameStatus,
+            CommandVals::SetColor => match arg {
+                Some(_) => {
+                    need(3)?;
+                    Command::SetColor(Rgb::new(payload[0], payload[1], payload[2]))
+                }
+                None => Command::GetColor,
+            },
+            CommandVals::DisplayOn => match arg {
+                Some(on) => Command::DisplayOn(bool_arg(on)?),
+                None => Command::GetDisplayOn,
+            },
+            CommandVals::InvertScreen => match arg {
+                Some(invert) => Command::InvertScreen(bool_arg(invert)?),
+                None => Command::GetInvertScreen,
+            },
+            CommandVals::SetPixelColumn => {
+                // 2B for column (u16)
+                // 50B for 400 pixels (400/8=50)
+                need(2 + PIXEL_COLUMN_BYTES)?;
+                let column = u16::from_le_bytes([payload[0], payload[1]]);
+                let mut pixels = [0; PIXEL_COLUMN_BYTES];
+                pixels.clone_from_slice(&payload[2..2 + PIXEL_COLUMN_BYTES]);
+                Command::SetPixelColumn(column, pixels)
+            }
+            CommandVals::FlushFramebuffer => Command::FlushFramebuffer,
+            CommandVals::ClearRam => Command::ClearRam,
+            CommandVals::ScreenSaver => match arg {
+                Some(on) => Command::ScreenSaver(bool_arg(on)?),
+                None => Command::GetScreenSaver,
+            },
+            CommandVals::SetFps => match arg {
+                Some(fps) => Command::SetFps(fps),
+                None => Command::GetFps,
+            },
+            CommandVals::SetPowerMode => match arg {
+                Some(mode) => Command::SetPowerMode(mode),
+                None => Command::GetPowerMode,
+            },
+            CommandVals::AnimationPeriod => match arg {
+                Some(_) => {
+                    need(2)?;
+                    Command::SetAnimationPeriod(u16::from_le_bytes([payload[0], payload[1]]))
+                }
+                None => Command::GetAnimationPeriod,
+            },
+            CommandVals::PwmFreq => match arg {
+                Some(freq) => {
+                    Command::SetPwmFreq(FromPrimitive::from_u8(freq).ok_or(invalid(freq))?)
+                }
+                None => Command::GetPwmFreq,
+            },
+            CommandVals::DebugMode => match arg {
+                Some(debug_mode) => Command::SetDebugMode(bool_arg(debug_mode)?),
+                None => Command::GetDebugMode,
+            },
+            CommandVals::Version => Command::Version,
+            CommandVals::AckMode => {
+                need(1)?;
+                Command::SetAckMode(bool_arg(payload[0])?)
+            }
+            CommandVals::Capabilities => Command::GetCapabilities,
+            CommandVals::HwRevision => Command::GetHwRevision,
+            CommandVals::Settings => {
+                need(1)?;
+                Command::Settings(FromPrimitive::from_u8(payload[0]).ok_or(invalid(payload[0]))?)
+            }
+            CommandVals::BootAnimation => {
+                need(1)?;
+                Command::BootAnimation(
+                    match FromPrimitive::from_u8(payload[0]).ok_or(invalid(payload[0]))? {
+                        BootAnimationVal::Begin => BootAnimationArg::Begin,
+                        BootAnimationVal::AddFrame => {
+                            need(3)?;
+                            BootAnimationArg::AddFrame(u16::from_le_bytes([payload[1], payload[2]]))
+                        }
+                        BootAnimationVal::Commit => BootAnimationArg::Commit,
+                        BootAnimationVal::Clear => BootAnimationArg::Clear,
+                    },
+                )
+            }
+            CommandVals::Sequence => {
+                need(1)?;
+                Command::Sequence(
+                    match FromPrimitive::from_u8(payload[0]).ok_or(invalid(payload[0]))? {
+                        SequenceVal::Begin => SequenceArg::Begin,
+                        SequenceVal::AddFrame => {
+                            need(3)?;
+                            SequenceArg::AddFrame(u16::from_le_bytes([payload[1], payload[2]]))
+                        }
+                        SequenceVal::Play => {
+                            need(2)?;
+                            SequenceArg::Play(payload[1])
+                        }
+                    },
+                )
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(command: Command) {
+        let mut buf = [0; MAX_COMMAND_LEN];
+        let len = command.encode(&mut buf);
+        assert_eq!(Command::parse(&buf[..len]), Ok(command));
+    }
+
+    #[test]
+    fn round_trip_all_commands() {
+        let mut text: String<MAX_TEXT_LEN> = String::new();
+        text.push_str("Framework").unwrap();
+        // The longest text that fits, anything longer can't be built
+        let mut longest: String<MAX_TEXT_LEN> = String::new();
+        for _ in 0..MAX_TEXT_LEN {
+            longest.push('x').unwrap();
+        }
+        assert!(longest.clone().push('x').is_err());
+        let mut grey_col = [0; HEIGHT];
+        for (i, val) in grey_col.iter_mut().enumerate() {
+            *val = i as u8 * 7;
+        }
+
+        let commands = [
+            Command::GetBrightness,
+            Command::SetBrightness(0x50),
+            Command::Pattern(PatternVals::Gradient),
+            Command::Pattern(PatternVals::DisplayLotus2),
+            Command::BootloaderReset,
+            Command::Percentage(42),
+            Command::Sleep(true),
+            Command::Sleep(false),
+            Command::IsSleeping,
+            Command::SetAnimate(true),
+            Command::GetAnimate,
+            Command::Panic,
+            Command::Draw([0xA5; DRAW_BYTES]),
+            Command::StageGreyCol(8, grey_col),
+            Command::DrawGreyColBuffer,
+            Command::SetText(text),
+            Command::SetText(longest),
+            Command::StartGame(Game::Snake),
+            Command::StartGame(Game::Pong(PongParam::default())),
+            Command::StartGame(Game::Pong(PongParam {
+                mode: PongMode::AiVsAi,
+                difficulty: PongDifficulty::Hard,
+                winning_score: 11,
+            })),
+            Command::StartGame(Game::Tetris),
+            Command::StartGame(Game::GameOfLife(GameOfLifeStartParam::Glider.into())),
+            Command::StartGame(Game::GameOfLife(GameOfLifeParam {
+                start: GameOfLifeStartParam::Soup,
+                rule: LifeRule::parse("B36/S23").unwrap(),
+                wrap: false,
+                reseed: true,
+                seed: 0xDEAD_BEEF,
+                density: 50,
+            })),
+            Command::GameControl(GameControlArg::SecondRight),
+            Command::GameStatus,
+            Command::Version,
+            Command::GetColor,
+            Command::SetColor(Rgb::new(0xFF, 0x00, 0x80)),
+            Command::DisplayOn(false),
+            Command::GetDisplayOn,
+            Command::InvertScreen(true),
+            Command::GetInvertScreen,
+            Command::SetPixelColumn(299, [0x0F; PIXEL_COLUMN_BYTES]),
+            Command::FlushFramebuffer,
+            Command::ClearRam,
+            Command::ScreenSaver(true),
+            Command::GetScreenSaver,
+            Command::SetFps(0b10101),
+            Command::GetFps,
+            Command::SetPowerMode(1),
+            Command::GetPowerMode,
+            Command::SetAnimationPeriod(1000),
+            Command::GetAnimationPeriod,
+            Command::SetPwmFreq(PwmFreqArg::P1k8),
+            Command::GetPwmFreq,
+            Command::SetDebugMode(true),
+            Command::GetDebugMode,
+            Command::SetAckMode(true),
+            Command::GetCapabilities,
+            Command::GetHwRevision,
+            Command::Settings(SettingsArg::Save),
+            Command::Settings(SettingsArg::FactoryReset),
+            Command::BootAnimation(BootAnimationArg::Begin),
+            Command::BootAnimation(BootAnimationArg::AddFrame(250)),
+            Command::BootAnimation(BootAnimationArg::Commit),
+            Command::BootAnimation(BootAnimationArg::Clear),
+            Command::Sequence(SequenceArg::Begin),
+            Command::Sequence(SequenceArg::AddFrame(40)),
+            Command::Sequence(SequenceArg::Play(0)),
+            Command::Sequence(SequenceArg::Play(3)),
+        ];
+        for command in commands {
+            round_trip(command);
+        }
+    }
+
+    #[test]
+    fn wire_format() {
+        let mut buf = [0; MAX_COMMAND_LEN];
+        let len = Command::SetBrightness(0x50).encode(&mut buf);
+        assert_eq!(&buf[..len], &[0x32, 0xAC, 0x00, 0x50]);
+
+        let len = Command::Percentage(100).encode(&mut buf);
+        assert_eq!(&buf[..len], &[0x32, 0xAC, 0x01, 0x00, 100]);
+
+        let len = Command::SetAnimationPeriod(0x1234).encode(&mut buf);
+        assert_eq!(&buf[..len], &[0x32, 0xAC, 0x1C, 0x34, 0x12]);
+
+        let len = Command::GetPwmFreq.encode(&mut buf);
+        assert_eq!(&buf[..len], &[0x32, 0xAC, 0x1E]);
+
+        let len = Command::StartGame(Game::Pong(PongParam::default())).encode(&mut buf);
+        assert_eq!(&buf[..len], &[0x32, 0xAC, 0x10, 0x01, 0x00, 0x01, 5]);
+        // Older hosts don't send the parameters
+        assert_eq!(
+            Command::parse(&[0x32, 0xAC, 0x10, 0x01]),
+            Ok(Command::StartGame(Game::Pong(PongParam::default())))
+        );
+        assert_eq!(
+            Command::parse(&[0x32, 0xAC, 0x10, 0x03, 0x05]),
+            Ok(Command::StartGame(Game::GameOfLife(
+                GameOfLifeStartParam::Glider.into()
+            )))
+        );
+
+        let param = GameOfLifeParam {
+            seed: 0x0102_0304,
+            ..GameOfLifeStartParam::Soup.into()
+        };
+        let len = Command::StartGame(Game::GameOfLife(param)).encode(&mut buf);
+        assert_eq!(
+            &buf[..len],
+            &[0x32, 0xAC, 0x10, 0x03, 0x07, 0x08, 0x00, 0x0C, 0x00, 0x01, 4, 3, 2, 1, 35]
+        );
+    }
+
+    #[test]
+    fn reject_malformed() {
+        use CommandVals as C;
+        let cases: &[(&[u8], ParseError)] = &[
+            (&[0x32, 0xAC], ParseError::TooShort),
+            (&[0x32, 0xAD, 0x00], ParseError::BadMagic),
+            (&[0x32, 0xAC, 0x1D], ParseError::UnknownCommand(0x1D)),
+            (
+                &[0x32, 0xAC, 0x06, 0xFF],
+                ParseError::PayloadLength {
+                    command: C::Draw,
+                    expected: DRAW_BYTES as u8,
+                    actual: 1,
+                },
+            ),
+            (
+                &[0x32, 0xAC, 0x01, 0x42],
+                ParseError::InvalidValue {
+                    command: C::Pattern,
+                    value: 0x42,
+                },
+            ),
+            (
+                &[0x32, 0xAC, 0x10, 0x03],
+                ParseError::PayloadLength {
+                    command: C::StartGame,
+                    expected: 2,
+                    actual: 1,
+                },
+            ),
+            (
+                &[0x32, 0xAC, 0x10, 0x01, 0x02],
+                ParseError::PayloadLength {
+                    command: C::StartGame,
+                    expected: 4,
+                    actual: 2,
+                },
+            ),
+            (
+                &[0x32, 0xAC, 0x10, 0x01, 0x00, 0x01, 0],
+                ParseError::InvalidValue {
+                    command: C::StartGame,
+                    value: 0,
+                },
+            ),
+            (
+                &[0x32, 0xAC, 0x10, 0x03, 0x07, 0x08],
+                ParseError::PayloadLength {
+                    command: C::StartGame,
+                    expected: 12,
+                    actual: 3,
+                },
+            ),
+            // B9 doesn't exist
+            (
+                &[
+                    0x32, 0xAC, 0x10, 0x03, 0x07, 0x00, 0x02, 0x0C, 0x00, 0x01, 0, 0, 0, 0, 35,
+                ],
+                ParseError::InvalidValue {
+                    command: C::StartGame,
+                    value: 0x02,
+                },
+            ),
+            (
+                &[
+                    0x32, 0xAC, 0x10, 0x03, 0x07, 0x08, 0x00, 0x0C, 0x00, 0x01, 0, 0, 0, 0, 101,
+                ],
+                ParseError::InvalidValue {
+                    command: C::StartGame,
+                    value: 101,
+                },
+            ),
+            // Used to be silently treated as GetAnimationPeriod
+            (
+                &[0x32, 0xAC, 0x1C, 0x10],
+                ParseError::PayloadLength {
+                    command: C::AnimationPeriod,
+                    expected: 2,
+                    actual: 1,
+                },
+            ),
+            (
+                &[0x32, 0xAC, 0x03, 0x02],
+                ParseError::InvalidValue {
+                    command: C::Sleep,
+                    value: 0x02,
+                },
+            ),
+            (
+                &[0x32, 0xAC, 0x07, WIDTH as u8],
+                ParseError::PayloadLength {
+                    command: C::StageGreyCol,
+                    expected: 1 + HEIGHT as u8,
+                    actual: 1,
+                },
+            ),
+            // Longer than MAX_TEXT_LEN
+            (
+                &[0x32, 0xAC, 0x09, 32],
+                ParseError::InvalidValue {
+                    command: C::SetText,
+                    value: 32,
+                },
+            ),
+            (&[0x32, 0xAC, 0x09, 1, 0xFF], ParseError::InvalidText),
+            (
+                &[0x32, 0xAC, 0x25, 0x01, 0x10],
+                ParseError::PayloadLength {
+                    command: C::BootAnimation,
+                    expected: 3,
+                    actual: 2,
+                },
+            ),
+        ];
+        for (buf, err) in cases {
+            assert_eq!(Command::parse(buf), Err(*err), "{:X?}", buf);
+        }
+
+        // Even when all of the text is there
+        let mut buf = [b'x'; 4 + MAX_TEXT_LEN + 1];
+        buf[..4].copy_from_slice(&[0x32, 0xAC, 0x09, MAX_TEXT_LEN as u8 + 1]);
+        assert_eq!(
+            Command::parse(&buf),
+            Err(ParseError::InvalidValue {
+                command: C::SetText,
+                value: MAX_TEXT_LEN as u8 + 1,
+            })
+        );
+    }
+
+    #[test]
+    fn sequenced() {
+        let mut buf = [0; MAX_COMMAND_LEN + 1];
+        let len = Command::SetAnimationPeriod(0x1234).encode_sequenced(7, &mut buf);
+        assert_eq!(&buf[..len], &[0x32, 0xAC, 0x1C, 7, 0x34, 0x12]);
+        assert_eq!(
+            Command::parse_sequenced(&buf[..len]),
+            (7, Ok(Command::SetAnimationPeriod(0x1234)))
+        );
+
+        let len = Command::GetBrightness.encode_sequenced(8, &mut buf);
+        assert_eq!(
+            Command::parse_sequenced(&buf[..len]),
+            (8, Ok(Command::GetBrightness))
+        );
+        assert_eq!(
+            Command::parse_sequenced(&[0x32, 0xAC, 0x00]),
+            (0, Err(ParseError::TooShort))
+        );
+    }
+
+    #[test]
+    fn ignore_trailing_bytes() {
+        // Some clients send a dummy parameter to commands that don't take any
+        assert_eq!(
+            Command::parse(&[0x32, 0xAC, 0x02, 0x00]),
+            Ok(Command::BootloaderReset)
+        );
+        assert_eq!(
+            Command::parse(&[0x32, 0xAC, 0x00, 0x10, 0x00, 0x00]),
+            Ok(Command::SetBrightness(0x10))
+        );
+    }
+
+    #[test]
+    fn garbage_never_panics() {
+        // Cheap deterministic fuzzing, xorshift PRNG
+        let mut seed: u32 = 0x1234_5678;
+        let mut buf = [0; MAX_COMMAND_LEN];
+        for _ in 0..100_000 {
+            for byte in buf.iter_mut() {
+                seed ^= seed << 13;
+                seed ^= seed >> 17;
+                seed ^= seed << 5;
+                *byte = seed as u8;
+            }
+            buf[..2].copy_from_slice(&MAGIC);
+            // Make sure to hit valid command IDs most of the time
+            buf[2] &= 0x3F;
+            let len = seed as usize % (MAX_COMMAND_LEN + 1);
+            if let Ok(command) = Command::parse(&buf[..len]) {
+                // Anything accepted must survive a round trip
+                round_trip(command);
+            }
+        }
+    }
+}
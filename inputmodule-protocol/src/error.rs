@@ -0,0 +1,56 @@
+use crate::CommandVals;
+
+/// Reason why a received command was rejected
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ParseError {
+    /// Not even long enough for magic and command ID
+    TooShort,
+    /// Doesn't start with the magic bytes
+    BadMagic,
+    /// Command ID that doesn't exist
+    UnknownCommand(u8),
+    /// Valid command, but the module doesn't implement it
+    Unsupported(CommandVals),
+    /// Fewer parameter bytes than the command needs
+    PayloadLength {
+        command: CommandVals,
+        expected: u8,
+        actual: u8,
+    },
+    /// Parameter is out of range or not a known enum value
+    InvalidValue { command: CommandVals, value: u8 },
+    /// SetText string is not valid UTF-8
+    InvalidText,
+    /// Frame checksum doesn't match, it was corrupted in transit
+    Checksum,
+}
+
+impl core::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            ParseError::TooShort => write!(f, "Command too short"),
+            ParseError::BadMagic => write!(f, "Bad magic bytes"),
+            ParseError::UnknownCommand(id) => write!(f, "Unknown command {:#04X}", id),
+            ParseError::Unsupported(command) => {
+                write!(f, "{:?} not supported by this module", command)
+            }
+            ParseError::PayloadLength {
+                command,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "{:?} needs {} parameter bytes, got {}",
+                command, expected, actual
+            ),
+            ParseError::InvalidValue { command, value } => {
+                write!(f, "Invalid value {:#04X} for {:?}", value, command)
+            }
+            ParseError::InvalidText => write!(f, "Text is not valid UTF-8"),
+            ParseError::Checksum => write!(f, "Frame checksum mismatch"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
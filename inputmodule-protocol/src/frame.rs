@@ -0,0 +1,83 @@
+//! Framing of commands sent to the module
+//!
+//! USB transfers don't preserve message boundaries. A command might be split
+//! across two reads, or two commands might arrive in one read. Framed commands
+//! carry their length and a checksum, so the firmware can reassemble them.
+//!
+//! Layout:
+//!
+//! ```text
+//! Byte 0-1: FRAME_MAGIC
+//! Byte 2:   Payload length N
+//! Byte 3-:  Payload, a regular command including its magic bytes
+//! Last 2:   CRC-16 of length and payload, little endian
+//! ```
+
+use crate::MAX_COMMAND_LEN;
+
+/// Magic bytes that every frame starts with
+pub const FRAME_MAGIC: [u8; 2] = [0x32, 0xAF];
+
+/// Longest payload, a command with sequence number
+pub const MAX_PAYLOAD_LEN: usize = MAX_COMMAND_LEN + 1;
+
+/// Magic, length and checksum
+pub const FRAME_OVERHEAD: usize = 2 + 1 + 2;
+
+pub const MAX_FRAME_LEN: usize = MAX_PAYLOAD_LEN + FRAME_OVERHEAD;
+
+const CRC: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_IBM_3740);
+
+/// Checksum over the length byte and the payload
+pub fn checksum(payload: &[u8]) -> u16 {
+    let mut digest = CRC.digest();
+    digest.update(&[payload.len() as u8]);
+    digest.update(payload);
+    digest.finalize()
+}
+
+/// Wrap the payload in a frame
+///
+/// Returns the number of bytes written.
+/// The buffer must be at least MAX_FRAME_LEN bytes long.
+pub fn encode(payload: &[u8], buf: &mut [u8]) -> usize {
+    assert!(payload.len() <= MAX_PAYLOAD_LEN);
+    let len = payload.len();
+    buf[..2].copy_from_slice(&FRAME_MAGIC);
+    buf[2] = len as u8;
+    buf[3..3 + len].copy_from_slice(payload);
+    buf[3 + len..3 + len + 2].copy_from_slice(&checksum(payload).to_le_bytes());
+    len + FRAME_OVERHEAD
+}
+
+#[cfg(feature = "std")]
+pub fn to_vec(payload: &[u8]) -> std::vec::Vec<u8> {
+    let mut buf = [0; MAX_FRAME_LEN];
+    let len = encode(payload, &mut buf);
+    buf[..len].to_vec()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Command;
+
+    #[test]
+    fn frame_layout() {
+        let mut command = [0; MAX_COMMAND_LEN];
+        let command_len = Command::SetBrightness(0x50).encode(&mut command);
+
+        let mut buf = [0; MAX_FRAME_LEN];
+        let len = encode(&command[..command_len], &mut buf);
+        assert_eq!(len, command_len + FRAME_OVERHEAD);
+        assert_eq!(&buf[..7], &[0x32, 0xAF, 4, 0x32, 0xAC, 0x00, 0x50]);
+        let crc = u16::from_le_bytes([buf[7], buf[8]]);
+        assert_eq!(crc, checksum(&command[..command_len]));
+    }
+
+    #[test]
+    fn checksum_covers_length() {
+        assert_ne!(checksum(&[]), checksum(&[0x00]));
+        assert_ne!(checksum(&[0x01, 0x02]), checksum(&[0x02, 0x01]));
+    }
+}
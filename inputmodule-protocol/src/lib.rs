@@ -0,0 +1,462 @@
+//! Serial protocol shared by the input module firmware and the host tools
+//!
+//! Every command starts with two magic bytes, followed by the command ID and
+//! its parameters. See `commands.md` for the human readable description.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+mod command;
+mod error;
+pub mod frame;
+pub mod response;
+
+pub use command::*;
+pub use error::ParseError;
+/// For building the text of Command::SetText
+pub use heapless;
+pub use num_traits::FromPrimitive;
+
+/// Magic bytes that every command starts with
+pub const MAGIC: [u8; 2] = [0x32, 0xAC];
+
+/// Maximum length of a single command, including magic and command ID
+pub const MAX_COMMAND_LEN: usize = 64;
+
+/// Width of the LED matrix
+pub const WIDTH: usize = 9;
+/// Height of the LED matrix
+pub const HEIGHT: usize = 34;
+
+/// Bytes needed to represent all LEDs with a single bit
+/// math.ceil(WIDTH * HEIGHT / 8)
+pub const DRAW_BYTES: usize = 39;
+
+/// Bytes needed for one 400 pixel column of the B1 display (400/8=50)
+pub const PIXEL_COLUMN_BYTES: usize = 50;
+
+/// Longest text that SetText accepts
+pub const MAX_TEXT_LEN: usize = 31;
+
+/// Most frames that fit into the boot animation region of the LED Matrix
+pub const MAX_BOOT_ANIMATION_FRAMES: usize = 256;
+
+/// Most frames of a sequence that the LED Matrix keeps in RAM
+pub const MAX_SEQUENCE_FRAMES: usize = 32;
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, num_derive::FromPrimitive)]
+/// All available commands
+pub enum CommandVals {
+    Brightness = 0x00,
+    Pattern = 0x01,
+    BootloaderReset = 0x02,
+    Sleep = 0x03,
+    Animate = 0x04,
+    Panic = 0x05,
+    Draw = 0x06,
+    StageGreyCol = 0x07,
+    DrawGreyColBuffer = 0x08,
+    SetText = 0x09,
+    StartGame = 0x10,
+    GameControl = 0x11,
+    GameStatus = 0x12,
+    SetColor = 0x13,
+    DisplayOn = 0x14,
+    InvertScreen = 0x15,
+    SetPixelColumn = 0x16,
+    FlushFramebuffer = 0x17,
+    ClearRam = 0x18,
+    ScreenSaver = 0x19,
+    SetFps = 0x1A,
+    SetPowerMode = 0x1B,
+    AnimationPeriod = 0x1C,
+    PwmFreq = 0x1E,
+    DebugMode = 0x1F,
+    Version = 0x20,
+    AckMode = 0x21,
+    Capabilities = 0x22,
+    HwRevision = 0x23,
+    Settings = 0x24,
+    BootAnimation = 0x25,
+    Sequence = 0x26,
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum PatternVals {
+    Percentage = 0x00,
+    Gradient = 0x01,
+    DoubleGradient = 0x02,
+    DisplayLotus = 0x03,
+    ZigZag = 0x04,
+    FullBrightness = 0x05,
+    DisplayPanic = 0x06,
+    DisplayLotus2 = 0x07,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Game {
+    Snake,
+    Pong(PongParam),
+    Tetris,
+    GameOfLife(GameOfLifeParam),
+}
+
+impl Game {
+    pub fn game_val(&self) -> GameVal {
+        match self {
+            Game::Snake => GameVal::Snake,
+            Game::Pong(_) => GameVal::Pong,
+            Game::Tetris => GameVal::Tetris,
+            Game::GameOfLife(_) => GameVal::GameOfLife,
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum GameVal {
+    Snake = 0,
+    Pong = 1,
+    Tetris = 2,
+    GameOfLife = 3,
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum GameControlArg {
+    Up = 0,
+    Down = 1,
+    Left = 2,
+    Right = 3,
+    Exit = 4,
+    SecondLeft = 5,
+    SecondRight = 6,
+}
+
+/// What to do with the settings persisted in flash
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum SettingsArg {
+    /// Persist the current settings
+    Save = 0,
+    /// Apply the persisted settings, like at boot
+    Load = 1,
+    /// Erase the persisted settings, defaults apply after the next boot
+    FactoryReset = 2,
+}
+
+/// Step of uploading a boot animation to flash
+///
+/// Frames are staged with StageGreyCol, like for DrawGreyColBuffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BootAnimationArg {
+    /// Erase the stored animation and start uploading a new one
+    Begin,
+    /// Append the staged columns as a frame, shown for the given milliseconds
+    AddFrame(u16),
+    /// Finish the upload, the animation is played from the next boot on
+    Commit,
+    /// Erase the stored animation, the built-in ones are played again
+    Clear,
+}
+
+impl BootAnimationArg {
+    pub fn boot_animation_val(&self) -> BootAnimationVal {
+        match self {
+            BootAnimationArg::Begin => BootAnimationVal::Begin,
+            BootAnimationArg::AddFrame(_) => BootAnimationVal::AddFrame,
+            BootAnimationArg::Commit => BootAnimationVal::Commit,
+            BootAnimationArg::Clear => BootAnimationVal::Clear,
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum BootAnimationVal {
+    Begin = 0,
+    AddFrame = 1,
+    Commit = 2,
+    Clear = 3,
+}
+
+/// Step of uploading a frame sequence to RAM and playing it
+///
+/// Frames are staged with StageGreyCol, like for DrawGreyColBuffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SequenceArg {
+    /// Forget the previously uploaded frames
+    Begin,
+    /// Append the staged columns as a frame, shown for the given milliseconds
+    AddFrame(u16),
+    /// Play the uploaded frames the given number of times, 0 loops forever
+    Play(u8),
+}
+
+impl SequenceArg {
+    pub fn sequence_val(&self) -> SequenceVal {
+        match self {
+            SequenceArg::Begin => SequenceVal::Begin,
+            SequenceArg::AddFrame(_) => SequenceVal::AddFrame,
+            SequenceArg::Play(_) => SequenceVal::Play,
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum SequenceVal {
+    Begin = 0,
+    AddFrame = 1,
+    Play = 2,
+}
+
+/// Who controls the paddles in Pong
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum PongMode {
+    /// Left/Right move the upper paddle, the computer plays the lower one
+    #[default]
+    PlayerVsAi = 0x00,
+    /// SecondLeft/SecondRight move the lower paddle
+    PlayerVsPlayer = 0x01,
+    /// The computer plays both paddles
+    AiVsAi = 0x02,
+}
+
+/// How well the computer plays Pong
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum PongDifficulty {
+    Easy = 0x00,
+    #[default]
+    Medium = 0x01,
+    Hard = 0x02,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct PongParam {
+    pub mode: PongMode,
+    pub difficulty: PongDifficulty,
+    /// The first player with this many points wins
+    pub winning_score: u8,
+}
+
+impl Default for PongParam {
+    fn default() -> Self {
+        Self {
+            mode: PongMode::default(),
+            difficulty: PongDifficulty::default(),
+            winning_score: 5,
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum GameOfLifeStartParam {
+    CurrentMatrix = 0x00,
+    Pattern1 = 0x01,
+    Blinker = 0x02,
+    Toad = 0x03,
+    Beacon = 0x04,
+    Glider = 0x05,
+    BeaconToadBlinker = 0x06,
+    /// Random cells, from GameOfLifeParam::seed and GameOfLifeParam::density
+    Soup = 0x07,
+}
+
+/// Life-like rule, by the number of live neighbours
+///
+/// Bit n of `birth` means that a dead cell with n live neighbours comes
+/// alive, bit n of `survival` that a live cell with n neighbours stays alive.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct LifeRule {
+    pub birth: u16,
+    pub survival: u16,
+}
+
+impl LifeRule {
+    /// Conway's Game of Life, B3/S23
+    pub const CONWAY: Self = Self {
+        birth: 1 << 3,
+        survival: 1 << 2 | 1 << 3,
+    };
+    /// Every neighbour count that a cell can have, 0 to 8
+    pub const MASK: u16 = 0x1FF;
+
+    /// Parse the B/S notation, like B36/S23 for HighLife or B2/S for Seeds
+    pub fn parse(rule: &str) -> Option<Self> {
+        let mut birth = None;
+        let mut survival = None;
+        for part in rule.split('/') {
+            let mut chars = part.trim().chars();
+            let counts = match chars.next()?.to_ascii_uppercase() {
+                'B' => &mut birth,
+                'S' => &mut survival,
+                _ => return None,
+            };
+            if counts.is_some() {
+                return None;
+            }
+            let mut mask = 0;
+            for c in chars {
+                let neighbours = c.to_digit(10).filter(|n| *n <= 8)?;
+                mask |= 1 << neighbours;
+            }
+            *counts = Some(mask);
+        }
+        Some(Self {
+            birth: birth?,
+            survival: survival?,
+        })
+    }
+
+    pub fn born(&self, neighbours: u8) -> bool {
+        self.birth & (1 << neighbours) != 0
+    }
+
+    pub fn survives(&self, neighbours: u8) -> bool {
+        self.survival & (1 << neighbours) != 0
+    }
+}
+
+impl Default for LifeRule {
+    fn default() -> Self {
+        Self::CONWAY
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct GameOfLifeParam {
+    pub start: GameOfLifeStartParam,
+    pub rule: LifeRule,
+    /// Cells at an edge are neighbours of the ones at the opposite edge
+    pub wrap: bool,
+    /// Start over with a new soup, once the cells stop changing or repeat
+    pub reseed: bool,
+    /// Of the random soups
+    pub seed: u32,
+    /// Percentage of the cells that are alive in a soup
+    pub density: u8,
+}
+
+impl From<GameOfLifeStartParam> for GameOfLifeParam {
+    /// Conway's rules on a wrapping board, like it always used to be
+    fn from(start: GameOfLifeStartParam) -> Self {
+        Self {
+            start,
+            rule: LifeRule::CONWAY,
+            wrap: true,
+            reseed: false,
+            seed: 0,
+            density: 35,
+        }
+    }
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum DisplayMode {
+    /// Low Power Mode
+    Lpm = 0x00,
+    /// High Power Mode
+    Hpm = 0x01,
+}
+
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum PwmFreqArg {
+    /// 29kHz
+    P29k = 0x00,
+    /// 3.6kHz
+    P3k6 = 0x01,
+    /// 1.8kHz
+    P1k8 = 0x02,
+    /// 900Hz
+    P900 = 0x03,
+}
+
+impl PwmFreqArg {
+    pub fn from_hz(hz: u16) -> Option<Self> {
+        match hz {
+            29000 => Some(Self::P29k),
+            3600 => Some(Self::P3k6),
+            1800 => Some(Self::P1k8),
+            900 => Some(Self::P900),
+            _ => None,
+        }
+    }
+
+    pub fn hz(&self) -> u16 {
+        match self {
+            Self::P29k => 29000,
+            Self::P3k6 => 3600,
+            Self::P1k8 => 1800,
+            Self::P900 => 900,
+        }
+    }
+}
+
+/// Type of input module, same as the low byte of its USB PID
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum ModuleType {
+    LedMatrix = 0x20,
+    B1Display = 0x21,
+    C1Minimal = 0x22,
+}
+
+/// 8-bit RGB color, as used by the C1 Minimal module
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub const fn new(r: u8, g: u8, b: u8) -> Self {
+        Self { r, g, b }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_life_rules() {
+        assert_eq!(LifeRule::parse("B3/S23"), Some(LifeRule::CONWAY));
+        assert_eq!(LifeRule::parse("s23/b3"), Some(LifeRule::CONWAY));
+        let high_life = LifeRule::parse("B36/S23").unwrap();
+        assert!(high_life.born(6) && !high_life.born(2) && high_life.survives(2));
+        // Seeds, nothing survives
+        assert_eq!(
+            LifeRule::parse("B2/S"),
+            Some(LifeRule {
+                birth: 1 << 2,
+                survival: 0
+            })
+        );
+        assert_eq!(
+            LifeRule::parse("B012345678/S012345678"),
+            Some(LifeRule {
+                birth: LifeRule::MASK,
+                survival: LifeRule::MASK
+            })
+        );
+
+        for invalid in [
+            "",
+            "B3",
+            "B3/S23/S1",
+            "B3/B23",
+            "B9/S23",
+            "X3/S23",
+            "B3/S2x",
+        ] {
+            assert_eq!(LifeRule::parse(invalid), None, "{invalid}");
+        }
+    }
+}
@@ -0,0 +1,500 @@
+//! Layout of the responses to getter commands
+//!
+//! Every response is a fixed size buffer. Values that don't fill it are
+//! padded with zeros.
+
+use crate::{CommandVals, FromPrimitive, GameVal, ModuleType, ParseError, Rgb, MAGIC};
+
+/// Size of every response
+pub const RESPONSE_SIZE: usize = 32;
+
+pub type Response = [u8; RESPONSE_SIZE];
+
+/// Response to commands returning a bool, like IsSleeping or GetAnimate
+pub fn encode_bool(val: bool) -> Response {
+    encode_u8(val as u8)
+}
+
+pub fn decode_bool(response: &Response) -> bool {
+    response[0] == 1
+}
+
+/// Response to commands returning a single byte, like GetBrightness
+pub fn encode_u8(val: u8) -> Response {
+    let mut response: Response = [0; RESPONSE_SIZE];
+    response[0] = val;
+    response
+}
+
+pub fn decode_u8(response: &Response) -> u8 {
+    response[0]
+}
+
+/// Response to commands returning a u16, like GetAnimationPeriod
+pub fn encode_u16(val: u16) -> Response {
+    let mut response: Response = [0; RESPONSE_SIZE];
+    response[0..2].copy_from_slice(&val.to_le_bytes());
+    response
+}
+
+pub fn decode_u16(response: &Response) -> u16 {
+    u16::from_le_bytes([response[0], response[1]])
+}
+
+impl Rgb {
+    pub fn to_response(&self) -> Response {
+        let mut response: Response = [0; RESPONSE_SIZE];
+        response[0] = self.r;
+        response[1] = self.g;
+        response[2] = self.b;
+        response
+    }
+
+    pub fn from_response(response: &Response) -> Self {
+        Self::new(response[0], response[1], response[2])
+    }
+}
+
+/// Firmware version, as returned by the Version command
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Version {
+    /// USB bcdDevice, 0xJJMN where JJ is major, M is minor and N is patch
+    pub bcd_device: u16,
+    pub pre_release: bool,
+}
+
+impl Version {
+    pub fn major(&self) -> u8 {
+        (self.bcd_device >> 8) as u8
+    }
+
+    pub fn minor(&self) -> u8 {
+        ((self.bcd_device & 0xF0) >> 4) as u8
+    }
+
+    pub fn patch(&self) -> u8 {
+        (self.bcd_device & 0x0F) as u8
+    }
+
+    pub fn to_response(&self) -> Response {
+        let mut response: Response = [0; RESPONSE_SIZE];
+        response[0..2].copy_from_slice(&self.bcd_device.to_be_bytes());
+        response[2] = self.pre_release as u8;
+        response
+    }
+
+    pub fn from_response(response: &Response) -> Self {
+        Self {
+            bcd_device: u16::from_be_bytes([response[0], response[1]]),
+            pre_release: response[2] == 1,
+        }
+    }
+}
+
+/// Description of the module, as returned by the GetCapabilities command
+///
+/// Lets the host decide which commands to offer, without relying on the USB PID.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Capabilities {
+    pub module: ModuleType,
+    /// Hardware revision, 0 if unknown
+    pub hw_revision: u8,
+    pub version: Version,
+    /// Display width in pixels
+    pub width: u16,
+    /// Display height in pixels
+    pub height: u16,
+    /// Bits per pixel. 1 for black/white, 8 for greyscale and 24 for RGB
+    pub pixel_depth: u8,
+    /// Bit N is set if the command with ID N is supported
+    pub commands: u64,
+}
+
+impl Capabilities {
+    /// Bitmap of the given commands, for the `commands` field
+    pub const fn command_bitmap(commands: &[CommandVals]) -> u64 {
+        let mut bitmap = 0;
+        let mut i = 0;
+        while i < commands.len() {
+            bitmap |= 1 << commands[i] as u8;
+            i += 1;
+        }
+        bitmap
+    }
+
+    pub fn supports(&self, command: CommandVals) -> bool {
+        self.commands & (1 << command as u8) != 0
+    }
+
+    /// Layout: module type, hardware revision, version response (3 bytes),
+    /// width and height (u16 little endian), pixel depth, command bitmap
+    /// (u64 little endian)
+    pub fn to_response(&self) -> Response {
+        let mut response: Response = [0; RESPONSE_SIZE];
+        response[0] = self.module as u8;
+        response[1] = self.hw_revision;
+        response[2..5].copy_from_slice(&self.version.to_response()[..3]);
+        response[5..7].copy_from_slice(&self.width.to_le_bytes());
+        response[7..9].copy_from_slice(&self.height.to_le_bytes());
+        response[9] = self.pixel_depth;
+        response[10..18].copy_from_slice(&self.commands.to_le_bytes());
+        response
+    }
+
+    /// Decode a capabilities response. Returns None for unknown module types.
+    pub fn from_response(response: &Response) -> Option<Self> {
+        let mut version: Response = [0; RESPONSE_SIZE];
+        version[..3].copy_from_slice(&response[2..5]);
+        let mut commands = [0; 8];
+        commands.copy_from_slice(&response[10..18]);
+        Some(Self {
+            module: ModuleType::from_u8(response[0])?,
+            hw_revision: response[1],
+            version: Version::from_response(&version),
+            width: u16::from_le_bytes([response[5], response[6]]),
+            height: u16::from_le_bytes([response[7], response[8]]),
+            pixel_depth: response[9],
+            commands: u64::from_le_bytes(commands),
+        })
+    }
+}
+
+/// Game currently on the LED matrix, as returned by the GameStatus command
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct GameStatus {
+    /// None if no game is running
+    pub game: Option<GameVal>,
+    /// Whether the game has ended. It stays on the matrix, showing the score,
+    /// until it's exited.
+    pub game_over: bool,
+    /// Points, for Pong the ones of the upper player
+    pub score: u32,
+    /// Points of the lower player in Pong, 0 in other games
+    pub second_score: u16,
+    /// Level or speed of the game, for Game of Life how often it reseeded
+    pub level: u8,
+    /// Game steps since the game started
+    pub ticks: u32,
+}
+
+/// First byte of a GameStatus response when no game is running
+const NO_GAME: u8 = 0xFF;
+
+impl GameStatus {
+    /// Layout: game (0xFF if none), game over, score (u32 little endian),
+    /// second score (u16 little endian), level, ticks (u32 little endian)
+    pub fn to_response(&self) -> Response {
+        let mut response: Response = [0; RESPONSE_SIZE];
+        response[0] = self.game.map(|game| game as u8).unwrap_or(NO_GAME);
+        response[1] = self.game_over as u8;
+        response[2..6].copy_from_slice(&self.score.to_le_bytes());
+        response[6..8].copy_from_slice(&self.second_score.to_le_bytes());
+        response[8] = self.level;
+        response[9..13].copy_from_slice(&self.ticks.to_le_bytes());
+        response
+    }
+
+    pub fn from_response(response: &Response) -> Self {
+        let u32_at = |i: usize| {
+            u32::from_le_bytes([
+                response[i],
+                response[i + 1],
+                response[i + 2],
+                response[i + 3],
+            ])
+        };
+        Self {
+            game: GameVal::from_u8(response[0]),
+            game_over: response[1] == 1,
+            score: u32_at(2),
+            second_score: u16::from_le_bytes([response[6], response[7]]),
+            level: response[8],
+            ticks: u32_at(9),
+        }
+    }
+}
+
+/// Third byte of an error response, after the magic bytes.
+///
+/// Getter responses can start the same way, like the one of GetColor for
+/// Rgb(0x32, 0xAC, 0xFF). Without ack mode an error is sent instead of the
+/// response, so hosts can't tell them apart. In ack mode it's part of the ack.
+pub const ERROR_MARKER: u8 = 0xFF;
+
+impl ParseError {
+    /// Error response, sent instead of ignoring an invalid command
+    ///
+    /// Layout: magic, ERROR_MARKER, error kind, up to three detail bytes
+    pub fn to_response(&self) -> Response {
+        let mut response: Response = [0; RESPONSE_SIZE];
+        response[..2].copy_from_slice(&MAGIC);
+        response[2] = ERROR_MARKER;
+        // Unused detail bytes are 0
+        let details: [u8; 4] = match *self {
+            ParseError::TooShort => [0x01, 0, 0, 0],
+            ParseError::BadMagic => [0x02, 0, 0, 0],
+            ParseError::UnknownCommand(id) => [0x03, id, 0, 0],
+            ParseError::Unsupported(command) => [0x04, command as u8, 0, 0],
+            ParseError::PayloadLength {
+                command,
+                expected,
+                actual,
+            } => [0x05, command as u8, expected, actual],
+            ParseError::InvalidValue { command, value } => [0x06, command as u8, value, 0],
+            ParseError::InvalidText => [0x07, 0, 0, 0],
+            ParseError::Checksum => [0x08, 0, 0, 0],
+        };
+        response[3..7].copy_from_slice(&details);
+        response
+    }
+
+    /// Decode an error response. Returns None for regular responses.
+    pub fn from_response(response: &Response) -> Option<Self> {
+        if response[..2] != MAGIC || response[2] != ERROR_MARKER {
+            return None;
+        }
+        let command = || CommandVals::from_u8(response[4]);
+        Some(match response[3] {
+            0x01 => ParseError::TooShort,
+            0x02 => ParseError::BadMagic,
+            0x03 => ParseError::UnknownCommand(response[4]),
+            0x04 => ParseError::Unsupported(command()?),
+            0x05 => ParseError::PayloadLength {
+                command: command()?,
+                expected: response[5],
+                actual: response[6],
+            },
+            0x06 => ParseError::InvalidValue {
+                command: command()?,
+                value: response[5],
+            },
+            0x07 => ParseError::InvalidText,
+            0x08 => ParseError::Checksum,
+            _ => return None,
+        })
+    }
+}
+
+/// Third byte of an ack response, after the magic bytes
+///
+/// Getter responses can start the same way too. Acks are told apart by where
+/// they are, they always come right after the command and before its response.
+pub const ACK_MARKER: u8 = 0xFE;
+
+/// Outcome of a command, as reported in ack mode
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, num_derive::FromPrimitive)]
+pub enum AckStatus {
+    Ok = 0x00,
+    /// Unknown command or not supported on this module
+    Unsupported = 0x01,
+    /// Parameters are missing or invalid
+    BadArgs = 0x02,
+    /// Module is sleeping and ignored the command
+    Sleeping = 0x03,
+}
+
+impl From<ParseError> for AckStatus {
+    fn from(err: ParseError) -> Self {
+        match err {
+            ParseError::UnknownCommand(_) | ParseError::Unsupported(_) => AckStatus::Unsupported,
+            _ => AckStatus::BadArgs,
+        }
+    }
+}
+
+/// Response sent for every command, once ack mode is enabled
+///
+/// It's sent before the regular response of getter commands.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Ack {
+    /// Sequence number of the command that this acknowledges
+    pub seq: u8,
+    pub status: AckStatus,
+    /// Why the command was rejected, if it couldn't be parsed
+    pub error: Option<ParseError>,
+}
+
+impl Ack {
+    pub fn ok(seq: u8) -> Self {
+        Self {
+            seq,
+            status: AckStatus::Ok,
+            error: None,
+        }
+    }
+
+    pub fn from_error(seq: u8, err: ParseError) -> Self {
+        Self {
+            seq,
+            status: err.into(),
+            error: Some(err),
+        }
+    }
+
+    /// Layout: magic, ACK_MARKER, sequence number, status,
+    /// followed by the error response details, if any
+    pub fn to_response(&self) -> Response {
+        let mut response: Response = [0; RESPONSE_SIZE];
+        response[..2].copy_from_slice(&MAGIC);
+        response[2] = ACK_MARKER;
+        response[3] = self.seq;
+        response[4] = self.status as u8;
+        if let Some(err) = self.error {
+            response[5..].copy_from_slice(&err.to_response()[3..RESPONSE_SIZE - 2]);
+        }
+        response
+    }
+
+    /// Decode an ack response. Returns None for anything else.
+    pub fn from_response(response: &Response) -> Option<Self> {
+        if response[..2] != MAGIC || response[2] != ACK_MARKER {
+            return None;
+        }
+        let mut error_response: Response = [0; RESPONSE_SIZE];
+        error_response[..2].copy_from_slice(&MAGIC);
+        error_response[2] = ERROR_MARKER;
+        error_response[3..RESPONSE_SIZE - 2].copy_from_slice(&response[5..]);
+        Some(Self {
+            seq: response[3],
+            status: AckStatus::from_u8(response[4])?,
+            error: ParseError::from_response(&error_response),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_responses() {
+        assert!(decode_bool(&encode_bool(true)));
+        assert!(!decode_bool(&encode_bool(false)));
+        assert_eq!(decode_u8(&encode_u8(0xAB)), 0xAB);
+        assert_eq!(decode_u16(&encode_u16(31_250)), 31_250);
+
+        let color = Rgb::new(1, 2, 3);
+        assert_eq!(Rgb::from_response(&color.to_response()), color);
+    }
+
+    #[test]
+    fn version_layout() {
+        let version = Version {
+            bcd_device: 0x0123,
+            pre_release: true,
+        };
+        let response = version.to_response();
+        assert_eq!(&response[..3], &[0x01, 0x23, 0x01]);
+        let version = Version::from_response(&response);
+        assert_eq!(
+            (version.major(), version.minor(), version.patch()),
+            (1, 2, 3)
+        );
+        assert!(version.pre_release);
+    }
+
+    #[test]
+    fn capabilities_layout() {
+        let caps = Capabilities {
+            module: ModuleType::B1Display,
+            hw_revision: 2,
+            version: Version {
+                bcd_device: 0x0123,
+                pre_release: false,
+            },
+            width: 300,
+            height: 400,
+            pixel_depth: 1,
+            commands: Capabilities::command_bitmap(&[CommandVals::Version, CommandVals::SetText]),
+        };
+        let response = caps.to_response();
+        assert_eq!(
+            &response[..10],
+            &[0x21, 2, 0x01, 0x23, 0x00, 0x2C, 0x01, 0x90, 0x01, 1]
+        );
+        assert_eq!(Capabilities::from_response(&response), Some(caps));
+        assert!(caps.supports(CommandVals::SetText));
+        assert!(!caps.supports(CommandVals::Draw));
+        assert_eq!(Capabilities::from_response(&encode_u8(0)), None);
+    }
+
+    #[test]
+    fn game_status_layout() {
+        let status = GameStatus {
+            game: Some(GameVal::Pong),
+            game_over: true,
+            score: 0x0102_0304,
+            second_score: 7,
+            level: 3,
+            ticks: 500,
+        };
+        let response = status.to_response();
+        assert_eq!(
+            &response[..13],
+            &[0x01, 1, 0x04, 0x03, 0x02, 0x01, 7, 0, 3, 0xF4, 0x01, 0, 0]
+        );
+        assert_eq!(GameStatus::from_response(&response), status);
+
+        let none = GameStatus::default();
+        assert_eq!(none.to_response()[0], 0xFF);
+        assert_eq!(GameStatus::from_response(&none.to_response()), none);
+    }
+
+    #[test]
+    fn error_responses() {
+        let errors = [
+            ParseError::TooShort,
+            ParseError::BadMagic,
+            ParseError::UnknownCommand(0x42),
+            ParseError::Unsupported(CommandVals::SetText),
+            ParseError::PayloadLength {
+                command: CommandVals::Draw,
+                expected: 39,
+                actual: 3,
+            },
+            ParseError::InvalidValue {
+                command: CommandVals::Pattern,
+                value: 0x10,
+            },
+            ParseError::InvalidText,
+            ParseError::Checksum,
+        ];
+        for err in errors {
+            assert_eq!(ParseError::from_response(&err.to_response()), Some(err));
+        }
+        assert_eq!(ParseError::from_response(&encode_bool(true)), None);
+    }
+
+    #[test]
+    fn ack_responses() {
+        let acks = [
+            Ack::ok(0),
+            Ack::ok(0xFF),
+            Ack {
+                seq: 3,
+                status: AckStatus::Sleeping,
+                error: None,
+            },
+            Ack::from_error(4, ParseError::UnknownCommand(0x42)),
+            Ack::from_error(
+                5,
+                ParseError::PayloadLength {
+                    command: CommandVals::SetColor,
+                    expected: 3,
+                    actual: 1,
+                },
+            ),
+        ];
+        for ack in acks {
+            assert_eq!(Ack::from_response(&ack.to_response()), Some(ack));
+        }
+        assert_eq!(acks[3].status, AckStatus::Unsupported);
+        assert_eq!(acks[4].status, AckStatus::BadArgs);
+        assert_eq!(Ack::from_response(&encode_u8(0)), None);
+        assert_eq!(
+            Ack::from_response(&ParseError::TooShort.to_response()),
+            None
+        );
+    }
+}
@@ -0,0 +1,59 @@
+use crate::SECTOR_SIZE;
+
+/// NOR flash region reserved for the store
+///
+/// Offsets are relative to the start of the region.
+pub trait Flash {
+    /// Size of the region in bytes, a multiple of SECTOR_SIZE
+    fn size(&self) -> usize;
+
+    fn read(&self, offset: usize, buf: &mut [u8]);
+
+    /// Set all bytes of the sector starting at offset to 0xFF
+    fn erase_sector(&mut self, offset: usize);
+
+    /// Program the bytes. Like on real flash, bits can only be cleared, not set.
+    fn write(&mut self, offset: usize, data: &[u8]);
+}
+
+/// Flash stand-in in RAM, to test the store on the host
+pub struct MemFlash<const SECTORS: usize> {
+    pub data: [[u8; SECTOR_SIZE]; SECTORS],
+    /// How often each sector was erased
+    pub erase_counts: [u32; SECTORS],
+}
+
+impl<const SECTORS: usize> Default for MemFlash<SECTORS> {
+    fn default() -> Self {
+        Self {
+            data: [[0xFF; SECTOR_SIZE]; SECTORS],
+            erase_counts: [0; SECTORS],
+        }
+    }
+}
+
+impl<const SECTORS: usize> Flash for MemFlash<SECTORS> {
+    fn size(&self) -> usize {
+        SECTORS * SECTOR_SIZE
+    }
+
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let pos = offset + i;
+            *byte = self.data[pos / SECTOR_SIZE][pos % SECTOR_SIZE];
+        }
+    }
+
+    fn erase_sector(&mut self, offset: usize) {
+        assert_eq!(offset % SECTOR_SIZE, 0);
+        self.data[offset / SECTOR_SIZE] = [0xFF; SECTOR_SIZE];
+        self.erase_counts[offset / SECTOR_SIZE] += 1;
+    }
+
+    fn write(&mut self, offset: usize, data: &[u8]) {
+        for (i, byte) in data.iter().enumerate() {
+            let pos = offset + i;
+            self.data[pos / SECTOR_SIZE][pos % SECTOR_SIZE] &= byte;
+        }
+    }
+}
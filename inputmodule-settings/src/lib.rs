@@ -0,0 +1,44 @@
+//! Wear-levelled key/value store for settings that persist across power cycles
+//!
+//! The store doesn't know about the hardware. The firmware provides access to
+//! the reserved flash region by implementing `Flash`. Tests use `MemFlash`.
+//!
+//! The region is split into sectors, only one of them is active at a time.
+//! Values are appended to it as records, the latest record of a key wins.
+//! Once the active sector is full, the latest values are copied into the next
+//! one. That way every sector is erased equally often.
+//!
+//! Sector layout:
+//!
+//! ```text
+//! Byte 0-1: SECTOR_MAGIC
+//! Byte 2-3: Sequence number, little endian. The highest one is active
+//! Byte 4-5: CRC-16 of byte 0-3, little endian
+//! Byte 6-7: Unused
+//! Byte 8-:  Records
+//! ```
+//!
+//! Record layout:
+//!
+//! ```text
+//! Byte 0:  Key, 0xFF marks the end of the records
+//! Byte 1:  Value length N
+//! Byte 2-: Value
+//! Last 2:  CRC-16 of key, length and value, little endian
+//! ```
+#![no_std]
+
+mod flash;
+mod store;
+
+pub use flash::{Flash, MemFlash};
+pub use store::{Store, StoreError};
+
+/// Smallest unit of flash that can be erased
+pub const SECTOR_SIZE: usize = 4096;
+
+/// Longest value that can be stored under a single key
+pub const MAX_VALUE_LEN: usize = 32;
+
+/// Magic bytes at the start of every sector in use
+pub const SECTOR_MAGIC: [u8; 2] = [0x32, 0x5E];
@@ -0,0 +1,414 @@
+use crate::{Flash, MAX_VALUE_LEN, SECTOR_MAGIC, SECTOR_SIZE};
+
+const HEADER_LEN: usize = 8;
+
+/// Key, length and checksum
+const RECORD_OVERHEAD: usize = 1 + 1 + 2;
+
+/// Value of erased flash, marks the end of the records
+const ERASED: u8 = 0xFF;
+
+const CRC: crc::Crc<u16> = crc::Crc::<u16>::new(&crc::CRC_16_IBM_3740);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum StoreError {
+    /// Key 0xFF is reserved or the value is longer than MAX_VALUE_LEN
+    InvalidRecord,
+    /// The latest values of all keys don't fit into a single sector
+    Full,
+    /// Flash didn't contain the written data afterwards
+    WriteFailed,
+}
+
+/// Key/value store on top of a flash region, see the crate documentation
+pub struct Store<F: Flash> {
+    flash: F,
+    /// Index and sequence number of the active sector, None if nothing is stored
+    active: Option<(usize, u16)>,
+    /// Offset of the next record in the active sector
+    write_pos: usize,
+    /// The last record was only partially written, for example due to power
+    /// loss. Can't append anymore, the next write has to move to a new sector.
+    torn: bool,
+}
+
+fn record_crc(key: u8, value: &[u8]) -> u16 {
+    let mut digest = CRC.digest();
+    digest.update(&[key, value.len() as u8]);
+    digest.update(value);
+    digest.finalize()
+}
+
+impl<F: Flash> Store<F> {
+    /// Find the active sector. Needs at least two sectors for wear levelling.
+    pub fn new(flash: F) -> Self {
+        assert!(flash.size() >= 2 * SECTOR_SIZE);
+        let mut store = Self {
+            flash,
+            active: None,
+            write_pos: HEADER_LEN,
+            torn: false,
+        };
+        for sector in 0..store.sectors() {
+            let Some(seq) = store.read_header(sector) else {
+                continue;
+            };
+            let newer = match store.active {
+                // Sequence numbers wrap around
+                Some((_, active_seq)) => (seq.wrapping_sub(active_seq) as i16) > 0,
+                None => true,
+            };
+            if newer {
+                store.active = Some((sector, seq));
+            }
+        }
+        if let Some((sector, _)) = store.active {
+            (store.write_pos, store.torn) = store.scan(sector, |_, _| {});
+        }
+        store
+    }
+
+    /// Give back the flash, for example to remount it
+    pub fn into_inner(self) -> F {
+        self.flash
+    }
+
+    /// Latest value of the key
+    ///
+    /// Returns its length or None, if the key isn't stored or the value
+    /// doesn't fit into the buffer.
+    pub fn get(&self, key: u8, buf: &mut [u8]) -> Option<usize> {
+        let (sector, _) = self.active?;
+        let mut found = None;
+        self.scan(sector, |record_key, value| {
+            if record_key == key {
+                found = buf.get_mut(..value.len()).map(|dest| {
+                    dest.copy_from_slice(value);
+                    value.len()
+                });
+            }
+        });
+        found
+    }
+
+    /// Store a value, unless it's the same as the stored one
+    pub fn set(&mut self, key: u8, value: &[u8]) -> Result<(), StoreError> {
+        if key == ERASED || value.len() > MAX_VALUE_LEN {
+            return Err(StoreError::InvalidRecord);
+        }
+        let mut current = [0; MAX_VALUE_LEN];
+        if self.get(key, &mut current).map(|len| &current[..len]) == Some(value) {
+            // Save a write
+            return Ok(());
+        }
+
+        let size = value.len() + RECORD_OVERHEAD;
+        if let Some((sector, _)) = self.active {
+            if !self.torn && self.write_pos + size <= SECTOR_SIZE {
+                if self.write_record(sector, self.write_pos, key, value) {
+                    self.write_pos += size;
+                    return Ok(());
+                }
+                self.torn = true;
+            }
+        }
+        self.compact(key, value)
+    }
+
+    /// Erase all values, like after flashing the firmware for the first time
+    pub fn clear(&mut self) {
+        for sector in 0..self.sectors() {
+            self.flash.erase_sector(sector * SECTOR_SIZE);
+        }
+        self.active = None;
+        self.write_pos = HEADER_LEN;
+        self.torn = false;
+    }
+
+    fn sectors(&self) -> usize {
+        self.flash.size() / SECTOR_SIZE
+    }
+
+    /// Sequence number of the sector, if it's in use
+    fn read_header(&self, sector: usize) -> Option<u16> {
+        let mut header = [0; 6];
+        self.flash.read(sector * SECTOR_SIZE, &mut header);
+        let crc = u16::from_le_bytes([header[4], header[5]]);
+        if header[..2] != SECTOR_MAGIC || crc != CRC.checksum(&header[..4]) {
+            return None;
+        }
+        Some(u16::from_le_bytes([header[2], header[3]]))
+    }
+
+    fn write_header(&mut self, sector: usize, seq: u16) -> bool {
+        let mut header = [0; 6];
+        header[..2].copy_from_slice(&SECTOR_MAGIC);
+        header[2..4].copy_from_slice(&seq.to_le_bytes());
+        let crc = CRC.checksum(&header[..4]);
+        header[4..].copy_from_slice(&crc.to_le_bytes());
+        self.flash.write(sector * SECTOR_SIZE, &header);
+        self.read_header(sector) == Some(seq)
+    }
+
+    /// Call `f` with every valid record of the sector, oldest first
+    ///
+    /// Returns the offset after the last record and whether there's a torn
+    /// record after it.
+    fn scan(&self, sector: usize, mut f: impl FnMut(u8, &[u8])) -> (usize, bool) {
+        let base = sector * SECTOR_SIZE;
+        let mut buf = [0; MAX_VALUE_LEN + RECORD_OVERHEAD];
+        let mut pos = HEADER_LEN;
+        while pos + RECORD_OVERHEAD <= SECTOR_SIZE {
+            self.flash.read(base + pos, &mut buf[..2]);
+            let (key, len) = (buf[0], buf[1] as usize);
+            if key == ERASED {
+                return (pos, buf[1] != ERASED);
+            }
+            let size = len + RECORD_OVERHEAD;
+            if len > MAX_VALUE_LEN || pos + size > SECTOR_SIZE {
+                return (pos, true);
+            }
+            self.flash.read(base + pos, &mut buf[..size]);
+            let (value, crc) = buf[2..size].split_at(len);
+            if u16::from_le_bytes([crc[0], crc[1]]) != record_crc(key, value) {
+                return (pos, true);
+            }
+            f(key, value);
+            pos += size;
+        }
+        (pos, false)
+    }
+
+    /// Returns whether it was written correctly
+    fn write_record(&mut self, sector: usize, pos: usize, key: u8, value: &[u8]) -> bool {
+        let size = value.len() + RECORD_OVERHEAD;
+        let mut record = [0; MAX_VALUE_LEN + RECORD_OVERHEAD];
+        record[0] = key;
+        record[1] = value.len() as u8;
+        record[2..2 + value.len()].copy_from_slice(value);
+        record[2 + value.len()..size].copy_from_slice(&record_crc(key, value).to_le_bytes());
+
+        let offset = sector * SECTOR_SIZE + pos;
+        self.flash.write(offset, &record[..size]);
+        let mut written = [0; MAX_VALUE_LEN + RECORD_OVERHEAD];
+        self.flash.read(offset, &mut written[..size]);
+        written[..size] == record[..size]
+    }
+
+    /// Copy the latest values to the next sector, together with a new value
+    fn compact(&mut self, key: u8, value: &[u8]) -> Result<(), StoreError> {
+        let mut stored = [false; ERASED as usize];
+        let (next, seq) = match self.active {
+            Some((sector, seq)) => {
+                self.scan(sector, |record_key, _| stored[record_key as usize] = true);
+                ((sector + 1) % self.sectors(), seq.wrapping_add(1))
+            }
+            None => (0, 0),
+        };
+        stored[key as usize] = false;
+
+        // Make sure everything fits, before erasing anything
+        let mut buf = [0; MAX_VALUE_LEN];
+        let mut size = HEADER_LEN + value.len() + RECORD_OVERHEAD;
+        for other in 0..ERASED {
+            if stored[other as usize] {
+                size += self.get(other, &mut buf).unwrap_or(0) + RECORD_OVERHEAD;
+            }
+        }
+        if size > SECTOR_SIZE {
+            return Err(StoreError::Full);
+        }
+
+        self.flash.erase_sector(next * SECTOR_SIZE);
+        let mut pos = HEADER_LEN;
+        for other in 0..ERASED {
+            if !stored[other as usize] {
+                continue;
+            }
+            let len = self.get(other, &mut buf).unwrap_or(0);
+            if !self.write_record(next, pos, other, &buf[..len]) {
+                return Err(StoreError::WriteFailed);
+            }
+            pos += len + RECORD_OVERHEAD;
+        }
+        if !self.write_record(next, pos, key, value) {
+            return Err(StoreError::WriteFailed);
+        }
+        pos += value.len() + RECORD_OVERHEAD;
+
+        // Only now the new sector becomes active. If power is lost before,
+        // the old sector still has all previous values.
+        if !self.write_header(next, seq) {
+            return Err(StoreError::WriteFailed);
+        }
+        self.active = Some((next, seq));
+        self.write_pos = pos;
+        self.torn = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MemFlash;
+
+    type TestStore = Store<MemFlash<4>>;
+
+    fn get(store: &TestStore, key: u8) -> Option<[u8; 2]> {
+        let mut buf = [0; MAX_VALUE_LEN];
+        let len = store.get(key, &mut buf)?;
+        assert_eq!(len, 2);
+        Some([buf[0], buf[1]])
+    }
+
+    fn remount(store: TestStore) -> TestStore {
+        Store::new(store.into_inner())
+    }
+
+    #[test]
+    fn set_and_get() {
+        let mut store = TestStore::new(MemFlash::default());
+        assert_eq!(get(&store, 1), None);
+
+        store.set(1, &[1, 2]).unwrap();
+        store.set(2, &[3, 4]).unwrap();
+        store.set(1, &[5, 6]).unwrap();
+        assert_eq!(get(&store, 1), Some([5, 6]));
+        assert_eq!(get(&store, 2), Some([3, 4]));
+
+        let store = remount(store);
+        assert_eq!(get(&store, 1), Some([5, 6]));
+        assert_eq!(get(&store, 2), Some([3, 4]));
+        assert_eq!(get(&store, 3), None);
+    }
+
+    #[test]
+    fn skip_unchanged_values() {
+        let mut store = TestStore::new(MemFlash::default());
+        store.set(1, &[1, 2]).unwrap();
+        let write_pos = store.write_pos;
+        store.set(1, &[1, 2]).unwrap();
+        assert_eq!(store.write_pos, write_pos);
+    }
+
+    #[test]
+    fn reject_invalid_records() {
+        let mut store = TestStore::new(MemFlash::default());
+        assert_eq!(store.set(ERASED, &[0]), Err(StoreError::InvalidRecord));
+        assert_eq!(
+            store.set(1, &[0; MAX_VALUE_LEN + 1]),
+            Err(StoreError::InvalidRecord)
+        );
+        let mut buf = [0; 1];
+        store.set(1, &[1, 2]).unwrap();
+        assert_eq!(store.get(1, &mut buf), None);
+    }
+
+    #[test]
+    fn wear_levelling() {
+        let mut store = TestStore::new(MemFlash::default());
+        store.set(1, &[0xAA, 0xBB]).unwrap();
+        for i in 0..10_000u16 {
+            store.set(2, &i.to_le_bytes()).unwrap();
+        }
+        assert_eq!(get(&store, 1), Some([0xAA, 0xBB]));
+        assert_eq!(get(&store, 2), Some(9_999u16.to_le_bytes()));
+
+        let store = remount(store);
+        assert_eq!(get(&store, 1), Some([0xAA, 0xBB]));
+        assert_eq!(get(&store, 2), Some(9_999u16.to_le_bytes()));
+
+        let erase_counts = store.flash.erase_counts;
+        let min = erase_counts.iter().min().unwrap();
+        let max = erase_counts.iter().max().unwrap();
+        assert!(*min > 0);
+        assert!(max - min <= 1, "{:?}", erase_counts);
+    }
+
+    #[test]
+    fn torn_record() {
+        let mut store = TestStore::new(MemFlash::default());
+        store.set(1, &[1, 2]).unwrap();
+        let (sector, _) = store.active.unwrap();
+        let pos = store.write_pos;
+
+        // Power lost in the middle of writing a record, checksum is missing
+        let mut flash = store.into_inner();
+        flash.write(sector * SECTOR_SIZE + pos, &[1, 2, 3, 4]);
+
+        let mut store = Store::new(flash);
+        assert_eq!(get(&store, 1), Some([1, 2]));
+        assert!(store.torn);
+
+        // Can't append anymore, so it moves to the next sector
+        store.set(1, &[5, 6]).unwrap();
+        assert_ne!(store.active.unwrap().0, sector);
+        let store = remount(store);
+        assert_eq!(get(&store, 1), Some([5, 6]));
+    }
+
+    #[test]
+    fn interrupted_compaction() {
+        let mut store = TestStore::new(MemFlash::default());
+        store.set(1, &[1, 2]).unwrap();
+        store.set(2, &[3, 4]).unwrap();
+        let (sector, seq) = store.active.unwrap();
+        store.compact(1, &[5, 6]).unwrap();
+
+        // Power lost before the new header was completely written
+        let (new_sector, _) = store.active.unwrap();
+        let mut flash = store.into_inner();
+        flash.data[new_sector][4] = 0;
+
+        let store = Store::new(flash);
+        assert_eq!(store.active, Some((sector, seq)));
+        assert_eq!(get(&store, 1), Some([1, 2]));
+        assert_eq!(get(&store, 2), Some([3, 4]));
+    }
+
+    #[test]
+    fn sequence_number_wraps() {
+        // First sector is the last one before the sequence number wraps around
+        let mut store = TestStore::new(MemFlash::default());
+        assert!(store.write_header(0, u16::MAX));
+        store.active = Some((0, u16::MAX));
+        store.set(1, &[1, 2]).unwrap();
+        store.compact(1, &[3, 4]).unwrap();
+        assert_eq!(store.active, Some((1, 0)));
+
+        let store = remount(store);
+        assert_eq!(store.active, Some((1, 0)));
+        assert_eq!(get(&store, 1), Some([3, 4]));
+    }
+
+    #[test]
+    fn full() {
+        let mut store = TestStore::new(MemFlash::default());
+        let mut result = Ok(());
+        for key in 0..ERASED {
+            result = store.set(key, &[key; MAX_VALUE_LEN]);
+            if result.is_err() {
+                break;
+            }
+        }
+        assert_eq!(result, Err(StoreError::Full));
+        // Everything else is still there
+        let mut buf = [0; MAX_VALUE_LEN];
+        assert_eq!(store.get(0, &mut buf), Some(MAX_VALUE_LEN));
+        assert_eq!(buf, [0; MAX_VALUE_LEN]);
+    }
+
+    #[test]
+    fn clear() {
+        let mut store = TestStore::new(MemFlash::default());
+        store.set(1, &[1, 2]).unwrap();
+        store.clear();
+        assert_eq!(get(&store, 1), None);
+
+        let mut store = remount(store);
+        assert_eq!(get(&store, 1), None);
+        store.set(2, &[3, 4]).unwrap();
+        assert_eq!(get(&store, 2), Some([3, 4]));
+    }
+}
@@ -0,0 +1,271 @@
+//! B1 Display, with the main loop of the firmware minus the hardware
+//!
+//! The display controller isn't simulated. Instead, everything that the
+//! firmware draws is drawn again into a framebuffer that can be rendered.
+use std::convert::Infallible;
+use std::time::{Duration, Instant};
+
+use embedded_graphics::pixelcolor::{Rgb565, Rgb888};
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::*;
+use fl16_inputmodules::control::*;
+use fl16_inputmodules::graphics::*;
+use fl16_inputmodules::settings::{self, SettingsStore};
+use fl16_inputmodules::sim::{Delay, SimPin, SimSpi};
+use image::{Rgb, RgbImage};
+use st7306::{FpsConfig, HpmFps, LpmFps, PowerMode, ST7306};
+
+use crate::SimModule;
+
+type SimST7306 = ST7306<SimSpi, SimPin, SimPin, SimPin, 25, 200>;
+
+const SCRNS_DELTA: i32 = 5;
+const WIDTH: i32 = 300;
+const HEIGHT: i32 = 400;
+const SIZE: Size = Size::new(WIDTH as u32, HEIGHT as u32);
+
+pub struct B1Display {
+    state: B1DIsplayState,
+    disp: SimST7306,
+    delay: Delay,
+    settings: SettingsStore,
+    logo_rect: Rectangle,
+    logo_pos: Point,
+    /// What's been drawn
+    framebuffer: Framebuffer,
+    /// What's on the display, the framebuffer as of the last flush
+    shown: Framebuffer,
+    animation_timer: Instant,
+}
+
+impl B1Display {
+    pub fn new() -> Self {
+        let mut state = B1DIsplayState {
+            sleeping: SimpleSleepState::Awake,
+            screen_inverted: false,
+            screen_on: true,
+            screensaver: Some(ScreenSaverState::default()),
+            power_mode: PowerMode::Lpm,
+            fps_config: FpsConfig {
+                hpm: HpmFps::ThirtyTwo,
+                lpm: LpmFps::Two,
+            },
+            animation_period: 1_000_000, // 1000ms = 1Hz
+        };
+        let mut delay = Delay;
+
+        const INVERTED: bool = false;
+        const AUTO_PWRDOWN: bool = true;
+        const TE_ENABLE: bool = true;
+        const COL_START: u16 = 0x12;
+        const ROW_START: u16 = 0x00;
+        let mut disp: SimST7306 = ST7306::new(
+            SimSpi,
+            SimPin::default(),
+            SimPin::default(),
+            SimPin::default(),
+            INVERTED,
+            AUTO_PWRDOWN,
+            TE_ENABLE,
+            state.fps_config,
+            WIDTH as u16,
+            HEIGHT as u16,
+            COL_START,
+            ROW_START,
+        );
+        disp.init(&mut delay).unwrap();
+
+        let mut framebuffer = Framebuffer::default();
+        let logo_pos = Point::new(LOGO_OFFSET_X, LOGO_OFFSET_Y);
+        let logo_rect = draw_logo(&mut framebuffer, logo_pos).unwrap();
+
+        let mut settings = SettingsStore::new(settings::flash());
+        handle_command(
+            &Command::Settings(SettingsArg::Load),
+            &mut state,
+            logo_rect,
+            &mut disp,
+            &mut delay,
+            &mut settings,
+        );
+
+        Self {
+            state,
+            disp,
+            delay,
+            settings,
+            logo_rect,
+            logo_pos,
+            shown: framebuffer.clone(),
+            framebuffer,
+            animation_timer: Instant::now(),
+        }
+    }
+
+    /// Same as the firmware, but without talking to the display controller
+    fn handle_sleep(&mut self, go_sleeping: bool) {
+        match (&self.state.sleeping, go_sleeping) {
+            (SimpleSleepState::Awake, true) => {
+                self.state.sleeping = SimpleSleepState::Sleeping;
+            }
+            (SimpleSleepState::Sleeping, false) => {
+                self.state.sleeping = SimpleSleepState::Awake;
+                // Turn screensaver on when resuming from sleep
+                self.state.screensaver = Some(ScreenSaverState::default());
+            }
+            _ => {}
+        }
+    }
+
+    /// Draw what handling the command drew on the display controller
+    fn mirror_command(&mut self, command: &Command) {
+        let text_pos = Point::new(
+            LOGO_OFFSET_X,
+            LOGO_OFFSET_Y + self.logo_rect.size.height as i32,
+        );
+        match command {
+            Command::SetText(text) => {
+                clear_text(&mut self.framebuffer, text_pos, Rgb565::WHITE).unwrap();
+                draw_text(&mut self.framebuffer, text, text_pos).unwrap();
+                self.shown = self.framebuffer.clone();
+            }
+            Command::SetPixelColumn(column, pixel_bytes) => {
+                let pixels = (0..HEIGHT).map(|y| {
+                    let black = pixel_bytes[y as usize / 8] & (1 << (y % 8)) != 0;
+                    let color = if black { Rgb565::BLACK } else { Rgb565::WHITE };
+                    Pixel(Point::new(*column as i32, y), color)
+                });
+                self.framebuffer.draw_iter(pixels).unwrap();
+            }
+            Command::FlushFramebuffer => self.shown = self.framebuffer.clone(),
+            // Clears the display right away
+            Command::ClearRam => {
+                self.framebuffer.clear(Rgb565::WHITE).unwrap();
+                self.shown = self.framebuffer.clone();
+            }
+            _ => {}
+        }
+    }
+}
+
+impl SimModule for B1Display {
+    fn handle_command(&mut self, command: &Command) -> (AckStatus, Option<Response>) {
+        match (command, &self.state.sleeping) {
+            (Command::Sleep(go_sleeping), _) => {
+                self.handle_sleep(*go_sleeping);
+                return (AckStatus::Ok, None);
+            }
+            (Command::BootloaderReset | Command::IsSleeping | Command::GetCapabilities, _) => {}
+            (_, SimpleSleepState::Awake) => {}
+            // While sleeping no command is handled, except waking up
+            (_, SimpleSleepState::Sleeping) => return (AckStatus::Sleeping, None),
+        }
+
+        let response = handle_command(
+            command,
+            &mut self.state,
+            self.logo_rect,
+            &mut self.disp,
+            &mut self.delay,
+            &mut self.settings,
+        );
+        self.mirror_command(command);
+        (AckStatus::Ok, response)
+    }
+
+    /// Bounce the logo around, if the screensaver is on
+    fn tick(&mut self) {
+        let animation_period = Duration::from_micros(self.state.animation_period);
+        if self.animation_timer.elapsed() < animation_period {
+            return;
+        }
+        self.animation_timer = Instant::now();
+        let Some(ref mut screensaver) = self.state.screensaver else {
+            return;
+        };
+
+        let (x, y) = (self.logo_pos.x, self.logo_pos.y);
+        let w = self.logo_rect.size.width as i32;
+        let h = self.logo_rect.size.height as i32;
+        // Bounce off the walls
+        if x <= 0 || x + w >= WIDTH {
+            screensaver.rightwards *= -1;
+        }
+        if y <= 0 || y + h >= HEIGHT {
+            screensaver.downwards *= -1;
+        }
+        self.logo_pos = Point::new(
+            x + screensaver.rightwards * SCRNS_DELTA,
+            y + screensaver.downwards * SCRNS_DELTA,
+        );
+
+        // Draw a border around the new logo, to clear previously drawn adjacent logos
+        let style = PrimitiveStyleBuilder::new()
+            .stroke_color(Rgb565::WHITE)
+            .stroke_width(2 * SCRNS_DELTA as u32)
+            .build();
+        Rectangle::new(
+            self.logo_pos - Point::new(SCRNS_DELTA, SCRNS_DELTA),
+            self.logo_rect.size + Size::new(2 * SCRNS_DELTA as u32, 2 * SCRNS_DELTA as u32),
+        )
+        .into_styled(style)
+        .draw(&mut self.framebuffer)
+        .unwrap();
+        draw_logo(&mut self.framebuffer, self.logo_pos).unwrap();
+        self.shown = self.framebuffer.clone();
+    }
+
+    /// One pixel per display pixel. Blank while the display is off.
+    fn render(&self) -> RgbImage {
+        let mut image = self.shown.0.clone();
+        if !self.state.screen_on {
+            image.fill(0xFF);
+        } else if self.state.screen_inverted {
+            image::imageops::invert(&mut image);
+        }
+        image
+    }
+}
+
+/// Display contents, that can be drawn on with embedded-graphics
+#[derive(Clone)]
+struct Framebuffer(RgbImage);
+
+impl Default for Framebuffer {
+    /// All white, like the firmware clears it at startup
+    fn default() -> Self {
+        Self(RgbImage::from_pixel(
+            SIZE.width,
+            SIZE.height,
+            Rgb([0xFF, 0xFF, 0xFF]),
+        ))
+    }
+}
+
+impl OriginDimensions for Framebuffer {
+    fn size(&self) -> Size {
+        SIZE
+    }
+}
+
+impl DrawTarget for Framebuffer {
+    type Color = Rgb565;
+    type Error = Infallible;
+
+    fn draw_iter<I>(&mut self, pixels: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Pixel<Self::Color>>,
+    {
+        for Pixel(point, color) in pixels {
+            let Ok((x, y)) = <(u32, u32)>::try_from(point) else {
+                continue;
+            };
+            if x < SIZE.width && y < SIZE.height {
+                let color = Rgb888::from(color);
+                self.0
+                    .put_pixel(x, y, Rgb([color.r(), color.g(), color.b()]));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,83 @@
+//! C1 Minimal, with the main loop of the firmware minus the hardware
+use fl16_inputmodules::control::*;
+use fl16_inputmodules::settings::{self, SettingsStore};
+use fl16_inputmodules::sim::SimLed;
+use image::{Rgb, RgbImage};
+use smart_leds::{colors, SmartLedsWrite};
+
+use crate::SimModule;
+
+pub struct C1Minimal {
+    state: C1MinimalState,
+    ws2812: SimLed,
+    settings: SettingsStore,
+}
+
+impl C1Minimal {
+    pub fn new() -> Self {
+        let mut state = C1MinimalState {
+            sleeping: SimpleSleepState::Awake,
+            color: colors::GREEN,
+            brightness: 10,
+        };
+        let mut ws2812 = SimLed::default();
+        let mut settings = SettingsStore::new(settings::flash());
+        handle_command(
+            &Command::Settings(SettingsArg::Load),
+            &mut state,
+            &mut ws2812,
+            &mut settings,
+        );
+
+        ws2812
+            .write(smart_leds::brightness(
+                [state.color].iter().cloned(),
+                state.brightness,
+            ))
+            .unwrap();
+
+        Self {
+            state,
+            ws2812,
+            settings,
+        }
+    }
+}
+
+impl SimModule for C1Minimal {
+    fn handle_command(&mut self, command: &Command) -> (AckStatus, Option<Response>) {
+        if let Command::Sleep(go_sleeping) = command {
+            self.state.sleeping = if *go_sleeping {
+                SimpleSleepState::Sleeping
+            } else {
+                SimpleSleepState::Awake
+            };
+            return (AckStatus::Ok, None);
+        }
+
+        // While sleeping no command is handled, except waking up
+        let awake = matches!(self.state.sleeping, SimpleSleepState::Awake);
+        if !awake && *command != Command::GetCapabilities {
+            return (AckStatus::Sleeping, None);
+        }
+        let response = handle_command(
+            command,
+            &mut self.state,
+            &mut self.ws2812,
+            &mut self.settings,
+        );
+        (AckStatus::Ok, response)
+    }
+
+    fn tick(&mut self) {}
+
+    /// A single pixel, in the color of the LED. Off while sleeping.
+    fn render(&self) -> RgbImage {
+        let mut image = RgbImage::new(1, 1);
+        if matches!(self.state.sleeping, SimpleSleepState::Awake) {
+            let color = self.ws2812.color;
+            image.put_pixel(0, 0, Rgb([color.r, color.g, color.b]));
+        }
+        image
+    }
+}
@@ -0,0 +1,115 @@
+//! LED Matrix, with the main loop of the firmware minus the hardware
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use fl16_inputmodules::animations::*;
+use fl16_inputmodules::boot_animation::BootAnimationIterator;
+use fl16_inputmodules::control::*;
+use fl16_inputmodules::fl16::{LedMatrix as Controller, EVT_CALC_PIXEL};
+use fl16_inputmodules::main_loop::MainLoop;
+use fl16_inputmodules::matrix::*;
+use fl16_inputmodules::patterns::*;
+use fl16_inputmodules::settings::{self, SettingsStore};
+use fl16_inputmodules::sim::{SimI2c, SimPin};
+use image::{Rgb, RgbImage};
+
+use crate::SimModule;
+
+pub struct LedMatrix {
+    state: LedmatrixState,
+    matrix: Foo,
+    settings: SettingsStore,
+    led_enable: SimPin,
+    random: Random,
+    main_loop: MainLoop,
+    /// Start of the timer, that the firmware has in hardware
+    start: Instant,
+}
+
+impl LedMatrix {
+    pub fn new() -> Self {
+        let mut state = LedmatrixState::default();
+        let mut matrix = Controller::new(SimI2c, EVT_CALC_PIXEL);
+        let mut settings = SettingsStore::new(settings::flash());
+        let mut random = Random::new();
+
+        let _ = handle_command(
+            &Command::Settings(SettingsArg::Load),
+            &mut state,
+            &mut matrix,
+            random.byte(),
+            &mut settings,
+        );
+        // Always the same built-in animation, so that the output is reproducible
+        state.upcoming_frames = Some(match BootAnimationIterator::load(state.animation_period) {
+            Some(boot_animation) => Animation::Boot(boot_animation),
+            None => Animation::Percentage(StartupPercentageIterator::default()),
+        });
+
+        Self {
+            state,
+            matrix,
+            settings,
+            led_enable: SimPin { high: true },
+            random,
+            main_loop: MainLoop::new(0),
+            start: Instant::now(),
+        }
+    }
+}
+
+impl SimModule for LedMatrix {
+    fn handle_command(&mut self, command: &Command) -> (AckStatus, Option<Response>) {
+        self.main_loop.handle_command(
+            command,
+            &mut self.state,
+            &mut self.matrix,
+            &mut self.led_enable,
+            self.random.byte(),
+            &mut self.settings,
+        )
+    }
+
+    fn tick(&mut self) {
+        let now = self.start.elapsed().as_micros() as u64;
+        self.main_loop.tick(
+            &mut self.state,
+            &mut self.matrix,
+            &mut self.led_enable,
+            now,
+            || self.random.byte(),
+        );
+    }
+
+    /// One pixel per LED, as bright as the LED
+    fn render(&self) -> RgbImage {
+        let mut image = RgbImage::new(WIDTH as u32, HEIGHT as u32);
+        if !self.led_enable.high {
+            return image;
+        }
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            let led = self.state.grid.0[x as usize][y as usize] as u64;
+            let brightness =
+                led * current_brightness(&self.state) as u64 / BRIGHTNESS_LEVELS as u64;
+            *pixel = Rgb([brightness as u8; 3]);
+        }
+        image
+    }
+}
+
+/// Random bytes for the games, instead of the ring oscillator
+struct Random(u32);
+
+impl Random {
+    fn new() -> Self {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        // Xorshift gets stuck at 0
+        Self(now.subsec_nanos() | 1)
+    }
+
+    fn byte(&mut self) -> u8 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0 as u8
+    }
+}
@@ -0,0 +1,127 @@
+//! Runs the firmware logic of an input module on the host
+//!
+//! The module shows up as a serial device, that inputmodule-control can talk
+//! to with `--serial-dev`. What's displayed is drawn in the terminal or saved
+//! as PNG.
+use std::fs;
+use std::os::unix::fs::symlink;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::Parser;
+use fl16_inputmodules::control::{AckStatus, Command, Response, Session};
+use fl16_inputmodules::framing::FrameDecoder;
+use image::RgbImage;
+
+mod pty;
+mod render;
+
+#[cfg(feature = "b1display")]
+mod b1display;
+#[cfg(feature = "c1minimal")]
+mod c1minimal;
+#[cfg(feature = "ledmatrix")]
+mod ledmatrix;
+
+#[cfg(feature = "b1display")]
+use b1display::B1Display as Module;
+#[cfg(feature = "c1minimal")]
+use c1minimal::C1Minimal as Module;
+#[cfg(feature = "ledmatrix")]
+use ledmatrix::LedMatrix as Module;
+
+use pty::{Event, VirtualSerial};
+use render::Output;
+
+/// How long to wait for commands, before animations are advanced
+const POLL_PERIOD: Duration = Duration::from_millis(5);
+
+/// Module, as the firmware's main loop sees it
+pub trait SimModule {
+    /// Handle a command that was received. Returns how to acknowledge it and
+    /// the response, if any.
+    fn handle_command(&mut self, command: &Command) -> (AckStatus, Option<Response>);
+
+    /// Advance animations and games, called regularly
+    fn tick(&mut self);
+
+    /// What's currently displayed
+    fn render(&self) -> RgbImage;
+}
+
+#[derive(Parser, Debug)]
+#[command(version)]
+struct Cli {
+    /// Create a symlink to the serial device, so that its path stays the same
+    #[arg(long)]
+    link: Option<PathBuf>,
+
+    /// Draw the display in the terminal
+    #[arg(long)]
+    terminal: bool,
+
+    /// Save the display as PNG, whenever it changes
+    #[arg(long)]
+    png: Option<PathBuf>,
+}
+
+fn main() {
+    let args = Cli::parse();
+
+    let mut serial = VirtualSerial::open().expect("Failed to create serial device");
+    if let Some(link) = &args.link {
+        // Left behind by a previous run
+        let _ = fs::remove_file(link);
+        symlink(serial.path(), link).expect("Failed to create symlink");
+    }
+    if !args.terminal {
+        println!("Serial device: {}", serial.path());
+    }
+
+    let mut module = Module::new();
+    let mut session = Session::default();
+    let mut decoder = FrameDecoder::default();
+    let mut output = Output::new(args.terminal, args.png);
+    output.update(module.render());
+
+    loop {
+        let mut buf = [0u8; 64];
+        match serial.read(&mut buf, POLL_PERIOD) {
+            Ok(Event::Data(count)) => {
+                session.update_dtr(true);
+                decoder.push(&buf[..count]);
+                while let Some(packet) = decoder.next_packet() {
+                    let command =
+                        packet.and_then(|packet| session.parse_command(packet.len(), &packet));
+                    let command = match command {
+                        Ok(command) => command,
+                        Err(err) => {
+                            if let Some(response) = session.reject(err, decoder.framed()) {
+                                let _ = serial.write(&response);
+                            }
+                            continue;
+                        }
+                    };
+                    // Doesn't return, acknowledge it before
+                    if command == Command::BootloaderReset {
+                        if let Some(ack) = session.ack(AckStatus::Ok) {
+                            let _ = serial.write(&ack);
+                        }
+                    }
+                    let (status, response) = module.handle_command(&command);
+                    if let Some(ack) = session.ack(status) {
+                        let _ = serial.write(&ack);
+                    }
+                    if let Some(response) = response {
+                        let _ = serial.write(&response);
+                    }
+                }
+            }
+            Ok(Event::Closed) => session.update_dtr(false),
+            Ok(Event::Idle) => {}
+            Err(err) => panic!("Failed to read from serial device: {err}"),
+        }
+        module.tick();
+        output.update(module.render());
+    }
+}
@@ -0,0 +1,76 @@
+//! Virtual serial device, that the host software connects to
+use std::fs::OpenOptions;
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::thread;
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::fcntl::OFlag;
+use nix::poll::{poll, PollFd, PollFlags};
+use nix::pty::{grantpt, posix_openpt, ptsname_r, unlockpt, PtyMaster};
+use nix::sys::termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg};
+
+pub enum Event {
+    /// Number of bytes that were received
+    Data(usize),
+    /// Nothing received before the timeout
+    Idle,
+    /// The serial device isn't open. Like DTR being low on the real module.
+    Closed,
+}
+
+pub struct VirtualSerial {
+    master: PtyMaster,
+    /// Path of the serial device, for example /dev/pts/3
+    path: String,
+}
+
+impl VirtualSerial {
+    pub fn open() -> io::Result<Self> {
+        let master = posix_openpt(OFlag::O_RDWR | OFlag::O_NOCTTY)?;
+        grantpt(&master)?;
+        unlockpt(&master)?;
+        let path = ptsname_r(&master)?;
+
+        // Raw mode, otherwise the responses are echoed back to us.
+        // Clients normally set it themselves, but not all of them do.
+        let slave = OpenOptions::new().read(true).write(true).open(&path)?;
+        let mut termios = tcgetattr(slave.as_raw_fd())?;
+        cfmakeraw(&mut termios);
+        tcsetattr(slave.as_raw_fd(), SetArg::TCSANOW, &termios)?;
+
+        Ok(Self { master, path })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// Wait for data from the host, at most for timeout
+    pub fn read(&mut self, buf: &mut [u8], timeout: Duration) -> io::Result<Event> {
+        let mut fds = [PollFd::new(self.master.as_raw_fd(), PollFlags::POLLIN)];
+        poll(&mut fds, timeout.as_millis() as i32)?;
+        let revents = fds[0].revents().unwrap_or(PollFlags::empty());
+
+        if revents.contains(PollFlags::POLLIN) {
+            match self.master.read(buf) {
+                Ok(count) => return Ok(Event::Data(count)),
+                // Nobody has the serial device open anymore
+                Err(err) if err.raw_os_error() == Some(Errno::EIO as i32) => {}
+                Err(err) => return Err(err),
+            }
+        }
+        if revents.contains(PollFlags::POLLHUP) {
+            // Poll returns right away, until the serial device is opened again
+            thread::sleep(timeout);
+            return Ok(Event::Closed);
+        }
+        Ok(Event::Idle)
+    }
+
+    /// Fails if nobody has the serial device open
+    pub fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.master.write_all(data)
+    }
+}
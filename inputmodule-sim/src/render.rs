@@ -0,0 +1,88 @@
+//! Showing what's on the simulated display
+use std::fmt::Write;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use image::imageops::{self, FilterType};
+use image::{ImageFormat, Rgb, RgbImage};
+
+/// Terminals aren't wider than this, larger images are scaled down
+const MAX_TERMINAL_WIDTH: u32 = 100;
+
+pub struct Output {
+    terminal: bool,
+    png: Option<PathBuf>,
+    /// Last image that was output, to skip it if nothing changed
+    last: Option<RgbImage>,
+}
+
+impl Output {
+    pub fn new(terminal: bool, png: Option<PathBuf>) -> Self {
+        if terminal {
+            // Clear the screen, images are drawn over each other afterwards
+            print!("\x1b[2J");
+        }
+        Self {
+            terminal,
+            png,
+            last: None,
+        }
+    }
+
+    pub fn update(&mut self, image: RgbImage) {
+        if self.last.as_ref() == Some(&image) {
+            return;
+        }
+        if self.terminal {
+            print!("{}", draw_terminal(&image));
+        }
+        if let Some(png) = &self.png {
+            save_png(&image, png);
+        }
+        self.last = Some(image);
+    }
+}
+
+/// ANSI escape codes that draw the image in the top left of the terminal
+///
+/// Every character shows two pixels on top of each other, that way they are
+/// about square.
+fn draw_terminal(image: &RgbImage) -> String {
+    let factor = image.width().div_ceil(MAX_TERMINAL_WIDTH);
+    let scaled;
+    let image = if factor > 1 {
+        let (width, height) = (image.width() / factor, image.height() / factor);
+        scaled = imageops::resize(image, width, height, FilterType::Triangle);
+        &scaled
+    } else {
+        image
+    };
+
+    let mut out = String::from("\x1b[H");
+    for y in (0..image.height()).step_by(2) {
+        for x in 0..image.width() {
+            let Rgb([r, g, b]) = *image.get_pixel(x, y);
+            let Rgb([r2, g2, b2]) = if y + 1 < image.height() {
+                *image.get_pixel(x, y + 1)
+            } else {
+                Rgb([0, 0, 0])
+            };
+            write!(
+                out,
+                "\x1b[38;2;{r};{g};{b}m\x1b[48;2;{r2};{g2};{b2}m\u{2580}"
+            )
+            .unwrap();
+        }
+        out.push_str("\x1b[0m\r\n");
+    }
+    out
+}
+
+/// Replace the file at once, so that readers never see half of it
+fn save_png(image: &RgbImage, path: &Path) {
+    let tmp = path.with_extension("png.tmp");
+    image
+        .save_with_format(&tmp, ImageFormat::Png)
+        .expect("Failed to save PNG");
+    fs::rename(&tmp, path).expect("Failed to save PNG");
+}
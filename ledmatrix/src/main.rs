@@ -3,36 +3,15 @@
 #![no_main]
 #![allow(clippy::needless_range_loop)]
 
-use cortex_m::delay::Delay;
 //use defmt::*;
 use defmt_rtt as _;
 use embedded_hal::digital::v2::{InputPin, OutputPin};
 
-use rp2040_hal::{
-    gpio::bank0::Gpio29,
-    rosc::{Enabled, RingOscillator},
-};
+use rp2040_hal::rosc::{Enabled, RingOscillator};
 //#[cfg(debug_assertions)]
 //use panic_probe as _;
 use rp2040_panic_usb_boot as _;
 
-#[derive(PartialEq, Eq)]
-#[allow(dead_code)]
-enum SleepMode {
-    /// Instantly go to sleep ant
-    Instant,
-    /// Fade brightness out and in slowly when sleeping/waking-up
-    Fading,
-    // Display "SLEEP" when sleeping, instead of turning LEDs off
-    Debug,
-}
-
-/// Static configuration whether sleep shohld instantly turn all LEDs on/off or
-/// slowly fade themm on/off
-const SLEEP_MODE: SleepMode = SleepMode::Fading;
-
-const STARTUP_ANIMATION: bool = true;
-
 /// Go to sleep after 60s awake
 const SLEEP_TIMEOUT: u64 = 60_000_000;
 
@@ -50,6 +29,13 @@ const MAX_BRIGHTNESS: u8 = 94;
 #[cfg(not(feature = "10k"))]
 const MAX_BRIGHTNESS: u8 = 50;
 
+/// Only used if the hardware revision isn't programmed in the serial number
+/// block or not known to this firmware
+const DEFAULT_HW_CONFIG: HwConfig = HwConfig {
+    evt: cfg!(feature = "evt"),
+    max_brightness: MAX_BRIGHTNESS,
+};
+
 // TODO: Doesn't work yet, unless I panic right at the beginning of main
 //#[cfg(not(debug_assertions))]
 //use core::panic::PanicInfo;
@@ -110,13 +96,9 @@ const MAX_BRIGHTNESS: u8 = 50;
 // Uncomment the BSP you included in Cargo.toml, the rest of the code does not need to change.
 use bsp::entry;
 use fl16_inputmodules::animations::*;
-#[cfg(not(feature = "evt"))]
-use fl16_inputmodules::fl16::DVT2_CALC_PIXEL;
-#[cfg(feature = "evt")]
-use fl16_inputmodules::fl16::EVT_CALC_PIXEL;
-use fl16_inputmodules::games::pong_animation::*;
-use fl16_inputmodules::games::snake_animation::*;
-use fl16_inputmodules::{games::game_of_life, led_hal as bsp};
+use fl16_inputmodules::boot_animation::BootAnimationIterator;
+use fl16_inputmodules::fl16::HwConfig;
+use fl16_inputmodules::led_hal as bsp;
 //use rp_pico as bsp;
 // use sparkfun_pro_micro_rp2040 as bsp;
 
@@ -136,16 +118,14 @@ use usb_device::{class_prelude::*, prelude::*};
 // USB Communications Class Device support
 use usbd_serial::{SerialPort, USB_CLASS_CDC};
 
-// Used to demonstrate writing formatted strings
-use core::fmt::Write;
-use heapless::String;
-
 use fl16_inputmodules::control::*;
 use fl16_inputmodules::fl16::LedMatrix;
-use fl16_inputmodules::games::{pong, snake};
+use fl16_inputmodules::framing::FrameDecoder;
+use fl16_inputmodules::main_loop::{show_startup_animation, MainLoop};
 use fl16_inputmodules::matrix::*;
 use fl16_inputmodules::patterns::*;
 use fl16_inputmodules::serialnum::{device_release, get_serialnum};
+use fl16_inputmodules::settings::{self, SettingsStore};
 
 //                            FRA                - Framwork
 //                               KDE             - C1 LED Matrix
@@ -197,11 +177,14 @@ fn main() -> ! {
     // Set up the USB Communications Class Device driver
     let mut serial = SerialPort::new(&usb_bus);
 
-    let serialnum = if let Some(serialnum) = get_serialnum() {
-        serialnum.serialnum
+    let (serialnum, hw_revision) = if let Some(serialnum) = get_serialnum() {
+        (serialnum.serialnum, Some(serialnum.hw_revision))
     } else {
-        DEFAULT_SERIAL
+        (DEFAULT_SERIAL, None)
     };
+    let hw_config = hw_revision
+        .and_then(HwConfig::from_revision)
+        .unwrap_or(DEFAULT_HW_CONFIG);
 
     let mut usb_dev = UsbDeviceBuilder::new(&usb_bus, UsbVidPid(0x32ac, 0x0020))
         .manufacturer("Framework Computer Inc")
@@ -230,71 +213,53 @@ fn main() -> ! {
 
     let dip1 = pins.dip1.into_pull_up_input();
 
-    let mut state = LedmatrixState {
-        grid: percentage(0),
-        col_buffer: Grid::default(),
-        animate: false,
-        brightness: 51, // Default to 51/255 = 20% brightness
-        sleeping: SleepState::Awake,
-        game: None,
-        animation_period: 31_250, // 31,250 us = 32 FPS
-        pwm_freq: PwmFreqArg::P29k,
-        debug_mode: false,
-        upcoming_frames: None,
-    };
-    state.debug_mode = dip1.is_low().unwrap();
-    if show_startup_animation(&state) {
-        state.upcoming_frames = Some(match get_random_byte(&rosc) % 8 {
-            0 => Animation::Percentage(StartupPercentageIterator::default()),
-            1 => Animation::ZigZag(ZigZagIterator::default()),
-            2 => Animation::Gof(GameOfLifeIterator::new(GameOfLifeStartParam::Pattern1, 200)),
-            3 => Animation::Gof(GameOfLifeIterator::new(
-                GameOfLifeStartParam::BeaconToadBlinker,
-                128,
-            )),
-            4 => Animation::Gof(GameOfLifeIterator::new(GameOfLifeStartParam::Glider, 128)),
-            5 => Animation::Breathing(BreathingIterator::default()),
-            6 => Animation::Pong(PongIterator::default()),
-            7 => Animation::Snake(SnakeIterator::default()),
-            _ => unreachable!(),
-        });
-    } else {
-        // If no startup animation, keep display always on
-        state.grid = percentage(100);
-    };
+    let mut state = LedmatrixState::default();
+    let mut session = Session::default();
+    let mut decoder = FrameDecoder::default();
 
-    #[cfg(feature = "evt")]
-    let mut matrix = LedMatrix::new(i2c, EVT_CALC_PIXEL);
-    #[cfg(not(feature = "evt"))]
-    let mut matrix = LedMatrix::new(i2c, DVT2_CALC_PIXEL);
+    let mut matrix = LedMatrix::new(i2c, hw_config.calc_pixel());
     matrix
         .setup(&mut delay)
         .expect("failed to setup RGB controller");
 
-    // EVT
-    #[cfg(feature = "evt")]
+    matrix.device.sw_enablement(hw_config.sw_setting()).unwrap();
+
     matrix
-        .device
-        .sw_enablement(is31fl3741::SwSetting::Sw1Sw9)
-        .unwrap();
-    // DVT
-    #[cfg(not(feature = "evt"))]
+        .set_scaling(hw_config.max_brightness)
+        .expect("failed to set scaling");
+
     matrix
         .device
-        .sw_enablement(is31fl3741::SwSetting::Sw1Sw8)
+        .set_pwm_freq(to_pwm_freq(state.pwm_freq))
         .unwrap();
 
-    matrix
-        .set_scaling(MAX_BRIGHTNESS)
-        .expect("failed to set scaling");
+    let mut settings = SettingsStore::new(settings::flash());
+    let _ = handle_command(
+        &Command::Settings(SettingsArg::Load),
+        &mut state,
+        &mut matrix,
+        get_random_byte(&rosc),
+        &mut settings,
+    );
 
-    matrix.device.set_pwm_freq(state.pwm_freq.into()).unwrap();
+    // Either the switch or the saved setting enables it
+    let mut last_dip1 = dip1.is_low().unwrap();
+    state.debug_mode |= last_dip1;
+    // After loading the settings, so that the saved animation period applies
+    if show_startup_animation(&state) {
+        state.upcoming_frames = Some(match BootAnimationIterator::load(state.animation_period) {
+            Some(boot_animation) => Animation::Boot(boot_animation),
+            None => startup_animation(get_random_byte(&rosc)),
+        });
+    } else {
+        // If no startup animation, keep display always on
+        state.grid = percentage(100);
+    };
 
     fill_grid_pixels(&state, &mut matrix);
 
     let timer = Timer::new(pac.TIMER, &mut pac.RESETS);
-    let mut animation_timer = timer.get_counter().ticks();
-    let mut game_timer = timer.get_counter().ticks();
+    let mut main_loop = MainLoop::new(timer.get_counter().ticks());
     let mut sleep_timer = timer.get_counter().ticks();
 
     // Detect whether the sleep pin is connected
@@ -313,14 +278,15 @@ fn main() -> ! {
     let mut usb_initialized = false;
     let mut usb_suspended = false;
     let mut last_usb_suspended = usb_suspended;
-    let mut sleep_reason: Option<SleepReason> = None;
-    let mut last_sleep_reason: Option<SleepReason>;
     let mut last_host_sleep = sleep.is_low().unwrap();
 
     loop {
-        last_sleep_reason = sleep_reason;
-
-        state.debug_mode = dip1.is_low().unwrap();
+        // Flipping the switch overrides the setting, until the next change
+        let dip1_on = dip1.is_low().unwrap();
+        if dip1_on != last_dip1 {
+            state.debug_mode = dip1_on;
+            last_dip1 = dip1_on;
+        }
         if sleep_present {
             // Go to sleep if the host is sleeping
             let host_sleeping = sleep.is_low().unwrap();
@@ -329,13 +295,7 @@ fn main() -> ! {
             // Or if it currently sleeping. Don't change if not sleeping
             // because then sleep is controlled by timing or by API.
             if host_sleep_changed || host_sleeping {
-                sleep_reason = assign_sleep_reason(
-                    last_sleep_reason,
-                    sleep_reason,
-                    host_sleeping,
-                    host_sleep_changed,
-                    SleepReason::SleepPin,
-                );
+                main_loop.sleep_signal(host_sleeping, host_sleep_changed, SleepReason::SleepPin);
             }
             last_host_sleep = host_sleeping;
         }
@@ -349,9 +309,7 @@ fn main() -> ! {
         // initialized for the first time. But we don't want to show the
         // sleep animation during startup.
         if usb_initialized && (usb_suspended_changed || usb_suspended) {
-            sleep_reason = assign_sleep_reason(
-                last_sleep_reason,
-                sleep_reason,
+            main_loop.sleep_signal(
                 usb_suspended,
                 usb_suspended_changed,
                 SleepReason::UsbSuspend,
@@ -361,51 +319,25 @@ fn main() -> ! {
 
         // Go to sleep after the timer has run out
         if timer.get_counter().ticks() > sleep_timer + SLEEP_TIMEOUT && !state.debug_mode {
-            sleep_reason = assign_sleep_reason(
-                last_sleep_reason,
-                sleep_reason,
-                true,
-                true,
-                SleepReason::Timeout,
-            );
+            main_loop.sleep_signal(true, true, SleepReason::Timeout);
         }
         // Constantly resetting timer during sleep is same as reset it once on waking up.
         // This means the timer ends up counting the time spent awake.
-        if sleep_reason.is_some() {
+        if main_loop.sleep_reason().is_some() {
             sleep_timer = timer.get_counter().ticks();
         }
 
-        handle_sleep(
-            sleep_reason,
+        main_loop.tick(
             &mut state,
             &mut matrix,
-            &mut delay,
             &mut led_enable,
+            timer.get_counter().ticks(),
+            || get_random_byte(&rosc),
         );
 
-        // Handle period display updates. Don't do it too often
-        let render_again = timer.get_counter().ticks() > animation_timer + state.animation_period;
-        if matches!(state.sleeping, SleepState::Awake) && render_again {
-            if let Some(ref mut upcoming) = state.upcoming_frames {
-                if let Some(next_frame) = upcoming.next() {
-                    state.grid = next_frame;
-                } else {
-                    // Animation is over. Clear screen
-                    state.grid = Grid::default();
-                }
-            }
-
-            fill_grid_pixels(&state, &mut matrix);
-            if state.animate {
-                for x in 0..WIDTH {
-                    state.grid.0[x].rotate_right(1);
-                }
-            }
-            animation_timer = timer.get_counter().ticks();
-        }
-
         // Check for new data
         if usb_dev.poll(&mut [&mut serial]) {
+            session.update_dtr(serial.dtr());
             match usb_dev.state() {
                 // Default: Device has just been created or reset
                 // Addressed: Device has received an address for the host
@@ -434,69 +366,51 @@ fn main() -> ! {
                 }
                 Ok(count) => {
                     let random = get_random_byte(&rosc);
-                    match (parse_command(count, &buf), &state.sleeping) {
-                        // Handle bootloader command without any delay
-                        // No need, it'll reset the device anyways
-                        (Some(c @ Command::BootloaderReset), _) => {
-                            handle_command(&c, &mut state, &mut matrix, random);
-                        }
-                        (Some(command), _) => {
-                            if let Command::Sleep(go_sleeping) = command {
-                                sleep_reason = assign_sleep_reason(
-                                    last_sleep_reason,
-                                    sleep_reason,
-                                    go_sleeping,
-                                    true,
-                                    SleepReason::Command,
+                    decoder.push(&buf[..count]);
+                    while let Some(packet) = decoder.next_packet() {
+                        let command =
+                            packet.and_then(|packet| session.parse_command(packet.len(), &packet));
+                        match (command, &state.sleeping) {
+                            // Handle bootloader command without any delay
+                            // No need, it'll reset the device anyways
+                            (Ok(c @ Command::BootloaderReset), _) => {
+                                if let Some(ack) = session.ack(AckStatus::Ok) {
+                                    let _ = serial.write(&ack);
+                                }
+                                let _ = handle_command(
+                                    &c,
+                                    &mut state,
+                                    &mut matrix,
+                                    random,
+                                    &mut settings,
                                 );
-                            } else {
-                                // If already sleeping, wake up.
-                                // This means every command will wake the device up.
-                                // Much more convenient than having to send the wakeup commmand.
-                                sleep_reason = None;
                             }
-                            // Make sure sleep animation only goes up to newly set brightness,
-                            // if setting the brightness causes wakeup
-                            if let SleepState::Sleeping((ref grid, _)) = state.sleeping {
-                                if let Command::SetBrightness(new_brightness) = command {
-                                    state.sleeping =
-                                        SleepState::Sleeping((grid.clone(), new_brightness));
+                            (Ok(command), _) => {
+                                // Reset sleep timer when interacting with the device
+                                // Very easy way to keep the device from going to sleep
+                                sleep_timer = timer.get_counter().ticks();
+
+                                let (status, response) = main_loop.handle_command(
+                                    &command,
+                                    &mut state,
+                                    &mut matrix,
+                                    &mut led_enable,
+                                    random,
+                                    &mut settings,
+                                );
+                                if let Some(ack) = session.ack(status) {
+                                    let _ = serial.write(&ack);
+                                }
+                                if let Some(response) = response {
+                                    let _ = serial.write(&response);
+                                };
+                            }
+                            (Err(err), _) => {
+                                if let Some(response) = session.reject(err, decoder.framed()) {
+                                    let _ = serial.write(&response);
                                 }
                             }
-                            handle_sleep(
-                                sleep_reason,
-                                &mut state,
-                                &mut matrix,
-                                &mut delay,
-                                &mut led_enable,
-                            );
-
-                            // If there's a very early command, cancel the startup animation
-                            state.upcoming_frames = None;
-
-                            // Reset sleep timer when interacting with the device
-                            // Very easy way to keep the device from going to sleep
-                            sleep_timer = timer.get_counter().ticks();
-
-                            if let Some(response) =
-                                handle_command(&command, &mut state, &mut matrix, random)
-                            {
-                                let _ = serial.write(&response);
-                            };
-                            // Must write AFTER writing response, otherwise the
-                            // client interprets this debug message as the response
-                            let mut text: String<64> = String::new();
-                            write!(
-                                &mut text,
-                                "Handled command {}:{}:{}:{}\r\n",
-                                buf[0], buf[1], buf[2], buf[3]
-                            )
-                            .unwrap();
-                            // let _ = serial.write(text.as_bytes());
-
-                            fill_grid_pixels(&state, &mut matrix);
                         }
-                        (None, _) => {}
                     }
                 }
             }
@@ -516,47 +430,6 @@ fn main() -> ! {
                 }
             }
         }
-
-        // Handle game state
-        let game_step_diff = match state.game {
-            Some(GameState::Pong(ref pong_state)) => 100_000 - 5_000 * pong_state.speed,
-            Some(GameState::Snake(_)) => 500_000,
-            Some(GameState::GameOfLife(_)) => 500_000,
-            _ => 500_000,
-        };
-        if timer.get_counter().ticks() > game_timer + game_step_diff {
-            let random = get_random_byte(&rosc);
-            match state.game {
-                Some(GameState::GameOfLife(_)) => {
-                    let _ = serial.write(b"GOL Game step\r\n");
-                    game_of_life::game_step(&mut state, random);
-                }
-                Some(GameState::Pong(_)) => {
-                    let _ = serial.write(b"Pong Game step\r\n");
-                    pong::game_step(&mut state, random);
-                }
-                Some(GameState::Snake(_)) => {
-                    let _ = serial.write(b"Snake Game step\r\n");
-                    let (direction, game_over, points, (x, y)) =
-                        snake::game_step(&mut state, random);
-
-                    if game_over {
-                        // TODO: Show score
-                    } else {
-                        let mut text: String<64> = String::new();
-                        write!(
-                            &mut text,
-                            "Dir: {:?} Status: {}, Points: {}, Head: ({},{})\r\n",
-                            direction, game_over, points, x, y
-                        )
-                        .unwrap();
-                        let _ = serial.write(text.as_bytes());
-                    }
-                }
-                None => {}
-            }
-            game_timer = timer.get_counter().ticks();
-        }
     }
 }
 
@@ -567,114 +440,3 @@ fn get_random_byte(rosc: &RingOscillator<Enabled>) -> u8 {
     }
     byte
 }
-
-fn dyn_sleep_mode(state: &LedmatrixState) -> SleepMode {
-    if state.debug_mode {
-        SleepMode::Debug
-    } else {
-        SLEEP_MODE
-    }
-}
-
-fn debug_mode(state: &LedmatrixState) -> bool {
-    dyn_sleep_mode(state) == SleepMode::Debug
-}
-
-fn show_startup_animation(state: &LedmatrixState) -> bool {
-    // Show startup animation
-    STARTUP_ANIMATION && !debug_mode(state)
-}
-
-fn assign_sleep_reason(
-    previous: Option<SleepReason>,
-    current: Option<SleepReason>,
-    need_sleep: bool,
-    // Whether the signal has actually changed in between firing
-    signal_changed: bool,
-    new: SleepReason,
-) -> Option<SleepReason> {
-    if !need_sleep {
-        None
-    } else if current.is_some() && (Some(new) == previous || !signal_changed) {
-        current
-    } else {
-        Some(new)
-    }
-}
-
-// Will do nothing if already in the right state
-fn handle_sleep(
-    sleep_reason: Option<SleepReason>,
-    state: &mut LedmatrixState,
-    matrix: &mut Foo,
-    delay: &mut Delay,
-    led_enable: &mut gpio::Pin<Gpio29, gpio::Output<gpio::PushPull>>,
-) {
-    match (state.sleeping.clone(), sleep_reason) {
-        // Awake and staying awake
-        (SleepState::Awake, None) => (),
-        (SleepState::Awake, Some(sleep_reason)) => {
-            state.sleeping = SleepState::Sleeping((state.grid.clone(), state.brightness));
-            // Slowly decrease brightness
-            if dyn_sleep_mode(state) == SleepMode::Fading {
-                let mut brightness = state.brightness;
-                loop {
-                    delay.delay_ms(100);
-                    brightness = if brightness <= 5 { 0 } else { brightness - 5 };
-                    set_brightness(state, brightness, matrix);
-                    if brightness == 0 {
-                        break;
-                    }
-                }
-            }
-
-            if debug_mode(state) {
-                state.grid = display_sleep_reason(sleep_reason);
-                fill_grid_pixels(state, matrix);
-            } else {
-                // Turn LED controller off to save power
-                led_enable.set_low().unwrap();
-            }
-
-            // TODO: Set up SLEEP# pin as interrupt and wfi
-            //cortex_m::asm::wfi();
-        }
-        // Already sleeping and new sleep reason => just keep sleeping
-        (SleepState::Sleeping(_), Some(sleep_reason)) => {
-            // If debug mode is enabled, then make sure the latest sleep reason is displayed
-            if debug_mode(state) {
-                state.grid = display_sleep_reason(sleep_reason);
-                fill_grid_pixels(state, matrix);
-            }
-        }
-        // Sleeping and need to wake up
-        (SleepState::Sleeping((old_grid, old_brightness)), None) => {
-            // Restore back grid before sleeping
-            state.sleeping = SleepState::Awake;
-            state.grid = old_grid;
-            fill_grid_pixels(state, matrix);
-
-            // Power LED controller back on
-            if !debug_mode(state) {
-                led_enable.set_high().unwrap();
-            }
-
-            // Slowly increase brightness
-            if dyn_sleep_mode(state) == SleepMode::Fading {
-                let mut brightness = 0;
-                loop {
-                    delay.delay_ms(100);
-                    brightness = if brightness >= old_brightness - 5 {
-                        old_brightness
-                    } else {
-                        brightness + 5
-                    };
-                    set_brightness(state, brightness, matrix);
-                    if brightness == old_brightness {
-                        break;
-                    }
-                }
-            }
-        }
-    }
-}
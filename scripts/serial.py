@@ -13,18 +13,19 @@ macropad       = b'FRAKDNEN4100000000' # EVT 1, config 1
 
 # This section is for modifying
 selected   = ledmatrix_2
+hw_rev     = b'\x02' # See flash_layout.md
 year       = b'3' # 2023
 week       = b'01'
 day        = b'1'
 part_sn    = b'0001'
 
 config     = selected[8:10]
-serial_rev = b'\x01'
+serial_rev = b'\x02'
 snum       = selected
 print(serial_rev + snum)
 snum       = snum[0:8] + config + year + week + day + part_sn
 
-checksum   = zlib.crc32(serial_rev + snum)
+checksum   = zlib.crc32(serial_rev + snum + hw_rev)
 print(serial_rev + snum)
 
 print('Checksum:', hex(zlib.crc32(snum)))
@@ -32,4 +33,5 @@ print('Digest:  ', hex(checksum))
 with open('serial.bin', 'wb') as f:
     f.write(serial_rev)
     f.write(snum)
+    f.write(hw_rev)
     f.write(checksum.to_bytes(4, 'little'))